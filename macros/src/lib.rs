@@ -0,0 +1,240 @@
+//! `arm!`, which assembles ARM instructions while the crate using it is compiled, reporting
+//! mistakes as compile errors on the token at fault.
+
+use assembler::{
+    error::{AssemblerError, ParseError},
+    instructions::{FlexibleOperand, Instruction, Offset},
+};
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Assemble ARM instructions separated by `;` into a `[u32; N]` of their encodings.
+///
+/// One immediate per instruction may be a Rust constant expression, written `#{expr}`. It is
+/// encoded when the constant is evaluated, and an immediate that doesn't fit fails to compile.
+#[proc_macro]
+pub fn arm(input: TokenStream) -> TokenStream {
+    let mut words = vec![];
+    for tokens in statements(input) {
+        match Statement::new(tokens).and_then(|statement| statement.assemble()) {
+            Ok(word) => words.push(word),
+            Err((message, span)) => return compile_error(&message, span),
+        }
+    }
+
+    let mut array = TokenStream::new();
+    for word in words {
+        array.extend(word);
+        array.extend([TokenTree::Punct(Punct::new(',', Spacing::Alone))]);
+    }
+    TokenTree::Group(Group::new(Delimiter::Bracket, array)).into()
+}
+
+/// Split `input` at each `;`, dropping empty statements.
+fn statements(input: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut statements = vec![vec![]];
+    for token in input {
+        match &token {
+            TokenTree::Punct(punct) if punct.as_char() == ';' => statements.push(vec![]),
+            _ => statements.last_mut().unwrap().push(token),
+        }
+    }
+    statements.retain(|tokens| !tokens.is_empty());
+    statements
+}
+
+/// `::core::compile_error!("message")`, pointing at `span`.
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut literal = Literal::string(message);
+    literal.set_span(span);
+    let tokens = [
+        TokenTree::Punct(Punct::new(':', Spacing::Joint)),
+        TokenTree::Punct(Punct::new(':', Spacing::Alone)),
+        TokenTree::Ident(Ident::new("core", span)),
+        TokenTree::Punct(Punct::new(':', Spacing::Joint)),
+        TokenTree::Punct(Punct::new(':', Spacing::Alone)),
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(Punct::new('!', Spacing::Alone)),
+        TokenTree::Group(Group::new(
+            Delimiter::Parenthesis,
+            TokenTree::Literal(literal).into(),
+        )),
+    ];
+    tokens
+        .into_iter()
+        .map(|mut token| {
+            token.set_span(span);
+            token
+        })
+        .collect()
+}
+
+/// One instruction, as the source text the assembler parses.
+struct Statement {
+    text: String,
+    /// The text of each word in the source, such as a register or number, and where it was.
+    words: Vec<(String, Span)>,
+    /// An interpolated immediate, with the `#` it followed.
+    immediate: Option<(TokenStream, Span)>,
+}
+
+type Error = (String, Span);
+
+impl Statement {
+    fn new(tokens: Vec<TokenTree>) -> Result<Self, Error> {
+        let mut statement = Self {
+            text: String::new(),
+            words: vec![],
+            immediate: None,
+        };
+        statement.push_tokens(tokens)?;
+        Ok(statement)
+    }
+
+    fn push_tokens(&mut self, tokens: Vec<TokenTree>) -> Result<(), Error> {
+        let mut tokens = tokens.into_iter().peekable();
+        let mut after_word = false;
+        while let Some(token) = tokens.next() {
+            match token {
+                TokenTree::Ident(_) | TokenTree::Literal(_) => {
+                    if after_word {
+                        self.text.push(' ');
+                    }
+                    self.separate_mnemonic();
+                    let text = token.to_string();
+                    self.text.push_str(&text);
+                    self.words.push((text, token.span()));
+                    after_word = true;
+                    continue;
+                }
+                TokenTree::Punct(punct) if punct.as_char() == '#' => {
+                    // `lsl #2` needs its space
+                    if after_word {
+                        self.text.push(' ');
+                    }
+                    self.separate_mnemonic();
+                    self.text.push('#');
+                    let Some(TokenTree::Group(group)) = tokens.peek() else {
+                        after_word = false;
+                        continue;
+                    };
+                    if group.delimiter() != Delimiter::Brace {
+                        continue;
+                    }
+                    if self.immediate.is_some() {
+                        let message = "only one immediate per instruction can be interpolated";
+                        return Err((message.to_owned(), group.span()));
+                    }
+                    // Assembled as zero, with the real value filled in by the constant
+                    self.text.push('0');
+                    self.immediate = Some((group.stream(), group.span()));
+                    tokens.next();
+                }
+                TokenTree::Punct(punct) => {
+                    self.separate_mnemonic();
+                    self.text.push(punct.as_char());
+                }
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::None => ("", ""),
+                    };
+                    self.separate_mnemonic();
+                    self.text.push_str(open);
+                    self.push_tokens(group.stream().into_iter().collect())?;
+                    self.text.push_str(close);
+                }
+            }
+            after_word = false;
+        }
+        Ok(())
+    }
+
+    /// Put a space after the mnemonic if nothing has followed it yet.
+    fn separate_mnemonic(&mut self) {
+        if matches!(self.words.as_slice(), [(mnemonic, _)] if *mnemonic == self.text) {
+            self.text.push(' ');
+        }
+    }
+
+    /// Where to report `error`: the word it names if there is one, or else the mnemonic.
+    fn span_of(&self, error: &AssemblerError) -> Span {
+        let detail = match error {
+            AssemblerError::Parse(
+                ParseError::BadMnemonic(detail)
+                | ParseError::BadRegister(detail)
+                | ParseError::BadFlexOperand(detail)
+                | ParseError::BadImmediate(detail)
+                | ParseError::UnexpectedCond(detail),
+            ) => detail.as_str(),
+            _ => "",
+        };
+        let named = self
+            .words
+            .iter()
+            .filter(|(word, _)| detail.contains(word.as_str()))
+            .max_by_key(|(word, _)| word.len());
+        named
+            .or(self.words.first())
+            .map_or_else(Span::call_site, |(_, span)| *span)
+    }
+
+    /// The encoding as a `u32` literal, or as a block that fills in the interpolated immediate.
+    fn assemble(self) -> Result<TokenStream, Error> {
+        let instruction = Instruction::try_from(self.text.as_str())
+            .map_err(|error| (format!("{error}"), self.span_of(&error)))?;
+        let word = instruction.clone().to_machine_code();
+        let Some((expr, span)) = self.immediate else {
+            return Ok(TokenTree::Literal(Literal::u32_suffixed(word)).into());
+        };
+
+        let (encoder, ty, word) = match instruction {
+            Instruction::DataProcessing(.., FlexibleOperand::ImmediateWithRotation(..)) => {
+                ("encode_rotated_immediate", "u32", word)
+            }
+            Instruction::MoveWide(..) => ("encode_wide_immediate", "u32", word),
+            // The offset sets the U bit itself
+            Instruction::Mem(.., Offset::Immediate(..)) => {
+                ("encode_offset_immediate", "i32", word & !(1 << 23))
+            }
+            _ => {
+                let message = format!("can't interpolate an immediate into `{}`", self.text);
+                return Err((message, span));
+            }
+        };
+
+        // { const WORD: u32 = match encoder((expr) as ty) { ... }; WORD }, so that the
+        // immediate is always encoded at compile time
+        let message = format!(
+            "`{expr}` can't be encoded as an immediate of `{}`",
+            self.words[0].0
+        );
+        let mut value: TokenStream =
+            TokenTree::Group(Group::new(Delimiter::Parenthesis, expr)).into();
+        value.extend(format!(" as {ty}").parse::<TokenStream>().unwrap());
+        let mut block: TokenStream =
+            format!("const WORD: u32 = match ::assembler::instructions::{encoder}")
+                .parse()
+                .unwrap();
+        block.extend([TokenTree::Group(Group::new(Delimiter::Parenthesis, value))]);
+        block.extend(
+            format!(
+                "{{ \
+                    ::core::option::Option::Some(field) => {word}u32 | field, \
+                    ::core::option::Option::None => ::core::panic!({message:?}), \
+                }}; WORD"
+            )
+            .parse::<TokenStream>()
+            .unwrap(),
+        );
+        let block = block.into_iter().map(|mut token| {
+            // Errors in the expression keep their own spans
+            if !matches!(&token, TokenTree::Group(group) if group.delimiter() == Delimiter::Parenthesis) {
+                token.set_span(span);
+            }
+            token
+        });
+        Ok(TokenTree::Group(Group::new(Delimiter::Brace, block.collect())).into())
+    }
+}
@@ -0,0 +1,52 @@
+use assembler::instructions::Instruction;
+use assembler_macros::arm;
+
+const PROLOGUE: [u32; 4] = arm! {
+    str fp, [sp, #-8]!;
+    str lr, [sp, #4];
+    add fp, sp, #4;
+    sub sp, sp, #8
+};
+
+const FRAME: u32 = 0x400;
+const OFFSET: i32 = -12;
+
+fn encode(source: &str) -> u32 {
+    Instruction::try_from(source).unwrap().to_machine_code()
+}
+
+#[test]
+fn test_arm() {
+    assert_eq!(
+        PROLOGUE,
+        [0xE52D_B008, 0xE58D_E004, 0xE28D_B004, 0xE24D_D008]
+    );
+    assert_eq!(
+        arm! { movne r0, r1; bx lr; },
+        [encode("movne r0, r1"), encode("bx lr")]
+    );
+    assert_eq!(
+        arm! { add r0, r1, r2, lsl #2; ldr r0, [r1, r2, asr #31] },
+        [0xE081_0102, 0xE791_0FC2]
+    );
+    assert_eq!(
+        arm! { push {fp, lr}; pop {fp, pc} },
+        [0xE92D_4800, 0xE8BD_8800]
+    );
+}
+
+#[test]
+fn test_interpolation() {
+    const WORDS: [u32; 3] = arm! {
+        sub sp, sp, #{FRAME};
+        movw r0, #{FRAME + 0x1234};
+        str r0, [fp, #{OFFSET}]
+    };
+    // 0x400 is 1 rotated right by 22 bits
+    assert_eq!(WORDS, [0xE24D_DB01, 0xE301_0634, 0xE50B_000C]);
+}
+
+#[test]
+fn test_compile_errors() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
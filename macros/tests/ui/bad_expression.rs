@@ -0,0 +1,5 @@
+use assembler_macros::arm;
+
+const WORDS: [u32; 1] = arm! { sub sp, sp, #{MISSING} };
+
+fn main() {}
@@ -0,0 +1,5 @@
+use assembler_macros::arm;
+
+const WORDS: [u32; 1] = arm! { frob r0, r1 };
+
+fn main() {}
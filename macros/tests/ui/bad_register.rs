@@ -0,0 +1,5 @@
+use assembler_macros::arm;
+
+const WORDS: [u32; 2] = arm! { mov r0, r1; add r0, r16, #1 };
+
+fn main() {}
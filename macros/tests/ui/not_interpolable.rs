@@ -0,0 +1,6 @@
+use assembler_macros::arm;
+
+const SHIFT: u32 = 2;
+const WORDS: [u32; 1] = arm! { mov r0, r1, lsl #{SHIFT} };
+
+fn main() {}
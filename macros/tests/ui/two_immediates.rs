@@ -0,0 +1,7 @@
+use assembler_macros::arm;
+
+const A: u32 = 1;
+const B: u32 = 2;
+const WORDS: [u32; 1] = arm! { mov r0, #{A}, #{B} };
+
+fn main() {}
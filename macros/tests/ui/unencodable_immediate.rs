@@ -0,0 +1,8 @@
+use assembler_macros::arm;
+
+const FRAME: u32 = 0x101;
+const WORDS: [u32; 1] = arm! { sub sp, sp, #{FRAME} };
+
+fn main() {
+    let _ = WORDS;
+}
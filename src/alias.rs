@@ -0,0 +1,251 @@
+//! Register names a source file defines for itself with `.req`, `.dn` and `.qn`. Each lasts from
+//! its definition until it's removed with `.unreq`. The NEON ones may also give an element type,
+//! as in `x .qn q1.f32`, which an instruction without a type of its own takes on.
+
+use std::collections::HashMap;
+
+use crate::{
+    cond::Cond,
+    error::{AssemblerError, ParseError},
+    instructions::parse_reg_id,
+    neon::{ElementType, NeonRegister},
+    vfp::VfpRegister,
+};
+
+/// The directive that defined an alias, which limits the registers it can name.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AliasKind {
+    /// `.req`, for any register.
+    Any,
+    /// `.dn`, for a double-precision register.
+    Double,
+    /// `.qn`, for a quad register.
+    Quad,
+}
+
+impl AliasKind {
+    pub fn from_directive(directive: &str) -> Option<Self> {
+        match directive.to_ascii_lowercase().as_str() {
+            "req" => Some(Self::Any),
+            "dn" => Some(Self::Double),
+            "qn" => Some(Self::Quad),
+            _ => None,
+        }
+    }
+}
+
+/// The register `value` names, spelled the way every parser accepts, if it's one `kind` allows.
+fn canonical_register(value: &str, kind: AliasKind) -> Option<String> {
+    match kind {
+        AliasKind::Any => parse_reg_id(value)
+            .ok()
+            .map(|id| format!("r{id}"))
+            .or_else(|| {
+                VfpRegister::try_from(value)
+                    .ok()
+                    .map(|register| register.to_string())
+            })
+            .or_else(|| canonical_register(value, AliasKind::Quad)),
+        AliasKind::Double => match VfpRegister::try_from(value).ok()? {
+            register @ VfpRegister::Double(_) => Some(register.to_string()),
+            VfpRegister::Single(_) => None,
+        },
+        AliasKind::Quad => match NeonRegister::try_from(value).ok()? {
+            NeonRegister::Quad(number) => Some(format!("q{number}")),
+            NeonRegister::Double(_) => None,
+        },
+    }
+}
+
+/// Whether `name` is a register without any alias being defined.
+fn is_builtin(name: &str) -> bool {
+    canonical_register(name, AliasKind::Any).is_some()
+}
+
+/// Whether the operand of instruction `name` is a label rather than a register: `b` and `bl`
+/// with any condition.
+fn is_branch_to_label(name: &str) -> bool {
+    ["bl", "b"].into_iter().any(|branch| {
+        name.get(..branch.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(branch))
+            && (name.len() == branch.len() || Cond::try_from(&name[branch.len()..]).is_ok())
+    })
+}
+
+/// The register an alias stands for, with the element type `.dn` and `.qn` may give it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Alias {
+    register: String,
+    data_type: Option<ElementType>,
+}
+
+/// The aliases in effect, keyed by lowercase name as they match in any case.
+#[derive(Clone, Debug, Default)]
+pub struct RegisterAliases {
+    aliases: HashMap<String, Alias>,
+}
+
+impl RegisterAliases {
+    /// Make `name` stand for `register`, which may itself be an alias and, for `.dn` and `.qn`,
+    /// have a type. Defining an alias again is only allowed if it names the same register and
+    /// type.
+    pub fn define(
+        &mut self,
+        name: &str,
+        kind: AliasKind,
+        register: &str,
+    ) -> Result<(), AssemblerError> {
+        let register = register.trim();
+        let bad_register = || ParseError::BadRegister(register.to_owned());
+        let (base, data_type) = match register.split_once('.') {
+            Some(_) if kind == AliasKind::Any => return Err(bad_register().into()),
+            Some((base, data_type)) => (base.trim(), Some(ElementType::try_from(data_type)?)),
+            None => (register, None),
+        };
+        // An alias of a typed alias keeps its type unless given another
+        let resolved = self.aliases.get(&base.to_ascii_lowercase());
+        let alias = Alias {
+            register: canonical_register(resolved.map_or(base, |alias| &alias.register), kind)
+                .ok_or_else(bad_register)?,
+            data_type: data_type.or(resolved.and_then(|alias| alias.data_type)),
+        };
+
+        let key = name.to_ascii_lowercase();
+        if is_builtin(name) || self.aliases.get(&key).is_some_and(|old| *old != alias) {
+            return Err(ParseError::DuplicateAlias(name.to_owned()).into());
+        }
+        self.aliases.insert(key, alias);
+        Ok(())
+    }
+
+    /// Remove the alias `name`.
+    pub fn undefine(&mut self, name: &str) -> Result<(), AssemblerError> {
+        self.aliases
+            .remove(&name.trim().to_ascii_lowercase())
+            .map(|_| ())
+            .ok_or_else(|| ParseError::UnknownAlias(name.trim().to_owned()).into())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// The register `name` stands for.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.aliases
+            .get(&name.to_ascii_lowercase())
+            .map(|alias| alias.register.as_str())
+    }
+
+    /// The operands of `opcode` with each alias replaced by its register, and the type of the
+    /// first typed alias among them. Words in strings, symbols after `#`, `=` or `:`, and the
+    /// label a branch goes to are left alone.
+    pub fn substitute(&self, opcode: &str, operands: &str) -> (String, Option<ElementType>) {
+        // Any width suffix doesn't matter, and cbz and cbnz only take a label after the register
+        let name = opcode.split('.').next().unwrap_or_default();
+        let (registers, label) = if is_branch_to_label(name) {
+            ("", operands)
+        } else if name.eq_ignore_ascii_case("cbz") || name.eq_ignore_ascii_case("cbnz") {
+            operands.split_at(operands.find(',').unwrap_or(operands.len()))
+        } else {
+            (operands, "")
+        };
+
+        let mut out = String::with_capacity(operands.len());
+        let mut data_type = None;
+        let mut in_string = false;
+        let mut previous = ' ';
+        let mut rest = registers;
+        while let Some(c) = rest.chars().next() {
+            let is_word_start = c.is_ascii_alphabetic() || c == '_';
+            if in_string || !is_word_start || previous.is_ascii_alphanumeric() {
+                in_string ^= c == '"' && previous != '\\';
+                out.push(c);
+                previous = c;
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || "_.$".contains(c)))
+                .unwrap_or(rest.len());
+            let (word, after) = rest.split_at(end);
+            match self.aliases.get(&word.to_ascii_lowercase()) {
+                Some(alias) if !"#=:".contains(previous) => {
+                    out.push_str(&alias.register);
+                    data_type = data_type.or(alias.data_type);
+                }
+                _ => out.push_str(word),
+            }
+            previous = word.chars().last().unwrap_or(previous);
+            rest = after;
+        }
+        out.push_str(label);
+        (out, data_type)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_aliases() {
+        let mut aliases = RegisterAliases::default();
+        aliases.define("count", AliasKind::Any, "r2").unwrap();
+        aliases.define("total", AliasKind::Any, "SB").unwrap();
+        aliases.define("tally", AliasKind::Any, "count").unwrap();
+        aliases.define("acc", AliasKind::Double, "d3").unwrap();
+        aliases.define("vec", AliasKind::Quad, "Q1").unwrap();
+        assert_eq!(aliases.get("COUNT"), Some("r2"));
+        assert_eq!(aliases.get("total"), Some("r9"));
+        assert_eq!(aliases.get("tally"), Some("r2"));
+
+        let substitute = |opcode, operands| aliases.substitute(opcode, operands).0;
+        assert_eq!(
+            substitute("ldm", "count, [total, #count], {tally-r5}"),
+            "r2, [r9, #count], {r2-r5}"
+        );
+        assert_eq!(substitute("vadd", "acc, vec, =count"), "d3, q1, =count");
+        assert_eq!(
+            substitute(".word", "\"count\", count2"),
+            "\"count\", count2"
+        );
+        // Branches go to labels, whatever aliases there are
+        assert_eq!(substitute("b", "count"), "count");
+        assert_eq!(substitute("BLNE", "count"), "count");
+        assert_eq!(substitute("bls.w", "count"), "count");
+        assert_eq!(substitute("bx", "count"), "r2");
+        assert_eq!(substitute("cbz", "count, count"), "r2, count");
+
+        // A typed alias passes its type on, and keeps it through another alias
+        aliases.define("x", AliasKind::Quad, "q2.f32").unwrap();
+        aliases.define("y", AliasKind::Quad, "x").unwrap();
+        let f32 = ElementType::try_from("f32").ok();
+        assert_eq!(
+            aliases.substitute("vadd", "vec, y, x"),
+            ("q1, q2, q2".to_owned(), f32)
+        );
+        assert_eq!(
+            aliases.substitute("vadd", "vec, vec"),
+            ("q1, q1".to_owned(), None)
+        );
+        assert!(aliases.define("x", AliasKind::Quad, "q2.i32").is_err());
+        assert!(aliases.define("z", AliasKind::Any, "r1.i32").is_err());
+        assert!(aliases.define("z", AliasKind::Double, "d1.f16").is_err());
+        assert_eq!(AliasKind::from_directive("Req"), Some(AliasKind::Any));
+        assert_eq!(AliasKind::from_directive("QN"), Some(AliasKind::Quad));
+
+        // Same register again is fine, a different one or a register name isn't
+        aliases.define("count", AliasKind::Any, "r2").unwrap();
+        assert!(aliases.define("count", AliasKind::Any, "r3").is_err());
+        assert!(aliases.define("sp", AliasKind::Any, "r3").is_err());
+        assert!(aliases.define("w", AliasKind::Double, "q1").is_err());
+        assert!(aliases.define("w", AliasKind::Quad, "d1").is_err());
+        assert!(aliases.define("w", AliasKind::Any, "r16").is_err());
+
+        aliases.undefine("count").unwrap();
+        assert_eq!(aliases.get("count"), None);
+        assert!(aliases.undefine("count").is_err());
+    }
+}
@@ -0,0 +1,482 @@
+use crate::error::{AssemblerError, ParseError};
+
+/// An ARM architecture version that code can be assembled for.
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, strum_macros::EnumIter, strum_macros::EnumString,
+)]
+#[strum(ascii_case_insensitive)]
+pub enum Arch {
+    #[strum(serialize = "armv4")]
+    V4,
+    #[strum(serialize = "armv4t")]
+    V4T,
+    #[strum(serialize = "armv5te")]
+    V5TE,
+    #[strum(serialize = "armv6")]
+    V6,
+    #[strum(serialize = "armv6k")]
+    V6K,
+    #[strum(serialize = "armv6t2")]
+    V6T2,
+    #[default]
+    #[strum(serialize = "armv7-a", serialize = "armv7a")]
+    V7A,
+    #[strum(serialize = "armv7-r", serialize = "armv7r")]
+    V7R,
+    #[strum(serialize = "armv7ve")]
+    V7VE,
+    #[strum(serialize = "armv6-m", serialize = "armv6m")]
+    V6M,
+    #[strum(serialize = "armv7-m", serialize = "armv7m")]
+    V7M,
+    #[strum(serialize = "armv7e-m", serialize = "armv7em")]
+    V7EM,
+}
+
+impl std::fmt::Display for Arch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arch::V4 => write!(f, "armv4"),
+            Arch::V4T => write!(f, "armv4t"),
+            Arch::V5TE => write!(f, "armv5te"),
+            Arch::V6 => write!(f, "armv6"),
+            Arch::V6K => write!(f, "armv6k"),
+            Arch::V6T2 => write!(f, "armv6t2"),
+            Arch::V7A => write!(f, "armv7-a"),
+            Arch::V7R => write!(f, "armv7-r"),
+            Arch::V7VE => write!(f, "armv7ve"),
+            Arch::V6M => write!(f, "armv6-m"),
+            Arch::V7M => write!(f, "armv7-m"),
+            Arch::V7EM => write!(f, "armv7e-m"),
+        }
+    }
+}
+
+/// Architectural features an instruction can depend on beyond the ARMv4 baseline.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum Feature {
+    /// The 32-bit ARM instruction set, which M-profile cores lack.
+    Arm,
+    V4T,
+    V5TE,
+    V6,
+    V6K,
+    V6T2,
+    V7,
+    /// 32-bit Thumb instructions beyond `bl`.
+    Thumb2,
+    /// The Thumb hints and `mrs`/`msr`, which ARMv6-M has without the rest of Thumb-2.
+    ThumbSystem,
+    /// `dmb`, `dsb` and `isb`, from ARMv7 and in ARMv6-M.
+    Barrier,
+    /// Multiprocessing extensions (`pldw`).
+    Mp,
+    /// Hardware integer divide.
+    Idiv,
+    /// Security extensions (`smc`).
+    Security,
+    /// Virtualization extensions (`hvc`).
+    Virtualization,
+    Vfpv2,
+    Vfpv3,
+    /// VFPv4, adding the fused multiply-accumulate instructions.
+    Vfpv4,
+    /// Double-precision VFP arithmetic, which single-precision only units such as FPv4-SP lack.
+    Fp64,
+    /// VFP with all 32 double-precision registers rather than 16.
+    VfpD32,
+    Neon,
+}
+
+impl std::fmt::Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Feature::Arm => write!(f, "ARM state"),
+            Feature::V4T => write!(f, "ARMv4T"),
+            Feature::V5TE => write!(f, "ARMv5TE"),
+            Feature::V6 => write!(f, "ARMv6"),
+            Feature::V6K => write!(f, "ARMv6K"),
+            Feature::V6T2 => write!(f, "ARMv6T2"),
+            Feature::V7 => write!(f, "ARMv7"),
+            Feature::Thumb2 => write!(f, "Thumb-2"),
+            Feature::ThumbSystem => write!(f, "the Thumb hints and mrs/msr"),
+            Feature::Barrier => write!(f, "memory barriers"),
+            Feature::Mp => write!(f, "the multiprocessing extensions"),
+            Feature::Idiv => write!(f, "hardware divide"),
+            Feature::Security => write!(f, "the security extensions"),
+            Feature::Virtualization => write!(f, "the virtualization extensions"),
+            Feature::Vfpv2 => write!(f, "VFPv2"),
+            Feature::Vfpv3 => write!(f, "VFPv3"),
+            Feature::Vfpv4 => write!(f, "VFPv4"),
+            Feature::Fp64 => write!(f, "double-precision VFP"),
+            Feature::VfpD32 => write!(f, "VFP registers d16-d31"),
+            Feature::Neon => write!(f, "NEON"),
+        }
+    }
+}
+
+impl Arch {
+    fn features(self) -> &'static [Feature] {
+        use Feature::*;
+
+        match self {
+            Arch::V4 => &[Arm],
+            Arch::V4T => &[Arm, V4T],
+            Arch::V5TE => &[Arm, V4T, V5TE],
+            Arch::V6 => &[Arm, V4T, V5TE, V6],
+            Arch::V6K => &[Arm, V4T, V5TE, V6, V6K],
+            Arch::V6T2 => &[Arm, V4T, V5TE, V6, V6T2, Thumb2, ThumbSystem],
+            Arch::V7A => &[
+                Arm,
+                V4T,
+                V5TE,
+                V6,
+                V6K,
+                V6T2,
+                V7,
+                Thumb2,
+                ThumbSystem,
+                Barrier,
+            ],
+            Arch::V7R => &[
+                Arm,
+                V4T,
+                V5TE,
+                V6,
+                V6K,
+                V6T2,
+                V7,
+                Thumb2,
+                ThumbSystem,
+                Barrier,
+                Idiv,
+            ],
+            Arch::V7VE => &[
+                Arm,
+                V4T,
+                V5TE,
+                V6,
+                V6K,
+                V6T2,
+                V7,
+                Thumb2,
+                ThumbSystem,
+                Barrier,
+                Mp,
+                Idiv,
+                Security,
+                Virtualization,
+            ],
+            Arch::V6M => &[V4T, V5TE, V6, ThumbSystem, Barrier],
+            Arch::V7M | Arch::V7EM => &[
+                V4T,
+                V5TE,
+                V6,
+                V6K,
+                V6T2,
+                V7,
+                Thumb2,
+                ThumbSystem,
+                Barrier,
+                Idiv,
+            ],
+        }
+    }
+
+    pub fn supports(self, feature: Feature) -> bool {
+        self.features().contains(&feature)
+    }
+
+    /// The `Tag_CPU_arch` value recorded in `.ARM.attributes`.
+    pub fn cpu_arch_tag(self) -> u8 {
+        match self {
+            Arch::V4 => 1,
+            Arch::V4T => 2,
+            Arch::V5TE => 4,
+            Arch::V6 => 6,
+            Arch::V6T2 => 8,
+            Arch::V6K => 9,
+            Arch::V7A | Arch::V7R | Arch::V7VE | Arch::V7M => 10,
+            Arch::V6M => 11,
+            Arch::V7EM => 13,
+        }
+    }
+
+    /// The `Tag_CPU_arch_profile` value, only recorded from ARMv7 onwards.
+    pub fn profile(self) -> Option<u8> {
+        match self {
+            Arch::V7A | Arch::V7VE => Some(b'A'),
+            Arch::V7R => Some(b'R'),
+            Arch::V6M | Arch::V7M | Arch::V7EM => Some(b'M'),
+            _ => None,
+        }
+    }
+}
+
+/// The floating-point/SIMD unit available to the target.
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, strum_macros::EnumIter, strum_macros::EnumString,
+)]
+#[strum(ascii_case_insensitive)]
+pub enum Fpu {
+    #[default]
+    #[strum(serialize = "none", serialize = "softvfp")]
+    None,
+    #[strum(serialize = "vfpv2", serialize = "vfp")]
+    Vfpv2,
+    #[strum(serialize = "vfpv3")]
+    Vfpv3,
+    #[strum(serialize = "vfpv3-d16")]
+    Vfpv3D16,
+    #[strum(serialize = "neon", serialize = "neon-vfpv3")]
+    Neon,
+    #[strum(serialize = "vfpv4")]
+    Vfpv4,
+    #[strum(serialize = "vfpv4-d16")]
+    Vfpv4D16,
+    /// Single-precision VFPv4 with 16 double registers, as on the Cortex-M4.
+    #[strum(serialize = "fpv4-sp-d16")]
+    FpV4SpD16,
+    #[strum(serialize = "neon-vfpv4")]
+    NeonVfpv4,
+}
+
+impl std::fmt::Display for Fpu {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fpu::None => write!(f, "none"),
+            Fpu::Vfpv2 => write!(f, "vfpv2"),
+            Fpu::Vfpv3 => write!(f, "vfpv3"),
+            Fpu::Vfpv3D16 => write!(f, "vfpv3-d16"),
+            Fpu::Neon => write!(f, "neon"),
+            Fpu::Vfpv4 => write!(f, "vfpv4"),
+            Fpu::Vfpv4D16 => write!(f, "vfpv4-d16"),
+            Fpu::FpV4SpD16 => write!(f, "fpv4-sp-d16"),
+            Fpu::NeonVfpv4 => write!(f, "neon-vfpv4"),
+        }
+    }
+}
+
+impl Fpu {
+    fn features(self) -> &'static [Feature] {
+        use Feature::*;
+
+        match self {
+            Fpu::None => &[],
+            Fpu::Vfpv2 => &[Vfpv2, Fp64],
+            Fpu::Vfpv3 => &[Vfpv2, Vfpv3, Fp64, VfpD32],
+            Fpu::Vfpv3D16 => &[Vfpv2, Vfpv3, Fp64],
+            Fpu::Neon => &[Vfpv2, Vfpv3, Fp64, VfpD32, Neon],
+            Fpu::Vfpv4 => &[Vfpv2, Vfpv3, Vfpv4, Fp64, VfpD32],
+            Fpu::Vfpv4D16 => &[Vfpv2, Vfpv3, Vfpv4, Fp64],
+            Fpu::FpV4SpD16 => &[Vfpv2, Vfpv3, Vfpv4],
+            Fpu::NeonVfpv4 => &[Vfpv2, Vfpv3, Vfpv4, Fp64, VfpD32, Neon],
+        }
+    }
+
+    pub fn supports(self, feature: Feature) -> bool {
+        self.features().contains(&feature)
+    }
+
+    /// The `Tag_FP_arch` value recorded in `.ARM.attributes`.
+    pub fn fp_arch_tag(self) -> u8 {
+        match self {
+            Fpu::None => 0,
+            Fpu::Vfpv2 => 2,
+            Fpu::Vfpv3 | Fpu::Neon => 3,
+            Fpu::Vfpv3D16 => 4,
+            Fpu::Vfpv4 | Fpu::NeonVfpv4 => 5,
+            Fpu::Vfpv4D16 | Fpu::FpV4SpD16 => 6,
+        }
+    }
+}
+
+/// Optional extensions that can be added to an architecture with `+ext` or `.arch_extension`.
+const EXTENSIONS: &[(&str, Feature)] = &[
+    ("idiv", Feature::Idiv),
+    ("mp", Feature::Mp),
+    ("sec", Feature::Security),
+    ("virt", Feature::Virtualization),
+];
+
+/// Known processors with their architecture, FPU and any extensions beyond the architecture.
+const CPUS: &[(&str, Arch, Fpu, &[Feature])] = &[
+    ("arm7tdmi", Arch::V4T, Fpu::None, &[]),
+    ("arm926ej-s", Arch::V5TE, Fpu::None, &[]),
+    ("arm1136j-s", Arch::V6, Fpu::None, &[]),
+    ("arm1136jf-s", Arch::V6, Fpu::Vfpv2, &[]),
+    ("arm1156t2-s", Arch::V6T2, Fpu::None, &[]),
+    ("arm1176jzf-s", Arch::V6K, Fpu::Vfpv2, &[Feature::Security]),
+    ("mpcore", Arch::V6K, Fpu::Vfpv2, &[]),
+    (
+        "cortex-a5",
+        Arch::V7A,
+        Fpu::Neon,
+        &[Feature::Mp, Feature::Security],
+    ),
+    ("cortex-a7", Arch::V7VE, Fpu::Neon, &[]),
+    ("cortex-a8", Arch::V7A, Fpu::Neon, &[Feature::Security]),
+    (
+        "cortex-a9",
+        Arch::V7A,
+        Fpu::Neon,
+        &[Feature::Mp, Feature::Security],
+    ),
+    ("cortex-a15", Arch::V7VE, Fpu::Neon, &[]),
+    ("cortex-r4", Arch::V7R, Fpu::None, &[]),
+    ("cortex-r4f", Arch::V7R, Fpu::Vfpv3D16, &[]),
+    ("cortex-r5", Arch::V7R, Fpu::None, &[]),
+    ("cortex-m0", Arch::V6M, Fpu::None, &[]),
+    ("cortex-m0plus", Arch::V6M, Fpu::None, &[]),
+    ("cortex-m3", Arch::V7M, Fpu::None, &[]),
+    ("cortex-m4", Arch::V7EM, Fpu::None, &[]),
+];
+
+/// Everything that determines which instructions may be assembled: the architecture, the FPU and
+/// any optional extensions. Set up from `-march`/`-mcpu`/`-mfpu` and changed by the `.arch`,
+/// `.arch_extension`, `.cpu` and `.fpu` directives.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Target {
+    pub arch: Arch,
+    pub fpu: Fpu,
+    pub cpu: Option<String>,
+    pub extensions: Vec<Feature>,
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.cpu {
+            Some(cpu) => write!(f, "{cpu} ({})", self.arch)?,
+            None => write!(f, "{}", self.arch)?,
+        }
+
+        for (name, feature) in EXTENSIONS {
+            if self.extensions.contains(feature) {
+                write!(f, "+{name}")?;
+            }
+        }
+
+        if self.fpu != Fpu::None {
+            write!(f, " with {}", self.fpu)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Target {
+    pub fn new(arch: Arch) -> Self {
+        Self {
+            arch,
+            ..Default::default()
+        }
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.arch.supports(feature)
+            || self.fpu.supports(feature)
+            || self.extensions.contains(&feature)
+    }
+
+    /// Check that `feature`, needed by the source text `inst`, is available.
+    pub fn check(&self, inst: &str, feature: Feature) -> Result<(), AssemblerError> {
+        if self.supports(feature) {
+            Ok(())
+        } else {
+            Err(AssemblerError::Unsupported(
+                inst.to_owned(),
+                feature,
+                self.clone(),
+            ))
+        }
+    }
+
+    /// Select an architecture, optionally followed by `+ext` modifiers, e.g. `armv7-a+idiv+sec`.
+    /// Like `.arch`, this forgets any previously selected CPU and extensions.
+    pub fn set_arch(&mut self, spec: &str) -> Result<(), AssemblerError> {
+        let mut parts = spec.trim().split('+');
+        let arch = parts.next().unwrap_or_default();
+        self.arch = Arch::try_from(arch).map_err(|_| ParseError::UnknownArch(arch.to_owned()))?;
+        self.cpu = None;
+        self.extensions.clear();
+
+        for extension in parts {
+            self.add_extension(extension)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn add_extension(&mut self, name: &str) -> Result<(), AssemblerError> {
+        let (_, feature) = EXTENSIONS
+            .iter()
+            .find(|(ext, _)| ext.eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| ParseError::UnknownExtension(name.to_owned()))?;
+
+        if !self.extensions.contains(feature) {
+            self.extensions.push(*feature);
+        }
+
+        Ok(())
+    }
+
+    /// Select a processor, which implies its architecture, FPU and extensions.
+    pub fn set_cpu(&mut self, name: &str) -> Result<(), AssemblerError> {
+        let name = name.trim();
+        let (cpu, arch, fpu, extensions) = CPUS
+            .iter()
+            .find(|(cpu, ..)| cpu.eq_ignore_ascii_case(name))
+            .ok_or_else(|| ParseError::UnknownCpu(name.to_owned()))?;
+
+        self.arch = *arch;
+        self.fpu = *fpu;
+        self.cpu = Some(cpu.to_string());
+        self.extensions = extensions.to_vec();
+
+        Ok(())
+    }
+
+    pub fn set_fpu(&mut self, name: &str) -> Result<(), AssemblerError> {
+        self.fpu =
+            Fpu::try_from(name.trim()).map_err(|_| ParseError::UnknownFpu(name.to_owned()))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Arch, Feature, Fpu, Target};
+
+    #[test]
+    fn test_set_arch() {
+        let mut target = Target::default();
+        target.set_arch("armv7-a+idiv+sec").unwrap();
+
+        assert_eq!(target.arch, Arch::V7A);
+        assert!(target.supports(Feature::Idiv));
+        assert!(target.supports(Feature::Security));
+        assert!(!target.supports(Feature::Virtualization));
+
+        // A new architecture drops the previous extensions
+        target.set_arch("armv6k").unwrap();
+        assert!(!target.supports(Feature::Idiv));
+        assert!(target.set_arch("armv9-z").is_err());
+    }
+
+    #[test]
+    fn test_set_cpu_and_fpu() {
+        let mut target = Target::new(Arch::V4T);
+        target.set_cpu("Cortex-A8").unwrap();
+
+        assert_eq!(target.arch, Arch::V7A);
+        assert_eq!(target.fpu, Fpu::Neon);
+        assert!(target.supports(Feature::Neon));
+
+        target.set_fpu("vfpv3-d16").unwrap();
+        assert!(target.supports(Feature::Vfpv3));
+        assert!(!target.supports(Feature::VfpD32));
+        assert!(target.set_fpu("vfpv9").is_err());
+        assert!(target.set_cpu("pentium").is_err());
+    }
+}
@@ -0,0 +1,3017 @@
+//! Turns preprocessed source lines into machine code, keeping track of the directives that change
+//! how later lines are assembled.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+use crate::{
+    alias::{AliasKind, RegisterAliases},
+    arch::{Feature, Target},
+    attributes::build_attributes,
+    cfi::{self, parse_register, CfiInstruction, CfiSections, Frame},
+    cond::Cond,
+    dwarf::{self, CompileUnit, Row, Sequence},
+    ehabi::{self, Entry, Unwind},
+    elf::{
+        Endianness, ObjectWriter, Relocation, Section, Symbol, EF_ARM_EABI_VER5, R_ARM_ABS32,
+        R_ARM_CALL, R_ARM_JUMP24, R_ARM_MOVT_ABS, R_ARM_MOVW_ABS_NC, R_ARM_NONE, R_ARM_PREL31,
+        R_ARM_THM_CALL, R_ARM_THM_JUMP19, R_ARM_THM_JUMP24, R_ARM_THM_MOVT_ABS,
+        R_ARM_THM_MOVW_ABS_NC, SHF_ALLOC, SHF_EXECINSTR, SHF_LINK_ORDER, SHF_WRITE, SHN_UNDEF,
+        SHT_ARM_ATTRIBUTES, SHT_ARM_EXIDX, SHT_NOBITS, SHT_PROGBITS, STB_GLOBAL, STB_LOCAL,
+        STB_WEAK, STT_FUNC, STT_NOTYPE, STT_OBJECT, STT_SECTION, STV_DEFAULT, STV_HIDDEN,
+        STV_INTERNAL, STV_PROTECTED,
+    },
+    error::{AssemblerError, ParseError},
+    instructions::{
+        parse_immediate, parse_reg_id, parse_reg_list, Instruction, Severity, Unpredictable,
+    },
+    listing::ListedSymbol,
+    mnemonics::Mnemonic,
+    source::{Location, Source},
+    thumb::{FlagSetting, ItBlock, ThumbInstruction, ThumbMnemonic, ThumbOpcode, Width},
+    thumb2::Thumb2Instruction,
+    vfp::{parse_vfp_reg_list, VfpRegister},
+};
+
+/// Layout normally settles within a few passes; this only guards against it never doing so.
+const MAX_LAYOUT_PASSES: usize = 16;
+
+/// Thumb `mov r8, r8`, used to pad Thumb code out to a word boundary.
+const THUMB_NOP: u16 = 0x46C0;
+
+/// Which encoding lines are currently assembled with.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum InstructionSet {
+    #[default]
+    Arm,
+    Thumb,
+}
+
+impl InstructionSet {
+    /// How far ahead of the current instruction the PC reads.
+    fn pc_offset(self) -> u32 {
+        match self {
+            Self::Arm => 8,
+            Self::Thumb => 4,
+        }
+    }
+}
+
+/// What a run of bytes in a section holds, as told to disassemblers by mapping symbols.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Mapping {
+    Code(InstructionSet),
+    Data,
+}
+
+impl Mapping {
+    /// The mapping symbol marking the start of such a run.
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Code(InstructionSet::Arm) => "$a",
+            Self::Code(InstructionSet::Thumb) => "$t",
+            Self::Data => "$d",
+        }
+    }
+}
+
+/// A label defined in the source, in the order it was seen.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Label {
+    name: String,
+    /// Index of the section it is in, in [`Assembler::sections`].
+    section: usize,
+    address: u32,
+    /// The instruction set in effect where it was defined.
+    isa: InstructionSet,
+    thumb_func: bool,
+    /// The source line it was defined on.
+    line: usize,
+}
+
+/// What `.global`, `.type` and the other symbol directives have said about a symbol.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct SymbolAttributes {
+    /// Labels are local unless declared otherwise.
+    binding: Option<u8>,
+    sym_type: Option<u8>,
+    visibility: u8,
+    size: Option<u32>,
+}
+
+/// What a relocation is against.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Reference {
+    /// The start of a section, for labels that are local to the file.
+    Section(usize),
+    Symbol(String),
+}
+
+/// A field that only the linker can fill in. The addend is kept in the field itself.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Fixup {
+    offset: u32,
+    reference: Reference,
+    r_type: u8,
+}
+
+/// Where the instructions at an address came from, for `.debug_line`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum LinePosition {
+    /// As given by `.loc`, with a file numbered by `.file`.
+    Loc { file: u32, line: u32, column: u32 },
+    /// A line of the source being assembled, by line number.
+    Source(usize),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct LineRow {
+    section: usize,
+    address: u32,
+    position: LinePosition,
+}
+
+/// A section as it is being assembled.
+#[derive(Clone, Debug)]
+struct SectionBuffer {
+    section: Section,
+    /// Where each run of ARM code, Thumb code or data starts.
+    mapping: Vec<(u32, Mapping)>,
+    fixups: Vec<Fixup>,
+    /// The section whose index goes in `sh_link`.
+    link: Option<usize>,
+}
+
+impl SectionBuffer {
+    fn new(name: &str, sh_type: u32, flags: u32) -> Self {
+        // Code has to be at least word aligned for ARM instructions
+        let align = if flags & SHF_EXECINSTR != 0 { 4 } else { 1 };
+        Self {
+            section: Section::new(name, sh_type, flags, align),
+            mapping: vec![],
+            fixups: vec![],
+            link: None,
+        }
+    }
+}
+
+/// How an instruction operand refers to a symbol.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum OperandKind {
+    Branch,
+    Lower16,
+    Upper16,
+}
+
+/// A symbol operand, resolved as far as the assembler can.
+enum Resolved {
+    /// A label in the current section, at this address.
+    Local(u32),
+    /// Left to the linker, with this addend.
+    Relocated(Reference, i64),
+}
+
+/// The value of a data directive or `.size` expression.
+#[derive(Debug, Eq, PartialEq)]
+enum Value {
+    Absolute(i64),
+    /// Only known once linked.
+    Relocatable(Reference, i64),
+}
+
+/// Whether `value` can name a label.
+pub(crate) fn is_symbol_name(value: &str) -> bool {
+    value
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+/// Whether `value` names a numeric local label, which can be defined any number of times.
+fn is_numeric_label(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_digit())
+}
+
+/// The name the `count`th definition of numeric label `number` is known by. Like any label
+/// starting with `.L`, it is left out of the symbol table.
+fn numeric_label_name(number: u32, count: usize) -> String {
+    format!(".L{number}${count}")
+}
+
+/// The name, kind and register of a `name .req register` line, or its `.dn`/`.qn` forms.
+fn alias_definition(line: &str) -> Option<(&str, AliasKind, &str)> {
+    let (name, rest) = line.split_once(char::is_whitespace)?;
+    let rest = rest.trim_start().strip_prefix('.')?;
+    let (directive, register) = rest.split_once(char::is_whitespace)?;
+    Some((name, AliasKind::from_directive(directive)?, register))
+}
+
+/// Whether `value` names a register rather than a label.
+fn is_register_name(value: &str) -> bool {
+    parse_reg_id(value).is_ok()
+}
+
+/// Drop `@` and `//` comments from a line, leaving string literals alone.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '@' if !in_string => return &line[..i],
+            '/' if !in_string && line[i..].starts_with("//") => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Parse the comma separated string literals of `.ascii` and `.asciz`, each followed by a NUL
+/// if `terminate` is set.
+fn parse_strings(args: &str, terminate: bool) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut rest = args.trim();
+    while !rest.is_empty() {
+        let mut chars = rest.strip_prefix('"')?.char_indices().peekable();
+        let end = loop {
+            let (i, c) = chars.next()?;
+            match c {
+                '"' => break i + 2,
+                '\\' => {
+                    let (_, escape) = chars.next()?;
+                    let byte = match escape {
+                        'n' => b'\n',
+                        't' => b'\t',
+                        'r' => b'\r',
+                        'b' => 0x08,
+                        'f' => 0x0C,
+                        // Up to three octal digits
+                        '0'..='7' => {
+                            let mut value = escape as u32 - '0' as u32;
+                            for _ in 0..2 {
+                                match chars.peek() {
+                                    Some(&(_, digit @ '0'..='7')) => {
+                                        value = value * 8 + (digit as u32 - '0' as u32);
+                                        chars.next();
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            value as u8
+                        }
+                        other => u8::try_from(other).ok()?,
+                    };
+                    bytes.push(byte);
+                }
+                c => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        };
+        if terminate {
+            bytes.push(0);
+        }
+
+        rest = rest[end..].trim_start();
+        if let Some(next) = rest.strip_prefix(',') {
+            rest = next.trim_start();
+        } else if !rest.is_empty() {
+            return None;
+        }
+    }
+
+    Some(bytes)
+}
+
+/// The type and flags of a section named by `.section` without giving any.
+fn default_section_kind(name: &str) -> (u32, u32) {
+    let is = |prefix: &str| name == prefix || name.starts_with(&format!("{prefix}."));
+    if is(".text") {
+        (SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR)
+    } else if is(".data") {
+        (SHT_PROGBITS, SHF_ALLOC | SHF_WRITE)
+    } else if is(".bss") {
+        (SHT_NOBITS, SHF_ALLOC | SHF_WRITE)
+    } else if is(".rodata") {
+        (SHT_PROGBITS, SHF_ALLOC)
+    } else {
+        (SHT_PROGBITS, 0)
+    }
+}
+
+pub struct Assembler {
+    target: Target,
+    isa: InstructionSet,
+    /// Sections in the order they were first switched to, starting with `.text`.
+    sections: Vec<SectionBuffer>,
+    /// Index of the section being assembled into.
+    current: usize,
+    /// The section to go back to with `.previous`.
+    previous: usize,
+    labels: HashMap<String, Label>,
+    defined: Vec<Label>,
+    symbols: BTreeMap<String, SymbolAttributes>,
+    /// Set by `.thumb_func` until the next label is defined.
+    thumb_func: bool,
+    /// Whether `.syntax unified` is in effect.
+    unified: bool,
+    /// The conditions still expected by the current IT block.
+    it: VecDeque<Cond>,
+    /// Counts calls to [`Assembler::assemble_line`], so that passes can refer to the same line.
+    line_number: usize,
+    /// Lines that have to use a wide Thumb encoding, generally because a narrow branch couldn't
+    /// reach its target. Only ever grows, so that layout settles.
+    relaxed: HashSet<usize>,
+    /// Whether every label in the source is known, so that any other symbol is defined elsewhere
+    /// rather than a forward reference that [`Assembler::layout`] has not resolved yet.
+    labels_known: bool,
+    endianness: Endianness,
+    /// Absolute symbols from `.set`, `.equ` and [`Assembler::define_symbol`].
+    equates: HashMap<String, i64>,
+    /// Lines that were ignored, by line number.
+    warnings: Vec<(usize, String)>,
+    /// Where each source line came from, if debug information is generated for it.
+    debug_locations: Option<Vec<Location>>,
+    /// The directory the source was assembled in, for debuggers to find it relative to.
+    comp_dir: String,
+    /// The source file named by `.file "name"`.
+    file_name: Option<String>,
+    /// The files numbered by `.file N "name"`, from 1.
+    files: Vec<String>,
+    /// Set by `.loc` until the next instruction.
+    loc: Option<LinePosition>,
+    line_rows: Vec<LineRow>,
+    cfi_sections: CfiSections,
+    /// The function between `.cfi_startproc` and `.cfi_endproc`.
+    frame: Option<Frame>,
+    frames: Vec<Frame>,
+    /// The function between `.fnstart` and `.fnend`.
+    unwind: Option<Unwind>,
+    /// How many times each numeric label has been defined so far.
+    numeric_labels: HashMap<u32, usize>,
+    /// Register names defined by `.req`, `.dn` and `.qn` and not yet removed by `.unreq`.
+    aliases: RegisterAliases,
+}
+
+impl Assembler {
+    pub fn new(target: Target) -> Self {
+        // M-profile cores can only run Thumb code
+        let isa = if target.supports(Feature::Arm) {
+            InstructionSet::Arm
+        } else {
+            InstructionSet::Thumb
+        };
+
+        Self {
+            target,
+            isa,
+            sections: vec![SectionBuffer::new(
+                ".text",
+                SHT_PROGBITS,
+                SHF_ALLOC | SHF_EXECINSTR,
+            )],
+            current: 0,
+            previous: 0,
+            labels: HashMap::new(),
+            defined: vec![],
+            symbols: BTreeMap::new(),
+            thumb_func: false,
+            unified: false,
+            it: VecDeque::new(),
+            line_number: 0,
+            relaxed: HashSet::new(),
+            labels_known: true,
+            endianness: Endianness::Little,
+            equates: HashMap::new(),
+            warnings: vec![],
+            debug_locations: None,
+            comp_dir: String::new(),
+            file_name: None,
+            files: vec![],
+            loc: None,
+            line_rows: vec![],
+            cfi_sections: CfiSections::default(),
+            frame: None,
+            frames: vec![],
+            unwind: None,
+            numeric_labels: HashMap::new(),
+            aliases: RegisterAliases::default(),
+        }
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    pub fn text(&self) -> &[u8] {
+        &self.sections[0].section.data
+    }
+
+    /// The contents of the section called `name`, if anything switched to it.
+    pub fn section(&self, name: &str) -> Option<&[u8]> {
+        self.sections
+            .iter()
+            .find(|buffer| buffer.section.name == name)
+            .map(|buffer| buffer.section.data.as_slice())
+    }
+
+    /// The name of the section being assembled into.
+    pub fn current_section(&self) -> &str {
+        &self.sections[self.current].section.name
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    /// Assemble for a big-endian system rather than a little-endian one. BE-8 needs ARMv6.
+    pub fn set_endianness(&mut self, endianness: Endianness) -> Result<(), AssemblerError> {
+        if endianness == Endianness::Be8 {
+            self.target.check("BE-8", Feature::V6)?;
+        }
+        self.endianness = endianness;
+        Ok(())
+    }
+
+    pub fn instruction_set(&self) -> InstructionSet {
+        self.isa
+    }
+
+    /// Define `name` as an absolute symbol, as `--defsym` does.
+    pub fn define_symbol(&mut self, name: &str, value: i64) {
+        self.equates.insert(name.to_owned(), value);
+    }
+
+    /// Generate DWARF line-number information mapping each instruction back to where it came
+    /// from in `source`, as `as -g` does. `.loc` directives take precedence if there are any.
+    pub fn enable_debug_info(&mut self, source: &Source) {
+        self.debug_locations = Some(source.locations.clone());
+        self.comp_dir = std::env::current_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default();
+    }
+
+    /// The lines that were ignored so far and why, by line number.
+    pub fn warnings(&self) -> &[(usize, String)] {
+        &self.warnings
+    }
+
+    /// How many lines have been assembled, so after an error, the line it was on.
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// Run passes over `lines` until every label has settled on an address, so that the real pass
+    /// can resolve forward references. Each pass may widen Thumb branches that can't reach, which
+    /// moves the labels after them.
+    pub fn layout<S: AsRef<str>>(&mut self, lines: &[S]) -> Result<(), AssemblerError> {
+        for pass_number in 0..MAX_LAYOUT_PASSES {
+            let mut pass = Self::new(self.target.clone());
+            pass.labels_known = pass_number > 0;
+            pass.isa = self.isa;
+            pass.endianness = self.endianness;
+            pass.labels = self.labels.clone();
+            pass.symbols = self.symbols.clone();
+            pass.equates = self.equates.clone();
+            pass.relaxed = self.relaxed.clone();
+            let result = lines
+                .iter()
+                .try_for_each(|line| pass.assemble_line(line.as_ref()).map(drop))
+                .and_then(|()| pass.finish());
+            if let Err(err) = result {
+                // Keep what led up to the error for reporting
+                self.line_number = pass.line_number;
+                self.warnings = pass.warnings;
+                return Err(err);
+            }
+
+            let settled = pass.labels == self.labels
+                && pass.symbols == self.symbols
+                && pass.equates == self.equates
+                && pass.relaxed == self.relaxed;
+            self.labels = pass.labels;
+            self.symbols = pass.symbols;
+            self.equates = pass.equates;
+            self.relaxed = pass.relaxed;
+            if settled {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Assemble a single source line, returning the bytes it emitted if it was an instruction.
+    /// A line may start with a `label:`. Anything that is neither a known directive nor an
+    /// instruction is an error.
+    pub fn assemble_line(&mut self, line: &str) -> Result<Option<Vec<u8>>, AssemblerError> {
+        self.line_number += 1;
+        let mut line = strip_comment(line).trim();
+        if let Some((label, rest)) = line.split_once(':') {
+            if is_numeric_label(label) {
+                let number = label.parse()?;
+                let count = self.numeric_labels.entry(number).or_default();
+                *count += 1;
+                let name = numeric_label_name(number, *count);
+                self.define_label(&name)?;
+                line = rest.trim();
+            } else if is_symbol_name(label) {
+                self.define_label(label)?;
+                line = rest.trim();
+            }
+        }
+        let line = self.substitute_numeric_labels(line)?;
+        let line = line.as_str();
+
+        if line.is_empty() {
+            return Ok(None);
+        }
+        if let Some((name, kind, register)) = alias_definition(line) {
+            if !is_symbol_name(name) {
+                return Err(ParseError::BadDirective(line.to_owned()).into());
+            }
+            self.aliases.define(name, kind, register)?;
+            return Ok(None);
+        }
+        let line = self.substitute_aliases(line);
+        let line = line.as_str();
+        if let Some(directive) = line.strip_prefix('.') {
+            let section = self.current;
+            self.directive(directive)?;
+            if self.current != section {
+                self.close_it_block()?;
+            }
+            return Ok(None);
+        }
+
+        self.align_code();
+        let written = line;
+        let (line, operand) = self.substitute_labels(line)?;
+        if let (Some((_, OperandKind::Branch)), InstructionSet::Thumb) = (&operand, self.isa) {
+            let opcode = ThumbOpcode::try_from(line.split_whitespace().next().unwrap_or_default())?;
+            match opcode.mnemonic {
+                ThumbMnemonic::BL => (),
+                // The linker can only fill in wide branches
+                ThumbMnemonic::B if opcode.width != Some(Width::Narrow) => {
+                    self.relaxed.insert(self.line_number);
+                }
+                _ => return Err(ParseError::Unrelocatable(line).into()),
+            }
+        }
+        let bytes = self.encode(&line).map_err(|err| match err {
+            // Name the branch target as it was written rather than as an offset
+            AssemblerError::Parse(ParseError::BranchOutOfRange(_, range)) => {
+                let (_, operands) = written.split_once(char::is_whitespace).unwrap_or_default();
+                let target = operands.rsplit(',').next().unwrap_or_default().trim();
+                ParseError::BranchOutOfRange(target.to_owned(), range).into()
+            }
+            err => err,
+        })?;
+
+        if let Some((reference, kind)) = operand {
+            let r_type = self.relocation_type(kind, &bytes, &line)?;
+            self.add_fixup(reference, r_type);
+        }
+        let address = self.address();
+        self.emit(&bytes);
+        self.add_line_row(address);
+        Ok(Some(bytes))
+    }
+
+    /// Encode an instruction whose labels have been substituted.
+    fn encode(&mut self, line: &str) -> Result<Vec<u8>, AssemblerError> {
+        Ok(match self.isa {
+            InstructionSet::Arm => {
+                let instruction = Instruction::try_from(line)?.for_target(&self.target);
+                self.target.check(line, Feature::Arm)?;
+                for feature in instruction.required_features() {
+                    self.target.check(line, feature)?;
+                }
+                self.check_unpredictable(line, instruction.unpredictable(&self.target))?;
+                let encoding = instruction.to_machine_code();
+                self.endianness.code().u32_bytes(encoding).to_vec()
+            }
+            InstructionSet::Thumb => {
+                let halfwords = self.assemble_thumb(line)?;
+                let order = self.endianness.code();
+                halfwords
+                    .into_iter()
+                    .flat_map(|halfword| order.u16_bytes(halfword))
+                    .collect()
+            }
+        })
+    }
+
+    /// Check that the input didn't leave anything unfinished, which for now means an IT block.
+    pub fn finish(&mut self) -> Result<(), AssemblerError> {
+        self.close_it_block()
+    }
+
+    /// Report an IT block still waiting for instructions, which can't run on into another section
+    /// or past the end of the input.
+    fn close_it_block(&mut self) -> Result<(), AssemblerError> {
+        match std::mem::take(&mut self.it).len() {
+            0 => Ok(()),
+            missing => Err(ParseError::UnterminatedItBlock(missing).into()),
+        }
+    }
+
+    /// Pick a narrow or wide encoding for a Thumb instruction, keeping track of IT blocks.
+    fn assemble_thumb(&mut self, line: &str) -> Result<Vec<u16>, AssemblerError> {
+        if let Some(block) = ItBlock::parse(line)? {
+            if !self.it.is_empty() {
+                return Err(ParseError::BadItBlock(line.to_owned()).into());
+            }
+            self.target.check(line, Feature::Thumb2)?;
+            self.it = block.conds.iter().copied().collect();
+            return Ok(vec![block.to_machine_code()]);
+        }
+
+        let (opcode, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let coprocessor = matches!(Mnemonic::try_from(opcode), Ok(Mnemonic::Coprocessor(_)));
+        if opcode.starts_with(['v', 'V']) || coprocessor {
+            return self.assemble_arm_encoded(line);
+        }
+        let opcode = ThumbOpcode::try_from(opcode)?;
+        let mnemonic = opcode.mnemonic;
+
+        // Inside an IT block the condition comes from the IT instruction, and branches must be last
+        let in_it = !self.it.is_empty();
+        let cond = match self.it.pop_front() {
+            Some(expected) => {
+                let misplaced = matches!(mnemonic, ThumbMnemonic::CBZ | ThumbMnemonic::CBNZ)
+                    || (mnemonic.is_branch() && !self.it.is_empty());
+                if opcode.cond != expected || misplaced {
+                    return Err(ParseError::BadItBlock(line.to_owned()).into());
+                }
+                Cond::AL
+            }
+            None if opcode.cond != Cond::AL && mnemonic != ThumbMnemonic::B => {
+                return Err(ParseError::UnexpectedCond(line.to_owned()).into());
+            }
+            None => opcode.cond,
+        };
+
+        // Narrow encodings only set the flags outside of IT blocks
+        let flags = match (in_it, opcode.set_flags) {
+            (true, false) => Some(FlagSetting::Any),
+            (true, true) => None,
+            (false, true) => Some(FlagSetting::Set),
+            (false, false) if self.unified => Some(FlagSetting::Preserve),
+            (false, false) => Some(FlagSetting::Any),
+        };
+        // bl is the same 32-bit instruction whatever its width says
+        let wide = opcode.width == Some(Width::Wide) && mnemonic != ThumbMnemonic::BL;
+        let relaxed = self.relaxed.contains(&self.line_number);
+
+        let narrow = flags
+            .filter(|_| !wide && !relaxed)
+            .map(|flags| ThumbInstruction::parse(mnemonic, cond, operands, flags));
+        match narrow {
+            Some(Ok(instruction)) => {
+                let instruction = instruction.for_target(&self.target);
+                self.target.check(line, instruction.required_feature())?;
+                return Ok(instruction.to_machine_code());
+            }
+            // A `.n` instruction has nothing to fall back on, and nor do cbz/cbnz or a branch
+            // without Thumb-2
+            Some(Err(error))
+                if opcode.width == Some(Width::Narrow)
+                    || matches!(mnemonic, ThumbMnemonic::CBZ | ThumbMnemonic::CBNZ)
+                    || mnemonic == ThumbMnemonic::B && !self.target.supports(Feature::Thumb2) =>
+            {
+                return Err(error)
+            }
+            None if opcode.width == Some(Width::Narrow) => {
+                return Err(ParseError::BadFlexOperand(line.to_owned()).into())
+            }
+            _ => (),
+        }
+
+        let instruction = Thumb2Instruction::parse(mnemonic, opcode.set_flags, cond, operands)?;
+        self.target.check(line, instruction.required_feature())?;
+        self.check_unpredictable(line, instruction.unpredictable())?;
+        if opcode.width.is_none() {
+            self.relaxed.insert(self.line_number);
+        }
+
+        Ok(instruction.to_machine_code())
+    }
+
+    /// Reject `line` for an UNPREDICTABLE use of registers, or just warn about it.
+    fn check_unpredictable(
+        &mut self,
+        line: &str,
+        unpredictable: Option<Unpredictable>,
+    ) -> Result<(), AssemblerError> {
+        match unpredictable {
+            Some(Unpredictable {
+                severity: Severity::Error,
+                reason,
+            }) => Err(ParseError::Unpredictable(line.to_owned(), reason.to_owned()).into()),
+            Some(Unpredictable {
+                severity: Severity::Warning,
+                reason,
+            }) => {
+                self.warn(format!("`{line}` is UNPREDICTABLE: {reason}"));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// VFP and coprocessor instructions keep their ARM encoding in Thumb state, always with the
+    /// `al` condition, and NEON instructions are remapped to their Thumb prefixes.
+    fn assemble_arm_encoded(&mut self, line: &str) -> Result<Vec<u16>, AssemblerError> {
+        let (cond, features, encoding) = match Instruction::try_from(line)? {
+            Instruction::Vfp(cond, vfp) => {
+                (cond, vfp.required_features(), vfp.to_machine_code(Cond::AL))
+            }
+            Instruction::Neon(cond, neon) => (
+                cond,
+                vec![neon.required_feature()],
+                neon.to_thumb_machine_code(),
+            ),
+            instruction @ (Instruction::CoprocessorData(cond, ..)
+            | Instruction::CoprocessorRegister(cond, ..)
+            | Instruction::CoprocessorRegisterPair(cond, ..)
+            | Instruction::CoprocessorMem(cond, ..)) => {
+                let features = instruction.required_features();
+                let encoding = instruction.to_machine_code();
+                // The unconditional `2` forms keep their 0xF prefix
+                let encoding = if encoding >> 28 == 0xF {
+                    encoding
+                } else {
+                    (encoding & 0x0FFF_FFFF) | 0xE000_0000
+                };
+                (cond, features, encoding)
+            }
+            _ => return Err(ParseError::BadMnemonic(line.to_owned()).into()),
+        };
+        match self.it.pop_front() {
+            Some(expected) if cond != expected => {
+                return Err(ParseError::BadItBlock(line.to_owned()).into());
+            }
+            None if cond != Cond::AL => {
+                return Err(ParseError::UnexpectedCond(line.to_owned()).into());
+            }
+            _ => (),
+        }
+
+        self.target.check(line, Feature::Thumb2)?;
+        for feature in features {
+            self.target.check(line, feature)?;
+        }
+
+        Ok(vec![(encoding >> 16) as u16, encoding as u16])
+    }
+
+    /// The offset in the current section that the next byte will be emitted at.
+    pub fn address(&self) -> u32 {
+        self.sections[self.current].section.data.len() as u32
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.emit_as(Mapping::Code(self.isa), bytes);
+    }
+
+    fn emit_data(&mut self, bytes: &[u8]) {
+        self.emit_as(Mapping::Data, bytes);
+    }
+
+    fn emit_as(&mut self, mapping: Mapping, bytes: &[u8]) {
+        let address = self.address();
+        let buffer = &mut self.sections[self.current];
+        let changed = buffer.mapping.last().map(|(_, mapping)| *mapping) != Some(mapping);
+        if changed && buffer.section.sh_type != SHT_NOBITS {
+            buffer.mapping.push((address, mapping));
+        }
+        buffer.section.data.extend_from_slice(bytes);
+    }
+
+    /// Pad the current section to a whole instruction after data that left it unaligned, as
+    /// GNU as does, moving the labels at the end of the data on to the instruction.
+    fn align_code(&mut self) {
+        let address = self.address();
+        let align = match self.isa {
+            InstructionSet::Arm => 4,
+            InstructionSet::Thumb => 2,
+        };
+        if address.is_multiple_of(align) {
+            return;
+        }
+        self.align_to(align);
+        let aligned = self.address();
+        let current = self.current;
+        for label in self.defined.iter_mut().rev() {
+            if label.section != current || label.address != address {
+                break;
+            }
+            label.address = aligned;
+            if let Some(label) = self.labels.get_mut(&label.name) {
+                label.address = aligned;
+            }
+        }
+    }
+
+    /// Pad the current section with zeros to a multiple of `align`.
+    fn align_to(&mut self, align: u32) {
+        let buffer = &mut self.sections[self.current];
+        buffer.section.align = buffer.section.align.max(align);
+        let end = buffer.section.data.len().next_multiple_of(align as usize);
+        buffer.section.data.resize(end, 0);
+    }
+
+    fn add_fixup(&mut self, reference: Reference, r_type: u8) {
+        self.add_fixup_at(0, reference, r_type);
+    }
+
+    /// Add a fixup for the field `offset` bytes on from the current address.
+    fn add_fixup_at(&mut self, offset: u32, reference: Reference, r_type: u8) {
+        let offset = self.address() + offset;
+        self.sections[self.current].fixups.push(Fixup {
+            offset,
+            reference,
+            r_type,
+        });
+    }
+
+    /// Record where the instruction just emitted at `address` came from.
+    fn add_line_row(&mut self, address: u32) {
+        let position = match self.loc.take() {
+            Some(position) => position,
+            None if self.debug_locations.is_some() => LinePosition::Source(self.line_number),
+            None => return,
+        };
+        self.line_rows.push(LineRow {
+            section: self.current,
+            address,
+            position,
+        });
+    }
+
+    fn define_label(&mut self, name: &str) -> Result<(), AssemblerError> {
+        if self.defined.iter().any(|label| label.name == name) {
+            return Err(ParseError::DuplicateLabel(name.to_owned()).into());
+        }
+        let label = Label {
+            name: name.to_owned(),
+            section: self.current,
+            address: self.address(),
+            isa: self.isa,
+            thumb_func: std::mem::take(&mut self.thumb_func),
+            line: self.line_number,
+        };
+        self.labels.insert(name.to_owned(), label.clone());
+        self.defined.push(label);
+        Ok(())
+    }
+
+    /// Replace each `Nb` and `Nf` with the name of the nearest definition of numeric label `N`
+    /// before or after this line. Strings are left alone.
+    fn substitute_numeric_labels(&self, line: &str) -> Result<String, AssemblerError> {
+        let mut out = String::new();
+        let mut start = None;
+        let mut in_string = false;
+        let mut escaped = false;
+        for (i, c) in line.char_indices().chain([(line.len(), ' ')]) {
+            if !in_string && (c.is_ascii_alphanumeric() || "_.$".contains(c)) {
+                start.get_or_insert(i);
+                continue;
+            }
+            if let Some(begin) = start.take() {
+                let token = &line[begin..i];
+                out.push_str(&self.numeric_reference(token)?.unwrap_or(token.to_owned()));
+            }
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if in_string => escaped = true,
+                '"' => in_string = !in_string,
+                _ => (),
+            }
+            if i < line.len() {
+                out.push(c);
+            }
+        }
+        Ok(out)
+    }
+
+    /// The label a reference such as `1b` or `2f` is to, if `token` is one.
+    fn numeric_reference(&self, token: &str) -> Result<Option<String>, AssemblerError> {
+        let Some((number, direction)) = token
+            .split_at_checked(token.len().saturating_sub(1))
+            .filter(|(number, _)| is_numeric_label(number))
+        else {
+            return Ok(None);
+        };
+        let count = self.numeric_labels.get(&number.parse()?).copied();
+        let count = match (direction, count) {
+            ("b", Some(count)) => count,
+            ("f", count) => count.unwrap_or(0) + 1,
+            ("b", None) => return Err(ParseError::UndefinedSymbol(token.to_owned()).into()),
+            _ => return Ok(None),
+        };
+        let name = numeric_label_name(number.parse()?, count);
+        if self.labels_known && !self.labels.contains_key(&name) {
+            return Err(ParseError::UndefinedSymbol(token.to_owned()).into());
+        }
+        Ok(Some(name))
+    }
+
+    /// Whether `name` has been declared global or weak, so that references to it are left to the
+    /// linker even when it is defined here.
+    fn is_exported(&self, name: &str) -> bool {
+        self.symbols
+            .get(name)
+            .and_then(|attributes| attributes.binding)
+            .is_some_and(|binding| binding != STB_LOCAL)
+    }
+
+    /// Whether calls to `label` have to switch to Thumb state.
+    fn is_thumb_function(&self, label: &Label) -> bool {
+        let function = self
+            .symbols
+            .get(&label.name)
+            .is_some_and(|attributes| attributes.sym_type == Some(STT_FUNC));
+        label.thumb_func || (function && label.isa == InstructionSet::Thumb)
+    }
+
+    fn resolve(&self, name: &str) -> Resolved {
+        match self.labels.get(name) {
+            Some(_) if self.is_exported(name) => {
+                Resolved::Relocated(Reference::Symbol(name.to_owned()), 0)
+            }
+            Some(label) if label.section == self.current => Resolved::Local(label.address),
+            Some(label) => {
+                Resolved::Relocated(Reference::Section(label.section), label.address.into())
+            }
+            None if self.labels_known => Resolved::Relocated(Reference::Symbol(name.to_owned()), 0),
+            // Not laid out yet, so pretend it's the PC to keep any branch in range
+            None => Resolved::Local(self.address() + self.isa.pc_offset()),
+        }
+    }
+
+    /// The label or `.` that the last operand of a branch or `adr` names, and the constant added
+    /// to it.
+    fn label_operand<'a>(
+        &self,
+        opcode: &str,
+        operand: &'a str,
+    ) -> Result<Option<(&'a str, i64)>, AssemblerError> {
+        let split = operand
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| matches!(c, '+' | '-'))
+            .map_or(operand.len(), |(i, _)| i);
+        let (name, offset) = operand.split_at(split);
+        let name = name.trim();
+        let refers_to_label = self.is_branch(opcode) || self.is_address(opcode);
+        if !refers_to_label || !is_symbol_name(name) || is_register_name(name) {
+            return Ok(None);
+        }
+        match self.evaluate(offset)? {
+            Value::Absolute(offset) => Ok(Some((name, offset))),
+            Value::Relocatable(..) => {
+                Err(ParseError::BadImmediate(offset.trim().to_owned()).into())
+            }
+        }
+    }
+
+    fn is_branch(&self, opcode: &str) -> bool {
+        match self.isa {
+            InstructionSet::Arm => matches!(Mnemonic::try_from(opcode), Ok(Mnemonic::Branch(_))),
+            InstructionSet::Thumb => ThumbOpcode::try_from(opcode).is_ok_and(|opcode| {
+                matches!(
+                    opcode.mnemonic,
+                    ThumbMnemonic::B | ThumbMnemonic::BL | ThumbMnemonic::CBZ | ThumbMnemonic::CBNZ
+                )
+            }),
+        }
+    }
+
+    /// Whether the opcode is a Thumb `adr`, which ARM state doesn't have.
+    fn is_address(&self, opcode: &str) -> bool {
+        self.isa == InstructionSet::Thumb
+            && ThumbOpcode::try_from(opcode)
+                .is_ok_and(|opcode| opcode.mnemonic == ThumbMnemonic::ADR)
+    }
+
+    /// Replace label operands with what the encoders expect: branch targets become an offset from
+    /// the PC, `adr` an offset from the word-aligned PC, and `:lower16:`/`:upper16:` take the
+    /// label's address. Operands that need a relocation are returned with it, the field holding
+    /// the addend.
+    fn substitute_labels(
+        &self,
+        line: &str,
+    ) -> Result<(String, Option<(Reference, OperandKind)>), AssemblerError> {
+        let line = self.substitute_equates(line);
+        let Some((opcode, operands)) = line.split_once(char::is_whitespace) else {
+            return Ok((line, None));
+        };
+        let operands = operands.trim();
+
+        // The target is the last operand, after the register for cbz/cbnz
+        let (leading, last) = match operands.rsplit_once(',') {
+            Some((leading, last)) => (format!("{leading}, "), last.trim()),
+            None => (String::new(), operands),
+        };
+        if let Some((name, offset)) = self.label_operand(opcode, last)? {
+            let resolved = match name {
+                "." => Resolved::Local(self.address()),
+                _ => self.resolve(name),
+            };
+            if self.is_address(opcode) {
+                let pc = (self.address() + self.isa.pc_offset()) & !3;
+                let target = match resolved {
+                    // Not laid out yet, so assume it's in reach of the narrow encoding
+                    _ if !self.labels_known && !self.labels.contains_key(name) => pc,
+                    Resolved::Local(address) => address,
+                    Resolved::Relocated(..) => {
+                        return Err(ParseError::Unrelocatable(line.clone()).into())
+                    }
+                };
+                let offset = i64::from(target) + offset - i64::from(pc);
+                return Ok((format!("{opcode} {leading}#{offset}"), None));
+            }
+
+            let pc = i64::from(self.address() + self.isa.pc_offset());
+            // Relocated branches are relative to the branch itself
+            let (target, operand) = match resolved {
+                Resolved::Local(address) => (i64::from(address) + offset, None),
+                Resolved::Relocated(reference, addend) => (
+                    i64::from(self.address()) + addend + offset,
+                    Some((reference, OperandKind::Branch)),
+                ),
+            };
+            return Ok((format!("{opcode} {leading}{}", target - pc), operand));
+        }
+
+        for (operator, kind) in [
+            (":lower16:", OperandKind::Lower16),
+            (":upper16:", OperandKind::Upper16),
+        ] {
+            if let Some((before, label)) = operands.split_once(operator) {
+                let label = label.trim();
+                if is_symbol_name(label) {
+                    let (reference, addend) = match self.resolve(label) {
+                        Resolved::Local(address) => {
+                            (Reference::Section(self.current), i64::from(address))
+                        }
+                        Resolved::Relocated(reference, addend) => (reference, addend),
+                    };
+                    // Both halves keep the low 16 bits of the addend in the field
+                    let line = format!("{opcode} {before}:lower16:{addend}");
+                    return Ok((line, Some((reference, kind))));
+                }
+            }
+        }
+
+        Ok((line, None))
+    }
+
+    /// Replace register aliases in the operands of an instruction, or of a directive that names
+    /// registers.
+    fn substitute_aliases(&self, line: &str) -> String {
+        let (opcode, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let names_registers = match opcode.strip_prefix('.') {
+            Some(directive) => {
+                directive.starts_with("cfi_")
+                    || ["save", "vsave", "setfp", "movsp"].contains(&directive)
+            }
+            None => true,
+        };
+        if !names_registers || self.aliases.is_empty() {
+            return line.to_owned();
+        }
+        let (operands, data_type) = self.aliases.substitute(opcode, operands);
+        // A typed alias gives its type to an instruction that doesn't have one
+        match data_type {
+            Some(data_type) if !opcode.contains('.') => format!("{opcode}.{data_type} {operands}"),
+            _ => format!("{opcode} {operands}"),
+        }
+    }
+
+    /// Replace `#name` immediates that name an absolute symbol with its value.
+    fn substitute_equates(&self, line: &str) -> String {
+        let mut out = String::new();
+        let mut rest = line;
+        while let Some(i) = rest.find('#') {
+            out.push_str(&rest[..=i]);
+            rest = &rest[i + 1..];
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || "_.$".contains(c)))
+                .unwrap_or(rest.len());
+            if let Some(value) = self.equates.get(&rest[..end]) {
+                out.push_str(&value.to_string());
+                rest = &rest[end..];
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// The relocation for an operand of `kind` in an instruction encoded as `bytes`.
+    fn relocation_type(
+        &self,
+        kind: OperandKind,
+        bytes: &[u8],
+        line: &str,
+    ) -> Result<u8, AssemblerError> {
+        let order = self.endianness.code();
+        Ok(match (self.isa, kind) {
+            // Only unconditional calls can be turned into blx by the linker
+            (InstructionSet::Arm, OperandKind::Branch) => match order.read_u32(bytes, 0) {
+                Some(word) if word >> 24 == 0xEB => R_ARM_CALL,
+                _ => R_ARM_JUMP24,
+            },
+            (InstructionSet::Thumb, OperandKind::Branch) => match order.read_u16(bytes, 2) {
+                Some(second) if second & 0x4000 != 0 => R_ARM_THM_CALL,
+                Some(second) if second & 0x1000 != 0 => R_ARM_THM_JUMP24,
+                Some(_) => R_ARM_THM_JUMP19,
+                None => return Err(ParseError::Unrelocatable(line.to_owned()).into()),
+            },
+            (InstructionSet::Arm, OperandKind::Lower16) => R_ARM_MOVW_ABS_NC,
+            (InstructionSet::Arm, OperandKind::Upper16) => R_ARM_MOVT_ABS,
+            (InstructionSet::Thumb, OperandKind::Lower16) => R_ARM_THM_MOVW_ABS_NC,
+            (InstructionSet::Thumb, OperandKind::Upper16) => R_ARM_THM_MOVT_ABS,
+        })
+    }
+
+    /// Evaluate a sum of numbers, labels and `.`. Labels in the same section may be subtracted
+    /// from each other, leaving at most one symbol or section for the linker.
+    fn evaluate(&self, expr: &str) -> Result<Value, AssemblerError> {
+        let bad = || AssemblerError::from(ParseError::BadImmediate(expr.trim().to_owned()));
+
+        let mut terms = vec![];
+        let (mut sign, mut start) = (1, 0);
+        for (i, c) in expr.char_indices().filter(|(_, c)| matches!(c, '+' | '-')) {
+            terms.push((sign, expr[start..i].trim()));
+            sign = if c == '-' { -1 } else { 1 };
+            start = i + 1;
+        }
+        terms.push((sign, expr[start..].trim()));
+
+        let mut constant = 0;
+        // How many times each section's start is added in, and the labels added to it
+        let mut sections: BTreeMap<usize, (i64, Vec<&Label>)> = BTreeMap::new();
+        let mut external = vec![];
+        for (sign, term) in terms {
+            if term == "." {
+                sections.entry(self.current).or_default().0 += sign;
+                constant += sign * i64::from(self.address());
+            } else if let Some(value) = self.equates.get(term) {
+                constant += sign * value;
+            } else if let Some(label) = self.labels.get(term) {
+                let (count, labels) = sections.entry(label.section).or_default();
+                *count += sign;
+                if sign > 0 {
+                    labels.push(label);
+                }
+                constant += sign * i64::from(label.address);
+            } else if is_symbol_name(term) && !self.labels_known {
+                // A forward reference that layout will come back to
+                return Ok(Value::Absolute(0));
+            } else if is_symbol_name(term) && sign > 0 {
+                external.push(term);
+            } else if !term.is_empty() {
+                constant += sign * parse_immediate(term).map_err(|_| bad())?;
+            }
+        }
+
+        sections.retain(|_, (count, _)| *count != 0);
+        match (sections.pop_first(), external.as_slice()) {
+            (None, []) => Ok(Value::Absolute(constant)),
+            (None, [name]) => Ok(Value::Relocatable(
+                Reference::Symbol((*name).to_owned()),
+                constant,
+            )),
+            (Some((section, (1, labels))), []) if sections.is_empty() => match labels[..] {
+                [label] if self.is_exported(&label.name) => Ok(Value::Relocatable(
+                    Reference::Symbol(label.name.clone()),
+                    constant - i64::from(label.address),
+                )),
+                // The address of a Thumb function has its low bit set
+                [label] if self.is_thumb_function(label) => Ok(Value::Relocatable(
+                    Reference::Section(section),
+                    constant | 1,
+                )),
+                _ => Ok(Value::Relocatable(Reference::Section(section), constant)),
+            },
+            _ => Err(bad()),
+        }
+    }
+
+    /// Emit each comma separated value of a `.byte`, `.short` or `.word` directive.
+    fn data(&mut self, args: &str, size: u32) -> Result<(), AssemblerError> {
+        for expr in args.split(',') {
+            let value = match self.evaluate(expr)? {
+                Value::Absolute(value) => value,
+                Value::Relocatable(reference, addend) if size == 4 => {
+                    self.add_fixup(reference, R_ARM_ABS32);
+                    addend
+                }
+                Value::Relocatable(..) => {
+                    return Err(ParseError::Unrelocatable(expr.trim().to_owned()).into());
+                }
+            };
+
+            // Either a signed or an unsigned value has to fit
+            let bits = 8 * size;
+            if value >= 1 << bits || value < -(1 << (bits - 1)) {
+                return Err(ParseError::BadImmediate(expr.trim().to_owned()).into());
+            }
+            let bytes = match size {
+                1 => vec![value as u8],
+                2 => self.endianness.u16_bytes(value as u16).to_vec(),
+                _ => self.endianness.u32_bytes(value as u32).to_vec(),
+            };
+            self.emit_data(&bytes);
+        }
+        Ok(())
+    }
+
+    /// An absolute value for a directive argument, such as a size or an alignment.
+    fn absolute(&self, expr: &str) -> Result<u32, AssemblerError> {
+        match self.evaluate(expr)? {
+            Value::Absolute(value) => u32::try_from(value)
+                .map_err(|_| ParseError::BadImmediate(expr.trim().to_owned()).into()),
+            Value::Relocatable(..) => Err(ParseError::BadImmediate(expr.trim().to_owned()).into()),
+        }
+    }
+
+    /// The directives describing how to unwind a function for exception handling.
+    fn unwind_directive(
+        &mut self,
+        directive: &str,
+        name: &str,
+        args: &str,
+    ) -> Result<(), AssemblerError> {
+        let bad = || AssemblerError::from(ParseError::BadDirective(directive.to_owned()));
+        let operands: Vec<&str> = args.split(',').map(str::trim).collect();
+        let immediate = |assembler: &Self, index: usize| match operands.get(index) {
+            Some(operand) => assembler.signed(operand.trim_start_matches('#')),
+            None => Ok(0),
+        };
+
+        if name == "fnstart" {
+            if self.unwind.is_some() {
+                return Err(bad());
+            }
+            self.unwind = Some(Unwind::new(self.current, self.address()));
+            return Ok(());
+        }
+        let mut unwind = self.unwind.take().ok_or_else(bad)?;
+        match name {
+            "fnend" => return self.finish_unwind(unwind).ok_or_else(bad),
+            "cantunwind" => unwind.cant_unwind = true,
+            "personality" => {
+                let symbol = args.trim();
+                if !is_symbol_name(symbol) {
+                    return Err(bad());
+                }
+                unwind.personality = Some(symbol.to_owned());
+            }
+            "personalityindex" => {
+                let index = u8::try_from(immediate(self, 0)?).map_err(|_| bad())?;
+                unwind.personality_index = Some(index);
+            }
+            "handlerdata" => {
+                // The handler data follows the table entry in .ARM.extab until .fnend
+                unwind.handler_data = Some(self.current);
+                let entry = unwind.entry().ok_or_else(bad)?;
+                let (_, extab) = ehabi::section_names(self.current_section());
+                unwind.table = Some(self.unwind_table(&extab, &entry));
+                self.switch_section(&extab, SHT_PROGBITS, SHF_ALLOC);
+            }
+            "save" | "vsave" if args.contains(['d', 'D']) => {
+                let (first, count) = parse_vfp_reg_list(args)?;
+                let VfpRegister::Double(first) = first else {
+                    return Err(bad());
+                };
+                unwind.save_vfp(first, count);
+            }
+            "save" => unwind.save_core(parse_reg_list(args)?),
+            "pad" => unwind.pad(immediate(self, 0)?),
+            "setfp" => {
+                let fp = parse_reg_id(operands[0])?;
+                let base = parse_reg_id(operands.get(1).ok_or_else(bad)?)?;
+                unwind.setfp(fp, base, immediate(self, 2)?);
+            }
+            "movsp" => unwind.movsp(parse_reg_id(operands[0])?, immediate(self, 1)?),
+            "unwind_raw" => {
+                let bytes = operands[1..]
+                    .iter()
+                    .map(|operand| u8::try_from(self.absolute(operand)?).map_err(|_| bad()))
+                    .collect::<Result<_, _>>()?;
+                unwind.raw(immediate(self, 0)?, bytes);
+            }
+            _ => return Err(bad()),
+        }
+        self.unwind = Some(unwind);
+        Ok(())
+    }
+
+    /// Write the table entry for `entry` to the section `extab`, returning where it starts.
+    fn unwind_table(&mut self, extab: &str, entry: &Entry) -> u32 {
+        let Entry::Table { personality, words } = entry else {
+            return 0;
+        };
+        let index = self.section_index(extab, SHT_PROGBITS, SHF_ALLOC);
+        self.in_section(index, |assembler| {
+            assembler.align_to(4);
+            let offset = assembler.address();
+            if let Some(personality) = personality {
+                assembler.add_fixup(Reference::Symbol(personality.clone()), R_ARM_PREL31);
+                assembler.emit_data(&[0; 4]);
+            }
+            for word in words {
+                assembler.emit_data(&assembler.endianness.u32_bytes(*word));
+            }
+            offset
+        })
+    }
+
+    /// `.fnend`: write the function's index entry, and its table entry if `.handlerdata` hasn't.
+    fn finish_unwind(&mut self, mut unwind: Unwind) -> Option<()> {
+        let entry = unwind.entry()?;
+        let code = self.sections[unwind.section].section.name.clone();
+        let (exidx, extab) = ehabi::section_names(&code);
+        let table = match (&entry, unwind.table) {
+            (Entry::Inline(_), _) => None,
+            (_, Some(offset)) => Some(offset),
+            (_, None) => Some(self.unwind_table(&extab, &entry)),
+        };
+
+        let extab = self.section_index(&extab, SHT_PROGBITS, SHF_ALLOC);
+        let index = self.section_index(&exidx, SHT_ARM_EXIDX, SHF_ALLOC | SHF_LINK_ORDER);
+        self.sections[index].link = Some(unwind.section);
+        self.sections[index].section.align = 4;
+        self.in_section(index, |assembler| {
+            let order = assembler.endianness;
+            // Make sure the linker pulls in the personality routine
+            if let Some(compact) = entry.compact_index() {
+                let routine = ehabi::personality_routine(compact);
+                assembler.add_fixup(Reference::Symbol(routine), R_ARM_NONE);
+            }
+            assembler.add_fixup(Reference::Section(unwind.section), R_ARM_PREL31);
+            let mut words = vec![unwind.start & 0x7FFF_FFFF];
+            match (entry, table) {
+                (_, Some(offset)) => {
+                    assembler.add_fixup_at(4, Reference::Section(extab), R_ARM_PREL31);
+                    words.push(offset);
+                }
+                (Entry::Inline(word), None) => words.push(word),
+                (Entry::Table { .. }, None) => unreachable!(),
+            }
+            let data = &mut assembler.sections[index].section.data;
+            data.extend(words.into_iter().flat_map(|word| order.u32_bytes(word)));
+        });
+
+        if let Some(section) = unwind.handler_data {
+            self.current = section;
+        }
+        Some(())
+    }
+
+    /// Evaluate `expr`, which has to be absolute but may be negative.
+    fn signed(&self, expr: &str) -> Result<i64, AssemblerError> {
+        match self.evaluate(expr)? {
+            Value::Absolute(value) => Ok(value),
+            Value::Relocatable(..) => Err(ParseError::BadImmediate(expr.trim().to_owned()).into()),
+        }
+    }
+
+    /// Apply `update` to each symbol named in the comma separated `names`.
+    fn update_symbols(
+        &mut self,
+        directive: &str,
+        names: &str,
+        update: impl Fn(&mut SymbolAttributes),
+    ) -> Result<(), AssemblerError> {
+        for name in names.split(',').map(str::trim) {
+            if !is_symbol_name(name) {
+                return Err(ParseError::BadDirective(directive.to_owned()).into());
+            }
+            update(self.symbols.entry(name.to_owned()).or_default());
+        }
+        Ok(())
+    }
+
+    /// Switch to the section called `name`, creating it as `sh_type` with `flags` if needed.
+    fn switch_section(&mut self, name: &str, sh_type: u32, flags: u32) {
+        let index = self.section_index(name, sh_type, flags);
+        self.previous = std::mem::replace(&mut self.current, index);
+    }
+
+    /// The index of the section called `name`, creating it as `sh_type` with `flags` if needed.
+    fn section_index(&mut self, name: &str, sh_type: u32, flags: u32) -> usize {
+        match self
+            .sections
+            .iter()
+            .position(|buffer| buffer.section.name == name)
+        {
+            Some(index) => index,
+            None => {
+                self.sections.push(SectionBuffer::new(name, sh_type, flags));
+                self.sections.len() - 1
+            }
+        }
+    }
+
+    /// Run `f` assembling into the section at `index`, then go back to the current one.
+    fn in_section<T>(&mut self, index: usize, f: impl FnOnce(&mut Self) -> T) -> T {
+        let current = std::mem::replace(&mut self.current, index);
+        let result = f(self);
+        self.current = current;
+        result
+    }
+
+    /// `.section name[, "flags"[, %type]]`
+    fn section_directive(&mut self, directive: &str, args: &str) -> Result<(), AssemblerError> {
+        let bad = || AssemblerError::from(ParseError::BadDirective(directive.to_owned()));
+        let mut args = args.split(',').map(str::trim);
+        let name = args
+            .next()
+            .filter(|name| !name.is_empty())
+            .ok_or_else(bad)?;
+        let (default_type, default_flags) = default_section_kind(name);
+
+        let flags = match args.next() {
+            Some(flags) => {
+                let flags = flags
+                    .strip_prefix('"')
+                    .and_then(|flags| flags.strip_suffix('"'))
+                    .ok_or_else(bad)?;
+                flags.chars().try_fold(0, |all, flag| match flag {
+                    'a' => Ok(all | SHF_ALLOC),
+                    'w' => Ok(all | SHF_WRITE),
+                    'x' => Ok(all | SHF_EXECINSTR),
+                    _ => Err(bad()),
+                })?
+            }
+            None => default_flags,
+        };
+        let sh_type = match args.next().map(|kind| kind.trim_start_matches(['%', '@'])) {
+            Some("progbits") => SHT_PROGBITS,
+            Some("nobits") => SHT_NOBITS,
+            Some(_) => return Err(bad()),
+            None => default_type,
+        };
+
+        self.switch_section(name, sh_type, flags);
+        Ok(())
+    }
+
+    /// Switch between ARM and Thumb code, as `.arm` and `.thumb` do.
+    pub fn set_instruction_set(&mut self, isa: InstructionSet) {
+        // ARM code has to be word aligned
+        if isa == InstructionSet::Arm && !self.address().is_multiple_of(4) {
+            self.emit(&self.endianness.code().u16_bytes(THUMB_NOP));
+        }
+        self.isa = isa;
+    }
+
+    fn directive(&mut self, directive: &str) -> Result<(), AssemblerError> {
+        let (name, args) = directive
+            .split_once(char::is_whitespace)
+            .unwrap_or((directive, ""));
+        let bad = || AssemblerError::from(ParseError::BadDirective(directive.to_owned()));
+
+        match name {
+            "arch" => self.target.set_arch(args),
+            "arch_extension" => self.target.add_extension(args),
+            "cpu" => self.target.set_cpu(args),
+            "fpu" => self.target.set_fpu(args),
+            "arm" => {
+                self.set_instruction_set(InstructionSet::Arm);
+                Ok(())
+            }
+            "thumb" => {
+                self.set_instruction_set(InstructionSet::Thumb);
+                Ok(())
+            }
+            "code" => {
+                match args.trim() {
+                    "16" => self.set_instruction_set(InstructionSet::Thumb),
+                    "32" => self.set_instruction_set(InstructionSet::Arm),
+                    _ => return Err(bad()),
+                }
+                Ok(())
+            }
+            "thumb_func" => {
+                self.thumb_func = true;
+                Ok(())
+            }
+            "syntax" => {
+                match args.trim() {
+                    "unified" => self.unified = true,
+                    "divided" => self.unified = false,
+                    _ => return Err(bad()),
+                }
+                Ok(())
+            }
+            "text" => {
+                self.switch_section(".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR);
+                Ok(())
+            }
+            "data" => {
+                self.switch_section(".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE);
+                Ok(())
+            }
+            "bss" => {
+                self.switch_section(".bss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE);
+                Ok(())
+            }
+            "section" => self.section_directive(directive, args),
+            "unreq" => self.aliases.undefine(args),
+            "previous" => {
+                self.current = std::mem::replace(&mut self.previous, self.current);
+                Ok(())
+            }
+            "byte" => self.data(args, 1),
+            "short" | "hword" | "2byte" => self.data(args, 2),
+            "word" | "long" | "int" | "4byte" => self.data(args, 4),
+            "ascii" | "asciz" | "string" => {
+                let bytes = parse_strings(args, name != "ascii").ok_or_else(bad)?;
+                self.emit_data(&bytes);
+                Ok(())
+            }
+            "space" | "skip" | "zero" => {
+                let (size, fill) = match args.split_once(',') {
+                    Some((size, fill)) if name != "zero" => (size, self.absolute(fill)?),
+                    _ => (args, 0),
+                };
+                let fill = u8::try_from(fill).map_err(|_| bad())?;
+                let size = self.absolute(size)?;
+                self.emit_data(&vec![fill; size as usize]);
+                Ok(())
+            }
+            // `.align` takes a power of two on ARM, defaulting to a word
+            "align" | "p2align" => {
+                let power = if args.trim().is_empty() {
+                    2
+                } else {
+                    self.absolute(args)?
+                };
+                let align = 1u32.checked_shl(power).ok_or_else(bad)?;
+                self.align_to(align);
+                Ok(())
+            }
+            "balign" => {
+                let align = self.absolute(args)?;
+                if !align.is_power_of_two() {
+                    return Err(bad());
+                }
+                self.align_to(align);
+                Ok(())
+            }
+            "global" | "globl" => {
+                self.update_symbols(directive, args, |symbol| symbol.binding = Some(STB_GLOBAL))
+            }
+            "weak" => {
+                self.update_symbols(directive, args, |symbol| symbol.binding = Some(STB_WEAK))
+            }
+            "local" => {
+                self.update_symbols(directive, args, |symbol| symbol.binding = Some(STB_LOCAL))
+            }
+            // Undefined symbols are external anyway
+            "extern" => self.update_symbols(directive, args, |_| ()),
+            "hidden" => self.update_symbols(directive, args, |symbol| {
+                symbol.visibility = STV_HIDDEN;
+            }),
+            "protected" => self.update_symbols(directive, args, |symbol| {
+                symbol.visibility = STV_PROTECTED;
+            }),
+            "internal" => self.update_symbols(directive, args, |symbol| {
+                symbol.visibility = STV_INTERNAL;
+            }),
+            "type" => {
+                let (symbol, kind) = args.split_once(',').ok_or_else(bad)?;
+                let sym_type = match kind.trim().trim_start_matches(['%', '#']) {
+                    "function" | "STT_FUNC" => STT_FUNC,
+                    "object" | "STT_OBJECT" => STT_OBJECT,
+                    "notype" | "STT_NOTYPE" => STT_NOTYPE,
+                    _ => return Err(bad()),
+                };
+                self.update_symbols(directive, symbol, |symbol| {
+                    symbol.sym_type = Some(sym_type);
+                })
+            }
+            "size" => {
+                let (symbol, size) = args.split_once(',').ok_or_else(bad)?;
+                let size = self.absolute(size)?;
+                self.update_symbols(directive, symbol, |symbol| symbol.size = Some(size))
+            }
+            "file" => self.file_directive(args).ok_or_else(bad),
+            "loc" => {
+                let mut numbers = args.split_whitespace().map(str::parse::<u32>);
+                let (Some(Ok(file)), Some(Ok(line))) = (numbers.next(), numbers.next()) else {
+                    return Err(bad());
+                };
+                // Anything after the column only matters to optimised code
+                let column = numbers.next().and_then(Result::ok).unwrap_or(0);
+                self.loc = Some(LinePosition::Loc { file, line, column });
+                Ok(())
+            }
+            // Only of interest to other tools
+            "ident" => Ok(()),
+            "set" | "equ" => {
+                let (symbol, value) = args.split_once(',').ok_or_else(bad)?;
+                let symbol = symbol.trim();
+                if !is_symbol_name(symbol) {
+                    return Err(bad());
+                }
+                let value = match self.evaluate(value)? {
+                    Value::Absolute(value) => value,
+                    Value::Relocatable(..) => return Err(bad()),
+                };
+                self.equates.insert(symbol.to_owned(), value);
+                Ok(())
+            }
+            _ if name.starts_with("cfi_") => self.cfi_directive(directive, &name[4..], args),
+            "fnstart" | "fnend" | "cantunwind" | "personality" | "personalityindex"
+            | "handlerdata" | "save" | "vsave" | "pad" | "setfp" | "movsp" | "unwind_raw" => {
+                self.unwind_directive(directive, name, args)
+            }
+            _ => Err(ParseError::UnknownDirective(name.to_owned()).into()),
+        }
+    }
+
+    /// The `.cfi_*` directives, `name` being the rest of the directive's name.
+    fn cfi_directive(
+        &mut self,
+        directive: &str,
+        name: &str,
+        args: &str,
+    ) -> Result<(), AssemblerError> {
+        let bad = || AssemblerError::from(ParseError::BadDirective(directive.to_owned()));
+        let operands: Vec<&str> = args.split(',').map(str::trim).collect();
+        let register = |index: usize| {
+            let operand = operands.get(index).ok_or_else(bad)?;
+            parse_register(operand).ok_or_else(bad)
+        };
+        let offset = |index: usize| self.signed(operands.get(index).ok_or_else(bad)?);
+
+        let instruction = match name {
+            "sections" => {
+                let mut sections = CfiSections {
+                    eh_frame: false,
+                    debug_frame: false,
+                };
+                for operand in operands {
+                    match operand {
+                        ".eh_frame" => sections.eh_frame = true,
+                        ".debug_frame" => sections.debug_frame = true,
+                        _ => return Err(bad()),
+                    }
+                }
+                self.cfi_sections = sections;
+                return Ok(());
+            }
+            "startproc" => {
+                let simple = match args.trim() {
+                    "" => false,
+                    "simple" => true,
+                    _ => return Err(bad()),
+                };
+                if self.frame.is_some() {
+                    return Err(bad());
+                }
+                let frame = Frame::new(self.current, self.address(), simple, self.endianness);
+                self.frame = Some(frame);
+                return Ok(());
+            }
+            "endproc" => {
+                let mut frame = self.frame.take().ok_or_else(bad)?;
+                frame.end = self.address();
+                self.frames.push(frame);
+                return Ok(());
+            }
+            "signal_frame" => {
+                self.frame.as_mut().ok_or_else(bad)?.signal_frame = true;
+                return Ok(());
+            }
+            "return_column" => {
+                let column = register(0).ok().filter(|column| *column < 256);
+                self.frame.as_mut().ok_or_else(bad)?.return_column = column.ok_or_else(bad)?;
+                return Ok(());
+            }
+            "def_cfa" => CfiInstruction::DefCfa(register(0)?, offset(1)?),
+            "def_cfa_register" => CfiInstruction::DefCfaRegister(register(0)?),
+            "def_cfa_offset" => CfiInstruction::DefCfaOffset(offset(0)?),
+            "adjust_cfa_offset" => CfiInstruction::AdjustCfaOffset(offset(0)?),
+            "offset" => CfiInstruction::Offset(register(0)?, offset(1)?),
+            "rel_offset" => CfiInstruction::RelOffset(register(0)?, offset(1)?),
+            "val_offset" => CfiInstruction::ValOffset(register(0)?, offset(1)?),
+            "register" => CfiInstruction::Register(register(0)?, register(1)?),
+            "restore" => CfiInstruction::Restore(register(0)?),
+            "undefined" => CfiInstruction::Undefined(register(0)?),
+            "same_value" => CfiInstruction::SameValue(register(0)?),
+            "remember_state" => CfiInstruction::RememberState,
+            "restore_state" => CfiInstruction::RestoreState,
+            "escape" => {
+                let bytes = operands
+                    .iter()
+                    .map(|operand| u8::try_from(self.absolute(operand)?).map_err(|_| bad()))
+                    .collect::<Result<_, _>>()?;
+                CfiInstruction::Escape(bytes)
+            }
+            _ => return Err(ParseError::UnknownDirective(format!("cfi_{name}")).into()),
+        };
+
+        let address = self.address();
+        let frame = self.frame.as_mut().ok_or_else(bad)?;
+        frame.push(address, instruction).ok_or_else(bad)
+    }
+
+    /// `.file "name"` names the source file, and `.file N ["dir"] "name"` numbers one for `.loc`.
+    fn file_directive(&mut self, args: &str) -> Option<()> {
+        let args = args.trim();
+        let (number, names) = match args.split_once(char::is_whitespace) {
+            Some((number, names)) if !args.starts_with('"') => (Some(number), names),
+            _ => (None, args),
+        };
+        // Quoted strings alternate with the space between them
+        let parts: Vec<&str> = names.trim().split('"').collect();
+        if parts.iter().step_by(2).any(|gap| !gap.trim().is_empty()) {
+            return None;
+        }
+        let name = match parts.as_slice() {
+            [_, name, _] => (*name).to_owned(),
+            [_, dir, _, name, _] => format!("{dir}/{name}"),
+            _ => return None,
+        };
+
+        match number.map(str::parse::<usize>) {
+            None | Some(Ok(0)) => self.file_name = Some(name),
+            Some(Ok(number)) => {
+                if self.files.len() < number {
+                    self.files.resize(number, String::new());
+                }
+                self.files[number - 1] = name;
+            }
+            Some(Err(_)) => return None,
+        }
+        Some(())
+    }
+
+    fn warn(&mut self, message: String) {
+        self.warnings.push((self.line_number, message));
+    }
+
+    /// Every label defined so far, followed by the symbols left for the linker to define.
+    pub fn symbol_table(&self) -> Vec<ListedSymbol> {
+        let defined = self.defined.iter().filter(|label| self.is_listed(label));
+        let defined = defined.map(|label| ListedSymbol {
+            name: label.name.clone(),
+            section: Some(self.sections[label.section].section.name.clone()),
+            value: label.address | u32::from(self.is_thumb_function(label)),
+            binding: self
+                .symbols
+                .get(&label.name)
+                .and_then(|attributes| attributes.binding)
+                .unwrap_or(STB_LOCAL),
+            line: Some(label.line),
+        });
+        let external = self
+            .external_symbols()
+            .into_iter()
+            .map(|name| ListedSymbol {
+                name: name.to_owned(),
+                section: None,
+                value: 0,
+                binding: self.undefined_binding(name),
+                line: None,
+            });
+        defined.chain(external).collect()
+    }
+
+    /// What the first field in the text section that needs relocating refers to, if any does.
+    #[cfg(feature = "jit")]
+    pub(crate) fn text_relocation(&self) -> Option<String> {
+        let fixup = self.sections[0].fixups.first()?;
+        Some(match &fixup.reference {
+            Reference::Section(index) => self.sections[*index].section.name.clone(),
+            Reference::Symbol(name) => name.clone(),
+        })
+    }
+
+    /// Whether `label` goes in the symbol table, which local labels starting with `.L` don't.
+    fn is_listed(&self, label: &Label) -> bool {
+        !label.name.starts_with(".L") || self.is_exported(&label.name)
+    }
+
+    /// Symbols declared or referenced here but defined elsewhere.
+    fn external_symbols(&self) -> BTreeSet<&str> {
+        let defined: HashSet<&str> = self
+            .defined
+            .iter()
+            .map(|label| label.name.as_str())
+            .collect();
+        let referenced = self
+            .sections
+            .iter()
+            .flat_map(|buffer| &buffer.fixups)
+            .filter_map(|fixup| match &fixup.reference {
+                Reference::Symbol(name) => Some(name.as_str()),
+                Reference::Section(_) => None,
+            });
+        self.symbols
+            .keys()
+            .filter(|name| self.is_exported(name))
+            .map(String::as_str)
+            .chain(referenced)
+            .filter(|name| !defined.contains(name))
+            .collect()
+    }
+
+    /// Undefined symbols are global unless declared weak.
+    fn undefined_binding(&self, name: &str) -> u8 {
+        self.symbols
+            .get(name)
+            .and_then(|attributes| attributes.binding)
+            .filter(|binding| *binding == STB_WEAK)
+            .unwrap_or(STB_GLOBAL)
+    }
+
+    /// The DWARF compile unit describing where the code came from, if there are any rows for it.
+    fn compile_unit(&self) -> Option<CompileUnit> {
+        let from_loc = self
+            .line_rows
+            .iter()
+            .any(|row| matches!(row.position, LinePosition::Loc { .. }));
+        let mut files = self.files.clone();
+        let mut sequences: Vec<Sequence> = vec![];
+        for line_row in &self.line_rows {
+            let (file, line, column) = match (&line_row.position, &self.debug_locations) {
+                (LinePosition::Loc { file, line, column }, _) => (*file, *line, *column),
+                (LinePosition::Source(number), Some(locations)) if !from_loc => {
+                    let Some(location) = locations.get(number - 1) else {
+                        continue;
+                    };
+                    let file = match files.iter().position(|file| *file == location.file) {
+                        Some(index) => index,
+                        None => {
+                            files.push(location.file.clone());
+                            files.len() - 1
+                        }
+                    };
+                    (file as u32 + 1, location.line as u32, 0)
+                }
+                _ => continue,
+            };
+            let row = Row {
+                address: line_row.address,
+                file,
+                line,
+                column,
+            };
+            match sequences
+                .iter_mut()
+                .find(|sequence| sequence.section == line_row.section)
+            {
+                Some(sequence) => sequence.rows.push(row),
+                None => sequences.push(Sequence {
+                    section: line_row.section,
+                    end: self.sections[line_row.section].section.data.len() as u32,
+                    rows: vec![row],
+                }),
+            }
+        }
+        if sequences.is_empty() {
+            return None;
+        }
+
+        let name = self
+            .file_name
+            .clone()
+            .or_else(|| files.first().cloned())
+            .unwrap_or_default();
+        Some(CompileUnit {
+            name,
+            comp_dir: self.comp_dir.clone(),
+            producer: concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")).to_owned(),
+            files,
+            sequences,
+        })
+    }
+
+    /// The sections assembled so far, with the DWARF sections describing them and their call
+    /// frames. Compilers that use `.loc` also write their own `.debug_info`, and leave an empty
+    /// `.debug_line` to be filled in.
+    fn sections_with_debug_info(&self) -> Vec<SectionBuffer> {
+        let mut sections = self.sections.clone();
+        let order = self.endianness;
+        let mut generated = vec![];
+        if let Some(unit) = self.compile_unit() {
+            let full = self.debug_locations.is_some() && self.section(".debug_info").is_none();
+            generated = unit.sections(full, order);
+        }
+        if !self.frames.is_empty() {
+            if self.cfi_sections.debug_frame {
+                generated.push(cfi::debug_frame(&self.frames, order));
+            }
+            if self.cfi_sections.eh_frame {
+                generated.push(cfi::eh_frame(&self.frames, order));
+            }
+        }
+
+        // Where each generated section starts, in a section of the same name if there is one
+        let mut placement = HashMap::new();
+        for debug in &generated {
+            let index = match sections
+                .iter()
+                .position(|buffer| buffer.section.name == debug.name)
+            {
+                Some(index) => index,
+                None => {
+                    sections.push(SectionBuffer::new(debug.name, SHT_PROGBITS, debug.flags));
+                    sections.len() - 1
+                }
+            };
+            let section = &mut sections[index].section;
+            section.align = section.align.max(debug.align);
+            let base = sections[index].section.data.len() as u32;
+            placement.insert(debug.name, (index, base));
+        }
+
+        for mut debug in generated {
+            let (index, base) = placement[debug.name];
+            for fixup in &debug.fixups {
+                let section = match fixup.target {
+                    dwarf::Target::Code(section) => section,
+                    dwarf::Target::Debug(name) => {
+                        let (section, start) = placement[name];
+                        let field = fixup.offset as usize;
+                        let value = order.read_u32(&debug.data, field).unwrap_or(0) + start;
+                        debug.data[field..field + 4].copy_from_slice(&order.u32_bytes(value));
+                        section
+                    }
+                };
+                sections[index].fixups.push(Fixup {
+                    offset: base + fixup.offset,
+                    reference: Reference::Section(section),
+                    r_type: fixup.r_type,
+                });
+            }
+            sections[index].section.data.extend(debug.data);
+        }
+        sections
+    }
+
+    /// Lay out everything assembled so far as an ELF relocatable object.
+    pub fn to_object(&self) -> Vec<u8> {
+        let sections = self.sections_with_debug_info();
+        let mut writer = ObjectWriter::new(EF_ARM_EABI_VER5);
+        writer.endianness = self.endianness;
+        let local = |name: &str, value, sym_type, section| Symbol {
+            name: name.to_owned(),
+            value,
+            size: 0,
+            binding: STB_LOCAL,
+            sym_type,
+            visibility: STV_DEFAULT,
+            section,
+        };
+
+        // Relocations against local labels are against their section's symbol instead
+        let mut indices: Vec<(u16, u32)> = vec![];
+        for buffer in &sections {
+            let mut section = buffer.section.clone();
+            if let Some(link) = buffer.link {
+                section.link = indices[link].0.into();
+            }
+            let index = writer.add_section(section);
+            let symbol = writer.add_symbol(local("", 0, STT_SECTION, index));
+            indices.push((index, symbol));
+        }
+        for (buffer, (index, _)) in sections.iter().zip(&indices) {
+            for (address, mapping) in &buffer.mapping {
+                writer.add_symbol(local(mapping.symbol(), *address, STT_NOTYPE, *index));
+            }
+        }
+
+        let mut symbols = HashMap::new();
+        for label in self.defined.iter().filter(|label| self.is_listed(label)) {
+            let attributes = self.symbols.get(&label.name).cloned().unwrap_or_default();
+            // The low bit of a Thumb function's address selects Thumb state on interworking calls
+            let thumb = self.is_thumb_function(label);
+            let sym_type = match attributes.sym_type {
+                Some(sym_type) => sym_type,
+                None if thumb => STT_FUNC,
+                None => STT_NOTYPE,
+            };
+            let index = writer.add_symbol(Symbol {
+                name: label.name.clone(),
+                value: label.address | u32::from(thumb),
+                size: attributes.size.unwrap_or(0),
+                binding: attributes.binding.unwrap_or(STB_LOCAL),
+                sym_type,
+                visibility: attributes.visibility,
+                section: indices[label.section].0,
+            });
+            symbols.insert(label.name.as_str(), index);
+        }
+
+        for name in self.external_symbols() {
+            let attributes = self.symbols.get(name).cloned().unwrap_or_default();
+            let index = writer.add_symbol(Symbol {
+                name: name.to_owned(),
+                value: 0,
+                size: 0,
+                binding: self.undefined_binding(name),
+                sym_type: attributes.sym_type.unwrap_or(STT_NOTYPE),
+                visibility: attributes.visibility,
+                section: SHN_UNDEF,
+            });
+            symbols.insert(name, index);
+        }
+
+        for (buffer, (index, _)) in sections.iter().zip(&indices) {
+            for fixup in &buffer.fixups {
+                let symbol = match &fixup.reference {
+                    Reference::Section(section) => indices[*section].1,
+                    Reference::Symbol(name) => symbols[name.as_str()],
+                };
+                let relocation = Relocation {
+                    offset: fixup.offset,
+                    symbol,
+                    r_type: fixup.r_type,
+                    addend: None,
+                };
+                writer.add_relocation(*index, relocation);
+            }
+        }
+
+        let mut attributes = Section::new(".ARM.attributes", SHT_ARM_ATTRIBUTES, 0, 1);
+        attributes.data = build_attributes(&self.target, self.endianness);
+        writer.add_section(attributes);
+
+        writer.to_bytes()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Assembler, InstructionSet, Mapping};
+    use crate::{
+        arch::{Arch, Target},
+        dwarf::{DW_LNS_ADVANCE_PC, DW_LNS_SET_COLUMN},
+        ehabi::EXIDX_CANTUNWIND,
+        elf::{
+            Endianness, ObjectFile, Relocation, EF_ARM_BE8, EF_ARM_EABI_VER5, ELFDATA2MSB,
+            R_ARM_ABS32, R_ARM_CALL, R_ARM_JUMP24, R_ARM_MOVW_ABS_NC, R_ARM_NONE, R_ARM_PREL31,
+            R_ARM_THM_CALL, R_ARM_THM_JUMP24, SHN_UNDEF, SHT_ARM_EXIDX, STB_GLOBAL, STB_WEAK,
+            STT_FUNC, STT_OBJECT, STV_HIDDEN,
+        },
+        error::AssemblerError,
+        listing::Listing,
+        source::Source,
+    };
+
+    fn assemble(target: Target, program: &[&str]) -> Assembler {
+        let mut assembler = Assembler::new(target);
+        assembler.layout(program).unwrap();
+        for line in program {
+            assembler.assemble_line(line).unwrap();
+        }
+        assembler
+    }
+
+    #[test]
+    fn test_arch_directives() {
+        let mut assembler = Assembler::new(Target::new(Arch::V6));
+        assert_eq!(
+            assembler.assemble_line("nop").unwrap(),
+            Some(vec![0x00, 0x00, 0xa0, 0xe1])
+        );
+        assert!(assembler.assemble_line("yield").is_err());
+        assembler.assemble_line(".thumb").unwrap();
+        assert_eq!(
+            assembler.assemble_line("nop").unwrap(),
+            Some(vec![0xc0, 0x46])
+        );
+        assert!(assembler.assemble_line("wfi").is_err());
+        assembler.assemble_line(".arm").unwrap();
+
+        let err = assembler.assemble_line("movw r0, #1").unwrap_err();
+        assert!(matches!(err, AssemblerError::Unsupported(..)));
+        assert_eq!(
+            err.to_string(),
+            "`movw r0, #1` requires ARMv6T2, which armv6 does not support"
+        );
+
+        assembler.assemble_line(".arch armv7-a").unwrap();
+        assert_eq!(
+            assembler.assemble_line("movw r0, #1").unwrap(),
+            Some(vec![0x01, 0x00, 0x00, 0xe3])
+        );
+        assert!(assembler.assemble_line("udiv r0, r1, r2").is_err());
+
+        assembler.assemble_line(".arch_extension idiv").unwrap();
+        assert!(assembler.assemble_line("udiv r0, r1, r2").is_ok());
+
+        assembler.assemble_line(".cpu arm7tdmi").unwrap();
+        assert!(assembler.assemble_line("dmb").is_err());
+        assert!(assembler.assemble_line(".fpu vfpv5").is_err());
+    }
+
+    #[test]
+    fn test_attributes_section() {
+        let mut assembler = Assembler::new(Target::default());
+        assembler.assemble_line(".cpu cortex-a8").unwrap();
+        let object = assembler.to_object();
+
+        let attributes: &[u8] = &[
+            b'A', 0x28, 0, 0, 0, b'a', b'e', b'a', b'b', b'i', 0, 1, 0x1e, 0, 0, 0, 5, b'C', b'O',
+            b'R', b'T', b'E', b'X', b'-', b'A', b'8', 0, 6, 10, 7, b'A', 8, 1, 9, 2, 10, 3, 12, 1,
+            68, 1,
+        ];
+        assert!(object
+            .windows(attributes.len())
+            .any(|window| window == attributes));
+    }
+
+    #[test]
+    fn test_thumb_switching() {
+        let program = [
+            ".thumb",
+            ".thumb_func",
+            "start:",
+            "mov r0, #1 @ set up",
+            "bl func",
+            "beq start",
+            "bx lr",
+            ".code 32",
+            "func: bx lr",
+        ];
+        let mut assembler = Assembler::new(Target::default());
+        assembler.layout(&program).unwrap();
+        for line in program {
+            assembler.assemble_line(line).unwrap();
+        }
+
+        assert_eq!(assembler.instruction_set(), InstructionSet::Arm);
+        assert_eq!(
+            assembler.text(),
+            &[
+                0x01, 0x20, 0x00, 0xf0, 0x03, 0xf8, 0xfb, 0xd0, 0x70, 0x47, 0xc0, 0x46, 0x1e, 0xff,
+                0x2f, 0xe1,
+            ]
+        );
+        assert_eq!(
+            assembler.sections[0].mapping,
+            vec![
+                (0, Mapping::Code(InstructionSet::Thumb)),
+                (12, Mapping::Code(InstructionSet::Arm))
+            ]
+        );
+        assert!(assembler.defined[0].thumb_func);
+        assert!(!assembler.defined[1].thumb_func);
+    }
+
+    #[test]
+    fn test_thumb_writeback() {
+        // Only the wide encodings have writeback, so these mustn't be taken as narrow offsets
+        let assembler = assemble(
+            Target::new(Arch::V7A),
+            &[
+                ".thumb",
+                "ldr r0, [r1], #4",
+                "ldr r0, [r1, #4]!",
+                "strb r2, [r3], #-1",
+            ],
+        );
+        assert_eq!(
+            assembler.text(),
+            &[0x51, 0xf8, 0x04, 0x0b, 0x51, 0xf8, 0x04, 0x0f, 0x03, 0xf8, 0x01, 0x29]
+        );
+
+        // The narrow ldm/stm write back unless they load the base, so the rest are wide
+        let mut assembler = assemble(
+            Target::new(Arch::V7A),
+            &[
+                ".thumb",
+                "ldm r0, {r1, r2}",
+                "stmia r0, {r1}",
+                "ldm r0, {r0, r1}",
+                "ldm r0!, {r1, r2}",
+            ],
+        );
+        assert_eq!(
+            assembler.text(),
+            &[0x90, 0xe8, 0x06, 0x00, 0x80, 0xe8, 0x02, 0x00, 0x03, 0xc8, 0x06, 0xc8]
+        );
+        assert!(assembler.assemble_line("ldmia r0!, {r0, r1}").is_err());
+        assert!(assembler.assemble_line("ldm.n r0, {r1, r2}").is_err());
+
+        let mut target = Target::default();
+        target.set_cpu("cortex-m0").unwrap();
+        let mut assembler = Assembler::new(target);
+        assembler.assemble_line("stm r0!, {r1}").unwrap();
+        for line in ["ldm r0, {r1, r2}", "stmia r0, {r1}", "ldmia r0!, {r0, r1}"] {
+            assert!(assembler.assemble_line(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn test_thumb_system() {
+        // ARMv6-M has the barriers, hints and status register moves but not the rest of Thumb-2
+        let mut target = Target::default();
+        target.set_cpu("cortex-m0").unwrap();
+        let assembler = assemble(
+            target.clone(),
+            &[
+                "cpsid i",
+                "wfi",
+                "dsb",
+                "mrs r0, primask",
+                "msr primask, r0",
+            ],
+        );
+        assert_eq!(
+            assembler.text(),
+            &[
+                0x72, 0xb6, 0x30, 0xbf, 0xbf, 0xf3, 0x4f, 0x8f, 0xef, 0xf3, 0x10, 0x80, 0x80, 0xf3,
+                0x10, 0x88
+            ]
+        );
+        let mut assembler = Assembler::new(target);
+        for line in ["nop.w", "ldrd r0, r1, [r2]"] {
+            assert!(assembler.assemble_line(line).is_err(), "{line}");
+        }
+
+        // Barriers arrived in ARMv7, though ARMv6T2 has the rest
+        let mut assembler = Assembler::new(Target::new(Arch::V6T2));
+        assembler.assemble_line(".thumb").unwrap();
+        assembler.assemble_line("mrs r0, apsr").unwrap();
+        assert!(assembler.assemble_line("dmb").is_err());
+    }
+
+    #[test]
+    fn test_thumb_adr() {
+        let program = [
+            ".thumb",
+            "start: adr r0, data",
+            "adr r8, data",
+            "adr r1, start",
+            "nop",
+            "data: .word 0",
+        ];
+        let assembler = assemble(Target::new(Arch::V7A), &program);
+        assert_eq!(
+            &assembler.text()[..12],
+            &[0x02, 0xa0, 0x0f, 0xf2, 0x08, 0x08, 0xaf, 0xf2, 0x08, 0x01, 0x00, 0xbf]
+        );
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        assembler.assemble_line(".thumb").unwrap();
+        assert!(assembler.assemble_line("adr r0, external").is_err());
+
+        // ARMv6-M only has the narrow forms of these
+        let mut target = Target::default();
+        target.set_cpu("cortex-m0").unwrap();
+        let assembler = assemble(
+            target.clone(),
+            &[
+                "rev r0, r1",
+                "uxth r2, r3",
+                "bkpt #1",
+                "adr r0, data",
+                "data: .word 0",
+            ],
+        );
+        assert_eq!(
+            &assembler.text()[..8],
+            &[0x08, 0xba, 0x9a, 0xb2, 0x01, 0xbe, 0x00, 0xa0]
+        );
+        let mut assembler = Assembler::new(target);
+        for line in ["rev r8, r1", "rbit r0, r1", "adr r0, #2", "clrex"] {
+            assert!(assembler.assemble_line(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn test_unified_syntax() {
+        let mut program = vec![
+            ".syntax unified",
+            "cmp r0, #0",
+            "ite eq",
+            "addeq r0, r0, r1",
+            "addsne r0, r0, r1",
+            "add r0, r0, r1",
+            "adds r0, r0, r1",
+            "beq done",
+        ];
+        // Too far for a narrow conditional branch
+        program.extend(["movs r0, r0"; 200]);
+        program.push("done: bx lr");
+
+        let mut target = Target::default();
+        target.set_cpu("cortex-m3").unwrap();
+        let mut assembler = Assembler::new(target);
+        assert_eq!(assembler.instruction_set(), InstructionSet::Thumb);
+
+        assembler.layout(&program).unwrap();
+        for line in &program {
+            assembler.assemble_line(line).unwrap();
+        }
+        assert_eq!(
+            &assembler.text()[..18],
+            &[
+                0x00, 0x28, 0x0c, 0xbf, 0x40, 0x18, 0x10, 0xeb, 0x01, 0x00, 0x08, 0x44, 0x40, 0x18,
+                0x00, 0xf0, 0xc8, 0x80,
+            ]
+        );
+
+        assert!(assembler.assemble_line("addeq r0, r1").is_err());
+        assembler.assemble_line("it ne").unwrap();
+        assert!(assembler.assemble_line("addeq r0, r1").is_err());
+
+        assembler.assemble_line(".arm").unwrap();
+        let err = assembler.assemble_line("mov r0, r1").unwrap_err();
+        assert!(matches!(err, AssemblerError::Unsupported(..)));
+
+        // An IT block can't run on into another section or past the end of the input
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        let err = assembler
+            .layout(&[".thumb", "itt eq", "moveq r0, r1"])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Parse Error: IT block is still open, expecting 1 more"
+        );
+        assert_eq!(assembler.line_number(), 3);
+        for line in [".thumb", "it eq", "moveq r0, r1", ".data", "it eq"] {
+            assembler.assemble_line(line).unwrap();
+        }
+        assert!(assembler.assemble_line(".text").is_err());
+        assert!(assembler.finish().is_ok());
+        assembler.assemble_line("ite ne").unwrap();
+        assert!(assembler.finish().is_err());
+    }
+
+    #[test]
+    fn test_vfp_fpu_selection() {
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        let err = assembler.assemble_line("vadd.f32 s0, s1, s2").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`vadd.f32 s0, s1, s2` requires VFPv2, which armv7-a does not support"
+        );
+
+        assembler.assemble_line(".fpu vfpv3-d16").unwrap();
+        assert_eq!(
+            assembler.assemble_line("vaddeq.f32 s0, s1, s2").unwrap(),
+            Some(vec![0x81, 0x0a, 0x30, 0x0e])
+        );
+        assert!(assembler.assemble_line("vmov.f64 d16, d0").is_err());
+        assert!(assembler.assemble_line("vfma.f32 s0, s1, s2").is_err());
+
+        // Thumb uses the same encoding as two halfwords, with conditions from IT blocks
+        let mut target = Target::default();
+        target.set_cpu("cortex-m4").unwrap();
+        target.set_fpu("fpv4-sp-d16").unwrap();
+        let mut assembler = Assembler::new(target);
+        for line in [
+            "vmov.f32 s0, #1.0",
+            "it gt",
+            "vaddgt.f32 s0, s0, s1",
+            "vpush {d8}",
+        ] {
+            assembler.assemble_line(line).unwrap();
+        }
+        assert_eq!(
+            assembler.text(),
+            &[0xb7, 0xee, 0x00, 0x0a, 0xc8, 0xbf, 0x30, 0xee, 0x20, 0x0a, 0x2d, 0xed, 0x02, 0x8b,]
+        );
+        assert!(assembler.assemble_line("vaddgt.f32 s0, s0, s1").is_err());
+        assert!(assembler.assemble_line("vadd.f64 d0, d1, d2").is_err());
+    }
+
+    #[test]
+    fn test_neon() {
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        assembler.assemble_line(".fpu vfpv3").unwrap();
+        assert!(assembler.assemble_line("vadd.i32 q0, q1, q2").is_err());
+        // VFP has the 32-bit scalar moves
+        assert!(assembler.assemble_line("vmov.32 r0, d1[1]").is_ok());
+
+        assembler.assemble_line(".fpu neon").unwrap();
+        assert_eq!(
+            assembler.assemble_line("vadd.i32 q0, q1, q2").unwrap(),
+            Some(vec![0x44, 0x08, 0x22, 0xf2])
+        );
+        assert!(assembler.assemble_line("vaddeq.i32 q0, q1, q2").is_err());
+
+        assembler.assemble_line(".thumb").unwrap();
+        for line in ["vqadd.u8 q0, q1, q2", "vld1.8 {d0}, [r0]", "vdup.32 q0, r1"] {
+            assembler.assemble_line(line).unwrap();
+        }
+        assert_eq!(
+            &assembler.text()[8..],
+            &[0x02, 0xff, 0x54, 0x00, 0x20, 0xf9, 0x0f, 0x07, 0xa0, 0xee, 0x10, 0x1b,]
+        );
+    }
+
+    #[test]
+    fn test_thumb_coprocessor() {
+        let mut target = Target::default();
+        target.set_cpu("cortex-m3").unwrap();
+        let mut assembler = Assembler::new(target);
+        for line in [
+            "mcr p15, 0, r0, c1, c0, 0",
+            "ite eq",
+            "ldceq p9, c5, [r4]",
+            "mrcne p15, 0, r1, c0, c0, 5",
+        ] {
+            assembler.assemble_line(line).unwrap();
+        }
+        assert_eq!(
+            assembler.text(),
+            &[0x01, 0xee, 0x10, 0x0f, 0x0c, 0xbf, 0x94, 0xed, 0x00, 0x59, 0x10, 0xee, 0xb0, 0x1f,]
+        );
+        assert!(assembler
+            .assemble_line("mcreq p15, 0, r0, c1, c0, 0")
+            .is_err());
+    }
+
+    #[test]
+    fn test_data_directives() {
+        let program = [
+            ".data",
+            "table: .word 1, end - table, -1",
+            ".short 0x1234",
+            ".byte 0, 255",
+            ".asciz \"a@b\\n\" @ comment",
+            ".balign 4",
+            ".space 2, 0xff",
+            "end:",
+        ];
+        let assembler = assemble(Target::default(), &program);
+        assert_eq!(
+            assembler.section(".data").unwrap(),
+            &[
+                1, 0, 0, 0, 26, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0x34, 0x12, 0, 0xff, b'a', b'@',
+                b'b', b'\n', 0, 0, 0, 0, 0xff, 0xff,
+            ]
+        );
+        assert_eq!(assembler.text(), &[]);
+
+        let mut assembler = Assembler::new(Target::default());
+        assert!(assembler.assemble_line(".byte 256").is_err());
+        assert!(assembler.assemble_line(".short elsewhere").is_err());
+        assert!(assembler.assemble_line(".section .x, \"q\"").is_err());
+    }
+
+    #[test]
+    fn test_relocations() {
+        let program = [
+            ".global start, table",
+            ".weak maybe",
+            ".hidden start",
+            ".type start, %function",
+            "start:",
+            "bl external",
+            "b local",
+            "movw r0, #:lower16:buffer",
+            "local: b start",
+            ".size start, . - start",
+            ".thumb",
+            "b external",
+            ".data",
+            ".type table, %object",
+            "table: .word start, external + 4, maybe",
+            ".bss",
+            "buffer: .space 4",
+        ];
+        let assembler = assemble(Target::new(Arch::V7A), &program);
+        let object = ObjectFile::parse(&assembler.to_object()).unwrap();
+
+        let symbol = |name: &str| {
+            let index = object.symbols.iter().position(|s| s.name == name).unwrap();
+            (index as u32, &object.symbols[index])
+        };
+        let (start, start_symbol) = symbol("start");
+        assert_eq!(
+            (
+                start_symbol.binding,
+                start_symbol.sym_type,
+                start_symbol.size
+            ),
+            (STB_GLOBAL, STT_FUNC, 16)
+        );
+        assert_eq!(start_symbol.visibility, STV_HIDDEN);
+        assert_eq!(symbol("table").1.sym_type, STT_OBJECT);
+        let (external, external_symbol) = symbol("external");
+        assert_eq!(external_symbol.section, SHN_UNDEF);
+        let (maybe, maybe_symbol) = symbol("maybe");
+        assert_eq!(maybe_symbol.binding, STB_WEAK);
+        // Section symbols come first, so .bss is symbol 3
+        let relocation = |offset, symbol, r_type| Relocation {
+            offset,
+            symbol,
+            r_type,
+            addend: None,
+        };
+        assert_eq!(
+            object.relocations,
+            vec![
+                (
+                    1,
+                    vec![
+                        relocation(0, external, R_ARM_CALL),
+                        relocation(8, 3, R_ARM_MOVW_ABS_NC),
+                        relocation(12, start, R_ARM_JUMP24),
+                        relocation(16, external, R_ARM_THM_JUMP24),
+                    ]
+                ),
+                (
+                    2,
+                    vec![
+                        relocation(0, start, R_ARM_ABS32),
+                        relocation(4, external, R_ARM_ABS32),
+                        relocation(8, maybe, R_ARM_ABS32),
+                    ]
+                ),
+            ]
+        );
+        // The fields hold the addends
+        assert_eq!(
+            &assembler.text()[..8],
+            &[0xfe, 0xff, 0xff, 0xeb, 0x00, 0x00, 0x00, 0xea]
+        );
+        assert_eq!(&assembler.text()[16..], &[0xff, 0xf7, 0xfe, 0xbf]);
+        assert_eq!(
+            assembler.section(".data").unwrap(),
+            &[0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        assembler.assemble_line(".thumb").unwrap();
+        assert!(assembler.assemble_line("cbz r0, elsewhere").is_err());
+    }
+
+    #[test]
+    fn test_branch_targets() {
+        // `.` is the branch itself, and constants can be added to either
+        let program = [
+            "f: nop",
+            "b .",
+            "bl .",
+            "beq .",
+            "b f+4",
+            "b .+8",
+            "bl external+4",
+            "b f - 4",
+            ".thumb",
+            "b .",
+            "beq .",
+            "bl .",
+        ];
+        let assembler = assemble(Target::new(Arch::V7A), &program);
+        let words: Vec<u32> = assembler.text()[..32]
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        assert_eq!(
+            words,
+            [
+                0xE320_F000,
+                0xEAFF_FFFE,
+                0xEBFF_FFFE,
+                0x0AFF_FFFE,
+                0xEAFF_FFFB,
+                0xEA00_0000,
+                0xEBFF_FFFF,
+                0xEAFF_FFF6,
+            ]
+        );
+        assert_eq!(
+            &assembler.text()[32..],
+            &[0xfe, 0xe7, 0xfe, 0xd0, 0xff, 0xf7, 0xfe, 0xff]
+        );
+
+        let object = ObjectFile::parse(&assembler.to_object()).unwrap();
+        assert!(object.symbols.iter().all(|symbol| symbol.name != "."));
+        let (_, relocations) = &object.relocations[0];
+        assert_eq!(relocations.len(), 1);
+        assert_eq!(relocations[0].offset, 24);
+        assert_eq!(relocations[0].r_type, R_ARM_CALL);
+
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        assert!(assembler.layout(&["f: nop", "g: nop", "b f+g"]).is_err());
+    }
+
+    #[test]
+    fn test_branch_range_errors() {
+        // The target is named as written, with the reach of the encoding that was tried
+        let error = |target: Target, program: &[&str]| {
+            let mut assembler = Assembler::new(target);
+            assembler.layout(program).unwrap_err().to_string()
+        };
+        let mut m0 = Target::default();
+        m0.set_cpu("cortex-m0").unwrap();
+        assert_eq!(
+            error(m0.clone(), &["b far", ".space 3000", "far: nop"]),
+            "Parse Error: branch to `far` out of range (±2 KiB)"
+        );
+        assert_eq!(
+            error(m0, &["beq far", ".space 300", "far: nop"]),
+            "Parse Error: branch to `far` out of range (±256 bytes)"
+        );
+        let v7 = Target::new(Arch::V7A);
+        assert_eq!(
+            error(
+                v7.clone(),
+                &[".thumb", "b.n far+4", ".space 3000", "far: nop"]
+            ),
+            "Parse Error: branch to `far+4` out of range (±2 KiB)"
+        );
+        assert_eq!(
+            error(v7.clone(), &[".thumb", "back: nop", "cbz r0, back"]),
+            "Parse Error: branch to `back` out of range (0 to 126 bytes forward)"
+        );
+        assert_eq!(
+            error(v7, &["b 0x4000000"]),
+            "Parse Error: branch to `0x4000000` out of range (±32 MiB)"
+        );
+    }
+
+    #[test]
+    fn test_instruction_alignment() {
+        // Instructions after odd-sized data are padded out, and take the label with them
+        let program = [
+            ".ascii \"twenty-five bytes of text\"",
+            "main: b main",
+            ".thumb",
+            ".byte 1",
+            "loop: b loop",
+        ];
+        let assembler = assemble(Target::new(Arch::V7A), &program);
+        let text = assembler.text();
+        assert_eq!(&text[25..32], &[0, 0, 0, 0xfe, 0xff, 0xff, 0xea]);
+        assert_eq!(&text[32..], &[1, 0, 0xfe, 0xe7]);
+
+        let object = ObjectFile::parse(&assembler.to_object()).unwrap();
+        let value = |name| {
+            object
+                .symbols
+                .iter()
+                .find(|s| s.name == name)
+                .unwrap()
+                .value
+        };
+        assert_eq!(value("main"), 28);
+        assert_eq!(value("loop"), 34);
+    }
+
+    #[test]
+    fn test_endianness() {
+        let program = [
+            ".syntax unified",
+            "start: bl external",
+            ".thumb",
+            "bl external",
+            ".data",
+            ".word 0x12345678",
+            ".short 0x1234",
+        ];
+        let mut assembler = Assembler::new(Target::new(Arch::V4T));
+        assembler.set_endianness(Endianness::Be32).unwrap();
+        assert!(assembler.set_endianness(Endianness::Be8).is_err());
+        for line in program {
+            assembler.assemble_line(line).unwrap();
+        }
+        assert_eq!(
+            assembler.text(),
+            &[0xeb, 0xff, 0xff, 0xfe, 0xf7, 0xff, 0xff, 0xfe]
+        );
+        assert_eq!(
+            assembler.section(".data").unwrap(),
+            &[0x12, 0x34, 0x56, 0x78, 0x12, 0x34]
+        );
+        let object = ObjectFile::parse(&assembler.to_object()).unwrap();
+        assert_eq!(object.endianness, Endianness::Be32);
+        assert_eq!(object.relocations[0].1[1].r_type, R_ARM_THM_CALL);
+
+        // BE-8 keeps instructions little-endian
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        assembler.set_endianness(Endianness::Be8).unwrap();
+        for line in program {
+            assembler.assemble_line(line).unwrap();
+        }
+        assert_eq!(
+            assembler.text(),
+            &[0xfe, 0xff, 0xff, 0xeb, 0xff, 0xf7, 0xfe, 0xff]
+        );
+        assert_eq!(
+            assembler.section(".data").unwrap(),
+            &[0x12, 0x34, 0x56, 0x78, 0x12, 0x34]
+        );
+        let bytes = assembler.to_object();
+        assert_eq!(bytes[5], ELFDATA2MSB);
+        assert_eq!(
+            &bytes[36..40],
+            &(EF_ARM_EABI_VER5 | EF_ARM_BE8).to_be_bytes()
+        );
+        assert_eq!(
+            ObjectFile::parse(&bytes).unwrap().endianness,
+            Endianness::Be8
+        );
+    }
+
+    #[test]
+    fn test_equates_and_errors() {
+        let program = [".set SIZE, BASE + 4", "mov r0, #SIZE", ".word SIZE"];
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        assembler.define_symbol("BASE", 0x10);
+        assembler.layout(&program).unwrap();
+        for line in program {
+            assembler.assemble_line(line).unwrap();
+        }
+        assert_eq!(assembler.text(), &[0x14, 0x00, 0xa0, 0xe3, 0x14, 0, 0, 0]);
+
+        // Nothing that fails to parse is skipped
+        for line in [
+            "frobnicate r0",
+            "add r0, r1, r99",
+            "mov r0, #0x101",
+            "ldr r0, [r1, #5000]",
+            "ubfx r0, r1, #8, #0",
+            ".frob",
+            ".cfi_frob",
+        ] {
+            assert!(assembler.assemble_line(line).is_err(), "{line}");
+        }
+        assembler.assemble_line(".thumb").unwrap();
+        for line in ["frobnicate r0", "adds r0, r1, r99", "ldr.n r0, [r1, #5000]"] {
+            assert!(assembler.assemble_line(line).is_err(), "{line}");
+        }
+        assert_eq!(assembler.text().len(), 8);
+        assert!(assembler.warnings().is_empty());
+        // Warnings before a failed layout are kept for reporting
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        assert!(assembler.layout(&["ldr r0, [r0], #4", "frob"]).is_err());
+        assert_eq!(assembler.warnings().len(), 1);
+    }
+
+    #[test]
+    fn test_register_aliases() {
+        let mut assembler = assemble(
+            Target::new(Arch::V7A),
+            &[
+                ".fpu neon",
+                "count .req r2",
+                "acc .dn d3",
+                "vec .qn q1",
+                "sub count, count, #1",
+                "ldr r0, [COUNT, #4]",
+                "push {v1-v4, lr}",
+                "vadd.f64 acc, acc, d4",
+                "vadd.i32 vec, vec, q2",
+                ".unreq count",
+                "count .req a1",
+                "add count, count, sb",
+                ".thumb",
+                "adds count, count, #1",
+            ],
+        );
+        assert_eq!(
+            assembler.text(),
+            &[
+                0x01, 0x20, 0x42, 0xe2, 0x04, 0x00, 0x92, 0xe5, 0xf0, 0x40, 0x2d, 0xe9, 0x04, 0x3b,
+                0x33, 0xee, 0x44, 0x28, 0x22, 0xf2, 0x09, 0x00, 0x80, 0xe0, 0x40, 0x1c,
+            ]
+        );
+        assert!(assembler.warnings().is_empty());
+
+        assert!(assembler.assemble_line("count .req r1").is_err());
+        assert!(assembler.assemble_line("lr .req r1").is_err());
+        assert!(assembler.assemble_line("acc .dn s1").is_err());
+        assert!(assembler.assemble_line(".unreq total").is_err());
+
+        // Directives in any case, typed aliases, and a label that shares an alias's name
+        let assembler = assemble(
+            Target::new(Arch::V7A),
+            &[
+                ".fpu neon",
+                "count .REQ r2",
+                "vf .Qn q2.f32",
+                "vadd vf, vf, vf",
+                "b count",
+                "count: bx count",
+            ],
+        );
+        assert_eq!(
+            assembler.text(),
+            &[0x44, 0x4d, 0x04, 0xf2, 0xff, 0xff, 0xff, 0xea, 0x12, 0xff, 0x2f, 0xe1]
+        );
+    }
+
+    #[test]
+    fn test_unpredictable() {
+        let mut assembler = assemble(Target::new(Arch::V5TE), &["mul r0, r0, r1"]);
+        assert_eq!(
+            assembler.warnings(),
+            &[(
+                1,
+                "`mul r0, r0, r1` is UNPREDICTABLE: Rd and Rm must differ before ARMv6".to_owned()
+            )]
+        );
+        let err = assembler.assemble_line("mul pc, r0, r1").unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Parse Error: `mul pc, r0, r1` is UNPREDICTABLE: pc can't be an operand of a multiply"
+        );
+    }
+
+    #[test]
+    fn test_local_labels() {
+        let program = [
+            "1: b 1f",
+            ".Lloop: b 1b",
+            "1: b .Lloop",
+            "b 1b",
+            ".ascii \"1b\"",
+        ];
+        let assembler = assemble(Target::new(Arch::V7A), &program);
+        let words: Vec<u32> = assembler.text()[..16]
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        assert_eq!(words, [0xEA00_0000, 0xEAFF_FFFD, 0xEAFF_FFFD, 0xEAFF_FFFD]);
+        assert_eq!(&assembler.text()[16..], b"1b");
+        assert!(assembler.symbol_table().is_empty());
+        let object = ObjectFile::parse(&assembler.to_object()).unwrap();
+        assert!(!object.symbols.iter().any(|s| s.name.starts_with(".L")));
+
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        let error = assembler.layout(&["b 2b"]).unwrap_err();
+        assert_eq!(error.to_string(), "Parse Error: Undefined symbol 2b");
+        let error = assembler.layout(&["a:", "a:"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Parse Error: Symbol a is already defined"
+        );
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let mut source = Source::default();
+        source
+            .add_text("t.s", "start:\n  mov r0, #1\n\n  bx lr\n.data\n.word 1\n")
+            .unwrap();
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        assembler.enable_debug_info(&source);
+        Listing::assemble(&mut assembler, "t.s", &source).unwrap();
+        let object = ObjectFile::parse(&assembler.to_object()).unwrap();
+
+        let index = |name: &str| {
+            let index = object.sections.iter().position(|s| s.name == name);
+            index.unwrap() as u16
+        };
+        let line = &object.sections[index(".debug_line") as usize];
+        assert!(line.data.windows(4).any(|name| name == b"t.s\0"));
+        // Rows for lines 2 and 4, then the end of the sequence
+        assert!(line
+            .data
+            .ends_with(&[0x13, 0x4C, DW_LNS_ADVANCE_PC, 4, 0, 1, 1]));
+        for name in [".debug_info", ".debug_abbrev", ".debug_aranges"] {
+            index(name);
+        }
+        let relocations = |name| {
+            let section = index(name);
+            let relocations = object.relocations.iter().find(|(i, _)| *i == section);
+            relocations.unwrap().1.clone()
+        };
+        assert_eq!(relocations(".debug_line")[0].r_type, R_ARM_ABS32);
+        assert_eq!(relocations(".debug_info").len(), 4);
+
+        // A compiler's own .debug_info and .loc rows replace the generated ones
+        let program = [
+            ".file 1 \"src\" \"c.c\"",
+            ".loc 1 7 3 prologue_end",
+            "mov r0, #1",
+            "bx lr",
+            ".section .debug_info,\"\",%progbits",
+            ".word 0",
+        ];
+        let mut source = Source::default();
+        source.add_text("c.s", &program.join("\n")).unwrap();
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        assembler.enable_debug_info(&source);
+        Listing::assemble(&mut assembler, "c.s", &source).unwrap();
+        let object = ObjectFile::parse(&assembler.to_object()).unwrap();
+        let line = &object.sections[index(".debug_line") as usize];
+        assert!(line.data.windows(8).any(|name| name == b"src/c.c\0"));
+        assert!(line
+            .data
+            .ends_with(&[DW_LNS_SET_COLUMN, 3, 0x18, DW_LNS_ADVANCE_PC, 8, 0, 1, 1]));
+        let debug_info = object.sections.iter().filter(|s| s.name == ".debug_info");
+        assert_eq!(debug_info.count(), 1);
+        assert!(!object.sections.iter().any(|s| s.name == ".debug_aranges"));
+    }
+
+    #[test]
+    fn test_cfi_directives() {
+        let program = [
+            ".cfi_sections .eh_frame",
+            "f:",
+            ".cfi_startproc",
+            "sub sp, sp, #8",
+            ".cfi_def_cfa_offset 8",
+            ".cfi_rel_offset d8, 0",
+            "bx lr",
+            ".cfi_endproc",
+        ];
+        let assembler = assemble(Target::new(Arch::V7A), &program);
+        let object = ObjectFile::parse(&assembler.to_object()).unwrap();
+        let eh_frame = object.sections.iter().find(|s| s.name == ".eh_frame");
+        // After the CIE and the FDE's header, d8 is saved at CFA - 8
+        let instructions = [0x42, 0x0E, 8, 0x05, 0x88, 0x02, 2];
+        assert_eq!(eh_frame.unwrap().data[20 + 17..], instructions);
+        assert!(object.sections.iter().all(|s| s.name != ".debug_frame"));
+
+        for program in [
+            &[".cfi_def_cfa_offset 8"][..],
+            &[".cfi_startproc", ".cfi_startproc"],
+            &[".cfi_startproc", ".cfi_offset r4, -6"],
+            &[".cfi_startproc", ".cfi_offset r99, -8"],
+        ] {
+            let mut assembler = Assembler::new(Target::new(Arch::V7A));
+            assert!(assembler.layout(program).is_err(), "{program:?}");
+        }
+    }
+
+    #[test]
+    fn test_unwind_tables() {
+        let program = [
+            "f:",
+            ".fnstart",
+            ".save {r4-r7, lr}",
+            ".pad #16",
+            "bx lr",
+            ".fnend",
+            "g:",
+            ".fnstart",
+            ".cantunwind",
+            "bx lr",
+            ".fnend",
+            "h:",
+            ".fnstart",
+            ".personality __gxx_personality_v0",
+            "bx lr",
+            ".handlerdata",
+            ".word 7",
+            ".fnend",
+            "bx lr",
+        ];
+        let assembler = assemble(Target::new(Arch::V7A), &program);
+        assert_eq!(assembler.current_section(), ".text");
+        let object = ObjectFile::parse(&assembler.to_object()).unwrap();
+        let index = |name: &str| object.sections.iter().position(|s| s.name == name).unwrap();
+        let exidx = &object.sections[index(".ARM.exidx")];
+        assert_eq!(exidx.sh_type, SHT_ARM_EXIDX);
+        assert_eq!(exidx.link, 1);
+        let words: Vec<u32> = exidx
+            .data
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        assert_eq!(words, [0, 0x8003_ABB0, 4, EXIDX_CANTUNWIND, 8, 0]);
+        let extab = &object.sections[index(".ARM.extab")];
+        assert_eq!(extab.data, [0, 0, 0, 0, 0xB0, 0xB0, 0xB0, 0, 7, 0, 0, 0]);
+
+        let (_, relocations) = object
+            .relocations
+            .iter()
+            .find(|(section, _)| usize::from(*section) == index(".ARM.exidx"))
+            .unwrap();
+        let types: Vec<u8> = relocations.iter().map(|r| r.r_type).collect();
+        assert_eq!(
+            types,
+            [
+                R_ARM_NONE,
+                R_ARM_PREL31,
+                R_ARM_PREL31,
+                R_ARM_PREL31,
+                R_ARM_PREL31
+            ]
+        );
+        let routine = &object.symbols[relocations[0].symbol as usize];
+        assert_eq!(routine.name, "__aeabi_unwind_cpp_pr0");
+
+        for program in [
+            &[".fnend"][..],
+            &[".save {r4}"],
+            &[".fnstart", ".fnstart"],
+            &[".fnstart", ".cantunwind", ".personality p", ".fnend"],
+            &[".fnstart", ".vsave {s0-s1}", ".fnend"],
+        ] {
+            let mut assembler = Assembler::new(Target::new(Arch::V7A));
+            assert!(assembler.layout(program).is_err(), "{program:?}");
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Builds the `.ARM.attributes` section describing what a [`Target`] requires, and reads back
+//! what an object's section says.
+
+use std::collections::HashMap;
+
+use crate::{
+    arch::{Feature, Fpu, Target},
+    elf::Endianness,
+};
+
+const FORMAT_VERSION: u8 = b'A';
+const VENDOR: &[u8] = b"aeabi\0";
+
+const TAG_FILE: u8 = 1;
+const TAG_CPU_RAW_NAME: u8 = 4;
+const TAG_CPU_NAME: u8 = 5;
+pub(crate) const TAG_CPU_ARCH: u8 = 6;
+const TAG_CPU_ARCH_PROFILE: u8 = 7;
+pub(crate) const TAG_ARM_ISA_USE: u8 = 8;
+pub(crate) const TAG_THUMB_ISA_USE: u8 = 9;
+const TAG_FP_ARCH: u8 = 10;
+const TAG_ADVANCED_SIMD_ARCH: u8 = 12;
+const TAG_ABI_HARDFP_USE: u8 = 27;
+const TAG_COMPATIBILITY: u8 = 32;
+const TAG_CONFORMANCE: u8 = 67;
+const TAG_MPEXTENSION_USE: u8 = 42;
+const TAG_DIV_USE: u8 = 44;
+const TAG_VIRTUALIZATION_USE: u8 = 68;
+
+pub(crate) fn push_uleb128(buf: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn read_uleb128(data: &[u8], offset: &mut usize) -> Option<u32> {
+    let mut value = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = *data.get(*offset)?;
+        *offset += 1;
+        value |= u32::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Decode the public `aeabi` attributes that apply to the whole file from the contents of an
+/// `.ARM.attributes` section. Only those with numeric values are kept.
+pub fn read_attributes(data: &[u8], endianness: Endianness) -> HashMap<u8, u32> {
+    let mut attributes = HashMap::new();
+    if data.first() != Some(&FORMAT_VERSION) {
+        return attributes;
+    }
+
+    let mut start = 1;
+    while let Some(size) = endianness.read_u32(data, start) {
+        let end = (start + size as usize).min(data.len());
+        let section = &data[start + 4..end.max(start + 4)];
+        start = end.max(start + 4);
+        let Some(subsections) = section.strip_prefix(VENDOR) else {
+            continue;
+        };
+
+        let mut offset = 0;
+        while let (Some(&tag), Some(size)) = (
+            subsections.get(offset),
+            endianness.read_u32(subsections, offset + 1),
+        ) {
+            let end = (offset + size as usize).min(subsections.len());
+            if tag == TAG_FILE {
+                read_file_attributes(&subsections[offset + 5..end], &mut attributes);
+            }
+            offset = end.max(offset + 5);
+        }
+    }
+    attributes
+}
+
+fn read_file_attributes(data: &[u8], attributes: &mut HashMap<u8, u32>) -> Option<()> {
+    let skip_string = |offset: &mut usize| {
+        let length = data[*offset..].iter().position(|&byte| byte == 0)?;
+        *offset += length + 1;
+        Some(())
+    };
+
+    let mut offset = 0;
+    while offset < data.len() {
+        let tag = read_uleb128(data, &mut offset)?;
+        // Which tags take strings is fixed by the ABI, so that unknown ones can be skipped
+        let string = [TAG_CPU_RAW_NAME, TAG_CPU_NAME, TAG_CONFORMANCE]
+            .map(u32::from)
+            .contains(&tag)
+            || (tag > u32::from(TAG_COMPATIBILITY) && tag % 2 == 1);
+        match tag {
+            _ if string => skip_string(&mut offset)?,
+            _ if tag == u32::from(TAG_COMPATIBILITY) => {
+                read_uleb128(data, &mut offset)?;
+                skip_string(&mut offset)?;
+            }
+            _ => {
+                let value = read_uleb128(data, &mut offset)?;
+                if let Ok(tag) = u8::try_from(tag) {
+                    attributes.insert(tag, value);
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+/// Encode the public `aeabi` attributes for `target`, in ascending tag order.
+pub fn build_attributes(target: &Target, endianness: Endianness) -> Vec<u8> {
+    let mut attributes = vec![];
+    let mut push_tag = |tag: u8, value: u32| {
+        push_uleb128(&mut attributes, tag.into());
+        push_uleb128(&mut attributes, value);
+    };
+
+    let cpu_name = target.cpu.as_ref().map(|cpu| cpu.to_uppercase());
+
+    push_tag(TAG_CPU_ARCH, target.arch.cpu_arch_tag().into());
+    if let Some(profile) = target.arch.profile() {
+        push_tag(TAG_CPU_ARCH_PROFILE, profile.into());
+    }
+    push_tag(TAG_ARM_ISA_USE, target.supports(Feature::Arm).into());
+
+    let thumb_isa = if target.supports(Feature::Thumb2) {
+        2
+    } else if target.supports(Feature::V4T) {
+        1
+    } else {
+        0
+    };
+    push_tag(TAG_THUMB_ISA_USE, thumb_isa);
+
+    if target.fpu != Fpu::None {
+        push_tag(TAG_FP_ARCH, target.fpu.fp_arch_tag().into());
+    }
+    if target.supports(Feature::Neon) {
+        // NEONv2 adds the fused multiply-accumulate instructions from VFPv4
+        let simd_arch = if target.supports(Feature::Vfpv4) {
+            2
+        } else {
+            1
+        };
+        push_tag(TAG_ADVANCED_SIMD_ARCH, simd_arch);
+    }
+    if target.fpu != Fpu::None && !target.supports(Feature::Fp64) {
+        // Single precision only
+        push_tag(TAG_ABI_HARDFP_USE, 1);
+    }
+    if target.extensions.contains(&Feature::Mp) {
+        push_tag(TAG_MPEXTENSION_USE, 1);
+    }
+    if target.extensions.contains(&Feature::Idiv) {
+        push_tag(TAG_DIV_USE, 2);
+    }
+
+    let virtualization_use = match (
+        target.supports(Feature::Security),
+        target.supports(Feature::Virtualization),
+    ) {
+        (false, false) => 0,
+        (true, false) => 1,
+        (false, true) => 2,
+        (true, true) => 3,
+    };
+    if virtualization_use != 0 {
+        push_tag(TAG_VIRTUALIZATION_USE, virtualization_use);
+    }
+
+    // Tag_CPU_name is a string, and goes first
+    let mut file_attributes = vec![];
+    if let Some(cpu_name) = cpu_name {
+        file_attributes.push(TAG_CPU_NAME);
+        file_attributes.extend_from_slice(cpu_name.as_bytes());
+        file_attributes.push(0);
+    }
+    file_attributes.extend(attributes);
+
+    // <format-version> [ <section-length> "vendor-name" [ <file-tag> <size> <attribute>* ] ]
+    let file_size = 1 + 4 + file_attributes.len() as u32;
+    let section_size = 4 + VENDOR.len() as u32 + file_size;
+
+    let mut out = vec![FORMAT_VERSION];
+    out.extend_from_slice(&endianness.u32_bytes(section_size));
+    out.extend_from_slice(VENDOR);
+    out.push(TAG_FILE);
+    out.extend_from_slice(&endianness.u32_bytes(file_size));
+    out.extend(file_attributes);
+
+    out
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::arch::Arch;
+
+    #[test]
+    fn test_attributes() {
+        let mut target = Target::new(Arch::V7A);
+        target.set_fpu("neon-vfpv4").unwrap();
+        let mut expected = vec![b'A', 0x1B, 0, 0, 0];
+        expected.extend_from_slice(b"aeabi\0");
+        expected.extend_from_slice(&[TAG_FILE, 0x11, 0, 0, 0]);
+        // v7, A profile, ARM and Thumb-2, VFPv4 and NEONv2
+        expected.extend_from_slice(&[6, 10, 7, b'A', 8, 1, 9, 2, 10, 5, 12, 2]);
+        assert_eq!(build_attributes(&target, Endianness::Little), expected);
+
+        // The CPU name comes first, and M-profile cores have no ARM state
+        let mut target = Target::default();
+        target.set_cpu("cortex-m3").unwrap();
+        let attributes = build_attributes(&target, Endianness::Little);
+        assert_eq!(
+            &attributes[16..],
+            b"\x05CORTEX-M3\0\x06\x0A\x07M\x08\x00\x09\x02"
+        );
+
+        let mut target = Target::new(Arch::V7M);
+        target.set_fpu("fpv4-sp-d16").unwrap();
+        let attributes =
+            read_attributes(&build_attributes(&target, Endianness::Be8), Endianness::Be8);
+        assert_eq!(attributes[&TAG_CPU_ARCH], 10);
+        assert_eq!(attributes[&TAG_ARM_ISA_USE], 0);
+        assert_eq!(attributes[&TAG_FP_ARCH], 6);
+        assert_eq!(attributes[&TAG_ABI_HARDFP_USE], 1);
+
+        let mut target = Target::default();
+        target.set_arch("armv7-a+mp+idiv+sec").unwrap();
+        let attributes = read_attributes(
+            &build_attributes(&target, Endianness::Little),
+            Endianness::Little,
+        );
+        assert_eq!(attributes[&TAG_MPEXTENSION_USE], 1);
+        assert_eq!(attributes[&TAG_DIV_USE], 2);
+        assert_eq!(attributes[&TAG_VIRTUALIZATION_USE], 1);
+        assert!(!attributes.contains_key(&TAG_FP_ARCH));
+    }
+}
@@ -0,0 +1,233 @@
+//! An assembler taking a subset of GNU as's options, so that it can stand in for
+//! `arm-none-eabi-as`:
+//!
+//! `arm-as [-o output] [-I dir] [--defsym symbol=value] [-march=arch] [-mcpu=cpu] [-mfpu=fpu]
+//! [-mthumb] [-EB|-EL] [-g] [-a[lhs][=file]] [--fatal-warnings] [-W] files...`
+//!
+//! The files are assembled one after the other as if they were a single file, reading standard
+//! input for `-` or when there are none. `-EB` produces BE-32 code, or BE-8 with `--be8`.
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use assembler::{
+    arch::Target,
+    assembler::{Assembler, InstructionSet},
+    elf::Endianness,
+    error::AssemblerError,
+    listing::{Listing, ListingOptions},
+    source::Source,
+};
+
+const USAGE: &str = "usage: arm-as [-o output] [-I dir] [--defsym symbol=value] [-march=arch] \
+                     [-mcpu=cpu] [-mfpu=fpu] [-mthumb] [-EB|-EL] [-g] [-a[lhs][=file]] \
+                     [--fatal-warnings] [-W] files...";
+
+const STDIN_NAME: &str = "{standard input}";
+
+fn parse_value(value: &str) -> Option<i64> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+    let magnitude = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+/// Parse the letters and file of a `-a` option. On its own it means `-ahls`.
+fn parse_listing(spec: &str) -> Result<(ListingOptions, Option<String>), String> {
+    let (letters, file) = match spec.split_once('=') {
+        Some((letters, file)) => (letters, Some(file.to_owned())),
+        None => (spec, None),
+    };
+    if let Some(letter) = letters.chars().find(|c| !"cdghlmns".contains(*c)) {
+        return Err(format!("unknown listing option {letter}"));
+    }
+    let letters = if letters.is_empty() { "hls" } else { letters };
+    let options = ListingOptions {
+        assembly: letters.contains('l'),
+        symbols: letters.contains('s'),
+    };
+    Ok((options, file))
+}
+
+fn set_target(result: Result<(), AssemblerError>) -> Result<(), String> {
+    result.map_err(|err| err.to_string())
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let mut target = Target::default();
+    let mut output = "a.out".to_owned();
+    let mut include_dirs = vec![];
+    let mut inputs = vec![];
+    let mut symbols = vec![];
+    let mut thumb = false;
+    let mut endianness = Endianness::Little;
+    let mut be8 = false;
+    let mut listing = None;
+    let mut fatal_warnings = false;
+    let mut warnings = true;
+    let mut debug = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{arg} needs a value"))
+        };
+        if arg == "-o" {
+            output = value()?;
+        } else if arg == "-I" {
+            include_dirs.push(PathBuf::from(value()?));
+        } else if let Some(dir) = arg.strip_prefix("-I") {
+            include_dirs.push(PathBuf::from(dir));
+        } else if arg == "--defsym" || arg.starts_with("--defsym=") {
+            let definition = match arg.strip_prefix("--defsym=") {
+                Some(definition) => definition.to_owned(),
+                None => value()?,
+            };
+            let (name, value) = definition
+                .split_once('=')
+                .ok_or_else(|| format!("bad --defsym {definition}"))?;
+            let value = parse_value(value).ok_or_else(|| format!("bad --defsym {definition}"))?;
+            symbols.push((name.to_owned(), value));
+        } else if let Some(arch) = arg.strip_prefix("-march=") {
+            set_target(target.set_arch(arch))?;
+        } else if let Some(cpu) = arg.strip_prefix("-mcpu=") {
+            set_target(target.set_cpu(cpu))?;
+        } else if let Some(fpu) = arg.strip_prefix("-mfpu=") {
+            set_target(target.set_fpu(fpu))?;
+        } else if arg == "-mthumb" {
+            thumb = true;
+        } else if arg == "-marm" {
+            thumb = false;
+        } else if arg == "-EB" || arg == "-mbig-endian" {
+            endianness = Endianness::Be32;
+        } else if arg == "-EL" || arg == "-mlittle-endian" {
+            endianness = Endianness::Little;
+        } else if arg == "--be8" {
+            be8 = true;
+        } else if arg == "-g" || arg == "--gdwarf-2" {
+            debug = true;
+        } else if let Some(spec) = arg.strip_prefix("-a") {
+            listing = Some(parse_listing(spec)?);
+        } else if arg == "--fatal-warnings" {
+            fatal_warnings = true;
+        } else if arg == "-W" || arg == "--no-warn" {
+            warnings = false;
+        } else if arg.starts_with('-') && arg != "-" {
+            return Err(format!("unknown option {arg}\n{USAGE}"));
+        } else {
+            inputs.push(arg.clone());
+        }
+    }
+    if inputs.is_empty() {
+        inputs.push("-".to_owned());
+    }
+
+    let mut source = Source::new(include_dirs);
+    for input in &inputs {
+        let result = if input == "-" {
+            let mut text = String::new();
+            io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|err| format!("{STDIN_NAME}: {err}"))?;
+            source.add_text(STDIN_NAME, &text)
+        } else {
+            source.add_file(input)
+        };
+        result.map_err(|err| format!("{input}: {err}"))?;
+    }
+
+    let mut assembler = Assembler::new(target);
+    if thumb {
+        assembler.set_instruction_set(InstructionSet::Thumb);
+    }
+    if endianness == Endianness::Be32 && be8 {
+        endianness = Endianness::Be8;
+    }
+    set_target(assembler.set_endianness(endianness))?;
+    for (name, value) in &symbols {
+        assembler.define_symbol(name, *value);
+    }
+    if debug {
+        assembler.enable_debug_info(&source);
+    }
+
+    let name = match inputs.as_slice() {
+        [input] if input != "-" => input.as_str(),
+        [_] => STDIN_NAME,
+        _ => "",
+    };
+    let result = Listing::assemble(&mut assembler, name, &source);
+    let location = |line| match source.location(line) {
+        Some(location) => format!("{location}: "),
+        None => String::new(),
+    };
+    // Warnings from before an error are still worth seeing
+    if warnings || fatal_warnings {
+        for (line, warning) in assembler.warnings() {
+            eprintln!("{}Warning: {warning}", location(*line));
+        }
+    }
+    let assembled =
+        result.map_err(|err| format!("{}Error: {err}", location(assembler.line_number())))?;
+
+    if fatal_warnings && !assembler.warnings().is_empty() {
+        return Err("warnings treated as errors".to_owned());
+    }
+
+    if let Some((options, file)) = listing {
+        let text = assembled.render(options);
+        match file {
+            Some(file) => fs::write(&file, text).map_err(|err| format!("{file}: {err}"))?,
+            None => print!("{text}"),
+        }
+    }
+    fs::write(&output, assembler.to_object()).map_err(|err| format!("{output}: {err}"))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("arm-as: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let dir = std::env::temp_dir().join(format!("arm-as-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let assemble = |text: &str| {
+            fs::write(path("in.s"), text).unwrap();
+            let args = ["-o", &path("out.o"), &path("in.s")].map(str::to_owned);
+            run(&args)
+        };
+
+        assert!(assemble("mov r0, #1\nbx lr\n").is_ok());
+        let err = assemble("mov r0, #1\nfrobnicate r0\n").unwrap_err();
+        assert!(err.contains("in.s:2: Error"), "{err}");
+        assert!(assemble("ldr r0, [r0], #4\n").is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
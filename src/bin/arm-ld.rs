@@ -0,0 +1,122 @@
+//! A static linker for ARM ELF objects, taking a subset of GNU ld's options:
+//!
+//! `arm-ld [-o output] [-e entry] [-T script] [-Ttext-segment=address] [--oformat format]
+//! objects...`
+//!
+//! Besides ELF, `--oformat` takes `binary`, `ihex` and `srec`. Raw binaries start at the lowest
+//! load address unless `--binary-base=address` says otherwise, with gaps filled with
+//! `--gap-fill=byte`.
+
+use std::{fs, process::ExitCode};
+
+use assembler::{
+    error::LinkError,
+    formats::{BinaryWriter, OutputFormat},
+    linker::Linker,
+    linker_script::LinkerScript,
+};
+
+const USAGE: &str = "usage: arm-ld [-o output] [-e entry] [-T script] [-Ttext-segment=address] \
+                     [--oformat format] [--binary-base=address] [--gap-fill=byte] objects...";
+
+fn parse_address(value: &str) -> Option<u32> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn read_script(path: &str) -> Result<LinkerScript, String> {
+    let source = fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+    LinkerScript::parse(&source).map_err(|err| format!("{path}: {err}"))
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let mut linker = Linker::new();
+    let mut output = "a.out".to_owned();
+    let mut inputs = vec![];
+    let mut format = OutputFormat::Elf;
+    let mut binary = BinaryWriter::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{arg} needs a value"))
+        };
+        if arg == "-o" {
+            output = value()?;
+        } else if arg == "-e" || arg == "--entry" {
+            linker.set_entry(&value()?);
+        } else if let Some(entry) = arg.strip_prefix("--entry=") {
+            linker.set_entry(entry);
+        } else if let Some(address) = arg.strip_prefix("-Ttext-segment=") {
+            let address = parse_address(address).ok_or_else(|| format!("bad address {address}"))?;
+            linker.set_base_address(address);
+        } else if arg == "-T" || arg == "--script" {
+            linker.set_script(read_script(&value()?)?);
+        } else if let Some(path) = arg
+            .strip_prefix("--script=")
+            .or_else(|| arg.strip_prefix("-T"))
+        {
+            linker.set_script(read_script(path)?);
+        } else if arg == "--oformat" {
+            format = value()?.parse().map_err(|err: LinkError| err.to_string())?;
+        } else if let Some(name) = arg.strip_prefix("--oformat=") {
+            format = name.parse().map_err(|err: LinkError| err.to_string())?;
+        } else if let Some(address) = arg.strip_prefix("--binary-base=") {
+            let address = parse_address(address).ok_or_else(|| format!("bad address {address}"))?;
+            binary.base_address = Some(address);
+        } else if let Some(fill) = arg.strip_prefix("--gap-fill=") {
+            binary.gap_fill = parse_address(fill)
+                .and_then(|fill| u8::try_from(fill).ok())
+                .ok_or_else(|| format!("bad gap fill {fill}"))?;
+        } else if arg.starts_with('-') {
+            return Err(format!("unknown option {arg}\n{USAGE}"));
+        } else {
+            inputs.push(arg.clone());
+        }
+    }
+    if inputs.is_empty() {
+        return Err(format!("no input files\n{USAGE}"));
+    }
+
+    for input in &inputs {
+        let bytes = fs::read(input).map_err(|err| format!("{input}: {err}"))?;
+        linker
+            .add_object(input, &bytes)
+            .map_err(|err| format!("{input}: {err}"))?;
+    }
+    if let OutputFormat::Binary(writer) = &mut format {
+        *writer = binary;
+    }
+    linker.set_output_format(format);
+    let image = linker.link().map_err(|err| err.to_string())?;
+    let executable = format == OutputFormat::Elf;
+    write_output(&output, &image, executable).map_err(|err| format!("{output}: {err}"))
+}
+
+fn write_output(path: &str, image: &[u8], executable: bool) -> Result<(), LinkError> {
+    fs::write(path, image)?;
+    #[cfg(unix)]
+    if executable {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("arm-ld: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Unsigned integers the width of the fields they're encoded into. They can only be made from
+//! values that fit, so encoding one never drops bits.
+
+macro_rules! bitfields {
+    ($($(#[$attr:meta])* $name:ident($repr:ty, $bits:literal)),*) => {$(
+        $(#[$attr])*
+        #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+        pub struct $name($repr);
+
+        impl $name {
+            pub const BITS: u32 = $bits;
+            pub const MAX: Self = Self(<$repr>::MAX >> (<$repr>::BITS - $bits));
+            pub const ZERO: Self = Self(0);
+
+            /// `value`, if it fits in the field.
+            pub const fn new(value: $repr) -> Option<Self> {
+                if value <= Self::MAX.0 {
+                    Some(Self(value))
+                } else {
+                    None
+                }
+            }
+
+            /// `value` in two's complement, if it fits in the field as a signed number.
+            pub const fn new_signed(value: i32) -> Option<Self> {
+                let limit = 1 << ($bits - 1);
+                if value >= -limit && value < limit {
+                    Some(Self((value as $repr) & Self::MAX.0))
+                } else {
+                    None
+                }
+            }
+
+            pub const fn get(self) -> $repr {
+                self.0
+            }
+
+            /// The field in the low bits of an encoding, ready to be shifted into place.
+            pub const fn bits(self) -> u32 {
+                self.0 as u32
+            }
+        }
+    )*};
+}
+
+bitfields!(
+    U3(u8, 3),
+    U4(u8, 4),
+    U5(u8, 5),
+    U6(u8, 6),
+    U8(u8, 8),
+    U11(u16, 11),
+    U12(u16, 12),
+    U24(u32, 24)
+);
+
+/// An ARM modified immediate: 8 bits rotated right by twice a 4-bit amount.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Imm8Rot {
+    imm8: u8,
+    rotation: U4,
+}
+
+impl Imm8Rot {
+    pub const fn new(imm8: u8, rotation: U4) -> Self {
+        Self { imm8, rotation }
+    }
+
+    /// The encoding of `value` with the smallest rotation, if it has one.
+    pub const fn encode(value: u32) -> Option<Self> {
+        let mut rotation = 0;
+        while rotation < 16 {
+            let imm8 = value.rotate_left(2 * rotation);
+            if imm8 <= 0xFF {
+                return Some(Self {
+                    imm8: imm8 as u8,
+                    rotation: U4(rotation as u8),
+                });
+            }
+            rotation += 1;
+        }
+        None
+    }
+
+    pub const fn imm8(self) -> u8 {
+        self.imm8
+    }
+
+    pub const fn rotation(self) -> U4 {
+        self.rotation
+    }
+
+    /// The value it stands for.
+    pub const fn value(self) -> u32 {
+        (self.imm8 as u32).rotate_right(2 * self.rotation.0 as u32)
+    }
+
+    /// The 12-bit `rotate:imm8` field.
+    pub const fn bits(self) -> u32 {
+        (self.rotation.bits() << 8) | self.imm8 as u32
+    }
+}
+
+/// The offset of an ARM branch target from the PC, which is a whole number of words within 32MB
+/// either way.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BranchOffset(U24);
+
+impl BranchOffset {
+    /// The offset of `bytes`, if a branch can reach that far.
+    pub const fn new(bytes: i64) -> Option<Self> {
+        if bytes % 4 != 0 || bytes < -(1 << 25) || bytes >= 1 << 25 {
+            return None;
+        }
+        // The word offset in 24-bit two's complement
+        Some(Self(U24(((bytes >> 2) as u32) & U24::MAX.0)))
+    }
+
+    pub const fn bytes(self) -> i32 {
+        // Sign extend from bit 23
+        ((self.0 .0 << 8) as i32) >> 6
+    }
+
+    /// The 24-bit `imm24` field.
+    pub const fn bits(self) -> u32 {
+        self.0.bits()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitfields() {
+        assert_eq!(U3::new(7), Some(U3::MAX));
+        assert_eq!(U3::new(8), None);
+        assert_eq!(U4::new(15).map(U4::bits), Some(15));
+        assert_eq!(U4::new(16), None);
+        assert_eq!(U5::new(31), Some(U5::MAX));
+        assert_eq!(U5::new(32), None);
+        assert_eq!(U6::new(63), Some(U6::MAX));
+        assert_eq!(U6::new(64), None);
+        assert_eq!(U8::MAX.get(), 0xFF);
+        assert_eq!(U11::new(0x7FF), Some(U11::MAX));
+        assert_eq!(U11::new(0x800), None);
+        assert_eq!(U12::new(0xFFF), Some(U12::MAX));
+        assert_eq!(U12::new(0x1000), None);
+        assert_eq!(U24::new(0xFF_FFFF), Some(U24::MAX));
+        assert_eq!(U24::new(0x100_0000), None);
+
+        assert_eq!(U8::new_signed(-1), Some(U8::MAX));
+        assert_eq!(U8::new_signed(-128).map(U8::bits), Some(0x80));
+        assert_eq!(U8::new_signed(127).map(U8::bits), Some(0x7F));
+        assert_eq!(U8::new_signed(128), None);
+        assert_eq!(U8::new_signed(-129), None);
+        assert_eq!(U11::new_signed(-1024).map(U11::bits), Some(0x400));
+        assert_eq!(U11::new_signed(1023).map(U11::bits), Some(0x3FF));
+        assert_eq!(U11::new_signed(1024), None);
+        assert_eq!(U11::new_signed(-1025), None);
+
+        let imm = Imm8Rot::encode(0x3F00).unwrap();
+        assert_eq!((imm.imm8(), imm.rotation().get()), (0x3F, 12));
+        assert_eq!(imm.value(), 0x3F00);
+        assert_eq!(imm.bits(), 0xC3F);
+        assert_eq!(Imm8Rot::encode(0xF000_000F).unwrap().bits(), 0x2FF);
+        assert_eq!(Imm8Rot::encode(0x101), None);
+
+        assert_eq!(BranchOffset::new(-8).unwrap().bits(), 0xFF_FFFE);
+        assert_eq!(BranchOffset::new(-8).unwrap().bytes(), -8);
+        assert_eq!(BranchOffset::new((1 << 25) - 4).unwrap().bits(), 0x7F_FFFF);
+        assert_eq!(BranchOffset::new(-(1 << 25)).unwrap().bytes(), -(1 << 25));
+        assert_eq!(BranchOffset::new(1 << 25), None);
+        assert_eq!(BranchOffset::new(6), None);
+    }
+}
@@ -0,0 +1,655 @@
+//! A fluent API for generating ARM code at run time, as a JIT does, without going through
+//! assembly source. Registers and operands are typed, branches can go to labels that are bound
+//! later, and everything is checked against the target when the code is finished.
+
+use crate::{
+    arch::{Feature, Target},
+    bitfield::{BranchOffset, Imm8Rot, U12},
+    cond::Cond,
+    elf::Endianness,
+    error::{AssemblerError, ParseError},
+    instructions::{
+        FlexibleOperand, IndexMode, Instruction, Offset, Rd, Rm, Rn, Rs, SetConditionCodes,
+        Severity, Shift, Unpredictable, UpDown,
+    },
+    mnemonics::{
+        BranchMnemonic, DataMnemonic, DivideMnemonic, HintMnemonic, MemoryMnemonic,
+        MoveWideMnemonic, MultiplyMnemonic,
+    },
+};
+
+/// A core register. Only `r0`-`r15` can be made, so encodings never see a bad register number.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Reg(u8);
+
+impl Reg {
+    /// `r{index}`, if there is one.
+    pub fn new(index: u8) -> Option<Self> {
+        (index < 16).then_some(Self(index))
+    }
+
+    pub fn index(self) -> u8 {
+        self.0
+    }
+}
+
+pub const R0: Reg = Reg(0);
+pub const R1: Reg = Reg(1);
+pub const R2: Reg = Reg(2);
+pub const R3: Reg = Reg(3);
+pub const R4: Reg = Reg(4);
+pub const R5: Reg = Reg(5);
+pub const R6: Reg = Reg(6);
+pub const R7: Reg = Reg(7);
+pub const R8: Reg = Reg(8);
+pub const R9: Reg = Reg(9);
+pub const R10: Reg = Reg(10);
+pub const R11: Reg = Reg(11);
+pub const R12: Reg = Reg(12);
+pub const R13: Reg = Reg(13);
+pub const R14: Reg = Reg(14);
+pub const R15: Reg = Reg(15);
+pub const FP: Reg = R11;
+pub const IP: Reg = R12;
+pub const SP: Reg = R13;
+pub const LR: Reg = R14;
+pub const PC: Reg = R15;
+
+/// The second operand of a data-processing instruction.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operand2 {
+    Register(Reg),
+    /// Checked to be an 8-bit value rotated right by an even amount when it is emitted.
+    Immediate(u32),
+}
+
+impl From<Reg> for Operand2 {
+    fn from(value: Reg) -> Self {
+        Self::Register(value)
+    }
+}
+
+/// An immediate second operand.
+pub fn imm(value: u32) -> Operand2 {
+    Operand2::Immediate(value)
+}
+
+/// The addressing of a load or store: a base register with an immediate or register offset.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MemOperand {
+    base: Reg,
+    offset: MemOffset,
+    mode: IndexMode,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum MemOffset {
+    Immediate(i32),
+    Register(Reg, UpDown),
+}
+
+/// `[base]`, which the methods of [`MemOperand`] add an offset and indexing to.
+pub fn mem(base: Reg) -> MemOperand {
+    MemOperand {
+        base,
+        offset: MemOffset::Immediate(0),
+        mode: IndexMode::Offset,
+    }
+}
+
+impl MemOperand {
+    /// `[base, #offset]`, checked to be within 4095 bytes either way when it is emitted.
+    pub fn offset(self, offset: i32) -> Self {
+        Self {
+            offset: MemOffset::Immediate(offset),
+            ..self
+        }
+    }
+
+    /// `[base, index]`
+    pub fn index(self, index: Reg) -> Self {
+        Self {
+            offset: MemOffset::Register(index, UpDown::Up),
+            ..self
+        }
+    }
+
+    /// `[base, -index]`
+    pub fn index_down(self, index: Reg) -> Self {
+        Self {
+            offset: MemOffset::Register(index, UpDown::Down),
+            ..self
+        }
+    }
+
+    /// `[base, offset]!`, writing the address back to the base register.
+    pub fn pre_index(self) -> Self {
+        Self {
+            mode: IndexMode::PreIndex,
+            ..self
+        }
+    }
+
+    /// `[base], offset`, adding the offset to the base register after the access.
+    pub fn post_index(self) -> Self {
+        Self {
+            mode: IndexMode::PostIndex,
+            ..self
+        }
+    }
+
+    fn encode(self) -> Result<Offset, AssemblerError> {
+        Ok(match self.offset {
+            MemOffset::Immediate(offset) => {
+                let imm = u16::try_from(offset.unsigned_abs())
+                    .ok()
+                    .and_then(U12::new)
+                    .ok_or_else(|| ParseError::BadImmediate(format!("#{offset}")))?;
+                let updown = if offset < 0 { UpDown::Down } else { UpDown::Up };
+                Offset::Immediate(imm, updown)
+            }
+            MemOffset::Register(index, updown) => {
+                Offset::RegisterWithShift(Rm(index.0), Shift::NONE, updown)
+            }
+        })
+    }
+}
+
+/// A place in the code for branches to go to, which may be bound after the branches are emitted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Label(usize);
+
+#[derive(Clone, Debug)]
+struct Item {
+    /// The mnemonic, for errors.
+    name: &'static str,
+    instruction: Instruction,
+    /// The label a branch goes to, which fills in its offset when the code is finished.
+    target: Option<Label>,
+}
+
+/// ARM code being generated for a target, with the labels its branches go to.
+#[derive(Debug)]
+pub struct CodeBuffer {
+    target: Target,
+    items: Vec<Item>,
+    /// Which item each label is bound to, by label.
+    labels: Vec<Option<usize>>,
+    /// The first mistake made, reported by [`CodeBuffer::finish`].
+    error: Option<AssemblerError>,
+}
+
+/// The instruction just emitted, which can still be made conditional or to set the flags.
+pub struct InstructionBuilder<'a> {
+    buffer: &'a mut CodeBuffer,
+    index: usize,
+}
+
+impl InstructionBuilder<'_> {
+    /// Only execute the instruction when `cond` holds.
+    pub fn cond(self, cond: Cond) -> Self {
+        let instruction = &mut self.buffer.items[self.index].instruction;
+        match instruction {
+            Instruction::DataProcessing(c, ..)
+            | Instruction::Mem(c, ..)
+            | Instruction::Branch(c, ..)
+            | Instruction::BranchExec(c, ..)
+            | Instruction::Mul(c, ..)
+            | Instruction::MoveWide(c, ..)
+            | Instruction::Divide(c, ..)
+            | Instruction::Hint(c, ..) => *c = cond,
+            _ => {
+                let name = self.buffer.items[self.index].name;
+                self.buffer
+                    .fail(ParseError::UnexpectedCond(name.to_owned()).into());
+            }
+        }
+        self
+    }
+
+    /// Update the condition flags from the result, as the `s` suffix does.
+    pub fn set_flags(self) -> Self {
+        let item = &mut self.buffer.items[self.index];
+        match &mut item.instruction {
+            Instruction::DataProcessing(_, _, flags, ..) | Instruction::Mul(_, _, flags, ..) => {
+                *flags = SetConditionCodes::SetCodes;
+            }
+            _ => {
+                let name = format!("{}s", item.name);
+                self.buffer.fail(ParseError::BadMnemonic(name).into());
+            }
+        }
+        self
+    }
+}
+
+macro_rules! data_processing {
+    ($($name:ident => $mnemonic:ident),* $(,)?) => {
+        $(
+            #[doc = concat!("`", stringify!($name), " rd, rn, op2`")]
+            pub fn $name(&mut self, rd: Reg, rn: Reg, op2: impl Into<Operand2>) -> InstructionBuilder<'_> {
+                self.data(stringify!($name), DataMnemonic::$mnemonic, rd, rn, op2.into())
+            }
+        )*
+    };
+}
+
+macro_rules! memory {
+    ($($name:ident => $mnemonic:ident),* $(,)?) => {
+        $(
+            #[doc = concat!("`", stringify!($name), " rt, [...]`")]
+            pub fn $name(&mut self, rt: Reg, address: MemOperand) -> InstructionBuilder<'_> {
+                self.memory(stringify!($name), MemoryMnemonic::$mnemonic, rt, address)
+            }
+        )*
+    };
+}
+
+impl CodeBuffer {
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            items: vec![],
+            labels: vec![],
+            error: None,
+        }
+    }
+
+    /// The offset in bytes of the next instruction.
+    pub fn offset(&self) -> usize {
+        4 * self.items.len()
+    }
+
+    /// A label to branch to, bound to a place with [`CodeBuffer::bind`].
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Make `label` refer to the next instruction.
+    pub fn bind(&mut self, label: Label) {
+        match self.labels.get_mut(label.0) {
+            Some(place @ None) => *place = Some(self.items.len()),
+            _ => self.fail(ParseError::DuplicateLabel(format!("label {}", label.0)).into()),
+        }
+    }
+
+    fn fail(&mut self, error: AssemblerError) {
+        self.error.get_or_insert(error);
+    }
+
+    fn push(&mut self, name: &'static str, instruction: Instruction) -> InstructionBuilder<'_> {
+        self.items.push(Item {
+            name,
+            instruction,
+            target: None,
+        });
+        InstructionBuilder {
+            index: self.items.len() - 1,
+            buffer: self,
+        }
+    }
+
+    fn operand2(&mut self, op2: Operand2) -> FlexibleOperand {
+        match op2 {
+            Operand2::Register(rm) => FlexibleOperand::RegisterWithShift(Rm(rm.0), Shift::NONE),
+            Operand2::Immediate(value) => match Imm8Rot::encode(value) {
+                Some(imm) => FlexibleOperand::ImmediateWithRotation(imm),
+                None => {
+                    self.fail(ParseError::BadImmediate(format!("#{value}")).into());
+                    FlexibleOperand::ImmediateWithRotation(Imm8Rot::default())
+                }
+            },
+        }
+    }
+
+    fn data(
+        &mut self,
+        name: &'static str,
+        mnemonic: DataMnemonic,
+        rd: Reg,
+        rn: Reg,
+        op2: Operand2,
+    ) -> InstructionBuilder<'_> {
+        let op2 = self.operand2(op2);
+        // Comparisons only set the flags
+        let flags = match mnemonic {
+            DataMnemonic::TST | DataMnemonic::TEQ | DataMnemonic::CMP | DataMnemonic::CMN => {
+                SetConditionCodes::SetCodes
+            }
+            _ => SetConditionCodes::DontSetCodes,
+        };
+        let instruction =
+            Instruction::DataProcessing(Cond::AL, mnemonic, flags, Rd(rd.0), Rn(rn.0), op2);
+        self.push(name, instruction)
+    }
+
+    fn memory(
+        &mut self,
+        name: &'static str,
+        mnemonic: MemoryMnemonic,
+        rt: Reg,
+        address: MemOperand,
+    ) -> InstructionBuilder<'_> {
+        let offset = address.encode().unwrap_or_else(|error| {
+            self.fail(error);
+            Offset::Immediate(U12::ZERO, UpDown::Up)
+        });
+        let instruction = Instruction::Mem(
+            Cond::AL,
+            mnemonic,
+            address.mode,
+            Rn(address.base.0),
+            Rd(rt.0),
+            offset,
+        );
+        self.push(name, instruction)
+    }
+
+    data_processing! {
+        and => AND,
+        eor => EOR,
+        sub => SUB,
+        rsb => RSB,
+        add => ADD,
+        adc => ADC,
+        sbc => SBC,
+        rsc => RSC,
+        orr => ORR,
+        bic => BIC,
+    }
+
+    memory! {
+        ldr => LDR,
+        ldrb => LDRB,
+        str => STR,
+        strb => STRB,
+    }
+
+    /// `mov rd, op2`
+    pub fn mov(&mut self, rd: Reg, op2: impl Into<Operand2>) -> InstructionBuilder<'_> {
+        self.data("mov", DataMnemonic::MOV, rd, R0, op2.into())
+    }
+
+    /// `mvn rd, op2`
+    pub fn mvn(&mut self, rd: Reg, op2: impl Into<Operand2>) -> InstructionBuilder<'_> {
+        self.data("mvn", DataMnemonic::MVN, rd, R0, op2.into())
+    }
+
+    /// `cmp rn, op2`
+    pub fn cmp(&mut self, rn: Reg, op2: impl Into<Operand2>) -> InstructionBuilder<'_> {
+        self.data("cmp", DataMnemonic::CMP, R0, rn, op2.into())
+    }
+
+    /// `cmn rn, op2`
+    pub fn cmn(&mut self, rn: Reg, op2: impl Into<Operand2>) -> InstructionBuilder<'_> {
+        self.data("cmn", DataMnemonic::CMN, R0, rn, op2.into())
+    }
+
+    /// `tst rn, op2`
+    pub fn tst(&mut self, rn: Reg, op2: impl Into<Operand2>) -> InstructionBuilder<'_> {
+        self.data("tst", DataMnemonic::TST, R0, rn, op2.into())
+    }
+
+    /// `teq rn, op2`
+    pub fn teq(&mut self, rn: Reg, op2: impl Into<Operand2>) -> InstructionBuilder<'_> {
+        self.data("teq", DataMnemonic::TEQ, R0, rn, op2.into())
+    }
+
+    /// `mul rd, rm, rs`
+    pub fn mul(&mut self, rd: Reg, rm: Reg, rs: Reg) -> InstructionBuilder<'_> {
+        let instruction = Instruction::Mul(
+            Cond::AL,
+            MultiplyMnemonic::MUL,
+            SetConditionCodes::DontSetCodes,
+            Rd(rd.0),
+            Rn(0),
+            Rs(rs.0),
+            Rm(rm.0),
+        );
+        self.push("mul", instruction)
+    }
+
+    /// `sdiv rd, rn, rm`
+    pub fn sdiv(&mut self, rd: Reg, rn: Reg, rm: Reg) -> InstructionBuilder<'_> {
+        let instruction =
+            Instruction::Divide(Cond::AL, DivideMnemonic::SDIV, Rd(rd.0), Rn(rn.0), Rm(rm.0));
+        self.push("sdiv", instruction)
+    }
+
+    /// `udiv rd, rn, rm`
+    pub fn udiv(&mut self, rd: Reg, rn: Reg, rm: Reg) -> InstructionBuilder<'_> {
+        let instruction =
+            Instruction::Divide(Cond::AL, DivideMnemonic::UDIV, Rd(rd.0), Rn(rn.0), Rm(rm.0));
+        self.push("udiv", instruction)
+    }
+
+    /// `movw rd, #imm16`
+    pub fn movw(&mut self, rd: Reg, imm16: u16) -> InstructionBuilder<'_> {
+        let instruction = Instruction::MoveWide(Cond::AL, MoveWideMnemonic::MOVW, Rd(rd.0), imm16);
+        self.push("movw", instruction)
+    }
+
+    /// `movt rd, #imm16`
+    pub fn movt(&mut self, rd: Reg, imm16: u16) -> InstructionBuilder<'_> {
+        let instruction = Instruction::MoveWide(Cond::AL, MoveWideMnemonic::MOVT, Rd(rd.0), imm16);
+        self.push("movt", instruction)
+    }
+
+    /// `b label`
+    pub fn b(&mut self, label: Label) -> InstructionBuilder<'_> {
+        self.branch("b", BranchMnemonic::B, label)
+    }
+
+    /// `bl label`
+    pub fn bl(&mut self, label: Label) -> InstructionBuilder<'_> {
+        self.branch("bl", BranchMnemonic::BL, label)
+    }
+
+    fn branch(
+        &mut self,
+        name: &'static str,
+        mnemonic: BranchMnemonic,
+        label: Label,
+    ) -> InstructionBuilder<'_> {
+        let builder = self.push(
+            name,
+            Instruction::Branch(Cond::AL, mnemonic, BranchOffset::default()),
+        );
+        builder.buffer.items[builder.index].target = Some(label);
+        builder
+    }
+
+    /// `bx rm`
+    pub fn bx(&mut self, rm: Reg) -> InstructionBuilder<'_> {
+        self.push("bx", Instruction::BranchExec(Cond::AL, Rn(rm.0)))
+    }
+
+    /// `nop`
+    pub fn nop(&mut self) -> InstructionBuilder<'_> {
+        self.push("nop", Instruction::Hint(Cond::AL, HintMnemonic::NOP))
+    }
+
+    /// The instructions that are UNPREDICTABLE on the target but will still be encoded, and why,
+    /// by their index in the code.
+    pub fn warnings(&self) -> Vec<(usize, String)> {
+        let mut warnings = vec![];
+        for (index, item) in self.items.iter().enumerate() {
+            let instruction = item.instruction.clone().for_target(&self.target);
+            if let Some(Unpredictable {
+                severity: Severity::Warning,
+                reason,
+            }) = instruction.unpredictable(&self.target)
+            {
+                warnings.push((index, format!("`{}` is UNPREDICTABLE: {reason}", item.name)));
+            }
+        }
+        warnings
+    }
+
+    /// Fill in the branches and encode everything, little-endian, checking that the target has
+    /// every instruction and that every label used was bound. Anything UNPREDICTABLE that is
+    /// still encoded is reported by [`CodeBuffer::warnings`] beforehand.
+    pub fn finish(self) -> Result<Vec<u8>, AssemblerError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        let mut code = vec![];
+        for (index, item) in self.items.into_iter().enumerate() {
+            let mut instruction = item.instruction.for_target(&self.target);
+            self.target.check(item.name, Feature::Arm)?;
+            for feature in instruction.required_features() {
+                self.target.check(item.name, feature)?;
+            }
+            if let Some(Unpredictable {
+                severity: Severity::Error,
+                reason,
+            }) = instruction.unpredictable(&self.target)
+            {
+                return Err(
+                    ParseError::Unpredictable(item.name.to_owned(), reason.to_owned()).into(),
+                );
+            }
+            if let (Instruction::Branch(_, _, offset), Some(label)) =
+                (&mut instruction, item.target)
+            {
+                let bound = self.labels[label.0]
+                    .ok_or_else(|| ParseError::UndefinedSymbol(format!("label {}", label.0)))?;
+                // Relative to the PC, which reads two instructions ahead
+                let delta = 4 * (bound as i64 - index as i64 - 2);
+                *offset = BranchOffset::new(delta).ok_or_else(|| {
+                    ParseError::BranchOutOfRange(format!("label {}", label.0), "±32 MiB")
+                })?;
+            }
+            code.extend(Endianness::Little.u32_bytes(instruction.to_machine_code()));
+        }
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::arch::Arch;
+
+    fn words(code: &[u8]) -> Vec<u32> {
+        code.chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    fn encode(source: &str) -> u32 {
+        Instruction::try_from(source).unwrap().to_machine_code()
+    }
+
+    #[test]
+    fn test_code_buffer() {
+        let mut asm = CodeBuffer::new(Target::new(Arch::V7A));
+        let top = asm.new_label();
+        let done = asm.new_label();
+        asm.bind(top);
+        asm.add(R4, R3, R5).cond(Cond::NE).set_flags();
+        asm.sub(R0, R0, imm(0x3F00));
+        asm.cmp(R0, imm(0));
+        asm.b(done).cond(Cond::EQ);
+        asm.ldr(R1, mem(SP).offset(8).pre_index());
+        asm.str(R1, mem(R2).offset(-4).post_index());
+        asm.ldrb(R1, mem(R2).index(R3));
+        asm.b(top);
+        asm.bind(done);
+        asm.mul(R0, R1, R2);
+        asm.movw(R0, 0x1234);
+        asm.bx(LR);
+
+        assert_eq!(
+            words(&asm.finish().unwrap()),
+            [
+                0x1093_4005,
+                0xE240_0C3F,
+                0xE350_0000,
+                0x0A00_0003,
+                0xE5BD_1008,
+                0xE402_1004,
+                0xE7D2_1003,
+                0xEAFF_FFF7,
+                encode("mul r0, r1, r2"),
+                encode("movw r0, #0x1234"),
+                encode("bx lr"),
+            ]
+        );
+
+        // Targets without the hints get the `mov r0, r0` that GNU as uses for nop
+        let mut asm = CodeBuffer::new(Target::new(Arch::V5TE));
+        asm.nop();
+        assert_eq!(words(&asm.finish().unwrap()), [0xE1A0_0000]);
+    }
+
+    #[test]
+    fn test_code_buffer_errors() {
+        let finish = |build: &dyn Fn(&mut CodeBuffer)| {
+            let mut asm = CodeBuffer::new(Target::new(Arch::V6K));
+            build(&mut asm);
+            asm.finish().unwrap_err().to_string()
+        };
+
+        assert_eq!(
+            finish(&|asm| {
+                asm.mov(R0, imm(0x101));
+            }),
+            "Parse Error: Bad immediate #257"
+        );
+        assert_eq!(
+            finish(&|asm| {
+                asm.ldr(R0, mem(R1).offset(4096));
+            }),
+            "Parse Error: Bad immediate #4096"
+        );
+        assert_eq!(
+            finish(&|asm| {
+                let label = asm.new_label();
+                asm.b(label);
+            }),
+            "Parse Error: Undefined symbol label 0"
+        );
+        assert_eq!(
+            finish(&|asm| {
+                asm.bx(LR).set_flags();
+            }),
+            "Parse Error: Failed to parse mnemonic from initial token: bxs"
+        );
+        assert_eq!(
+            finish(&|asm| {
+                asm.mul(PC, R0, R1);
+            }),
+            "Parse Error: `mul` is UNPREDICTABLE: pc can't be an operand of a multiply"
+        );
+        assert!(finish(&|asm| {
+            asm.movw(R0, 1);
+        })
+        .starts_with("`movw` requires"));
+        assert_eq!(Reg::new(16), None);
+
+        // Everything the buffer builds is ARM, which M-profile cores don't have
+        let mut asm = CodeBuffer::new(Target::new(Arch::V7M));
+        asm.add(R0, R0, R1);
+        assert!(asm
+            .finish()
+            .unwrap_err()
+            .to_string()
+            .starts_with("`add` requires ARM state"));
+
+        // What's only a warning is still encoded, and reported by the instruction it was on
+        let mut asm = CodeBuffer::new(Target::new(Arch::V5TE));
+        asm.mov(R0, R1);
+        asm.mul(R0, R0, R1);
+        assert_eq!(
+            asm.warnings(),
+            [(
+                1,
+                "`mul` is UNPREDICTABLE: Rd and Rm must differ before ARMv6".to_owned()
+            )]
+        );
+        assert_eq!(words(&asm.finish().unwrap()), [0xE1A0_0001, 0xE000_0190]);
+    }
+}
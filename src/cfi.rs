@@ -0,0 +1,502 @@
+//! Call frame information from the `.cfi_*` directives: how to find each function's caller at
+//! every point in it, as an FDE per function sharing a CIE, in `.debug_frame` or `.eh_frame`.
+
+use crate::{
+    attributes::push_uleb128,
+    dwarf::{push_sleb128, DebugSection, Target},
+    elf::{Endianness, SHF_ALLOC},
+    instructions::parse_reg_id,
+    vfp::VfpRegister,
+};
+
+/// Instructions are at least 2 bytes.
+const CODE_ALIGNMENT_FACTOR: u32 = 2;
+/// Registers are saved a word apart.
+const DATA_ALIGNMENT_FACTOR: i64 = -4;
+const VERSION: u8 = 1;
+const SP: u32 = 13;
+pub const LR: u32 = 14;
+
+const DW_CFA_ADVANCE_LOC: u8 = 0x40;
+const DW_CFA_OFFSET: u8 = 0x80;
+const DW_CFA_RESTORE: u8 = 0xC0;
+const DW_CFA_NOP: u8 = 0x00;
+const DW_CFA_ADVANCE_LOC1: u8 = 0x02;
+const DW_CFA_ADVANCE_LOC2: u8 = 0x03;
+const DW_CFA_ADVANCE_LOC4: u8 = 0x04;
+const DW_CFA_OFFSET_EXTENDED: u8 = 0x05;
+const DW_CFA_RESTORE_EXTENDED: u8 = 0x06;
+const DW_CFA_UNDEFINED: u8 = 0x07;
+const DW_CFA_SAME_VALUE: u8 = 0x08;
+const DW_CFA_REGISTER: u8 = 0x09;
+const DW_CFA_REMEMBER_STATE: u8 = 0x0A;
+const DW_CFA_RESTORE_STATE: u8 = 0x0B;
+const DW_CFA_DEF_CFA: u8 = 0x0C;
+const DW_CFA_DEF_CFA_REGISTER: u8 = 0x0D;
+const DW_CFA_DEF_CFA_OFFSET: u8 = 0x0E;
+const DW_CFA_OFFSET_EXTENDED_SF: u8 = 0x11;
+const DW_CFA_DEF_CFA_SF: u8 = 0x12;
+const DW_CFA_DEF_CFA_OFFSET_SF: u8 = 0x13;
+const DW_CFA_VAL_OFFSET: u8 = 0x14;
+const DW_CFA_VAL_OFFSET_SF: u8 = 0x15;
+
+/// `.eh_frame` addresses are 4 bytes relative to the field holding them.
+const DW_EH_PE_PCREL_SDATA4: u8 = 0x1B;
+
+/// Which sections `.cfi_sections` asked for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CfiSections {
+    pub eh_frame: bool,
+    pub debug_frame: bool,
+}
+
+impl Default for CfiSections {
+    fn default() -> Self {
+        Self {
+            eh_frame: false,
+            debug_frame: true,
+        }
+    }
+}
+
+/// A rule from one of the `.cfi_*` directives, with registers numbered as DWARF does for ARM.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CfiInstruction {
+    DefCfa(u32, i64),
+    DefCfaRegister(u32),
+    DefCfaOffset(i64),
+    AdjustCfaOffset(i64),
+    /// Saved at an offset from the CFA.
+    Offset(u32, i64),
+    /// Saved at an offset from the register the CFA is currently based on.
+    RelOffset(u32, i64),
+    /// Its value is the CFA plus an offset.
+    ValOffset(u32, i64),
+    Register(u32, u32),
+    Restore(u32),
+    Undefined(u32),
+    SameValue(u32),
+    RememberState,
+    RestoreState,
+    Escape(Vec<u8>),
+}
+
+/// The call frame information for one function, between `.cfi_startproc` and `.cfi_endproc`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Frame {
+    /// The assembler's index for the code section it is in.
+    pub section: usize,
+    pub start: u32,
+    pub end: u32,
+    /// Whether `.cfi_startproc simple` left out the initial rule that the CFA is SP.
+    pub simple: bool,
+    pub signal_frame: bool,
+    pub return_column: u32,
+    order: Endianness,
+    instructions: Vec<u8>,
+    /// The address the instructions so far describe.
+    location: u32,
+    cfa_offset: i64,
+    /// CFA offsets saved by `.cfi_remember_state`.
+    remembered: Vec<i64>,
+}
+
+impl Frame {
+    pub fn new(section: usize, start: u32, simple: bool, order: Endianness) -> Self {
+        Self {
+            section,
+            start,
+            end: start,
+            simple,
+            signal_frame: false,
+            return_column: LR,
+            order,
+            instructions: vec![],
+            location: start,
+            cfa_offset: 0,
+            remembered: vec![],
+        }
+    }
+
+    /// Add `instruction`, taking effect at `address`. Returns `None` if it can't be encoded, such
+    /// as an offset that isn't a whole number of words or a `.cfi_restore_state` with nothing
+    /// remembered.
+    pub fn push(&mut self, address: u32, instruction: CfiInstruction) -> Option<()> {
+        self.advance_to(address);
+        let data = &mut self.instructions;
+        match instruction {
+            CfiInstruction::DefCfa(register, offset) => {
+                match u32::try_from(offset) {
+                    Ok(offset) => {
+                        data.push(DW_CFA_DEF_CFA);
+                        push_uleb128(data, register);
+                        push_uleb128(data, offset);
+                    }
+                    Err(_) => {
+                        data.push(DW_CFA_DEF_CFA_SF);
+                        push_uleb128(data, register);
+                        push_sleb128(data, factor(offset)?);
+                    }
+                }
+                self.cfa_offset = offset;
+            }
+            CfiInstruction::DefCfaRegister(register) => {
+                data.push(DW_CFA_DEF_CFA_REGISTER);
+                push_uleb128(data, register);
+            }
+            CfiInstruction::DefCfaOffset(offset) => self.def_cfa_offset(offset)?,
+            CfiInstruction::AdjustCfaOffset(delta) => {
+                self.def_cfa_offset(self.cfa_offset + delta)?;
+            }
+            CfiInstruction::Offset(register, offset) => {
+                let factored = factor(offset)?;
+                match u32::try_from(factored) {
+                    Ok(factored) if register < 64 => {
+                        data.push(DW_CFA_OFFSET | register as u8);
+                        push_uleb128(data, factored);
+                    }
+                    Ok(factored) => {
+                        data.push(DW_CFA_OFFSET_EXTENDED);
+                        push_uleb128(data, register);
+                        push_uleb128(data, factored);
+                    }
+                    Err(_) => {
+                        data.push(DW_CFA_OFFSET_EXTENDED_SF);
+                        push_uleb128(data, register);
+                        push_sleb128(data, factored);
+                    }
+                }
+            }
+            CfiInstruction::RelOffset(register, offset) => {
+                let offset = offset - self.cfa_offset;
+                return self.push(address, CfiInstruction::Offset(register, offset));
+            }
+            CfiInstruction::ValOffset(register, offset) => {
+                let factored = factor(offset)?;
+                match u32::try_from(factored) {
+                    Ok(factored) => {
+                        data.push(DW_CFA_VAL_OFFSET);
+                        push_uleb128(data, register);
+                        push_uleb128(data, factored);
+                    }
+                    Err(_) => {
+                        data.push(DW_CFA_VAL_OFFSET_SF);
+                        push_uleb128(data, register);
+                        push_sleb128(data, factored);
+                    }
+                }
+            }
+            CfiInstruction::Register(register, saved_in) => {
+                data.push(DW_CFA_REGISTER);
+                push_uleb128(data, register);
+                push_uleb128(data, saved_in);
+            }
+            CfiInstruction::Restore(register) if register < 64 => {
+                data.push(DW_CFA_RESTORE | register as u8);
+            }
+            CfiInstruction::Restore(register) => {
+                data.push(DW_CFA_RESTORE_EXTENDED);
+                push_uleb128(data, register);
+            }
+            CfiInstruction::Undefined(register) => {
+                data.push(DW_CFA_UNDEFINED);
+                push_uleb128(data, register);
+            }
+            CfiInstruction::SameValue(register) => {
+                data.push(DW_CFA_SAME_VALUE);
+                push_uleb128(data, register);
+            }
+            CfiInstruction::RememberState => {
+                data.push(DW_CFA_REMEMBER_STATE);
+                self.remembered.push(self.cfa_offset);
+            }
+            CfiInstruction::RestoreState => {
+                self.cfa_offset = self.remembered.pop()?;
+                data.push(DW_CFA_RESTORE_STATE);
+            }
+            CfiInstruction::Escape(bytes) => data.extend(bytes),
+        }
+        Some(())
+    }
+
+    fn def_cfa_offset(&mut self, offset: i64) -> Option<()> {
+        match u32::try_from(offset) {
+            Ok(unsigned) => {
+                self.instructions.push(DW_CFA_DEF_CFA_OFFSET);
+                push_uleb128(&mut self.instructions, unsigned);
+            }
+            Err(_) => {
+                self.instructions.push(DW_CFA_DEF_CFA_OFFSET_SF);
+                push_sleb128(&mut self.instructions, factor(offset)?);
+            }
+        }
+        self.cfa_offset = offset;
+        Some(())
+    }
+
+    /// Move the location the following instructions apply from up to `address`.
+    fn advance_to(&mut self, address: u32) {
+        let delta = address.saturating_sub(self.location) / CODE_ALIGNMENT_FACTOR;
+        if delta == 0 {
+            return;
+        }
+        self.location += delta * CODE_ALIGNMENT_FACTOR;
+        let data = &mut self.instructions;
+        if delta < 0x40 {
+            data.push(DW_CFA_ADVANCE_LOC | delta as u8);
+        } else if let Ok(delta) = u8::try_from(delta) {
+            data.extend([DW_CFA_ADVANCE_LOC1, delta]);
+        } else if let Ok(delta) = u16::try_from(delta) {
+            data.push(DW_CFA_ADVANCE_LOC2);
+            data.extend(self.order.u16_bytes(delta));
+        } else {
+            data.push(DW_CFA_ADVANCE_LOC4);
+            data.extend(self.order.u32_bytes(delta));
+        }
+    }
+
+    /// The CIE fields that frames can differ in, so that frames that agree can share one.
+    fn cie_key(&self) -> (bool, bool, u32) {
+        (self.simple, self.signal_frame, self.return_column)
+    }
+}
+
+/// The DWARF number of a core or VFP register, which can also be given as a number.
+pub fn parse_register(value: &str) -> Option<u32> {
+    if let Ok(number) = value.parse() {
+        return Some(number);
+    }
+    match VfpRegister::try_from(value) {
+        Ok(VfpRegister::Single(number)) => Some(64 + u32::from(number)),
+        Ok(VfpRegister::Double(number)) => Some(256 + u32::from(number)),
+        Err(_) => parse_reg_id(value)
+            .ok()
+            .filter(|register| *register < 16)
+            .map(u32::from),
+    }
+}
+
+/// An offset in bytes as a multiple of [`DATA_ALIGNMENT_FACTOR`].
+fn factor(offset: i64) -> Option<i64> {
+    (offset % DATA_ALIGNMENT_FACTOR == 0).then_some(offset / DATA_ALIGNMENT_FACTOR)
+}
+
+/// Pad a record that starts at `start` with no-ops to a whole number of words.
+fn pad(section: &mut DebugSection, start: usize) {
+    let end = start + (section.data.len() - start).next_multiple_of(4);
+    section.data.resize(end, DW_CFA_NOP);
+}
+
+/// `.debug_frame`, for debuggers.
+pub fn debug_frame(frames: &[Frame], order: Endianness) -> DebugSection {
+    build(DebugSection::new(".debug_frame", 0, 4), frames, order)
+}
+
+/// `.eh_frame`, which is loaded with the program for unwinders to read at run time.
+pub fn eh_frame(frames: &[Frame], order: Endianness) -> DebugSection {
+    build(DebugSection::new(".eh_frame", SHF_ALLOC, 4), frames, order)
+}
+
+fn build(mut section: DebugSection, frames: &[Frame], order: Endianness) -> DebugSection {
+    let eh = section.name == ".eh_frame";
+    let mut cies = vec![];
+    for frame in frames {
+        let cie = match cies.iter().find(|(key, _)| *key == frame.cie_key()) {
+            Some((_, offset)) => *offset,
+            None => {
+                let offset = section.data.len() as u32;
+                push_cie(&mut section, frame, eh, order);
+                cies.push((frame.cie_key(), offset));
+                offset
+            }
+        };
+
+        let start = section.data.len();
+        section.push_u32(order, 0);
+        if eh {
+            // The distance back to the CIE from this field
+            section.push_u32(order, start as u32 + 4 - cie);
+            section.push_relative(order, Target::Code(frame.section), frame.start);
+        } else {
+            section.push_fixup(order, Target::Debug(".debug_frame"), cie);
+            section.push_fixup(order, Target::Code(frame.section), frame.start);
+        }
+        section.push_u32(order, frame.end - frame.start);
+        if eh {
+            push_uleb128(&mut section.data, 0);
+        }
+        section.data.extend_from_slice(&frame.instructions);
+        pad(&mut section, start);
+        section.set_length(order, start);
+    }
+    section
+}
+
+fn push_cie(section: &mut DebugSection, frame: &Frame, eh: bool, order: Endianness) {
+    let start = section.data.len();
+    section.push_u32(order, 0);
+    section.push_u32(order, if eh { 0 } else { u32::MAX });
+    section.data.push(VERSION);
+    let augmentation = match (eh, frame.signal_frame) {
+        (false, _) => "",
+        (true, false) => "zR",
+        (true, true) => "zRS",
+    };
+    section.push_string(augmentation);
+    push_uleb128(&mut section.data, CODE_ALIGNMENT_FACTOR);
+    push_sleb128(&mut section.data, DATA_ALIGNMENT_FACTOR);
+    section.data.push(frame.return_column as u8);
+    if eh {
+        push_uleb128(&mut section.data, 1);
+        section.data.push(DW_EH_PE_PCREL_SDATA4);
+    }
+    // On entry the CFA is the stack pointer
+    if !frame.simple {
+        section.data.extend([DW_CFA_DEF_CFA, SP as u8, 0]);
+    }
+    pad(section, start);
+    section.set_length(order, start);
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::elf::{R_ARM_ABS32, R_ARM_REL32};
+
+    #[test]
+    fn test_frames() {
+        let order = Endianness::Little;
+        let mut frame = Frame::new(0, 8, false, order);
+        // push {r4, lr}; sub sp, sp, #8
+        frame.push(12, CfiInstruction::DefCfaOffset(8)).unwrap();
+        frame.push(12, CfiInstruction::Offset(4, -8)).unwrap();
+        frame.push(12, CfiInstruction::Offset(LR, -4)).unwrap();
+        frame.push(16, CfiInstruction::AdjustCfaOffset(8)).unwrap();
+        frame.push(16, CfiInstruction::RememberState).unwrap();
+        frame.push(400, CfiInstruction::Restore(LR)).unwrap();
+        frame.push(400, CfiInstruction::RestoreState).unwrap();
+        assert!(frame.push(400, CfiInstruction::Offset(5, -6)).is_none());
+        frame.end = 404;
+        assert_eq!(
+            frame.instructions,
+            [
+                0x42,
+                DW_CFA_DEF_CFA_OFFSET,
+                8,
+                0x84,
+                2,
+                0x8E,
+                1,
+                0x42,
+                DW_CFA_DEF_CFA_OFFSET,
+                16,
+                DW_CFA_REMEMBER_STATE,
+                DW_CFA_ADVANCE_LOC1,
+                192,
+                0xCE,
+                DW_CFA_RESTORE_STATE,
+            ]
+        );
+
+        let frames = [frame.clone(), frame];
+        let debug = debug_frame(&frames, order);
+        let cie = [
+            12,
+            0,
+            0,
+            0,
+            0xFF,
+            0xFF,
+            0xFF,
+            0xFF,
+            VERSION,
+            0,
+            2,
+            0x7C,
+            14,
+            DW_CFA_DEF_CFA,
+            13,
+            0,
+        ];
+        assert_eq!(debug.data[..16], cie);
+        // Both FDEs share the CIE
+        assert_eq!(debug.data.len(), 16 + 2 * 32);
+        assert_eq!(debug.data[16..20], [28, 0, 0, 0]);
+        assert_eq!(debug.fixups.len(), 4);
+        assert_eq!(debug.fixups[2].target, Target::Debug(".debug_frame"));
+        assert_eq!(debug.fixups[3].r_type, R_ARM_ABS32);
+        assert_eq!(debug.data[56..60], [8, 0, 0, 0]);
+
+        let eh = eh_frame(&frames[..1], order);
+        assert_eq!(eh.flags, SHF_ALLOC);
+        assert_eq!(&eh.data[8..12], &[VERSION, b'z', b'R', 0]);
+        // The FDE's CIE pointer is the distance back to the CIE
+        assert_eq!(eh.data[24..28], [24, 0, 0, 0]);
+        assert_eq!(eh.fixups[0].offset, 28);
+        assert_eq!(eh.fixups[0].r_type, R_ARM_REL32);
+    }
+
+    #[test]
+    fn test_cfa_offset_and_restore() {
+        let mut frame = Frame::new(0, 0, false, Endianness::Little);
+        let d8 = parse_register("d8").unwrap();
+        // push {r4, r5, lr}; vpush {d8}; sub sp, sp, #300 and back, as llvm-mc encodes them
+        for (address, instruction) in [
+            (4, CfiInstruction::DefCfaOffset(12)),
+            (4, CfiInstruction::Offset(LR, -4)),
+            (4, CfiInstruction::Offset(5, -8)),
+            (4, CfiInstruction::Offset(4, -12)),
+            (8, CfiInstruction::AdjustCfaOffset(8)),
+            (8, CfiInstruction::Offset(d8, -20)),
+            (12, CfiInstruction::AdjustCfaOffset(300)),
+            (16, CfiInstruction::AdjustCfaOffset(-300)),
+            (20, CfiInstruction::AdjustCfaOffset(-8)),
+            (20, CfiInstruction::Restore(d8)),
+            (24, CfiInstruction::DefCfaOffset(0)),
+            (24, CfiInstruction::Restore(LR)),
+            (24, CfiInstruction::Restore(4)),
+        ] {
+            frame.push(address, instruction).unwrap();
+        }
+        assert_eq!(
+            frame.instructions,
+            [
+                0x42,
+                DW_CFA_DEF_CFA_OFFSET,
+                12,
+                0x8E,
+                1,
+                0x85,
+                2,
+                0x84,
+                3,
+                0x42,
+                DW_CFA_DEF_CFA_OFFSET,
+                20,
+                DW_CFA_OFFSET_EXTENDED,
+                0x88,
+                2,
+                5,
+                // 320 takes two bytes
+                0x42,
+                DW_CFA_DEF_CFA_OFFSET,
+                0xC0,
+                2,
+                0x42,
+                DW_CFA_DEF_CFA_OFFSET,
+                20,
+                0x42,
+                DW_CFA_DEF_CFA_OFFSET,
+                12,
+                DW_CFA_RESTORE_EXTENDED,
+                0x88,
+                2,
+                0x42,
+                DW_CFA_DEF_CFA_OFFSET,
+                0,
+                0xCE,
+                0xC4,
+            ]
+        );
+    }
+}
@@ -1,8 +1,13 @@
+use strum::IntoEnumIterator;
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter, strum_macros::EnumString)]
+#[strum(ascii_case_insensitive)]
 pub enum Cond {
     EQ,
     NE,
+    #[strum(serialize = "CS", serialize = "HS")]
     CS,
+    #[strum(serialize = "CC", serialize = "LO")]
     CC,
     MI,
     PL,
@@ -40,3 +45,12 @@ impl From<Cond> for u8 {
         }
     }
 }
+
+impl Cond {
+    /// The condition that holds exactly when this one does not.
+    pub fn inverse(self) -> Self {
+        Self::iter()
+            .nth((u8::from(self) ^ 1).into())
+            .unwrap_or(self)
+    }
+}
@@ -0,0 +1,499 @@
+//! DWARF debug information for assembly sources: a `.debug_line` program mapping instruction
+//! addresses back to source lines, and with it a compile unit in `.debug_info`, `.debug_abbrev`
+//! and `.debug_aranges` so that debuggers find it.
+
+use crate::{
+    attributes::push_uleb128,
+    elf::{Endianness, R_ARM_ABS32, R_ARM_REL32},
+};
+
+const VERSION: u16 = 3;
+const ARANGES_VERSION: u16 = 2;
+
+/// Line program parameters. Instructions are at least 2 bytes, but data in code sections can
+/// leave them at any address.
+const MINIMUM_INSTRUCTION_LENGTH: u8 = 1;
+const LINE_BASE: i8 = -5;
+const LINE_RANGE: u8 = 14;
+const OPCODE_BASE: u8 = 13;
+const STANDARD_OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+const DW_LNS_COPY: u8 = 1;
+pub(crate) const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+pub(crate) const DW_LNS_SET_COLUMN: u8 = 5;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+
+const DW_TAG_COMPILE_UNIT: u32 = 0x11;
+const DW_CHILDREN_NO: u8 = 0;
+const DW_AT_NAME: u32 = 0x03;
+const DW_AT_STMT_LIST: u32 = 0x10;
+const DW_AT_LOW_PC: u32 = 0x11;
+const DW_AT_HIGH_PC: u32 = 0x12;
+const DW_AT_LANGUAGE: u32 = 0x13;
+const DW_AT_COMP_DIR: u32 = 0x1B;
+const DW_AT_PRODUCER: u32 = 0x25;
+const DW_AT_RANGES: u32 = 0x55;
+const DW_FORM_ADDR: u32 = 0x01;
+const DW_FORM_DATA2: u32 = 0x05;
+const DW_FORM_DATA4: u32 = 0x06;
+const DW_FORM_STRING: u32 = 0x08;
+const DW_LANG_MIPS_ASSEMBLER: u16 = 0x8001;
+
+/// A source position that instructions from `address` on came from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Row {
+    pub address: u32,
+    /// Index into [`CompileUnit::files`], counted from 1.
+    pub file: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// The rows of one code section, which ends at `end`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Sequence {
+    pub section: usize,
+    pub end: u32,
+    pub rows: Vec<Row>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CompileUnit {
+    pub name: String,
+    pub comp_dir: String,
+    pub producer: String,
+    pub files: Vec<String>,
+    pub sequences: Vec<Sequence>,
+}
+
+/// What a field that the linker has to adjust holds an address or offset in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Target {
+    /// A code section, by the assembler's index for it.
+    Code(usize),
+    /// One of the other debug sections.
+    Debug(&'static str),
+}
+
+/// A 32-bit field holding an offset from the start of `target`, or from itself for
+/// `R_ARM_REL32`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Fixup {
+    pub offset: u32,
+    pub target: Target,
+    pub r_type: u8,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DebugSection {
+    pub name: &'static str,
+    pub flags: u32,
+    pub align: u32,
+    pub data: Vec<u8>,
+    pub fixups: Vec<Fixup>,
+}
+
+impl DebugSection {
+    pub(crate) fn new(name: &'static str, flags: u32, align: u32) -> Self {
+        Self {
+            name,
+            flags,
+            align,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn push_u16(&mut self, order: Endianness, value: u16) {
+        self.data.extend_from_slice(&order.u16_bytes(value));
+    }
+
+    pub(crate) fn push_u32(&mut self, order: Endianness, value: u32) {
+        self.data.extend_from_slice(&order.u32_bytes(value));
+    }
+
+    /// Push `value` as an offset into `target`, for the linker to adjust.
+    pub(crate) fn push_fixup(&mut self, order: Endianness, target: Target, value: u32) {
+        self.push_fixup_as(order, target, value, R_ARM_ABS32);
+    }
+
+    /// Push `value` as an offset into `target`, for the linker to make relative to the field.
+    pub(crate) fn push_relative(&mut self, order: Endianness, target: Target, value: u32) {
+        self.push_fixup_as(order, target, value, R_ARM_REL32);
+    }
+
+    fn push_fixup_as(&mut self, order: Endianness, target: Target, value: u32, r_type: u8) {
+        self.fixups.push(Fixup {
+            offset: self.data.len() as u32,
+            target,
+            r_type,
+        });
+        self.push_u32(order, value);
+    }
+
+    pub(crate) fn push_string(&mut self, value: &str) {
+        self.data.extend_from_slice(value.as_bytes());
+        self.data.push(0);
+    }
+
+    /// Fill in the initial length of a unit that starts at `start`.
+    pub(crate) fn set_length(&mut self, order: Endianness, start: usize) {
+        let length = (self.data.len() - start - 4) as u32;
+        self.data[start..start + 4].copy_from_slice(&order.u32_bytes(length));
+    }
+}
+
+pub(crate) fn push_sleb128(buf: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+impl CompileUnit {
+    /// The `.debug_line` section, followed by the compile unit sections if `full` is set.
+    pub fn sections(&self, full: bool, order: Endianness) -> Vec<DebugSection> {
+        let mut sections = vec![self.debug_line(order)];
+        if full {
+            let ranges = self.sequences.len() > 1;
+            sections.push(self.debug_info(ranges, order));
+            sections.push(Self::debug_abbrev(ranges));
+            sections.push(self.debug_aranges(order));
+            if ranges {
+                sections.push(self.debug_ranges(order));
+            }
+        }
+        sections
+    }
+
+    fn debug_line(&self, order: Endianness) -> DebugSection {
+        let mut section = DebugSection::new(".debug_line", 0, 1);
+        section.push_u32(order, 0);
+        section.push_u16(order, VERSION);
+        let header_length_offset = section.data.len();
+        section.push_u32(order, 0);
+        section.data.extend_from_slice(&[
+            MINIMUM_INSTRUCTION_LENGTH,
+            1, // default_is_stmt
+            LINE_BASE as u8,
+            LINE_RANGE,
+            OPCODE_BASE,
+        ]);
+        section.data.extend_from_slice(&STANDARD_OPCODE_LENGTHS);
+        // No include directories, so every file is relative to the compilation directory
+        section.data.push(0);
+        for file in &self.files {
+            section.push_string(file);
+            section.data.extend_from_slice(&[0, 0, 0]);
+        }
+        section.data.push(0);
+        let header_length = (section.data.len() - header_length_offset - 4) as u32;
+        section.data[header_length_offset..header_length_offset + 4]
+            .copy_from_slice(&order.u32_bytes(header_length));
+
+        for sequence in &self.sequences {
+            let Some(first) = sequence.rows.first() else {
+                continue;
+            };
+            section.data.extend_from_slice(&[0, 5, DW_LNE_SET_ADDRESS]);
+            section.push_fixup(order, Target::Code(sequence.section), first.address);
+
+            let (mut address, mut file, mut line, mut column) = (first.address, 1, 1, 0);
+            for row in &sequence.rows {
+                if row.file != file {
+                    section.data.push(DW_LNS_SET_FILE);
+                    push_uleb128(&mut section.data, row.file);
+                    file = row.file;
+                }
+                if row.column != column {
+                    section.data.push(DW_LNS_SET_COLUMN);
+                    push_uleb128(&mut section.data, row.column);
+                    column = row.column;
+                }
+                let line_delta = i64::from(row.line) - i64::from(line);
+                let address_delta = row.address - address;
+                advance(&mut section.data, line_delta, address_delta);
+                (address, line) = (row.address, row.line);
+            }
+
+            if sequence.end > address {
+                section.data.push(DW_LNS_ADVANCE_PC);
+                push_uleb128(&mut section.data, sequence.end - address);
+            }
+            section.data.extend_from_slice(&[0, 1, DW_LNE_END_SEQUENCE]);
+        }
+
+        section.set_length(order, 0);
+        section
+    }
+
+    fn debug_info(&self, ranges: bool, order: Endianness) -> DebugSection {
+        let mut section = DebugSection::new(".debug_info", 0, 1);
+        section.push_u32(order, 0);
+        section.push_u16(order, VERSION);
+        section.push_fixup(order, Target::Debug(".debug_abbrev"), 0);
+        section.data.push(4); // address_size
+
+        push_uleb128(&mut section.data, 1);
+        section.push_fixup(order, Target::Debug(".debug_line"), 0);
+        match self.sequences.as_slice() {
+            [sequence] if !ranges => {
+                let start = sequence.rows.first().map_or(0, |row| row.address);
+                section.push_fixup(order, Target::Code(sequence.section), start);
+                section.push_fixup(order, Target::Code(sequence.section), sequence.end);
+            }
+            _ => {
+                // Ranges are relative to a base address of zero
+                section.push_u32(order, 0);
+                section.push_fixup(order, Target::Debug(".debug_ranges"), 0);
+            }
+        }
+        section.push_string(&self.name);
+        section.push_string(&self.comp_dir);
+        section.push_string(&self.producer);
+        section.push_u16(order, DW_LANG_MIPS_ASSEMBLER);
+
+        section.set_length(order, 0);
+        section
+    }
+
+    fn debug_abbrev(ranges: bool) -> DebugSection {
+        let mut section = DebugSection::new(".debug_abbrev", 0, 1);
+        push_uleb128(&mut section.data, 1);
+        push_uleb128(&mut section.data, DW_TAG_COMPILE_UNIT);
+        section.data.push(DW_CHILDREN_NO);
+        let range = if ranges {
+            (DW_AT_RANGES, DW_FORM_DATA4)
+        } else {
+            (DW_AT_HIGH_PC, DW_FORM_ADDR)
+        };
+        for (attribute, form) in [
+            (DW_AT_STMT_LIST, DW_FORM_DATA4),
+            (DW_AT_LOW_PC, DW_FORM_ADDR),
+            range,
+            (DW_AT_NAME, DW_FORM_STRING),
+            (DW_AT_COMP_DIR, DW_FORM_STRING),
+            (DW_AT_PRODUCER, DW_FORM_STRING),
+            (DW_AT_LANGUAGE, DW_FORM_DATA2),
+            (0, 0),
+        ] {
+            push_uleb128(&mut section.data, attribute);
+            push_uleb128(&mut section.data, form);
+        }
+        section.data.push(0);
+        section
+    }
+
+    fn debug_aranges(&self, order: Endianness) -> DebugSection {
+        let mut section = DebugSection::new(".debug_aranges", 0, 1);
+        section.push_u32(order, 0);
+        section.push_u16(order, ARANGES_VERSION);
+        section.push_fixup(order, Target::Debug(".debug_info"), 0);
+        // address_size and segment_size, then padding to a multiple of the tuple size
+        section.data.extend_from_slice(&[4, 0, 0, 0, 0, 0]);
+        for (sequence, start) in self.ranges() {
+            section.push_fixup(order, Target::Code(sequence.section), start);
+            section.push_u32(order, sequence.end - start);
+        }
+        section.push_u32(order, 0);
+        section.push_u32(order, 0);
+
+        section.set_length(order, 0);
+        section
+    }
+
+    fn debug_ranges(&self, order: Endianness) -> DebugSection {
+        let mut section = DebugSection::new(".debug_ranges", 0, 1);
+        for (sequence, start) in self.ranges() {
+            section.push_fixup(order, Target::Code(sequence.section), start);
+            section.push_fixup(order, Target::Code(sequence.section), sequence.end);
+        }
+        section.push_u32(order, 0);
+        section.push_u32(order, 0);
+        section
+    }
+
+    /// Each sequence with rows and the address its code starts at.
+    fn ranges(&self) -> impl Iterator<Item = (&Sequence, u32)> {
+        self.sequences
+            .iter()
+            .filter_map(|sequence| Some((sequence, sequence.rows.first()?.address)))
+    }
+}
+
+/// Advance the line and address and add a row, with a special opcode if there is one for it.
+fn advance(data: &mut Vec<u8>, line_delta: i64, address_delta: u32) {
+    if let Some(opcode) = special_opcode(line_delta, address_delta) {
+        data.push(opcode);
+        return;
+    }
+    if line_delta != 0 {
+        data.push(DW_LNS_ADVANCE_LINE);
+        push_sleb128(data, line_delta);
+    }
+    if address_delta != 0 {
+        data.push(DW_LNS_ADVANCE_PC);
+        push_uleb128(data, address_delta);
+    }
+    data.push(DW_LNS_COPY);
+}
+
+/// The special opcode that advances both the line and the address, if there is one.
+fn special_opcode(line_delta: i64, address_delta: u32) -> Option<u8> {
+    let line = u32::try_from(line_delta - i64::from(LINE_BASE)).ok()?;
+    if line >= u32::from(LINE_RANGE) {
+        return None;
+    }
+    let address = address_delta / u32::from(MINIMUM_INSTRUCTION_LENGTH);
+    let opcode = line + u32::from(LINE_RANGE) * address + u32::from(OPCODE_BASE);
+    u8::try_from(opcode).ok()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{
+        arch::Arch, assembler::Assembler, elf::ObjectFile, listing::Listing, source::Source,
+    };
+
+    #[test]
+    fn test_line_program() {
+        let unit = CompileUnit {
+            name: "t.s".to_owned(),
+            files: vec!["t.s".to_owned()],
+            sequences: vec![Sequence {
+                section: 0,
+                end: 12,
+                rows: vec![
+                    Row {
+                        address: 0,
+                        file: 1,
+                        line: 3,
+                        column: 0,
+                    },
+                    Row {
+                        address: 4,
+                        file: 1,
+                        line: 40,
+                        column: 0,
+                    },
+                    Row {
+                        address: 8,
+                        file: 1,
+                        line: 38,
+                        column: 0,
+                    },
+                ],
+            }],
+            ..Default::default()
+        };
+        let sections = unit.sections(true, Endianness::Little);
+        let names: Vec<_> = sections.iter().map(|section| section.name).collect();
+        assert_eq!(
+            names,
+            [
+                ".debug_line",
+                ".debug_info",
+                ".debug_abbrev",
+                ".debug_aranges"
+            ]
+        );
+
+        let line = &sections[0];
+        let header_end = 10 + 5 + 12 + 1 + 7 + 1;
+        assert_eq!(line.data.len() as u32, 4 + read(&line.data, 0));
+        assert_eq!(read(&line.data, 6) as usize, header_end - 10);
+        assert_eq!(
+            &line.data[header_end..],
+            &[
+                0,
+                5,
+                DW_LNE_SET_ADDRESS,
+                0,
+                0,
+                0,
+                0,
+                // Line 3 is a special opcode, 37 needs advance_line, -2 is special again
+                0x14,
+                DW_LNS_ADVANCE_LINE,
+                37,
+                DW_LNS_ADVANCE_PC,
+                4,
+                DW_LNS_COPY,
+                0x48,
+                DW_LNS_ADVANCE_PC,
+                4,
+                0,
+                1,
+                DW_LNE_END_SEQUENCE,
+            ]
+        );
+        assert_eq!(
+            line.fixups,
+            [Fixup {
+                offset: header_end as u32 + 3,
+                target: Target::Code(0),
+                r_type: R_ARM_ABS32,
+            }]
+        );
+
+        // The header and abbreviation code, stmt_list, low_pc and then high_pc
+        let info = &sections[1];
+        assert_eq!(read(&info.data, 20), 12);
+        assert_eq!(info.fixups.len(), 4);
+        assert_eq!(info.fixups[3].target, Target::Code(0));
+    }
+
+    #[test]
+    fn test_line_program_include() {
+        let dir = std::env::temp_dir().join(format!("dwarf-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("inc.s"), "mov r0, #1\nmov r1, #2\n").unwrap();
+
+        let mut source = Source::new(vec![dir.clone()]);
+        source
+            .add_text("t.s", "start: nop\n.include \"inc.s\"\n\n\nbx lr\n")
+            .unwrap();
+        let mut assembler = Assembler::new(crate::arch::Target::new(Arch::V7A));
+        assembler.enable_debug_info(&source);
+        Listing::assemble(&mut assembler, "t.s", &source).unwrap();
+        let object = ObjectFile::parse(&assembler.to_object()).unwrap();
+        let line = object.sections.iter().find(|s| s.name == ".debug_line");
+        let line = &line.unwrap().data;
+
+        let inc = dir.join("inc.s").display().to_string();
+        assert!(line
+            .windows(inc.len() + 1)
+            .any(|name| name == format!("{inc}\0").as_bytes()));
+        assert!(line.ends_with(&[
+            // t.s:1, inc.s:1 and inc.s:2 at successive words, then back to t.s:5
+            0x12,
+            DW_LNS_SET_FILE,
+            2,
+            0x4A,
+            0x4B,
+            DW_LNS_SET_FILE,
+            1,
+            0x4D,
+            DW_LNS_ADVANCE_PC,
+            4,
+            0,
+            1,
+            DW_LNE_END_SEQUENCE,
+        ]));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn read(data: &[u8], offset: usize) -> u32 {
+        Endianness::Little.read_u32(data, offset).unwrap()
+    }
+}
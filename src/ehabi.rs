@@ -0,0 +1,385 @@
+//! Exception handling tables for the ARM EABI, from the `.fnstart` family of directives: the
+//! unwind opcodes that undo a function's prologue, packed into its `.ARM.exidx` entry or an
+//! `.ARM.extab` entry that the index refers to.
+
+use crate::attributes::push_uleb128;
+
+/// An index entry's second word for a function that exceptions can't unwind through.
+pub const EXIDX_CANTUNWIND: u32 = 1;
+
+const SP: u8 = 13;
+const LR: u8 = 14;
+
+/// Pops nothing; pads the opcodes out to a whole word.
+const FINISH: u8 = 0xB0;
+/// `vsp = vsp + 0x204 + (uleb128 << 2)`
+const ADD_VSP_ULEB128: u8 = 0xB2;
+/// Pop any of r0-r3, given as a mask in the next byte.
+const POP_R0_R3: u8 = 0xB1;
+/// Pop d(first)-d(first + count), given as nibbles in the next byte, for d0-d15.
+const POP_VFP_D0_D15: u8 = 0xC9;
+/// The same for d16-d31.
+const POP_VFP_D16_D31: u8 = 0xC8;
+
+/// The personality routine the ABI defines for each compact model.
+pub fn personality_routine(index: u8) -> String {
+    format!("__aeabi_unwind_cpp_pr{index}")
+}
+
+/// The index and table sections for unwinding the code in `section`.
+pub fn section_names(section: &str) -> (String, String) {
+    let suffix = if section == ".text" { "" } else { section };
+    (format!(".ARM.exidx{suffix}"), format!(".ARM.extab{suffix}"))
+}
+
+/// What a function's index entry holds after its start address.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Entry {
+    /// [`EXIDX_CANTUNWIND`] or up to three opcodes for the first compact model.
+    Inline(u32),
+    /// A table entry in `.ARM.extab`, starting with a reference to `personality` if it isn't one
+    /// of the compact models. Handler data follows the words.
+    Table {
+        personality: Option<String>,
+        words: Vec<u32>,
+    },
+}
+
+impl Entry {
+    /// Which of the compact models' personality routines it needs, if any.
+    pub fn compact_index(&self) -> Option<u8> {
+        let word = match self {
+            Self::Inline(EXIDX_CANTUNWIND)
+            | Self::Table {
+                personality: Some(_),
+                ..
+            } => {
+                return None;
+            }
+            Self::Inline(word) => *word,
+            Self::Table { words, .. } => words[0],
+        };
+        Some((word >> 24) as u8 & 0x0F)
+    }
+}
+
+/// What is known of the function between `.fnstart` and `.fnend`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Unwind {
+    /// The assembler's index for the code section the function is in, and where it starts.
+    pub section: usize,
+    pub start: u32,
+    pub cant_unwind: bool,
+    pub personality: Option<String>,
+    pub personality_index: Option<u8>,
+    /// Set by `.handlerdata`, with the section to return to at `.fnend`.
+    pub handler_data: Option<usize>,
+    /// Where the table entry was written in `.ARM.extab`.
+    pub table: Option<u32>,
+    entry: Option<Entry>,
+    /// Opcodes in the order their directives came, so undoing the prologue in reverse.
+    opcodes: Vec<Vec<u8>>,
+    /// Stack adjustments waiting to be combined into one opcode.
+    pending_offset: i64,
+    /// How far the stack pointer has moved from its value on entry.
+    frame_size: i64,
+    /// The register `.setfp` made the frame pointer, and where it points in the frame.
+    fp_reg: Option<u8>,
+    fp_offset: i64,
+}
+
+impl Unwind {
+    pub fn new(section: usize, start: u32) -> Self {
+        Self {
+            section,
+            start,
+            ..Self::default()
+        }
+    }
+
+    /// `.save {registers}`, for a mask of core registers pushed to the stack.
+    pub fn save_core(&mut self, mask: u16) {
+        self.flush();
+        self.frame_size += 4 * i64::from(mask.count_ones());
+
+        // r4 up to r11, with or without lr, have a short form
+        let high = mask >> 4;
+        let block = high & !(1 << (LR - 4));
+        let count = block.trailing_ones() as u8;
+        if block.count_ones() == u32::from(count) && (1..=8).contains(&count) {
+            let with_lr = if high & (1 << (LR - 4)) != 0 { 0x08 } else { 0 };
+            self.opcodes.push(vec![0xA0 | with_lr | (count - 1)]);
+        } else if high != 0 {
+            self.opcodes
+                .push(vec![0x80 | (high >> 8) as u8, high as u8]);
+        }
+        // r0-r3 are at the bottom of the block, so they are popped first
+        let low = (mask & 0xF) as u8;
+        if low != 0 {
+            self.opcodes.push(vec![POP_R0_R3, low]);
+        }
+    }
+
+    /// `.vsave {dN-dM}`, for `count` VFP registers starting at `first` pushed by VPUSH.
+    pub fn save_vfp(&mut self, first: u8, count: u8) {
+        self.flush();
+        self.frame_size += 8 * i64::from(count);
+        // A block that crosses d16 needs an opcode for each half
+        let low = count.min(16u8.saturating_sub(first));
+        if low < count {
+            let start = first.max(16) - 16;
+            self.opcodes
+                .push(vec![POP_VFP_D16_D31, (start << 4) | (count - low - 1)]);
+        }
+        if low > 0 {
+            self.opcodes
+                .push(vec![POP_VFP_D0_D15, (first << 4) | (low - 1)]);
+        }
+    }
+
+    /// `.pad #n`, for the stack pointer moving down by `offset` bytes.
+    pub fn pad(&mut self, offset: i64) {
+        self.pending_offset += offset;
+        self.frame_size += offset;
+    }
+
+    /// `.setfp fp, sp|fp2, #offset`, for `fp` being set to `base` plus `offset`.
+    pub fn setfp(&mut self, fp: u8, base: u8, offset: i64) {
+        self.fp_offset = if base == SP {
+            self.frame_size - offset
+        } else {
+            self.fp_offset - offset
+        };
+        self.fp_reg = Some(fp);
+    }
+
+    /// `.movsp reg, #offset`, for the stack pointer having been copied to `reg` plus `offset`.
+    pub fn movsp(&mut self, reg: u8, offset: i64) {
+        self.flush();
+        self.opcodes.push(vec![0x90 | reg]);
+        self.fp_offset = self.frame_size - offset;
+        self.fp_reg = None;
+    }
+
+    /// `.unwind_raw offset, bytes`, for opcodes the directives can't describe that move the stack
+    /// pointer by `offset`.
+    pub fn raw(&mut self, offset: i64, bytes: Vec<u8>) {
+        self.flush();
+        self.frame_size += offset;
+        self.opcodes.push(bytes);
+    }
+
+    /// Combine the pending stack adjustments into an opcode.
+    fn flush(&mut self) {
+        let mut offset = std::mem::take(&mut self.pending_offset);
+        if offset > 0x200 {
+            let mut opcode = vec![ADD_VSP_ULEB128];
+            push_uleb128(&mut opcode, ((offset - 0x204) >> 2) as u32);
+            self.opcodes.push(opcode);
+            return;
+        }
+        // Runs of the largest adjustment, then the remainder
+        let mut opcodes = vec![];
+        while offset > 0x100 {
+            opcodes.push(0x3F);
+            offset -= 0x100;
+        }
+        while offset < -0x100 {
+            opcodes.push(0x7F);
+            offset += 0x100;
+        }
+        if offset > 0 {
+            opcodes.push(((offset - 4) >> 2) as u8 & 0x3F);
+        } else if offset < 0 {
+            opcodes.push(0x40 | (((-offset - 4) >> 2) as u8 & 0x3F));
+        }
+        self.opcodes
+            .extend(opcodes.into_iter().map(|opcode| vec![opcode]));
+    }
+
+    /// The opcodes that unwind the whole function, ending with restoring the stack pointer from
+    /// the frame pointer if there is one.
+    fn finish(&mut self) -> Vec<u8> {
+        if let Some(fp) = self.fp_reg {
+            self.pending_offset += self.fp_offset - self.frame_size;
+            self.flush();
+            self.opcodes.push(vec![0x90 | fp]);
+        } else {
+            self.flush();
+        }
+        self.opcodes.iter().rev().flatten().copied().collect()
+    }
+
+    /// The entry for the function, or `None` if its opcodes don't fit the personality routine it
+    /// asked for. Later directives don't change it once it is made.
+    pub fn entry(&mut self) -> Option<Entry> {
+        if self.entry.is_none() {
+            self.entry = self.build_entry();
+        }
+        self.entry.clone()
+    }
+
+    fn build_entry(&mut self) -> Option<Entry> {
+        if self.cant_unwind {
+            return (self.personality.is_none() && self.handler_data.is_none())
+                .then_some(Entry::Inline(EXIDX_CANTUNWIND));
+        }
+        let opcodes = self.finish();
+        let table = self.handler_data.is_some();
+
+        if let Some(personality) = &self.personality {
+            return Some(Entry::Table {
+                personality: Some(personality.clone()),
+                words: pack(&opcodes, 3, |extra| extra << 24, !table),
+            });
+        }
+        let index = match self.personality_index {
+            Some(index) => index,
+            None if opcodes.len() <= 3 => 0,
+            None => 1,
+        };
+        let header = 0x8000_0000 | (u32::from(index) << 24);
+        match index {
+            0 if opcodes.len() > 3 => None,
+            0 if !table => Some(Entry::Inline(pack(&opcodes, 3, |_| header, false)[0])),
+            0 => Some(Entry::Table {
+                personality: None,
+                words: pack(&opcodes, 3, |_| header, false),
+            }),
+            1 | 2 if opcodes.len() <= 2 + 4 * 0xFF => Some(Entry::Table {
+                personality: None,
+                words: pack(&opcodes, 2, |extra| header | (extra << 16), !table),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Pack `opcodes` into words, `first` of them into the first word after the header, which
+/// `header` makes from the number of words that follow. A zero word ends the descriptors if
+/// `terminate` is set.
+fn pack(opcodes: &[u8], first: usize, header: impl Fn(u32) -> u32, terminate: bool) -> Vec<u32> {
+    let rest = opcodes.get(first..).unwrap_or_default();
+    let extra = rest.len().div_ceil(4);
+    let byte = |index| u32::from(*opcodes.get(index).unwrap_or(&FINISH));
+
+    let mut word = header(extra as u32);
+    for i in 0..first {
+        word |= byte(i) << (8 * (first - 1 - i));
+    }
+    let mut words = vec![word];
+    for chunk in 0..extra {
+        let start = first + 4 * chunk;
+        words.push((0..4).fold(0, |word, i| (word << 8) | byte(start + i)));
+    }
+    if terminate {
+        words.push(0);
+    }
+    words
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unwind_opcodes() {
+        // push {r4-r7, lr}; sub sp, sp, #16
+        let mut unwind = Unwind::new(0, 0);
+        unwind.save_core(0x40F0);
+        unwind.pad(16);
+        assert_eq!(unwind.entry(), Some(Entry::Inline(0x8003_ABB0)));
+
+        // push {r4, r6, fp, lr}; add fp, sp, #8; vpush {d8-d9}
+        let mut unwind = Unwind::new(0, 0);
+        unwind.save_core(0x4850);
+        unwind.setfp(11, SP, 8);
+        unwind.save_vfp(8, 2);
+        assert_eq!(
+            unwind.entry(),
+            Some(Entry::Table {
+                personality: None,
+                words: vec![0x8101_9B45, 0xC981_8485, 0],
+            })
+        );
+
+        // A large frame and r0-r3 with the rest
+        let mut unwind = Unwind::new(0, 0);
+        unwind.save_core(0x400F);
+        unwind.pad(0x1000);
+        unwind.personality = Some("__gxx_personality_v0".to_owned());
+        unwind.handler_data = Some(0);
+        assert_eq!(
+            unwind.entry(),
+            Some(Entry::Table {
+                personality: Some("__gxx_personality_v0".to_owned()),
+                words: vec![0x01B2_FF06, 0xB10F_8400],
+            })
+        );
+
+        let mut unwind = Unwind::new(0, 0);
+        unwind.cant_unwind = true;
+        assert_eq!(unwind.entry(), Some(Entry::Inline(EXIDX_CANTUNWIND)));
+        let mut unwind = Unwind::new(0, 0);
+        unwind.save_core(0x0FF0);
+        unwind.save_vfp(16, 4);
+        unwind.pad(8);
+        unwind.personality_index = Some(0);
+        assert_eq!(unwind.entry(), None);
+    }
+
+    #[test]
+    fn test_inline_and_table_entries() {
+        // push {r4, lr}, with each way of asking for a personality routine
+        let unwind = |personality: Option<&str>, index: Option<u8>, handler_data: bool| {
+            let mut unwind = Unwind::new(0, 0);
+            unwind.save_core(0x4010);
+            unwind.personality = personality.map(str::to_owned);
+            unwind.personality_index = index;
+            unwind.handler_data = handler_data.then_some(0);
+            unwind.entry().unwrap()
+        };
+
+        let inline = unwind(None, None, false);
+        assert_eq!(inline, Entry::Inline(0x80A8_B0B0));
+        assert_eq!(inline.compact_index(), Some(0));
+
+        // A routine of the function's own leaves the first word for a reference to it, and ends
+        // the descriptors unless .handlerdata supplies them
+        let gxx = Some("__gxx_personality_v0");
+        let table = unwind(gxx, None, false);
+        assert_eq!(
+            table,
+            Entry::Table {
+                personality: gxx.map(str::to_owned),
+                words: vec![0x00A8_B0B0, 0],
+            }
+        );
+        assert_eq!(table.compact_index(), None);
+        assert_eq!(
+            unwind(gxx, None, true),
+            Entry::Table {
+                personality: gxx.map(str::to_owned),
+                words: vec![0x00A8_B0B0],
+            }
+        );
+
+        // The opcodes would fit inline, but the second compact model is always in the table
+        let table = unwind(None, Some(1), false);
+        assert_eq!(
+            table,
+            Entry::Table {
+                personality: None,
+                words: vec![0x8100_A8B0, 0],
+            }
+        );
+        assert_eq!(table.compact_index(), Some(1));
+        assert_eq!(
+            unwind(None, Some(0), true),
+            Entry::Table {
+                personality: None,
+                words: vec![0x80A8_B0B0],
+            }
+        );
+    }
+}
@@ -0,0 +1,1017 @@
+//! Minimal ELF32 support for ARM: writing relocatable objects and linked executables, and reading
+//! relocatable objects back in for the linker.
+
+use crate::error::LinkError;
+
+pub const EM_ARM: u16 = 40;
+pub const ET_REL: u16 = 1;
+pub const ET_EXEC: u16 = 2;
+pub const EV_CURRENT: u8 = 1;
+pub const ELFCLASS32: u8 = 1;
+pub const ELFDATA2LSB: u8 = 1;
+pub const ELFDATA2MSB: u8 = 2;
+pub const EF_ARM_EABI_VER5: u32 = 0x0500_0000;
+pub const EF_ARM_BE8: u32 = 0x0080_0000;
+
+pub const SHT_NULL: u32 = 0;
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_REL: u32 = 9;
+pub const SHT_ARM_EXIDX: u32 = 0x7000_0001;
+pub const SHT_ARM_ATTRIBUTES: u32 = 0x7000_0003;
+
+pub const SHF_WRITE: u32 = 0x1;
+pub const SHF_ALLOC: u32 = 0x2;
+pub const SHF_EXECINSTR: u32 = 0x4;
+pub const SHF_LINK_ORDER: u32 = 0x80;
+
+pub const SHN_UNDEF: u16 = 0;
+pub const SHN_ABS: u16 = 0xFFF1;
+pub const SHN_COMMON: u16 = 0xFFF2;
+
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+pub const STB_WEAK: u8 = 2;
+
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+pub const STT_SECTION: u8 = 3;
+pub const STT_FILE: u8 = 4;
+
+pub const STV_DEFAULT: u8 = 0;
+pub const STV_INTERNAL: u8 = 1;
+pub const STV_HIDDEN: u8 = 2;
+pub const STV_PROTECTED: u8 = 3;
+
+pub const PT_LOAD: u32 = 1;
+
+pub const PF_X: u32 = 0x1;
+pub const PF_W: u32 = 0x2;
+pub const PF_R: u32 = 0x4;
+
+pub const R_ARM_NONE: u8 = 0;
+pub const R_ARM_PC24: u8 = 1;
+pub const R_ARM_ABS32: u8 = 2;
+pub const R_ARM_REL32: u8 = 3;
+pub const R_ARM_THM_CALL: u8 = 10;
+pub const R_ARM_CALL: u8 = 28;
+pub const R_ARM_JUMP24: u8 = 29;
+pub const R_ARM_THM_JUMP24: u8 = 30;
+pub const R_ARM_V4BX: u8 = 40;
+pub const R_ARM_PREL31: u8 = 42;
+pub const R_ARM_MOVW_ABS_NC: u8 = 43;
+pub const R_ARM_MOVT_ABS: u8 = 44;
+pub const R_ARM_THM_MOVW_ABS_NC: u8 = 47;
+pub const R_ARM_THM_MOVT_ABS: u8 = 48;
+pub const R_ARM_THM_JUMP19: u8 = 51;
+
+const EHDR_SIZE: u16 = 52;
+pub const PHDR_SIZE: u16 = 32;
+const SHDR_SIZE: u16 = 40;
+const SYM_SIZE: u32 = 16;
+const REL_SIZE: u32 = 8;
+const RELA_SIZE: u32 = 12;
+
+/// The byte order of a file. Instructions and data only differ in BE-8.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Endianness {
+    #[default]
+    Little,
+    /// Legacy big-endian, with big-endian code and data, up to ARMv6.
+    Be32,
+    /// Big-endian data with little-endian code, from ARMv6.
+    Be8,
+}
+
+impl Endianness {
+    /// The byte order of instructions.
+    pub fn code(self) -> Self {
+        match self {
+            Self::Be8 => Self::Little,
+            order => order,
+        }
+    }
+
+    /// The `EI_DATA` byte of the ELF header.
+    pub fn data_encoding(self) -> u8 {
+        match self {
+            Self::Little => ELFDATA2LSB,
+            Self::Be32 | Self::Be8 => ELFDATA2MSB,
+        }
+    }
+
+    /// The `e_flags` bits that go with it.
+    pub fn flags(self) -> u32 {
+        match self {
+            Self::Be8 => EF_ARM_BE8,
+            Self::Little | Self::Be32 => 0,
+        }
+    }
+
+    pub fn u16_bytes(self, value: u16) -> [u8; 2] {
+        match self {
+            Self::Little => value.to_le_bytes(),
+            Self::Be32 | Self::Be8 => value.to_be_bytes(),
+        }
+    }
+
+    pub fn u32_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            Self::Little => value.to_le_bytes(),
+            Self::Be32 | Self::Be8 => value.to_be_bytes(),
+        }
+    }
+
+    pub fn read_u16(self, bytes: &[u8], offset: usize) -> Option<u16> {
+        let field = bytes.get(offset..offset + 2)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u16::from_le_bytes(field),
+            Self::Be32 | Self::Be8 => u16::from_be_bytes(field),
+        })
+    }
+
+    pub fn read_u32(self, bytes: &[u8], offset: usize) -> Option<u32> {
+        let field = bytes.get(offset..offset + 4)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u32::from_le_bytes(field),
+            Self::Be32 | Self::Be8 => u32::from_be_bytes(field),
+        })
+    }
+}
+
+/// A section's contents. `SHT_NOBITS` sections hold zeros for their size, which is never written
+/// out.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub sh_type: u32,
+    pub flags: u32,
+    pub align: u32,
+    /// The index of a related section: for `.ARM.exidx`, the code it describes.
+    pub link: u32,
+    pub data: Vec<u8>,
+}
+
+impl Section {
+    pub fn new(name: &str, sh_type: u32, flags: u32, align: u32) -> Self {
+        Self {
+            name: name.to_owned(),
+            sh_type,
+            flags,
+            align,
+            link: 0,
+            data: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u32,
+    pub size: u32,
+    pub binding: u8,
+    pub sym_type: u8,
+    /// One of the `STV_*` values, kept in `st_other`.
+    pub visibility: u8,
+    /// Index of the defining section as returned by [`ObjectWriter::add_section`], or one of the
+    /// reserved `SHN_*` indices.
+    pub section: u16,
+}
+
+impl Symbol {
+    fn info(&self) -> u8 {
+        (self.binding << 4) | (self.sym_type & 0xF)
+    }
+
+    /// Whether this is a Thumb function, whose address has the low bit set.
+    pub fn is_thumb_func(&self) -> bool {
+        self.sym_type == STT_FUNC && self.value & 1 == 1
+    }
+}
+
+/// An entry of a `SHT_REL` or `SHT_RELA` section.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Relocation {
+    /// Offset of the field to patch within its section.
+    pub offset: u32,
+    /// Index of the symbol in the symbol table, where 0 is the null symbol. For [`ObjectWriter`]
+    /// this is the index returned by [`ObjectWriter::add_symbol`].
+    pub symbol: u32,
+    pub r_type: u8,
+    /// The explicit addend of a `SHT_RELA` entry. `SHT_REL` entries keep it in the field itself.
+    pub addend: Option<i32>,
+}
+
+impl Relocation {
+    fn info(&self) -> u32 {
+        (self.symbol << 8) | u32::from(self.r_type)
+    }
+}
+
+/// Collects sections and symbols and lays them out as an `ET_REL` object. The symbol and string
+/// tables are generated when writing.
+#[derive(Clone, Debug, Default)]
+pub struct ObjectWriter {
+    pub flags: u32,
+    pub endianness: Endianness,
+    sections: Vec<Section>,
+    symbols: Vec<Symbol>,
+    /// Relocations by the index of the section they apply to.
+    relocations: Vec<(u16, Vec<Relocation>)>,
+}
+
+/// A string table under construction.
+#[derive(Default)]
+struct StringTable(Vec<u8>);
+
+impl StringTable {
+    fn new() -> Self {
+        Self(vec![0])
+    }
+
+    fn add(&mut self, s: &str) -> u32 {
+        if s.is_empty() {
+            return 0;
+        }
+
+        let offset = self.0.len() as u32;
+        self.0.extend_from_slice(s.as_bytes());
+        self.0.push(0);
+        offset
+    }
+}
+
+fn push_u16(buf: &mut Vec<u8>, order: Endianness, value: u16) {
+    buf.extend_from_slice(&order.u16_bytes(value));
+}
+
+fn push_u32(buf: &mut Vec<u8>, order: Endianness, value: u32) {
+    buf.extend_from_slice(&order.u32_bytes(value));
+}
+
+fn align_to(buf: &mut Vec<u8>, align: u32) {
+    while !buf.len().is_multiple_of(align.max(1) as usize) {
+        buf.push(0);
+    }
+}
+
+/// A built symbol table, with local symbols moved before all others as ELF requires.
+struct SymbolTable {
+    data: Vec<u8>,
+    /// Index of the first non-local symbol, for the symbol table's `sh_info`.
+    first_global: u32,
+    /// Where each symbol ended up, by the order it was added in.
+    indices: Vec<u32>,
+}
+
+impl SymbolTable {
+    fn build(symbols: &[Symbol], strtab: &mut StringTable, endianness: Endianness) -> Self {
+        let mut order: Vec<usize> = (0..symbols.len()).collect();
+        order.sort_by_key(|&i| symbols[i].binding != STB_LOCAL);
+        let first_global = 1 + order
+            .iter()
+            .take_while(|&&i| symbols[i].binding == STB_LOCAL)
+            .count() as u32;
+
+        let mut data = vec![0; SYM_SIZE as usize];
+        let mut indices = vec![0; symbols.len()];
+        for (position, &i) in order.iter().enumerate() {
+            let symbol = &symbols[i];
+            indices[i] = position as u32 + 1;
+            push_u32(&mut data, endianness, strtab.add(&symbol.name));
+            push_u32(&mut data, endianness, symbol.value);
+            push_u32(&mut data, endianness, symbol.size);
+            data.push(symbol.info());
+            data.push(symbol.visibility & 0x3);
+            push_u16(&mut data, endianness, symbol.section);
+        }
+
+        Self {
+            data,
+            first_global,
+            indices,
+        }
+    }
+}
+
+/// The fields of the ELF header that differ between objects and executables.
+struct FileHeader {
+    e_type: u16,
+    entry: u32,
+    phnum: u16,
+    shoff: u32,
+    shnum: u16,
+    flags: u32,
+    endianness: Endianness,
+}
+
+impl FileHeader {
+    fn write(&self, out: &mut [u8]) {
+        let order = self.endianness;
+        let data = order.data_encoding();
+        let mut ehdr = vec![0x7F, b'E', b'L', b'F', ELFCLASS32, data, EV_CURRENT];
+        ehdr.resize(16, 0);
+        push_u16(&mut ehdr, order, self.e_type);
+        push_u16(&mut ehdr, order, EM_ARM);
+        push_u32(&mut ehdr, order, EV_CURRENT.into());
+        push_u32(&mut ehdr, order, self.entry);
+        // Program headers directly follow the ELF header
+        push_u32(
+            &mut ehdr,
+            order,
+            if self.phnum > 0 { EHDR_SIZE.into() } else { 0 },
+        );
+        push_u32(&mut ehdr, order, self.shoff);
+        push_u32(&mut ehdr, order, self.flags | order.flags());
+        push_u16(&mut ehdr, order, EHDR_SIZE);
+        push_u16(&mut ehdr, order, if self.phnum > 0 { PHDR_SIZE } else { 0 });
+        push_u16(&mut ehdr, order, self.phnum);
+        push_u16(&mut ehdr, order, SHDR_SIZE);
+        push_u16(&mut ehdr, order, self.shnum);
+        push_u16(&mut ehdr, order, self.shnum - 1); // e_shstrndx
+        out[..EHDR_SIZE as usize].copy_from_slice(&ehdr);
+    }
+}
+
+/// Append the section headers, ending with the section name string table, and return their
+/// offset and count.
+fn write_section_headers(
+    out: &mut Vec<u8>,
+    mut headers: Vec<SectionHeader>,
+    mut shstrtab: StringTable,
+    order: Endianness,
+) -> (u32, u16) {
+    let name = shstrtab.add(".shstrtab");
+    headers.push(SectionHeader {
+        name,
+        sh_type: SHT_STRTAB,
+        offset: out.len() as u32,
+        size: shstrtab.0.len() as u32,
+        align: 1,
+        ..Default::default()
+    });
+    out.extend_from_slice(&shstrtab.0);
+
+    align_to(out, 4);
+    let shoff = out.len() as u32;
+    out.extend_from_slice(&[0; SHDR_SIZE as usize]);
+    for header in &headers {
+        header.write(out, order);
+    }
+    (shoff, headers.len() as u16 + 1)
+}
+
+impl ObjectWriter {
+    pub fn new(flags: u32) -> Self {
+        Self {
+            flags,
+            ..Default::default()
+        }
+    }
+
+    /// Add a section, returning its index in the section header table.
+    pub fn add_section(&mut self, section: Section) -> u16 {
+        self.sections.push(section);
+        self.sections.len() as u16
+    }
+
+    /// Add a symbol, returning the index relocations refer to it by.
+    pub fn add_symbol(&mut self, symbol: Symbol) -> u32 {
+        self.symbols.push(symbol);
+        self.symbols.len() as u32
+    }
+
+    /// Add a relocation against the section at index `section`.
+    pub fn add_relocation(&mut self, section: u16, relocation: Relocation) {
+        match self
+            .relocations
+            .iter_mut()
+            .find(|(index, _)| *index == section)
+        {
+            Some((_, relocations)) => relocations.push(relocation),
+            None => self.relocations.push((section, vec![relocation])),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut strtab = StringTable::new();
+        let mut shstrtab = StringTable::new();
+        let order = self.endianness;
+        let symtab = SymbolTable::build(&self.symbols, &mut strtab, order);
+
+        // Relocation sections go between the sections and the symbol table
+        let symtab_index = (self.sections.len() + self.relocations.len()) as u32 + 1;
+        let mut sections: Vec<(SectionHeader, Vec<u8>)> = self
+            .sections
+            .iter()
+            .map(|section| {
+                let header = SectionHeader {
+                    name: shstrtab.add(&section.name),
+                    sh_type: section.sh_type,
+                    flags: section.flags,
+                    size: section.data.len() as u32,
+                    align: section.align,
+                    link: section.link,
+                    ..Default::default()
+                };
+                (header, section.data.clone())
+            })
+            .collect();
+
+        for (index, relocations) in &self.relocations {
+            let target = &self.sections[*index as usize - 1];
+            let mut data = vec![];
+            for relocation in relocations {
+                let symbol = match relocation.symbol {
+                    0 => 0,
+                    symbol => symtab.indices[symbol as usize - 1],
+                };
+                push_u32(&mut data, order, relocation.offset);
+                push_u32(
+                    &mut data,
+                    order,
+                    Relocation {
+                        symbol,
+                        ..*relocation
+                    }
+                    .info(),
+                );
+            }
+            let header = SectionHeader {
+                name: shstrtab.add(&format!(".rel{}", target.name)),
+                sh_type: SHT_REL,
+                size: data.len() as u32,
+                link: symtab_index,
+                info: (*index).into(),
+                align: 4,
+                entsize: REL_SIZE,
+                ..Default::default()
+            };
+            sections.push((header, data));
+        }
+
+        let symtab_header = SectionHeader {
+            name: shstrtab.add(".symtab"),
+            sh_type: SHT_SYMTAB,
+            size: symtab.data.len() as u32,
+            link: symtab_index + 1,
+            info: symtab.first_global,
+            align: 4,
+            entsize: SYM_SIZE,
+            ..Default::default()
+        };
+        sections.push((symtab_header, symtab.data));
+
+        let strtab_header = SectionHeader {
+            name: shstrtab.add(".strtab"),
+            sh_type: SHT_STRTAB,
+            size: strtab.0.len() as u32,
+            align: 1,
+            ..Default::default()
+        };
+        sections.push((strtab_header, strtab.0));
+
+        // Section contents follow the ELF header, the section header table goes last
+        let mut out = vec![0; EHDR_SIZE as usize];
+        let mut headers = vec![];
+        for (mut header, data) in sections {
+            align_to(&mut out, header.align);
+            header.offset = out.len() as u32;
+            if header.sh_type != SHT_NOBITS {
+                out.extend_from_slice(&data);
+            }
+            headers.push(header);
+        }
+
+        let (shoff, shnum) = write_section_headers(&mut out, headers, shstrtab, order);
+        FileHeader {
+            e_type: ET_REL,
+            entry: 0,
+            phnum: 0,
+            shoff,
+            shnum,
+            flags: self.flags,
+            endianness: order,
+        }
+        .write(&mut out);
+
+        out
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub offset: u32,
+    pub vaddr: u32,
+    pub paddr: u32,
+    pub filesz: u32,
+    pub memsz: u32,
+    pub flags: u32,
+    pub align: u32,
+}
+
+impl ProgramHeader {
+    fn read(bytes: &[u8], order: Endianness) -> Self {
+        let field = |i: usize| order.read_u32(bytes, i * 4).unwrap_or_default();
+        Self {
+            p_type: field(0),
+            offset: field(1),
+            vaddr: field(2),
+            paddr: field(3),
+            filesz: field(4),
+            memsz: field(5),
+            flags: field(6),
+            align: field(7),
+        }
+    }
+
+    fn write(&self, buf: &mut Vec<u8>, order: Endianness) {
+        for field in [
+            self.p_type,
+            self.offset,
+            self.vaddr,
+            self.paddr,
+            self.filesz,
+            self.memsz,
+            self.flags,
+            self.align,
+        ] {
+            push_u32(buf, order, field);
+        }
+    }
+}
+
+/// Writes an `ET_EXEC` image that has already been laid out: every section comes with its address
+/// and file offset, and the segments are written as given.
+#[derive(Clone, Debug, Default)]
+pub struct ExecutableWriter {
+    pub entry: u32,
+    pub flags: u32,
+    pub endianness: Endianness,
+    segments: Vec<ProgramHeader>,
+    sections: Vec<(Section, u32, u32)>,
+    symbols: Vec<Symbol>,
+}
+
+impl ExecutableWriter {
+    pub fn new(entry: u32, flags: u32) -> Self {
+        Self {
+            entry,
+            flags,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_segment(&mut self, segment: ProgramHeader) {
+        self.segments.push(segment);
+    }
+
+    /// Add a section placed at `address` in memory and `offset` in the file, returning its index
+    /// in the section header table. Sections must be added in file order, after the room
+    /// [`ExecutableWriter::headers_size`] reserves.
+    pub fn add_section(&mut self, section: Section, address: u32, offset: u32) -> u16 {
+        self.sections.push((section, address, offset));
+        self.sections.len() as u16
+    }
+
+    pub fn add_symbol(&mut self, symbol: Symbol) {
+        self.symbols.push(symbol);
+    }
+
+    /// The size of the ELF and program headers at the start of a file with `segments` segments.
+    pub fn headers_size(segments: usize) -> u32 {
+        u32::from(EHDR_SIZE) + segments as u32 * u32::from(PHDR_SIZE)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut shstrtab = StringTable::new();
+        let mut strtab = StringTable::new();
+        let order = self.endianness;
+
+        // Program headers directly follow the ELF header
+        let mut out = vec![0; EHDR_SIZE as usize];
+        for segment in &self.segments {
+            segment.write(&mut out, order);
+        }
+
+        let mut headers = vec![];
+        for (section, address, offset) in &self.sections {
+            if section.sh_type != SHT_NOBITS {
+                out.resize(*offset as usize, 0);
+                out.extend_from_slice(&section.data);
+            }
+            headers.push(SectionHeader {
+                name: shstrtab.add(&section.name),
+                sh_type: section.sh_type,
+                flags: section.flags,
+                addr: *address,
+                offset: *offset,
+                size: section.data.len() as u32,
+                align: section.align,
+                ..Default::default()
+            });
+        }
+
+        let symtab = SymbolTable::build(&self.symbols, &mut strtab, order);
+        align_to(&mut out, 4);
+        let symtab_index = self.sections.len() as u32 + 1;
+        headers.push(SectionHeader {
+            name: shstrtab.add(".symtab"),
+            sh_type: SHT_SYMTAB,
+            offset: out.len() as u32,
+            size: symtab.data.len() as u32,
+            link: symtab_index + 1,
+            info: symtab.first_global,
+            align: 4,
+            entsize: SYM_SIZE,
+            ..Default::default()
+        });
+        out.extend_from_slice(&symtab.data);
+
+        headers.push(SectionHeader {
+            name: shstrtab.add(".strtab"),
+            sh_type: SHT_STRTAB,
+            offset: out.len() as u32,
+            size: strtab.0.len() as u32,
+            align: 1,
+            ..Default::default()
+        });
+        out.extend_from_slice(&strtab.0);
+
+        let (shoff, shnum) = write_section_headers(&mut out, headers, shstrtab, order);
+        FileHeader {
+            e_type: ET_EXEC,
+            entry: self.entry,
+            phnum: self.segments.len() as u16,
+            shoff,
+            shnum,
+            flags: self.flags,
+            endianness: order,
+        }
+        .write(&mut out);
+
+        out
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct SectionHeader {
+    name: u32,
+    sh_type: u32,
+    flags: u32,
+    addr: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+    info: u32,
+    align: u32,
+    entsize: u32,
+}
+
+impl SectionHeader {
+    fn write(&self, buf: &mut Vec<u8>, order: Endianness) {
+        for field in [
+            self.name,
+            self.sh_type,
+            self.flags,
+            self.addr,
+            self.offset,
+            self.size,
+            self.link,
+            self.info,
+            self.align,
+            self.entsize,
+        ] {
+            push_u32(buf, order, field);
+        }
+    }
+
+    fn read(bytes: &[u8], order: Endianness) -> Self {
+        let field = |i: usize| order.read_u32(bytes, i * 4).unwrap_or_default();
+        Self {
+            name: field(0),
+            sh_type: field(1),
+            flags: field(2),
+            addr: field(3),
+            offset: field(4),
+            size: field(5),
+            link: field(6),
+            info: field(7),
+            align: field(8),
+            entsize: field(9),
+        }
+    }
+}
+
+/// Read the NUL terminated string at `offset` in a string table.
+fn read_string(table: &[u8], offset: u32) -> String {
+    let rest = table.get(offset as usize..).unwrap_or_default();
+    let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+    String::from_utf8_lossy(&rest[..end]).into_owned()
+}
+
+fn bad_object(reason: &str) -> LinkError {
+    LinkError::BadObject(reason.to_owned())
+}
+
+/// An ELF relocatable object read back in, as produced by [`ObjectWriter`] or another assembler.
+#[derive(Clone, Debug, Default)]
+pub struct ObjectFile {
+    pub endianness: Endianness,
+    pub flags: u32,
+    /// Every section in section header table order, starting with the null section, so that
+    /// symbols' section indices index into it.
+    pub sections: Vec<Section>,
+    /// The symbol table, starting with the null symbol.
+    pub symbols: Vec<Symbol>,
+    /// Relocations by the index of the section they apply to.
+    pub relocations: Vec<(u16, Vec<Relocation>)>,
+}
+
+/// Check the ELF header of `bytes` for an ARM file of type `e_type`, and read its byte order,
+/// flags and section headers.
+fn read_headers(
+    bytes: &[u8],
+    e_type: u16,
+    expected: &str,
+) -> Result<(Endianness, u32, Vec<SectionHeader>, u16), LinkError> {
+    if bytes.get(..4) != Some(b"\x7FELF") {
+        return Err(bad_object("not an ELF file"));
+    }
+    if bytes[4] != ELFCLASS32 {
+        return Err(bad_object("not an ELF32 file"));
+    }
+    let mut order = match bytes.get(5) {
+        Some(&ELFDATA2LSB) => Endianness::Little,
+        Some(&ELFDATA2MSB) => Endianness::Be32,
+        _ => return Err(bad_object("unknown byte order")),
+    };
+    let header = |offset| {
+        order
+            .read_u16(bytes, offset)
+            .ok_or_else(|| bad_object("truncated"))
+    };
+    if header(16)? != e_type {
+        return Err(bad_object(&format!("not {expected}")));
+    }
+    if header(18)? != EM_ARM {
+        return Err(bad_object("not an ARM file"));
+    }
+    let word = |offset| {
+        order
+            .read_u32(bytes, offset)
+            .ok_or_else(|| bad_object("truncated"))
+    };
+    let flags = word(36)?;
+    let shoff = word(32)? as usize;
+    let (shentsize, shnum, shstrndx) = (header(46)?, header(48)?, header(50)?);
+
+    let headers = (0..shnum as usize)
+        .map(|i| {
+            let start = shoff + i * shentsize as usize;
+            bytes
+                .get(start..start + SHDR_SIZE as usize)
+                .map(|header| SectionHeader::read(header, order))
+                .ok_or_else(|| bad_object("truncated section header table"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if order == Endianness::Be32 && flags & EF_ARM_BE8 != 0 {
+        order = Endianness::Be8;
+    }
+
+    Ok((order, flags, headers, shstrndx))
+}
+
+impl ObjectFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self, LinkError> {
+        let (endianness, flags, headers, shstrndx) =
+            read_headers(bytes, ET_REL, "a relocatable object")?;
+        let order = endianness;
+
+        let contents = |header: &SectionHeader| -> Result<Vec<u8>, LinkError> {
+            if header.sh_type == SHT_NOBITS {
+                return Ok(vec![0; header.size as usize]);
+            }
+            let start = header.offset as usize;
+            bytes
+                .get(start..start + header.size as usize)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| bad_object("section extends past the end of the file"))
+        };
+
+        let names = match headers.get(shstrndx as usize) {
+            Some(header) => contents(header)?,
+            None => vec![],
+        };
+        let mut sections = vec![];
+        for header in &headers {
+            sections.push(Section {
+                name: read_string(&names, header.name),
+                sh_type: header.sh_type,
+                flags: header.flags,
+                align: header.align,
+                link: header.link,
+                data: contents(header)?,
+            });
+        }
+
+        let mut symbols = vec![];
+        if let Some(symtab) = headers.iter().find(|header| header.sh_type == SHT_SYMTAB) {
+            let data = contents(symtab)?;
+            let strtab = match headers.get(symtab.link as usize) {
+                Some(header) => contents(header)?,
+                None => vec![],
+            };
+            for entry in data.chunks_exact(SYM_SIZE as usize) {
+                let info = entry[12];
+                symbols.push(Symbol {
+                    name: read_string(&strtab, order.read_u32(entry, 0).unwrap_or_default()),
+                    value: order.read_u32(entry, 4).unwrap_or_default(),
+                    size: order.read_u32(entry, 8).unwrap_or_default(),
+                    binding: info >> 4,
+                    sym_type: info & 0xF,
+                    visibility: entry[13] & 0x3,
+                    section: order.read_u16(entry, 14).unwrap_or_default(),
+                });
+            }
+        }
+
+        let mut relocations = vec![];
+        for (header, section) in headers.iter().zip(&sections) {
+            let entry_size = match header.sh_type {
+                SHT_REL => REL_SIZE,
+                SHT_RELA => RELA_SIZE,
+                _ => continue,
+            };
+            let entries = section
+                .data
+                .chunks_exact(entry_size as usize)
+                .map(|entry| {
+                    let info = order.read_u32(entry, 4).unwrap_or_default();
+                    Relocation {
+                        offset: order.read_u32(entry, 0).unwrap_or_default(),
+                        symbol: info >> 8,
+                        r_type: info as u8,
+                        addend: order.read_u32(entry, 8).map(|addend| addend as i32),
+                    }
+                })
+                .collect();
+            relocations.push((header.info as u16, entries));
+        }
+
+        Ok(Self {
+            endianness,
+            flags,
+            sections,
+            symbols,
+            relocations,
+        })
+    }
+}
+
+/// A linked ELF executable read back in, as far as loading it goes.
+#[derive(Clone, Debug, Default)]
+pub struct ExecutableFile {
+    pub entry: u32,
+    pub endianness: Endianness,
+    pub flags: u32,
+    pub segments: Vec<ProgramHeader>,
+    /// The contents of each allocated section that takes up room in the file, with the address
+    /// it is loaded at. That is the physical address of its segment, which for initialised data
+    /// is generally not where it runs.
+    pub sections: Vec<(String, u32, Vec<u8>)>,
+}
+
+impl ExecutableFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self, LinkError> {
+        let (endianness, flags, headers, shstrndx) = read_headers(bytes, ET_EXEC, "an executable")?;
+        let order = endianness;
+        let entry = order
+            .read_u32(bytes, 24)
+            .ok_or_else(|| bad_object("truncated"))?;
+        let phoff = order
+            .read_u32(bytes, 28)
+            .ok_or_else(|| bad_object("truncated"))? as usize;
+        let phnum = order
+            .read_u16(bytes, 44)
+            .ok_or_else(|| bad_object("truncated"))?;
+
+        let segments = (0..phnum as usize)
+            .map(|i| {
+                let start = phoff + i * PHDR_SIZE as usize;
+                bytes
+                    .get(start..start + PHDR_SIZE as usize)
+                    .map(|header| ProgramHeader::read(header, order))
+                    .ok_or_else(|| bad_object("truncated program header table"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let contents = |header: &SectionHeader| {
+            let start = header.offset as usize;
+            bytes
+                .get(start..start + header.size as usize)
+                .ok_or_else(|| bad_object("section extends past the end of the file"))
+        };
+        let names = match headers.get(shstrndx as usize) {
+            Some(header) => contents(header)?,
+            None => &[],
+        };
+
+        let mut sections = vec![];
+        for header in &headers {
+            if header.flags & SHF_ALLOC == 0 || header.sh_type == SHT_NOBITS || header.size == 0 {
+                continue;
+            }
+            let segment = segments.iter().find(|segment| {
+                segment.p_type == PT_LOAD
+                    && (segment.offset..segment.offset + segment.filesz).contains(&header.offset)
+            });
+            let address = match segment {
+                Some(segment) => segment.paddr + (header.offset - segment.offset),
+                None => header.addr,
+            };
+            let name = read_string(names, header.name);
+            sections.push((name, address, contents(header)?.to_vec()));
+        }
+
+        Ok(Self {
+            entry,
+            endianness,
+            flags,
+            segments,
+            sections,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{
+        ObjectFile, ObjectWriter, Relocation, Section, Symbol, R_ARM_CALL, SHF_ALLOC,
+        SHF_EXECINSTR, SHT_PROGBITS, SHT_REL, STB_GLOBAL, STT_NOTYPE, STV_DEFAULT, STV_HIDDEN,
+    };
+
+    #[test]
+    fn test_object_round_trip() {
+        let mut writer = ObjectWriter::new(0);
+        let mut text = Section::new(".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 4);
+        text.data = vec![0xFE, 0xFF, 0xFF, 0xEB];
+        let text_index = writer.add_section(text.clone());
+        let callee = writer.add_symbol(Symbol {
+            name: "callee".to_owned(),
+            value: 0,
+            size: 0,
+            binding: STB_GLOBAL,
+            sym_type: STT_NOTYPE,
+            visibility: STV_HIDDEN,
+            section: 0,
+        });
+        let local = Symbol {
+            name: "$a".to_owned(),
+            value: 0,
+            size: 0,
+            binding: 0,
+            sym_type: STT_NOTYPE,
+            visibility: STV_DEFAULT,
+            section: text_index,
+        };
+        writer.add_symbol(local.clone());
+        let relocation = Relocation {
+            offset: 0,
+            symbol: callee,
+            r_type: R_ARM_CALL,
+            addend: None,
+        };
+        writer.add_relocation(text_index, relocation);
+
+        let object = ObjectFile::parse(&writer.to_bytes()).unwrap();
+        assert_eq!(object.sections[1], text);
+        assert_eq!(object.sections[2].sh_type, SHT_REL);
+        assert_eq!(object.sections[2].name, ".rel.text");
+        // Locals are moved before the global the relocation refers to
+        assert_eq!(object.symbols[1], local);
+        assert_eq!(object.symbols[2].name, "callee");
+        assert_eq!(object.symbols[2].visibility, STV_HIDDEN);
+        assert_eq!(
+            object.relocations,
+            vec![(
+                1,
+                vec![Relocation {
+                    symbol: 2,
+                    ..relocation
+                }]
+            )]
+        );
+
+        assert!(ObjectFile::parse(&include_bytes!("../return_0.o")[..40]).is_err());
+        let object = ObjectFile::parse(include_bytes!("../return_0.o")).unwrap();
+        assert_eq!(object.sections[2].name, ".text");
+        assert_eq!(object.symbols[1].name, "main");
+    }
+}
@@ -2,6 +2,8 @@ use std::num::ParseIntError;
 
 use thiserror::Error;
 
+use crate::arch::{Feature, Target};
+
 #[derive(Debug, Error)]
 pub enum AssemblerError {
     #[error("Parse Error: {0}")]
@@ -12,6 +14,10 @@ pub enum AssemblerError {
     Strum(#[from] strum::ParseError),
     #[error("IOError: {0}")]
     IO(#[from] std::io::Error),
+    #[error("`{0}` requires {1}, which {2} does not support")]
+    Unsupported(String, Feature, Target),
+    #[error("Can't find include file {0}")]
+    MissingInclude(String),
 }
 
 #[derive(Debug, Error)]
@@ -24,4 +30,72 @@ pub enum ParseError {
     RanOutOfOperands,
     #[error("Bad flex operand {0}")]
     BadFlexOperand(String),
+    #[error("Bad immediate {0}")]
+    BadImmediate(String),
+    #[error("{0} cannot be conditional")]
+    UnexpectedCond(String),
+    #[error("Unknown architecture {0}")]
+    UnknownArch(String),
+    #[error("Unknown architecture extension {0}")]
+    UnknownExtension(String),
+    #[error("Unknown CPU {0}")]
+    UnknownCpu(String),
+    #[error("Unknown FPU {0}")]
+    UnknownFpu(String),
+    #[error("Undefined symbol {0}")]
+    UndefinedSymbol(String),
+    #[error("Symbol {0} is already defined")]
+    DuplicateLabel(String),
+    #[error("Bad directive {0}")]
+    BadDirective(String),
+    #[error("Unknown directive .{0}")]
+    UnknownDirective(String),
+    #[error("branch to `{0}` out of range ({1})")]
+    BranchOutOfRange(String, &'static str),
+    #[error("{0} does not fit in its IT block")]
+    BadItBlock(String),
+    #[error("IT block is still open, expecting {0} more")]
+    UnterminatedItBlock(usize),
+    #[error("{0} can't refer to a symbol defined in another section or file")]
+    Unrelocatable(String),
+    #[error("Bad data type {0}")]
+    BadDataType(String),
+    #[error("`{0}` is UNPREDICTABLE: {1}")]
+    Unpredictable(String, String),
+    #[error("Register alias {0} is already defined")]
+    DuplicateAlias(String),
+    #[error("Unknown register alias {0}")]
+    UnknownAlias(String),
+    #[error("{0} has no narrow encoding with that writeback")]
+    BadWriteback(String),
+}
+
+#[derive(Debug, Error)]
+pub enum LinkError {
+    #[error("IOError: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("Bad object file: {0}")]
+    BadObject(String),
+    #[error("{0}: undefined reference to {1}")]
+    UndefinedSymbol(String, String),
+    #[error("Bad linker script: {0}")]
+    BadScript(String),
+    #[error("Region {0} overflowed by {1} bytes")]
+    RegionOverflow(String, u64),
+    #[error("Entry symbol {0} is not defined")]
+    UndefinedEntry(String),
+    #[error("Multiple definitions of {0}")]
+    DuplicateSymbol(String),
+    #[error("{0}: unsupported relocation type {1}")]
+    UnsupportedRelocation(String, u8),
+    #[error("{0}: relocation against {1} is out of range")]
+    RelocationOutOfRange(String, String),
+    #[error("{0}: branch to {1} has to change instruction set")]
+    Interworking(String, String),
+    #[error("{0}: byte order doesn't match the other objects")]
+    MixedEndianness(String),
+    #[error("Unknown output format {0}")]
+    UnknownFormat(String),
+    #[error("Bad record on line {0}: {1}")]
+    BadRecord(usize, String),
 }
@@ -0,0 +1,517 @@
+//! The formats that flash programmers and bootloaders take instead of ELF: raw binaries, Intel HEX
+//! and Motorola S-records. Each is written from a [`LoadImage`] and can be read back into one.
+
+use std::{fmt::Write, str::FromStr};
+
+use crate::{elf::ExecutableFile, error::LinkError};
+
+/// Bytes to be loaded at an address.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// What a program loader needs: the contents of memory and where to start.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LoadImage {
+    pub entry: Option<u32>,
+    /// In address order, without overlaps.
+    pub segments: Vec<Segment>,
+}
+
+impl LoadImage {
+    /// The sections a linked executable loads, at their load addresses.
+    pub fn from_elf(bytes: &[u8]) -> Result<Self, LinkError> {
+        let executable = ExecutableFile::parse(bytes)?;
+        let mut image = Self {
+            entry: Some(executable.entry),
+            segments: vec![],
+        };
+        for (_, address, data) in executable.sections {
+            image.add(address, &data);
+        }
+        Ok(image)
+    }
+
+    /// Read a raw binary that is loaded at `base_address`.
+    pub fn from_binary(bytes: &[u8], base_address: u32) -> Self {
+        let mut image = Self::default();
+        image.add(base_address, bytes);
+        image
+    }
+
+    /// Load `data` at `address`, merging it with the segment it continues.
+    pub fn add(&mut self, address: u32, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let index = self
+            .segments
+            .partition_point(|segment| segment.address <= address);
+        if let Some(previous) = index.checked_sub(1).map(|i| &mut self.segments[i]) {
+            if previous.address + previous.data.len() as u32 == address {
+                previous.data.extend_from_slice(data);
+                return;
+            }
+        }
+        self.segments.insert(
+            index,
+            Segment {
+                address,
+                data: data.to_vec(),
+            },
+        );
+    }
+
+    /// The lowest address loaded and the one just past the highest.
+    pub fn bounds(&self) -> Option<(u32, u32)> {
+        let first = self.segments.first()?;
+        let end = self
+            .segments
+            .iter()
+            .map(|segment| segment.address + segment.data.len() as u32)
+            .max()?;
+        Some((first.address, end))
+    }
+}
+
+/// Turns a [`LoadImage`] into the bytes of a file.
+pub trait ImageWriter {
+    fn write(&self, image: &LoadImage) -> Vec<u8>;
+}
+
+/// A flat memory dump, as produced by `objcopy -O binary`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BinaryWriter {
+    /// The address of the first byte of the file. Defaults to the lowest address loaded, and
+    /// anything below it is left out.
+    pub base_address: Option<u32>,
+    /// What goes between segments.
+    pub gap_fill: u8,
+}
+
+impl ImageWriter for BinaryWriter {
+    fn write(&self, image: &LoadImage) -> Vec<u8> {
+        let Some((start, end)) = image.bounds() else {
+            return vec![];
+        };
+        let base = self.base_address.unwrap_or(start);
+        let mut out = vec![self.gap_fill; end.saturating_sub(base) as usize];
+        for segment in &image.segments {
+            let skip = base.saturating_sub(segment.address) as usize;
+            if let Some(data) = segment.data.get(skip..) {
+                let offset = (segment.address + skip as u32 - base) as usize;
+                out[offset..offset + data.len()].copy_from_slice(data);
+            }
+        }
+        out
+    }
+}
+
+/// Append the bytes of a record as hex digits.
+fn push_record(out: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        let _ = write!(out, "{byte:02X}");
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Intel HEX, with extended linear address records for addresses past 64K.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IntelHexWriter {
+    /// Data bytes per record.
+    pub record_size: u8,
+}
+
+impl Default for IntelHexWriter {
+    fn default() -> Self {
+        Self { record_size: 16 }
+    }
+}
+
+impl IntelHexWriter {
+    fn record(out: &mut String, record_type: u8, address: u16, data: &[u8]) {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend_from_slice(&address.to_be_bytes());
+        bytes.push(record_type);
+        bytes.extend_from_slice(data);
+        bytes.push(checksum(&bytes).wrapping_neg());
+        out.push(':');
+        push_record(out, &bytes);
+        out.push('\n');
+    }
+}
+
+impl ImageWriter for IntelHexWriter {
+    fn write(&self, image: &LoadImage) -> Vec<u8> {
+        let mut out = String::new();
+        let mut upper = 0;
+        for segment in &image.segments {
+            let mut address = segment.address;
+            let mut data = segment.data.as_slice();
+            while !data.is_empty() {
+                if address >> 16 != upper {
+                    upper = address >> 16;
+                    Self::record(&mut out, 0x04, 0, &(upper as u16).to_be_bytes());
+                }
+                // Records can't cross into the next 64K
+                let room = 0x1_0000 - (address & 0xFFFF);
+                let size = data
+                    .len()
+                    .min(self.record_size.max(1).into())
+                    .min(room as usize);
+                Self::record(&mut out, 0x00, address as u16, &data[..size]);
+                address += size as u32;
+                data = &data[size..];
+            }
+        }
+        if let Some(entry) = image.entry {
+            Self::record(&mut out, 0x05, 0, &entry.to_be_bytes());
+        }
+        Self::record(&mut out, 0x01, 0, &[]);
+        out.into_bytes()
+    }
+}
+
+/// Which S-records carry data, which sets how wide their addresses are.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SrecFormat {
+    /// S1 records with 16-bit addresses.
+    S19,
+    /// S2 records with 24-bit addresses.
+    S28,
+    /// S3 records with 32-bit addresses.
+    S37,
+}
+
+impl SrecFormat {
+    fn address_size(self) -> usize {
+        match self {
+            Self::S19 => 2,
+            Self::S28 => 3,
+            Self::S37 => 4,
+        }
+    }
+
+    /// The narrowest format that can hold `address`.
+    fn for_address(address: u32) -> Self {
+        match address {
+            0..=0xFFFF => Self::S19,
+            0x1_0000..=0xFF_FFFF => Self::S28,
+            _ => Self::S37,
+        }
+    }
+}
+
+/// Motorola S-records.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SrecWriter {
+    /// Defaults to the narrowest format that can hold every address.
+    pub format: Option<SrecFormat>,
+    /// Data bytes per record.
+    pub record_size: u8,
+}
+
+impl Default for SrecWriter {
+    fn default() -> Self {
+        Self {
+            format: None,
+            record_size: 16,
+        }
+    }
+}
+
+impl SrecWriter {
+    fn record(out: &mut String, record_type: u8, address_size: usize, address: u32, data: &[u8]) {
+        let mut bytes = vec![(address_size + data.len() + 1) as u8];
+        bytes.extend_from_slice(&address.to_be_bytes()[4 - address_size..]);
+        bytes.extend_from_slice(data);
+        bytes.push(!checksum(&bytes));
+        let _ = write!(out, "S{record_type}");
+        push_record(out, &bytes);
+        out.push('\n');
+    }
+}
+
+impl ImageWriter for SrecWriter {
+    fn write(&self, image: &LoadImage) -> Vec<u8> {
+        let highest = image.bounds().map_or(0, |(_, end)| end.saturating_sub(1));
+        let format = self.format.unwrap_or_else(|| {
+            SrecFormat::for_address(highest.max(image.entry.unwrap_or_default()))
+        });
+        let size = format.address_size();
+
+        let mut out = String::new();
+        Self::record(&mut out, 0, 2, 0, &[]);
+        let mut count = 0;
+        for segment in &image.segments {
+            for (i, chunk) in segment
+                .data
+                .chunks(self.record_size.max(1).into())
+                .enumerate()
+            {
+                let address = segment.address + (i * usize::from(self.record_size.max(1))) as u32;
+                Self::record(&mut out, size as u8 - 1, size, address, chunk);
+                count += 1;
+            }
+        }
+        if count <= 0xFFFF {
+            Self::record(&mut out, 5, 2, count, &[]);
+        }
+        Self::record(
+            &mut out,
+            11 - size as u8,
+            size,
+            image.entry.unwrap_or(0),
+            &[],
+        );
+        out.into_bytes()
+    }
+}
+
+fn bad_record(line: usize, reason: &str) -> LinkError {
+    LinkError::BadRecord(line, reason.to_owned())
+}
+
+/// The bytes of a record written as hex digits after its one character start code, checking
+/// that they add up to `expected_sum`.
+fn record_bytes(line: &str, number: usize, expected_sum: u8) -> Result<Vec<u8>, LinkError> {
+    let digits = line.get(1..).unwrap_or_default();
+    if digits.len() % 2 != 0 {
+        return Err(bad_record(number, "odd number of digits"));
+    }
+    let bytes = (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| bad_record(number, "not a hex digit"))?;
+    if checksum(&bytes) != expected_sum {
+        return Err(bad_record(number, "bad checksum"));
+    }
+    Ok(bytes)
+}
+
+impl LoadImage {
+    pub fn from_intel_hex(text: &str) -> Result<Self, LinkError> {
+        let mut image = Self::default();
+        let mut base = 0;
+        for (number, line) in (1..).zip(text.lines().map(str::trim)) {
+            if line.is_empty() {
+                continue;
+            }
+            if !line.starts_with(':') {
+                return Err(bad_record(number, "missing start code"));
+            }
+            let bytes = record_bytes(line, number, 0)?;
+            let [length, high, low, record_type, ref data @ .., _] = bytes[..] else {
+                return Err(bad_record(number, "too short"));
+            };
+            if data.len() != length as usize {
+                return Err(bad_record(number, "wrong length"));
+            }
+            let value = data
+                .iter()
+                .fold(0, |value, byte| (value << 8) | u32::from(*byte));
+
+            match record_type {
+                0x00 => image.add(base + u32::from(u16::from_be_bytes([high, low])), data),
+                0x01 => break,
+                0x02 => base = value << 4,
+                // The CS:IP of a start segment address record
+                0x03 => image.entry = Some(((value >> 16) << 4) + (value & 0xFFFF)),
+                0x04 => base = value << 16,
+                0x05 => image.entry = Some(value),
+                _ => return Err(bad_record(number, "unknown record type")),
+            }
+        }
+        Ok(image)
+    }
+
+    pub fn from_srec(text: &str) -> Result<Self, LinkError> {
+        let mut image = Self::default();
+        for (number, line) in (1..).zip(text.lines().map(str::trim)) {
+            if line.is_empty() {
+                continue;
+            }
+            let Some(record_type) = line
+                .strip_prefix('S')
+                .and_then(|rest| rest.chars().next())
+                .and_then(|c| c.to_digit(10))
+            else {
+                return Err(bad_record(number, "missing start code"));
+            };
+            let bytes = record_bytes(&line[1..], number, 0xFF)?;
+            let address_size = match record_type {
+                0 | 1 | 5 | 9 => 2,
+                2 | 6 | 8 => 3,
+                3 | 7 => 4,
+                _ => return Err(bad_record(number, "unknown record type")),
+            };
+            let [length, ref rest @ ..] = bytes[..] else {
+                return Err(bad_record(number, "too short"));
+            };
+            if rest.len() != length as usize || rest.len() < address_size + 1 {
+                return Err(bad_record(number, "wrong length"));
+            }
+            let (address, data) = rest[..rest.len() - 1].split_at(address_size);
+            let address = address
+                .iter()
+                .fold(0, |value, byte| (value << 8) | u32::from(*byte));
+
+            match record_type {
+                1..=3 => image.add(address, data),
+                7..=9 => image.entry = Some(address),
+                _ => (),
+            }
+        }
+        Ok(image)
+    }
+}
+
+/// What [`crate::linker::Linker`] writes, named as for GNU ld's `--oformat`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Elf,
+    Binary(BinaryWriter),
+    IntelHex(IntelHexWriter),
+    Srec(SrecWriter),
+}
+
+impl OutputFormat {
+    /// Convert a linked executable to this format.
+    pub fn convert(&self, elf: &[u8]) -> Result<Vec<u8>, LinkError> {
+        let writer: &dyn ImageWriter = match self {
+            Self::Elf => return Ok(elf.to_vec()),
+            Self::Binary(writer) => writer,
+            Self::IntelHex(writer) => writer,
+            Self::Srec(writer) => writer,
+        };
+        Ok(writer.write(&LoadImage::from_elf(elf)?))
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = LinkError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "elf" | "elf32-littlearm" => Ok(Self::Elf),
+            "binary" => Ok(Self::Binary(BinaryWriter::default())),
+            "ihex" => Ok(Self::IntelHex(IntelHexWriter::default())),
+            "srec" => Ok(Self::Srec(SrecWriter::default())),
+            "symbolsrec" | "srec19" => Ok(Self::Srec(SrecWriter {
+                format: Some(SrecFormat::S19),
+                ..Default::default()
+            })),
+            _ => Err(LinkError::UnknownFormat(name.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{
+        BinaryWriter, ImageWriter, IntelHexWriter, LoadImage, OutputFormat, Segment, SrecFormat,
+        SrecWriter,
+    };
+
+    fn image() -> LoadImage {
+        let mut image = LoadImage {
+            entry: Some(0x0800_0101),
+            segments: vec![],
+        };
+        image.add(0x0800_FFF8, &[0x11; 12]);
+        image.add(0x0801_0004, &[0x22, 0x33]);
+        image.add(0x0800_0000, &[1, 2, 3, 4]);
+        image
+    }
+
+    #[test]
+    fn test_binary() {
+        let image = image();
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.bounds(), Some((0x0800_0000, 0x0801_0006)));
+
+        let writer = BinaryWriter {
+            base_address: Some(0x0800_FFF6),
+            gap_fill: 0xFF,
+        };
+        let binary = writer.write(&image);
+        assert_eq!(
+            binary,
+            [&[0xFF, 0xFF][..], &[0x11; 12], &[0x22, 0x33]].concat()
+        );
+        assert_eq!(
+            LoadImage::from_binary(&binary, 0x100).segments,
+            vec![Segment {
+                address: 0x100,
+                data: binary
+            }]
+        );
+        assert_eq!(BinaryWriter::default().write(&image).len(), 0x1_0006);
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let hex = IntelHexWriter::default().write(&image());
+        let hex = String::from_utf8(hex).unwrap();
+        assert_eq!(
+            hex,
+            ":020000040800F2\n\
+             :0400000001020304F2\n\
+             :08FFF800111111111111111179\n\
+             :020000040801F1\n\
+             :0600000011111111223361\n\
+             :0400000508000101ED\n\
+             :00000001FF\n"
+        );
+        assert_eq!(LoadImage::from_intel_hex(&hex).unwrap(), image());
+
+        assert!(LoadImage::from_intel_hex(":0400000001020304F3").is_err());
+        assert_eq!(
+            LoadImage::from_intel_hex(":00000001FF\n:bad")
+                .unwrap()
+                .segments,
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_srec() {
+        let srec = String::from_utf8(SrecWriter::default().write(&image())).unwrap();
+        assert_eq!(
+            srec.lines().collect::<Vec<_>>(),
+            [
+                "S0030000FC",
+                "S3090800000001020304E4",
+                "S3130800FFF81111111111111111111111112233CC",
+                "S5030002FA",
+                "S70508000101F0",
+            ]
+        );
+        assert_eq!(LoadImage::from_srec(&srec).unwrap(), image());
+
+        let mut small = LoadImage::from_binary(&[0xAB], 0x1234);
+        small.entry = Some(0x1234);
+        let srec = SrecWriter::default().write(&small);
+        assert_eq!(
+            String::from_utf8(srec).unwrap(),
+            "S0030000FC\nS1041234AB0A\nS5030001FB\nS9031234B6\n"
+        );
+        let forced = SrecWriter {
+            format: Some(SrecFormat::S28),
+            ..Default::default()
+        };
+        let srec = String::from_utf8(forced.write(&small)).unwrap();
+        assert_eq!(LoadImage::from_srec(&srec).unwrap(), small);
+        assert!(LoadImage::from_srec("S1041234AB0D").is_err());
+
+        assert!(matches!("ihex".parse(), Ok(OutputFormat::IntelHex(_))));
+        assert!("coff".parse::<OutputFormat>().is_err());
+    }
+}
@@ -1,7 +1,18 @@
 use crate::{
+    arch::{Feature, Target},
+    bitfield::{BranchOffset, Imm8Rot, U12, U3, U4, U5},
     cond::Cond,
     error::{AssemblerError, ParseError},
-    mnemonics::{BranchMnemonic, DataMnemonic, MemoryMnemonic, Mnemonic, MultiplyMnemonic},
+    mnemonics::{
+        BarrierMnemonic, BitfieldMnemonic, BranchMnemonic, CoprocessorMnemonic, DataMnemonic,
+        DivideMnemonic, ExceptionMnemonic, ExclusiveMnemonic, HintMnemonic, MemoryMnemonic,
+        Mnemonic, MoveWideMnemonic, MultiplyMnemonic, PreloadMnemonic, ReverseMnemonic,
+        StackMnemonic,
+    },
+    neon::NeonInstruction,
+    thumb::{split_address, ThumbMnemonic},
+    thumb2::{parse_shift, ShiftType},
+    vfp::VfpInstruction,
 };
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -20,58 +31,346 @@ pub enum Operand {
     Immediate(u64),
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub struct Rd(pub u8);
+macro_rules! registers {
+    ($($(#[$attr:meta])* $name:ident),*) => {$(
+        $(#[$attr])*
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        pub struct $name(pub(crate) u8);
+
+        impl $name {
+            /// `r{index}`, which must be one of `r0`-`r15`.
+            pub fn new(index: u8) -> Result<Self, AssemblerError> {
+                if index < 16 {
+                    Ok(Self(index))
+                } else {
+                    Err(ParseError::BadRegister(format!("r{index}")).into())
+                }
+            }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub struct Rn(pub u8);
+            pub fn index(self) -> u8 {
+                self.0
+            }
+        }
+    )*};
+}
 
+registers!(
+    /// The destination register, or the one transferred by loads and stores.
+    Rd,
+    /// The first operand register, or the base register of an address.
+    Rn,
+    /// The register holding a shift amount or multiplier.
+    Rs,
+    /// The second operand register.
+    Rm,
+    /// The accumulator of a multiply-accumulate.
+    Ra
+);
+
+/// A coprocessor number, `p0`-`p15`.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub struct Rs(pub u8);
+pub struct Coproc(pub U4);
 
+/// A coprocessor register, `c0`-`c15`.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub struct Rm(pub u8);
+pub struct CReg(pub U4);
+
+/// Parse a `p`/`c`-prefixed number below 16, for coprocessors and their registers.
+fn parse_numbered(value: &str, prefix: char) -> Result<U4, AssemblerError> {
+    value
+        .trim()
+        .strip_prefix([prefix, prefix.to_ascii_uppercase()])
+        .and_then(|number| number.parse::<u8>().ok())
+        .and_then(U4::new)
+        .ok_or_else(|| ParseError::BadRegister(value.to_owned()).into())
+}
+
+pub(crate) fn parse_coproc(value: &str) -> Result<Coproc, AssemblerError> {
+    parse_numbered(value, 'p').map(Coproc)
+}
+
+pub(crate) fn parse_coproc_reg(value: &str) -> Result<CReg, AssemblerError> {
+    parse_numbered(value, 'c').map(CReg)
+}
 
-fn parse_reg_id(value: &str) -> Result<u8, AssemblerError> {
+/// Parse a core register, `r0`-`r15` or one of its APCS names, in any case.
+pub(crate) fn parse_reg_id(value: &str) -> Result<u8, AssemblerError> {
     if value.is_empty() {
-        panic!("empty string");
+        return Err(ParseError::RanOutOfOperands.into());
     }
 
-    if value.contains("fp") {
-        return Ok(11);
-    } else if value.contains("ip") {
-        return Ok(12);
-    } else if value.contains("sp") {
-        return Ok(13);
-    } else if value.contains("lr") {
-        return Ok(14);
-    } else if value.contains("pc") {
-        return Ok(15);
+    let value_lower = value.to_ascii_lowercase();
+    let numbered = |prefix: char, first: u8, count: u8| {
+        value_lower
+            .strip_prefix(prefix)
+            .filter(|number| number.bytes().all(|digit| digit.is_ascii_digit()))
+            .and_then(|number| number.parse::<u8>().ok())
+            .filter(|number| (first..first + count).contains(number))
+    };
+    let id = match value_lower.as_str() {
+        "wr" => Some(7),
+        "sb" => Some(9),
+        "sl" => Some(10),
+        "fp" => Some(11),
+        "ip" => Some(12),
+        "sp" => Some(13),
+        "lr" => Some(14),
+        "pc" => Some(15),
+        // Arguments a1-a4 are r0-r3, and variables v1-v8 are r4-r11
+        _ => numbered('r', 0, 16)
+            .or_else(|| numbered('a', 1, 4).map(|number| number - 1))
+            .or_else(|| numbered('v', 1, 8).map(|number| number + 3)),
+    };
+    id.ok_or_else(|| ParseError::BadRegister(value.to_owned()).into())
+}
+
+/// Parse an immediate such as `#12`, `#0x1f` or `#-4`. The leading `#` is optional.
+pub(crate) fn parse_immediate(value: &str) -> Result<i64, AssemblerError> {
+    let value = value.trim().trim_start_matches('#');
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+
+    let magnitude = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16)?
+    } else if let Some(bin) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i64::from_str_radix(bin, 2)?
+    } else {
+        digits.parse::<i64>()?
+    };
+
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+/// Parse an unsigned immediate that must fit in `0..=max`.
+pub(crate) fn parse_bounded_immediate(value: &str, max: u32) -> Result<u32, AssemblerError> {
+    u32::try_from(parse_immediate(value)?)
+        .ok()
+        .filter(|imm| *imm <= max)
+        .ok_or_else(|| ParseError::BadImmediate(value.to_owned()).into())
+}
+
+/// Parse an unsigned immediate into a field made by `new`, which checks that it fits.
+pub(crate) fn parse_field<R: TryFrom<i64>, T>(
+    value: &str,
+    new: impl Fn(R) -> Option<T>,
+) -> Result<T, AssemblerError> {
+    R::try_from(parse_immediate(value)?)
+        .ok()
+        .and_then(new)
+        .ok_or_else(|| ParseError::BadImmediate(value.to_owned()).into())
+}
+
+/// The rotated form of an immediate data-processing operand: 8 bits rotated right by twice the
+/// number in bits 8-11. `None` if `value` has no such form.
+pub const fn encode_rotated_immediate(value: u32) -> Option<u32> {
+    match Imm8Rot::encode(value) {
+        Some(imm) => Some(imm.bits()),
+        None => None,
     }
+}
 
-    if !["r", "R"].contains(&(&value[0..1])) {
-        return Err(ParseError::BadRegister(value.to_owned()).into());
+/// The `imm4:imm12` fields of `movw`/`movt`, or `None` if `value` doesn't fit in 16 bits.
+pub const fn encode_wide_immediate(value: u32) -> Option<u32> {
+    if value > 0xFFFF {
+        None
+    } else {
+        Some(((value & 0xF000) << 4) | (value & 0xFFF))
     }
+}
 
-    if let Ok(register_id) = &value[1..].parse::<u8>() {
-        Ok(*register_id)
+/// The U bit and 12-bit offset of `ldr`/`str`, or `None` if `value` is too far either way.
+pub const fn encode_offset_immediate(value: i32) -> Option<u32> {
+    let magnitude = value.unsigned_abs();
+    if magnitude > 0xFFF {
+        None
+    } else if value < 0 {
+        Some(magnitude)
     } else {
-        Err(ParseError::BadRegister(value.to_owned()).into())
+        Some((1 << 23) | magnitude)
     }
 }
 
-// TODO: u4
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub struct Rotation(u8);
+/// Parse the 16-bit operand of `movw`/`movt`, including the `:lower16:`/`:upper16:` operators.
+pub(crate) fn parse_move_wide_immediate(value: &str) -> Result<u16, AssemblerError> {
+    let expr = value.trim().trim_start_matches('#');
+
+    if let Some(lower) = expr.strip_prefix(":lower16:") {
+        Ok((parse_immediate(lower)? & 0xFFFF) as u16)
+    } else if let Some(upper) = expr.strip_prefix(":upper16:") {
+        Ok(((parse_immediate(upper)? >> 16) & 0xFFFF) as u16)
+    } else {
+        Ok(parse_bounded_immediate(expr, u16::MAX.into())? as u16)
+    }
+}
+
+/// Split an operand list on commas, keeping `[...]` and `{...}` groups together.
+pub(crate) fn split_operands(value: &str) -> Vec<&str> {
+    let mut operands = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(value[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+
+    let last = value[start..].trim();
+    if !last.is_empty() || !operands.is_empty() {
+        operands.push(last);
+    }
+
+    operands
+}
+
+/// Parse a register list such as `{r0-r3, lr}` into a bitmask with bit n set for rn.
+pub(crate) fn parse_reg_list(value: &str) -> Result<u16, AssemblerError> {
+    let inner = value
+        .trim()
+        .strip_prefix('{')
+        .and_then(|list| list.strip_suffix('}'))
+        .ok_or_else(|| ParseError::BadRegister(value.to_owned()))?;
+
+    let mut mask = 0;
+    for item in inner.split(',') {
+        match item.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse_reg_id(first.trim())?, parse_reg_id(last.trim())?);
+                if first > last {
+                    return Err(ParseError::BadRegister(item.to_owned()).into());
+                }
+                for reg in first..=last {
+                    mask |= 1 << reg;
+                }
+            }
+            None => mask |= 1 << parse_reg_id(item.trim())?,
+        }
+    }
+
+    Ok(mask)
+}
+
+/// Parse a bracketed base register such as `[r0` or `[r0]`.
+pub(crate) fn parse_base_reg(value: &str) -> Result<u8, AssemblerError> {
+    parse_reg_id(
+        value
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .trim(),
+    )
+}
+
+/// Parse the addressing of `ldr`/`str` and `pld`: `[Rn{, offset}]`, `[Rn, offset]!` or
+/// `[Rn], offset`, where `post` holds the operands after the brackets.
+fn parse_address(address: &str, post: &[&str]) -> Result<(IndexMode, Rn, Offset), AssemblerError> {
+    let bad_address = || ParseError::BadFlexOperand(address.to_owned());
+    let (bracketed, writeback) = match address.strip_suffix('!') {
+        Some(bracketed) => (bracketed.trim_end(), true),
+        None => (address, false),
+    };
+    let (base, inner) = split_address(bracketed)?;
+    let rn = Rn(parse_reg_id(base)?);
+
+    let inner = inner.map(split_operands);
+    let (index_mode, offset) = match (inner.as_deref(), writeback, post) {
+        (None, false, []) => {
+            return Ok((
+                IndexMode::Offset,
+                rn,
+                Offset::Immediate(U12::ZERO, UpDown::Up),
+            ))
+        }
+        (Some(offset), false, []) => (IndexMode::Offset, offset),
+        (Some(offset), true, []) => (IndexMode::PreIndex, offset),
+        (None, false, offset) => (IndexMode::PostIndex, offset),
+        _ => return Err(bad_address().into()),
+    };
+    Ok((index_mode, rn, Offset::parse(offset)?))
+}
 
-// TODO: u4
+/// A register operand's shift by an immediate amount, as encoded. An amount of 0 stands for 32
+/// with `lsr` and `asr`, and for `rrx` with `ror`.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub struct Shift(u8);
+pub struct Shift(pub ShiftType, pub U5);
+
+impl Shift {
+    /// `lsl #0`, which leaves the register as it is.
+    pub const NONE: Self = Self(ShiftType::LSL, U5::ZERO);
+
+    /// Parse an optional shift such as `lsl #2` or `rrx` following a register operand.
+    pub(crate) fn parse(value: Option<&str>) -> Result<Self, AssemblerError> {
+        let (shift, amount) = parse_shift(value)?;
+        // A shift right by 32 is encoded as 0
+        let amount = U5::new(amount % 32).unwrap_or(U5::ZERO);
+        Ok(Self(shift, amount))
+    }
+
+    /// The `imm5:type:0` field in bits 4-11.
+    fn bits(self) -> u32 {
+        (self.1.bits() << 3) | (u32::from(u16::from(self.0)) << 1)
+    }
+}
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum FlexibleOperand {
-    RegisterWithShift(u8, Shift),
-    ImmediateWithRotation(u8, Rotation),
+    RegisterWithShift(Rm, Shift),
+    /// `Rm, <shift> Rs`, shifted by the bottom byte of Rs.
+    RegisterShiftedRegister(Rm, ShiftType, Rs),
+    ImmediateWithRotation(Imm8Rot),
+}
+
+impl FlexibleOperand {
+    /// Parse `#imm` or `Rm{, shift}`, given the operands from the second operand on.
+    pub(crate) fn parse(operands: &[&str]) -> Result<Self, AssemblerError> {
+        match operands {
+            [] => Err(ParseError::RanOutOfOperands.into()),
+            [operand] => Self::try_from(*operand),
+            [rm, shift] => {
+                let rm = Rm(parse_reg_id(rm)?);
+                let shift_register = shift
+                    .split_once(char::is_whitespace)
+                    .filter(|(_, rs)| parse_reg_id(rs.trim()).is_ok());
+                match shift_register {
+                    Some((name, rs)) => {
+                        let shift = ThumbMnemonic::try_from(name)
+                            .ok()
+                            .and_then(ShiftType::from_mnemonic)
+                            .ok_or_else(|| ParseError::BadFlexOperand((*shift).to_owned()))?;
+                        let rs = Rs(parse_reg_id(rs.trim())?);
+                        Ok(Self::RegisterShiftedRegister(rm, shift, rs))
+                    }
+                    None => Ok(Self::RegisterWithShift(rm, Shift::parse(Some(shift))?)),
+                }
+            }
+            _ => Err(ParseError::BadFlexOperand(operands.join(", ")).into()),
+        }
+    }
+
+    /// The 12-bit operand field.
+    fn bits(self) -> u32 {
+        match self {
+            FlexibleOperand::RegisterWithShift(rm, shift) => (shift.bits() << 4) | rm.0 as u32,
+            FlexibleOperand::RegisterShiftedRegister(rm, shift, rs) => {
+                (rs.0 as u32) << 8 | (u16::from(shift) as u32) << 5 | 1 << 4 | rm.0 as u32
+            }
+            FlexibleOperand::ImmediateWithRotation(imm) => imm.bits(),
+        }
+    }
 }
 
 impl TryFrom<&str> for FlexibleOperand {
@@ -79,12 +378,13 @@ impl TryFrom<&str> for FlexibleOperand {
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         if let Ok(reg_id) = parse_reg_id(value) {
-            return Ok(FlexibleOperand::RegisterWithShift(reg_id, Shift(0)));
+            Ok(FlexibleOperand::RegisterWithShift(Rm(reg_id), Shift::NONE))
         } else if value.contains('#') {
-            Ok(FlexibleOperand::ImmediateWithRotation(
-                value.replace('#', "").parse::<u8>()?,
-                Rotation(0),
-            ))
+            u32::try_from(parse_immediate(value)?)
+                .ok()
+                .and_then(Imm8Rot::encode)
+                .map(FlexibleOperand::ImmediateWithRotation)
+                .ok_or_else(|| ParseError::BadImmediate(value.to_owned()).into())
         } else {
             Err(AssemblerError::Parse(ParseError::BadFlexOperand(
                 value.to_string(),
@@ -101,9 +401,58 @@ pub enum SetConditionCodes {
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Offset {
-    RegisterWithShift(u8, Shift, UpDown),
-    // TODO: u12
-    Immediate(u16, UpDown),
+    RegisterWithShift(Rm, Shift, UpDown),
+    Immediate(U12, UpDown),
+}
+
+impl Offset {
+    /// Parse `#imm` or `{+|-}Rm{, shift}`, given the offset's operands.
+    fn parse(operands: &[&str]) -> Result<Self, AssemblerError> {
+        match operands {
+            [imm] if imm.starts_with('#') || parse_immediate(imm).is_ok() => {
+                let value = parse_immediate(imm)?;
+                let updown = if value < 0 { UpDown::Down } else { UpDown::Up };
+                let imm = u16::try_from(value.unsigned_abs())
+                    .ok()
+                    .and_then(U12::new)
+                    .ok_or_else(|| ParseError::BadImmediate((*imm).to_owned()))?;
+                Ok(Self::Immediate(imm, updown))
+            }
+            [rm, shift @ ..] if shift.len() <= 1 => {
+                let (updown, rm) = match rm.strip_prefix('-') {
+                    Some(rm) => (UpDown::Down, rm),
+                    None => (UpDown::Up, rm.strip_prefix('+').unwrap_or(rm)),
+                };
+                let rm = Rm(parse_reg_id(rm.trim())?);
+                Ok(Self::RegisterWithShift(
+                    rm,
+                    Shift::parse(shift.first().copied())?,
+                    updown,
+                ))
+            }
+            _ => Err(ParseError::BadFlexOperand(operands.join(", ")).into()),
+        }
+    }
+
+    /// The U bit, set when the offset is added.
+    fn up_bit(self) -> u32 {
+        match self {
+            Offset::RegisterWithShift(_, _, updown) | Offset::Immediate(_, updown) => {
+                match updown {
+                    UpDown::Up => 1 << 23,
+                    UpDown::Down => 0,
+                }
+            }
+        }
+    }
+
+    /// The 12-bit offset field.
+    fn bits(self) -> u32 {
+        match self {
+            Offset::RegisterWithShift(rm, shift, _) => (shift.bits() << 4) | rm.0 as u32,
+            Offset::Immediate(imm, _) => imm.bits(),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -112,6 +461,44 @@ pub enum UpDown {
     Down,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum BarrierOption {
+    SY,
+    ST,
+    ISH,
+    ISHST,
+    #[strum(serialize = "NSH", serialize = "UN")]
+    NSH,
+    #[strum(serialize = "NSHST", serialize = "UNST")]
+    NSHST,
+    OSH,
+    OSHST,
+}
+
+impl From<BarrierOption> for u8 {
+    fn from(value: BarrierOption) -> Self {
+        match value {
+            BarrierOption::SY => 0b1111,
+            BarrierOption::ST => 0b1110,
+            BarrierOption::ISH => 0b1011,
+            BarrierOption::ISHST => 0b1010,
+            BarrierOption::NSH => 0b0111,
+            BarrierOption::NSHST => 0b0110,
+            BarrierOption::OSH => 0b0011,
+            BarrierOption::OSHST => 0b0010,
+        }
+    }
+}
+
+/// The addressing of `ldc`/`stc`, with immediates counted in words.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CoprocessorOffset {
+    Immediate(IndexMode, UpDown, u8),
+    /// `[Rn], {option}`, passed to the coprocessor without changing Rn.
+    Unindexed(u8),
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum IndexMode {
     PostIndex,
@@ -119,6 +506,40 @@ pub enum IndexMode {
     PreIndex,
 }
 
+/// How an UNPREDICTABLE use of registers is reported.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    /// Assembled anyway, as other assemblers do, but with a warning.
+    Warning,
+    Error,
+}
+
+/// A use of registers that the architecture leaves UNPREDICTABLE.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Unpredictable {
+    pub severity: Severity,
+    pub reason: &'static str,
+}
+
+impl Unpredictable {
+    pub(crate) const fn error(reason: &'static str) -> Option<Self> {
+        Some(Self {
+            severity: Severity::Error,
+            reason,
+        })
+    }
+
+    pub(crate) const fn warning(reason: &'static str) -> Option<Self> {
+        Some(Self {
+            severity: Severity::Warning,
+            reason,
+        })
+    }
+}
+
+pub(crate) const SP: u8 = 13;
+pub(crate) const PC: u8 = 15;
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Instruction {
     // TODO: src vs dest register?
@@ -131,26 +552,89 @@ pub enum Instruction {
         FlexibleOperand,
     ),
     Mem(Cond, MemoryMnemonic, IndexMode, Rn, Rd, Offset),
-    Branch(Cond, BranchMnemonic, u32),
+    /// `push`/`pop` of a register list, with bit n of the mask set for rn.
+    Stack(Cond, StackMnemonic, u16),
+    /// Branches hold the offset of the target from the PC, which reads as the address of the
+    /// branch plus 8.
+    Branch(Cond, BranchMnemonic, BranchOffset),
     BranchExec(Cond, Rn),
     Mul(Cond, MultiplyMnemonic, SetConditionCodes, Rd, Rn, Rs, Rm),
+    MoveWide(Cond, MoveWideMnemonic, Rd, u16),
+    /// Bitfield ops with the lsb of the field, then its msb for `bfc`/`bfi` or its width - 1 for
+    /// `sbfx`/`ubfx`, as encoded. `bfc` uses Rn = 15.
+    Bitfield(Cond, BitfieldMnemonic, Rd, Rn, U5, U5),
+    Reverse(Cond, ReverseMnemonic, Rd, Rm),
+    Divide(Cond, DivideMnemonic, Rd, Rn, Rm),
+    Barrier(BarrierMnemonic, BarrierOption),
+    /// `ldrex{b,h,d} Rt, [Rn]`, with Rt stored in Rd.
+    LoadExclusive(Cond, ExclusiveMnemonic, Rd, Rn),
+    /// `strex{b,h,d} Rd, Rt, [Rn]`, with the status register in Rd and Rt stored in Rm.
+    StoreExclusive(Cond, ExclusiveMnemonic, Rd, Rm, Rn),
+    ClearExclusive,
+    Preload(PreloadMnemonic, Rn, Offset),
+    Hint(Cond, HintMnemonic),
+    Exception(Cond, ExceptionMnemonic, u16),
+    Vfp(Cond, VfpInstruction),
+    Neon(Cond, NeonInstruction),
+    /// `cdp{2} coproc, #opc1, CRd, CRn, CRm{, #opc2}`
+    CoprocessorData(Cond, CoprocessorMnemonic, Coproc, U4, CReg, CReg, CReg, U3),
+    /// `mcr{2}/mrc{2} coproc, #opc1, Rt, CRn, CRm{, #opc2}`, where `mrc` may write `APSR_nzcv`,
+    /// encoded as pc
+    CoprocessorRegister(Cond, CoprocessorMnemonic, Coproc, U3, Rd, CReg, CReg, U3),
+    /// `mcrr{2}/mrrc{2} coproc, #opc1, Rt, Rt2, CRm`
+    CoprocessorRegisterPair(Cond, CoprocessorMnemonic, Coproc, U4, Rd, Rn, CReg),
+    /// `ldc/stc{2}{l} coproc, CRd, [Rn...]`
+    CoprocessorMem(
+        Cond,
+        CoprocessorMnemonic,
+        Coproc,
+        CReg,
+        Rn,
+        CoprocessorOffset,
+    ),
 }
 
 impl TryFrom<&str> for Instruction {
     type Error = AssemblerError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let (opcode_cond, rest) = value
-            .trim()
-            .split_once(' ')
-            .ok_or(ParseError::RanOutOfOperands)?;
-        let mut operands = rest.split(",");
-        let mnemonic = Mnemonic::try_from(opcode_cond)?;
-        let cond_maybe = opcode_cond.replace(&mnemonic.to_string(), "");
+        let value = value.trim();
+        let (opcode_cond, rest) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+        if opcode_cond.starts_with(['v', 'V']) {
+            // Several mnemonics are shared, with the registers and data type deciding which
+            return match VfpInstruction::parse(value) {
+                Ok((cond, instruction)) => Ok(Self::Vfp(cond, instruction)),
+                Err(vfp_error) => match NeonInstruction::parse(value) {
+                    Ok((cond, instruction)) => Ok(Self::Neon(cond, instruction)),
+                    // Report the error from whichever knows the mnemonic
+                    Err(AssemblerError::Parse(ParseError::BadMnemonic(_))) => Err(vfp_error),
+                    Err(neon_error) => Err(neon_error),
+                },
+            };
+        }
+        let mut operands = rest.split(',');
+        let (mnemonic, set_flags) = match Mnemonic::try_from(opcode_cond) {
+            Ok(mnemonic) => (mnemonic, SetConditionCodes::DontSetCodes),
+            Err(err) => Mnemonic::parse_set_flags(opcode_cond)
+                .map(|mnemonic| (mnemonic, SetConditionCodes::SetCodes))
+                .ok_or(err)?,
+        };
+        let suffix_start = match set_flags {
+            SetConditionCodes::SetCodes => mnemonic.to_string().len() + 1,
+            SetConditionCodes::DontSetCodes => mnemonic.to_string().len(),
+        };
+        let cond_maybe = &opcode_cond[suffix_start..];
         let cond = if cond_maybe.is_empty() {
             Cond::AL
         } else {
-            Cond::try_from(cond_maybe.as_str())?
+            Cond::try_from(cond_maybe)?
+        };
+        let unconditional = || -> Result<(), Self::Error> {
+            if cond == Cond::AL {
+                Ok(())
+            } else {
+                Err(ParseError::UnexpectedCond(mnemonic.to_string()).into())
+            }
         };
 
         let mut get_next_op = || -> Result<&str, Self::Error> {
@@ -164,48 +648,50 @@ impl TryFrom<&str> for Instruction {
 
         match mnemonic {
             Mnemonic::Data(data_mnemonic) => {
-                let rd = Rd(get_reg_id()?);
-
-                let (rn, flex_op) = if data_mnemonic == DataMnemonic::MOV {
-                    (Rn(0), FlexibleOperand::try_from(get_next_op()?)?)
+                let operands = split_operands(rest);
+                let reg = |i: usize| -> Result<u8, Self::Error> {
+                    parse_reg_id(operands.get(i).ok_or(ParseError::RanOutOfOperands)?)
+                };
+                // Compares have no destination, moves have no first operand, and the others can
+                // leave out the first operand when it's the destination
+                let (rd, rn, src, set_flags) = if data_mnemonic.is_compare() {
+                    (Rd(0), Rn(reg(0)?), 1, SetConditionCodes::SetCodes)
+                } else if data_mnemonic.is_move() {
+                    (Rd(reg(0)?), Rn(0), 1, set_flags)
                 } else {
-                    (
-                        Rn(get_reg_id()?),
-                        FlexibleOperand::try_from(get_next_op()?)?,
-                    )
+                    let rd = Rd(reg(0)?);
+                    match operands.get(2) {
+                        Some(op) if op.starts_with('#') || parse_reg_id(op).is_ok() => {
+                            (rd, Rn(reg(1)?), 2, set_flags)
+                        }
+                        _ => (rd, Rn(rd.0), 1, set_flags),
+                    }
                 };
+                let flex_op = FlexibleOperand::parse(operands.get(src..).unwrap_or_default())?;
 
                 Ok(Self::DataProcessing(
                     cond,
                     data_mnemonic,
-                    SetConditionCodes::DontSetCodes,
+                    set_flags,
                     rd,
                     rn,
                     flex_op,
                 ))
             }
             Mnemonic::Mem(mem_mnemonic) => {
-                let rd = Rd(get_reg_id()?);
-                let rn = Rn(get_reg_id()?);
-
-                let mut offset = 0;
-                while let Some(maybe_offset) = operands.next() {
-                    // Should do something more robust, but this will do for handling brackets for now.
-                    // Should use something like the compiler lexer where i expand out special syms
-                    if let Ok(parsed_offset) = maybe_offset.replace(']', "").trim().parse::<u16>() {
-                        offset = parsed_offset;
-                    }
-                }
+                let operands = split_operands(rest);
+                let (rt, address) = match operands.as_slice() {
+                    [rt, address, ..] => (Rd(parse_reg_id(rt)?), *address),
+                    _ => return Err(ParseError::RanOutOfOperands.into()),
+                };
+                let (index_mode, rn, offset) = parse_address(address, &operands[2..])?;
 
-                Ok(Self::Mem(
-                    cond,
-                    mem_mnemonic,
-                    IndexMode::Offset,
-                    rn,
-                    rd,
-                    Offset::Immediate(offset, UpDown::Up),
-                ))
+                Ok(Self::Mem(cond, mem_mnemonic, index_mode, rn, rt, offset))
             }
+            Mnemonic::Stack(stack_mnemonic) => match parse_reg_list(rest)? {
+                0 => Err(ParseError::BadRegister(rest.to_owned()).into()),
+                mask => Ok(Self::Stack(cond, stack_mnemonic, mask)),
+            },
             Mnemonic::Mul(mul_mnemonic) => {
                 let rd = Rd(get_reg_id()?);
                 let reg_2_id = get_reg_id()?;
@@ -222,37 +708,282 @@ impl TryFrom<&str> for Instruction {
                     (rn, rm, rs)
                 };
 
-                Ok(Self::Mul(
-                    cond,
-                    mul_mnemonic,
-                    SetConditionCodes::DontSetCodes,
-                    rd,
-                    rn,
-                    rs,
-                    rm,
-                ))
+                Ok(Self::Mul(cond, mul_mnemonic, set_flags, rd, rn, rs, rm))
             }
             Mnemonic::Branch(b_mnemonic) => {
-                let offset = (get_next_op()?).parse::<u32>()?;
+                let offset = parse_immediate(get_next_op()?)?;
+                let offset = BranchOffset::new(offset)
+                    .ok_or_else(|| ParseError::BranchOutOfRange(offset.to_string(), "±32 MiB"))?;
                 Ok(Self::Branch(cond, b_mnemonic, offset))
             }
             Mnemonic::BranchExec(_bx_mnemonic) => {
                 let rn = Rn(get_reg_id()?);
                 Ok(Self::BranchExec(cond, rn))
             }
+            Mnemonic::MoveWide(movw_mnemonic) => {
+                let rd = Rd(parse_reg_id(get_next_op()?)?);
+                let imm = parse_move_wide_immediate(get_next_op()?)?;
+                Ok(Self::MoveWide(cond, movw_mnemonic, rd, imm))
+            }
+            Mnemonic::Bitfield(bf_mnemonic) => {
+                let rd = Rd(parse_reg_id(get_next_op()?)?);
+                let rn = if bf_mnemonic == BitfieldMnemonic::BFC {
+                    Rn(15)
+                } else {
+                    Rn(parse_reg_id(get_next_op()?)?)
+                };
+                let lsb_op = get_next_op()?;
+                let lsb = parse_bounded_immediate(lsb_op, 31)?;
+                let width_op = get_next_op()?;
+                let width = parse_bounded_immediate(width_op, 32 - lsb)?;
+                // Insert/clear encode the msb, extracts encode width - 1
+                let field = match bf_mnemonic {
+                    BitfieldMnemonic::BFC | BitfieldMnemonic::BFI => (lsb + width).checked_sub(1),
+                    BitfieldMnemonic::SBFX | BitfieldMnemonic::UBFX => width.checked_sub(1),
+                };
+                let field = field
+                    .filter(|_| width > 0)
+                    .and_then(|field| U5::new(field as u8))
+                    .ok_or_else(|| ParseError::BadImmediate(width_op.to_owned()))?;
+                let lsb = U5::new(lsb as u8)
+                    .ok_or_else(|| ParseError::BadImmediate(lsb_op.to_owned()))?;
+                Ok(Self::Bitfield(cond, bf_mnemonic, rd, rn, lsb, field))
+            }
+            Mnemonic::Reverse(rev_mnemonic) => {
+                let rd = Rd(get_reg_id()?);
+                let rm = Rm(get_reg_id()?);
+                Ok(Self::Reverse(cond, rev_mnemonic, rd, rm))
+            }
+            Mnemonic::Divide(div_mnemonic) => {
+                let rd = Rd(get_reg_id()?);
+                let rn = Rn(get_reg_id()?);
+                let rm = Rm(get_reg_id()?);
+                Ok(Self::Divide(cond, div_mnemonic, rd, rn, rm))
+            }
+            Mnemonic::Barrier(barrier_mnemonic) => {
+                unconditional()?;
+                let option = match get_next_op()? {
+                    "" => BarrierOption::SY,
+                    option => BarrierOption::try_from(option)?,
+                };
+                if barrier_mnemonic == BarrierMnemonic::ISB && option != BarrierOption::SY {
+                    return Err(ParseError::BadFlexOperand(format!("{option:?}")).into());
+                }
+                Ok(Self::Barrier(barrier_mnemonic, option))
+            }
+            Mnemonic::Exclusive(ExclusiveMnemonic::CLREX) => {
+                unconditional()?;
+                Ok(Self::ClearExclusive)
+            }
+            Mnemonic::Exclusive(ex_mnemonic) => {
+                let status = if ex_mnemonic.is_load() {
+                    None
+                } else {
+                    Some(Rd(parse_reg_id(get_next_op()?)?))
+                };
+                let rt_op = get_next_op()?;
+                let rt = parse_reg_id(rt_op)?;
+                if ex_mnemonic.is_doubleword() {
+                    // The second register is implied by the encoding, but must still be written.
+                    let rt2_op = get_next_op()?;
+                    if rt % 2 != 0 || rt == 14 {
+                        return Err(ParseError::BadRegister(rt_op.to_owned()).into());
+                    }
+                    if parse_reg_id(rt2_op)? != rt + 1 {
+                        return Err(ParseError::BadRegister(rt2_op.to_owned()).into());
+                    }
+                }
+                let rn = Rn(parse_base_reg(get_next_op()?)?);
+
+                match status {
+                    None => Ok(Self::LoadExclusive(cond, ex_mnemonic, Rd(rt), rn)),
+                    Some(rd) => Ok(Self::StoreExclusive(cond, ex_mnemonic, rd, Rm(rt), rn)),
+                }
+            }
+            Mnemonic::Preload(pld_mnemonic) => {
+                unconditional()?;
+                let (index_mode, rn, offset) = match split_operands(rest).as_slice() {
+                    [address] => parse_address(address, &[])?,
+                    _ => return Err(ParseError::BadFlexOperand(rest.trim().to_owned()).into()),
+                };
+                // Preloads have no writeback
+                if index_mode != IndexMode::Offset {
+                    return Err(ParseError::BadFlexOperand(rest.trim().to_owned()).into());
+                }
+                Ok(Self::Preload(pld_mnemonic, rn, offset))
+            }
+            Mnemonic::Hint(hint_mnemonic) => Ok(Self::Hint(cond, hint_mnemonic)),
+            Mnemonic::Exception(exc_mnemonic) => {
+                let max = match exc_mnemonic {
+                    ExceptionMnemonic::SMC => 0xF,
+                    ExceptionMnemonic::HVC => {
+                        unconditional()?;
+                        0xFFFF
+                    }
+                };
+                let imm = parse_bounded_immediate(get_next_op()?, max)?;
+                Ok(Self::Exception(cond, exc_mnemonic, imm as u16))
+            }
+            Mnemonic::Coprocessor(cp_mnemonic) => {
+                if cp_mnemonic.is_unconditional() {
+                    unconditional()?;
+                }
+                Self::parse_coprocessor(cond, cp_mnemonic, rest)
+            }
         }
     }
 }
 
 impl Instruction {
+    fn parse_coprocessor(
+        cond: Cond,
+        cp_mnemonic: CoprocessorMnemonic,
+        rest: &str,
+    ) -> Result<Self, AssemblerError> {
+        use CoprocessorMnemonic::*;
+
+        let operands = split_operands(rest);
+        let bad_operands = || ParseError::BadFlexOperand(rest.trim().to_owned());
+        let op = |i: usize| -> Result<&str, AssemblerError> {
+            Ok(operands.get(i).ok_or(ParseError::RanOutOfOperands)?)
+        };
+        let coproc = parse_coproc(op(0)?)?;
+
+        match cp_mnemonic {
+            CDP | CDP2 => {
+                let opc1 = parse_field(op(1)?, U4::new)?;
+                let (crd, crn, crm) = (
+                    parse_coproc_reg(op(2)?)?,
+                    parse_coproc_reg(op(3)?)?,
+                    parse_coproc_reg(op(4)?)?,
+                );
+                let opc2 = match operands.get(5) {
+                    Some(opc2) => parse_field(opc2, U3::new)?,
+                    None => U3::ZERO,
+                };
+                Ok(Self::CoprocessorData(
+                    cond,
+                    cp_mnemonic,
+                    coproc,
+                    opc1,
+                    crd,
+                    crn,
+                    crm,
+                    opc2,
+                ))
+            }
+            MCR | MCR2 | MRC | MRC2 => {
+                let opc1 = parse_field(op(1)?, U3::new)?;
+                let rt = match op(2)? {
+                    apsr if cp_mnemonic.is_load() && apsr.eq_ignore_ascii_case("apsr_nzcv") => 15,
+                    rt => parse_reg_id(rt)?,
+                };
+                let (crn, crm) = (parse_coproc_reg(op(3)?)?, parse_coproc_reg(op(4)?)?);
+                let opc2 = match operands.get(5) {
+                    Some(opc2) => parse_field(opc2, U3::new)?,
+                    None => U3::ZERO,
+                };
+                Ok(Self::CoprocessorRegister(
+                    cond,
+                    cp_mnemonic,
+                    coproc,
+                    opc1,
+                    Rd(rt),
+                    crn,
+                    crm,
+                    opc2,
+                ))
+            }
+            MCRR | MCRR2 | MRRC | MRRC2 => {
+                let opc1 = parse_field(op(1)?, U4::new)?;
+                let (rt, rt2) = (parse_reg_id(op(2)?)?, parse_reg_id(op(3)?)?);
+                let crm = parse_coproc_reg(op(4)?)?;
+                Ok(Self::CoprocessorRegisterPair(
+                    cond,
+                    cp_mnemonic,
+                    coproc,
+                    opc1,
+                    Rd(rt),
+                    Rn(rt2),
+                    crm,
+                ))
+            }
+            _ => {
+                let crd = parse_coproc_reg(op(1)?)?;
+                let address = op(2)?;
+                let (address, writeback) = match address.strip_suffix('!') {
+                    Some(address) => (address.trim(), true),
+                    None => (address, false),
+                };
+                let inner = address
+                    .strip_prefix('[')
+                    .and_then(|address| address.strip_suffix(']'))
+                    .ok_or_else(bad_operands)?;
+                let (base, offset) = match inner.split_once(',') {
+                    Some((base, offset)) => (base, Some(offset.trim())),
+                    None => (inner, None),
+                };
+
+                // Offsets are in words and the option is passed through as is
+                let parse_offset = |offset: &str| -> Result<(UpDown, u8), AssemblerError> {
+                    let imm = parse_immediate(offset)?;
+                    if imm % 4 != 0 || imm.unsigned_abs() > 1020 {
+                        return Err(ParseError::BadImmediate(offset.to_owned()).into());
+                    }
+                    let updown = if imm < 0 { UpDown::Down } else { UpDown::Up };
+                    Ok((updown, (imm.unsigned_abs() / 4) as u8))
+                };
+                let offset = match (offset, writeback, operands.get(3)) {
+                    (Some(offset), _, None) => {
+                        let (updown, imm) = parse_offset(offset)?;
+                        let mode = if writeback {
+                            IndexMode::PreIndex
+                        } else {
+                            IndexMode::Offset
+                        };
+                        CoprocessorOffset::Immediate(mode, updown, imm)
+                    }
+                    (None, false, None) => {
+                        CoprocessorOffset::Immediate(IndexMode::Offset, UpDown::Up, 0)
+                    }
+                    (None, false, Some(option)) if option.starts_with('{') => {
+                        let option = option
+                            .strip_prefix('{')
+                            .and_then(|option| option.strip_suffix('}'))
+                            .ok_or_else(bad_operands)?;
+                        CoprocessorOffset::Unindexed(parse_bounded_immediate(option, 0xFF)? as u8)
+                    }
+                    (None, false, Some(offset)) => {
+                        let (updown, imm) = parse_offset(offset)?;
+                        CoprocessorOffset::Immediate(IndexMode::PostIndex, updown, imm)
+                    }
+                    _ => return Err(bad_operands().into()),
+                };
+
+                Ok(Self::CoprocessorMem(
+                    cond,
+                    cp_mnemonic,
+                    coproc,
+                    crd,
+                    Rn(parse_reg_id(base.trim())?),
+                    offset,
+                ))
+            }
+        }
+    }
+
+    /// The 32-bit encoding, independent of the byte order it ends up stored in.
     pub fn to_machine_code(self) -> u32 {
-        let encoding = match self {
+        match self {
             Instruction::DataProcessing(cond, dp_mnemonic, set_condition_codes, rd, rn, op2) => {
                 Self::encode_dp_inst(cond, dp_mnemonic, set_condition_codes, rd, rn, op2)
             }
             Instruction::Mem(cond, opcode, index_mode, rn, rd, offset) => {
                 Self::encode_mem_inst(cond, opcode, index_mode, rn, rd, offset)
             }
+            Instruction::Stack(cond, stack_mnemonic, mask) => {
+                Self::encode_stack_inst(cond, stack_mnemonic, mask)
+            }
             Instruction::Mul(cond, mul_mnemonic, set_condition_codes, rd, rn, rs, rm) => {
                 Self::encode_mul_inst(cond, mul_mnemonic, set_condition_codes, rd, rn, rs, rm)
             }
@@ -260,206 +991,736 @@ impl Instruction {
                 Self::encode_branch_inst(cond, b_mnemonic, offset)
             }
             Instruction::BranchExec(cond, rn) => Self::encode_branch_exec_inst(cond, rn),
+            Instruction::MoveWide(cond, movw_mnemonic, rd, imm) => {
+                Self::encode_move_wide_inst(cond, movw_mnemonic, rd, imm)
+            }
+            Instruction::Bitfield(cond, bf_mnemonic, rd, rn, lsb, width) => {
+                Self::encode_bitfield_inst(cond, bf_mnemonic, rd, rn, lsb, width)
+            }
+            Instruction::Reverse(cond, _rev_mnemonic, rd, rm) => {
+                Self::encode_reverse_inst(cond, rd, rm)
+            }
+            Instruction::Divide(cond, div_mnemonic, rd, rn, rm) => {
+                Self::encode_divide_inst(cond, div_mnemonic, rd, rn, rm)
+            }
+            Instruction::Barrier(barrier_mnemonic, option) => {
+                Self::encode_barrier_inst(barrier_mnemonic, option)
+            }
+            Instruction::LoadExclusive(cond, ex_mnemonic, rd, rn) => {
+                Self::encode_exclusive_inst(cond, ex_mnemonic, rn, rd.0, 0b1111)
+            }
+            Instruction::StoreExclusive(cond, ex_mnemonic, rd, rm, rn) => {
+                Self::encode_exclusive_inst(cond, ex_mnemonic, rn, rd.0, rm.0)
+            }
+            Instruction::ClearExclusive => 0xF5_7F_F0_1F,
+            Instruction::Preload(pld_mnemonic, rn, offset) => {
+                Self::encode_preload_inst(pld_mnemonic, rn, offset)
+            }
+            Instruction::Hint(cond, hint_mnemonic) => Self::encode_hint_inst(cond, hint_mnemonic),
+            Instruction::Exception(cond, exc_mnemonic, imm) => {
+                Self::encode_exception_inst(cond, exc_mnemonic, imm)
+            }
+            Instruction::Vfp(cond, ref instruction) => instruction.to_machine_code(cond),
+            Instruction::Neon(cond, ref instruction) => instruction.to_machine_code(cond),
+            Instruction::CoprocessorData(cond, cp_mnemonic, coproc, opc1, crd, crn, crm, opc2) => {
+                Self::encode_coprocessor_inst(
+                    cond,
+                    cp_mnemonic,
+                    coproc,
+                    (opc1.bits(), opc2),
+                    crd.0.get(),
+                    crn,
+                    crm,
+                )
+            }
+            Instruction::CoprocessorRegister(
+                cond,
+                cp_mnemonic,
+                coproc,
+                opc1,
+                rt,
+                crn,
+                crm,
+                opc2,
+            ) => Self::encode_coprocessor_inst(
+                cond,
+                cp_mnemonic,
+                coproc,
+                (opc1.bits(), opc2),
+                rt.0,
+                crn,
+                crm,
+            ),
+            Instruction::CoprocessorRegisterPair(cond, cp_mnemonic, coproc, opc1, rt, rt2, crm) => {
+                Self::encode_coprocessor_pair_inst(cond, cp_mnemonic, coproc, opc1, rt, rt2, crm)
+            }
+            Instruction::CoprocessorMem(cond, cp_mnemonic, coproc, crd, rn, offset) => {
+                Self::encode_coprocessor_mem_inst(cond, cp_mnemonic, coproc, crd, rn, offset)
+            }
+        }
+    }
+
+    /// The architecture feature this instruction needs beyond the ARMv4 baseline, if any.
+    pub fn required_feature(&self) -> Option<Feature> {
+        match self {
+            Instruction::DataProcessing(..)
+            | Instruction::Mem(..)
+            | Instruction::Stack(..)
+            | Instruction::Branch(..)
+            | Instruction::Mul(..) => None,
+            Instruction::BranchExec(..) => Some(Feature::V4T),
+            Instruction::MoveWide(..) | Instruction::Bitfield(..) | Instruction::Reverse(..) => {
+                Some(Feature::V6T2)
+            }
+            Instruction::Divide(..) => Some(Feature::Idiv),
+            Instruction::Barrier(..) => Some(Feature::V7),
+            Instruction::LoadExclusive(_, ex_mnemonic, ..)
+            | Instruction::StoreExclusive(_, ex_mnemonic, ..) => match ex_mnemonic {
+                ExclusiveMnemonic::LDREX | ExclusiveMnemonic::STREX => Some(Feature::V6),
+                _ => Some(Feature::V6K),
+            },
+            Instruction::ClearExclusive | Instruction::Hint(..) => Some(Feature::V6K),
+            Instruction::Preload(pld_mnemonic, ..) => match pld_mnemonic {
+                PreloadMnemonic::PLD => Some(Feature::V5TE),
+                PreloadMnemonic::PLDW => Some(Feature::Mp),
+                PreloadMnemonic::PLI => Some(Feature::V7),
+            },
+            Instruction::Exception(_, exc_mnemonic, _) => match exc_mnemonic {
+                ExceptionMnemonic::SMC => Some(Feature::Security),
+                ExceptionMnemonic::HVC => Some(Feature::Virtualization),
+            },
+            Instruction::Vfp(_, instruction) => instruction.required_features().first().copied(),
+            Instruction::Neon(_, instruction) => Some(instruction.required_feature()),
+            Instruction::CoprocessorRegisterPair(_, cp_mnemonic, ..) => {
+                if cp_mnemonic.is_unconditional() {
+                    Some(Feature::V6)
+                } else {
+                    Some(Feature::V5TE)
+                }
+            }
+            Instruction::CoprocessorData(_, cp_mnemonic, ..)
+            | Instruction::CoprocessorRegister(_, cp_mnemonic, ..)
+            | Instruction::CoprocessorMem(_, cp_mnemonic, ..) => {
+                cp_mnemonic.is_unconditional().then_some(Feature::V5TE)
+            }
+        }
+    }
+
+    /// Every feature this instruction needs. Only VFP instructions can need more than one, such
+    /// as VFPv3 and the registers d16-d31. NEON always comes with all 32 double registers.
+    pub fn required_features(&self) -> Vec<Feature> {
+        match self {
+            Instruction::Vfp(_, instruction) => instruction.required_features(),
+            _ => self.required_feature().into_iter().collect(),
+        }
+    }
+
+    /// What to assemble for this on `target`. Without the architectural hints, `nop` becomes
+    /// `mov r0, r0` as in GNU as.
+    pub fn for_target(self, target: &Target) -> Self {
+        match self {
+            Instruction::Hint(cond, HintMnemonic::NOP) if !target.supports(Feature::V6K) => {
+                Instruction::DataProcessing(
+                    cond,
+                    DataMnemonic::MOV,
+                    SetConditionCodes::DontSetCodes,
+                    Rd(0),
+                    Rn(0),
+                    FlexibleOperand::RegisterWithShift(Rm(0), Shift::NONE),
+                )
+            }
+            instruction => instruction,
+        }
+    }
+
+    /// Whether `target` leaves what this does with its registers UNPREDICTABLE.
+    pub fn unpredictable(&self, target: &Target) -> Option<Unpredictable> {
+        match *self {
+            Instruction::Mul(.., rd, _, rs, rm) => {
+                if [rd.0, rs.0, rm.0].contains(&PC) {
+                    Unpredictable::error("pc can't be an operand of a multiply")
+                } else if rd.0 == rm.0 && !target.supports(Feature::V6) {
+                    Unpredictable::warning("Rd and Rm must differ before ARMv6")
+                } else {
+                    None
+                }
+            }
+            Instruction::Mem(_, _, IndexMode::PreIndex | IndexMode::PostIndex, rn, rt, _) => {
+                if rn.0 == PC {
+                    Unpredictable::error("pc can't be written back")
+                } else if rn.0 == rt.0 {
+                    Unpredictable::warning("the base register is written back and transferred")
+                } else {
+                    None
+                }
+            }
+            Instruction::Stack(_, _, mask) if mask & (1 << SP) != 0 => {
+                if target.supports(Feature::V7) {
+                    Unpredictable::error("sp can't be in the register list from ARMv7")
+                } else if mask == 1 << SP {
+                    // A single register is pushed or popped with writeback to sp
+                    Unpredictable::warning("the base register is written back and transferred")
+                } else {
+                    None
+                }
+            }
+            Instruction::Divide(_, _, rd, rn, rm) if [rd.0, rn.0, rm.0].contains(&PC) => {
+                Unpredictable::error("pc can't be an operand of a divide")
+            }
+            Instruction::MoveWide(_, _, rd, _) | Instruction::Bitfield(_, _, rd, ..)
+                if rd.0 == PC =>
+            {
+                Unpredictable::error("pc can't be the destination")
+            }
+            Instruction::Bitfield(
+                _,
+                BitfieldMnemonic::BFC | BitfieldMnemonic::BFI,
+                _,
+                _,
+                lsb,
+                msb,
+            ) if msb < lsb => Unpredictable::error("the field ends before it starts"),
+            Instruction::DataProcessing(
+                ..,
+                rd,
+                rn,
+                FlexibleOperand::RegisterShiftedRegister(rm, _, rs),
+            ) if [rd.0, rn.0, rm.0, rs.0].contains(&PC) => {
+                Unpredictable::error("pc can't be an operand of a register-shifted register")
+            }
+            Instruction::Bitfield(
+                _,
+                BitfieldMnemonic::SBFX | BitfieldMnemonic::UBFX,
+                _,
+                _,
+                lsb,
+                width,
+            ) if lsb.bits() + width.bits() > 31 => {
+                Unpredictable::error("the field runs past bit 31")
+            }
+            Instruction::Reverse(_, _, rd, rm) if rd.0 == PC || rm.0 == PC => {
+                Unpredictable::error("pc can't be an operand of a byte reverse")
+            }
+            Instruction::LoadExclusive(_, _, rt, rn) if rt.0 == PC || rn.0 == PC => {
+                Unpredictable::error("pc can't be an operand of an exclusive load")
+            }
+            Instruction::StoreExclusive(_, _, rd, rt, rn) => {
+                if [rd.0, rt.0, rn.0].contains(&PC) {
+                    Unpredictable::error("pc can't be an operand of an exclusive store")
+                } else if rd.0 == rt.0 || rd.0 == rn.0 {
+                    Unpredictable::error("the status register must differ from the others")
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn encode_dp_inst(
+        cond: Cond,
+        dp_mnemonic: DataMnemonic,
+        set_condition_codes: SetConditionCodes,
+        rd: Rd,
+        rn: Rn,
+        op2: FlexibleOperand,
+    ) -> u32 {
+        let mut encoding: u32 = 0;
+
+        let cond_mask = (cond as u8 as u32) << 28;
+        encoding |= cond_mask;
+
+        let i_mask = match op2 {
+            FlexibleOperand::RegisterWithShift(..)
+            | FlexibleOperand::RegisterShiftedRegister(..) => 0,
+            FlexibleOperand::ImmediateWithRotation(_) => 1 << 25,
+        };
+        encoding |= i_mask;
+
+        let dp_mnemonic_mask = (dp_mnemonic as u8 as u32) << 21;
+        encoding |= dp_mnemonic_mask;
+
+        let s_mask = match set_condition_codes {
+            SetConditionCodes::SetCodes => 1 << 20,
+            SetConditionCodes::DontSetCodes => 0,
+        };
+        encoding |= s_mask;
+
+        let rn_mask = (rn.0 as u32) << 16;
+        encoding |= rn_mask;
+
+        let rd_mask = (rd.0 as u32) << 12;
+        encoding |= rd_mask;
+
+        let op2_mask = op2.bits();
+        encoding |= op2_mask;
+
+        encoding
+    }
+
+    fn encode_stack_inst(cond: Cond, stack_mnemonic: StackMnemonic, mask: u16) -> u32 {
+        let cond_mask = (cond as u8 as u32) << 28;
+
+        // A single register is moved with `str rt, [sp, #-4]!` or `ldr rt, [sp], #4`, as other
+        // assemblers do
+        if mask.count_ones() == 1 {
+            let rt_mask = mask.trailing_zeros() << 12;
+            return match stack_mnemonic {
+                StackMnemonic::PUSH => cond_mask | 0x052D_0004 | rt_mask,
+                StackMnemonic::POP => cond_mask | 0x049D_0004 | rt_mask,
+            };
+        }
+
+        // stmdb sp!, {...} and ldmia sp!, {...}
+        let opcode_mask = match stack_mnemonic {
+            StackMnemonic::PUSH => 0x092D_0000,
+            StackMnemonic::POP => 0x08BD_0000,
         };
+        cond_mask | opcode_mask | u32::from(mask)
+    }
+
+    fn encode_mem_inst(
+        cond: Cond,
+        mem_mnemonic: MemoryMnemonic,
+        index_mode: IndexMode,
+        rn: Rn,
+        rd: Rd,
+        offset: Offset,
+    ) -> u32 {
+        let mut encoding: u32 = 0;
+
+        let cond_mask = (cond as u8 as u32) << 28;
+        encoding |= cond_mask;
+
+        // not sure what to call this?
+        let random_bit_mask = 1 << 26;
+        encoding |= random_bit_mask;
+
+        let i_mask = match offset {
+            Offset::RegisterWithShift(..) => 1 << 25,
+            Offset::Immediate(..) => 0,
+        };
+        encoding |= i_mask;
+
+        let p_mask = match index_mode {
+            IndexMode::PostIndex => 0,
+            IndexMode::Offset | IndexMode::PreIndex => 1 << 24,
+        };
+        encoding |= p_mask;
+
+        let u_mask = offset.up_bit();
+        encoding |= u_mask;
+
+        let b_mask = match mem_mnemonic {
+            MemoryMnemonic::STR | MemoryMnemonic::LDR => 0,
+            MemoryMnemonic::STRB | MemoryMnemonic::LDRB => 1 << 22,
+        };
+        encoding |= b_mask;
+
+        let w_mask = match index_mode {
+            IndexMode::PostIndex | IndexMode::Offset => 0,
+            IndexMode::PreIndex => 1 << 21,
+        };
+        encoding |= w_mask;
+
+        let l_mask = match mem_mnemonic {
+            MemoryMnemonic::STR | MemoryMnemonic::STRB => 0,
+            MemoryMnemonic::LDR | MemoryMnemonic::LDRB => 1 << 20,
+        };
+        encoding |= l_mask;
+
+        let rn_mask = (rn.0 as u32) << 16;
+        encoding |= rn_mask;
+
+        let rd_mask = (rd.0 as u32) << 12;
+        encoding |= rd_mask;
+
+        let offset_mask = offset.bits();
+        encoding |= offset_mask;
+
+        encoding
+    }
+
+    fn encode_mul_inst(
+        cond: Cond,
+        mul_mnemonic: MultiplyMnemonic,
+        set_condition_codes: SetConditionCodes,
+        rd: Rd,
+        rn: Rn,
+        rs: Rs,
+        rm: Rm,
+    ) -> u32 {
+        let mut encoding: u32 = 0;
+
+        let cond_mask = (cond as u8 as u32) << 28;
+        encoding |= cond_mask;
+
+        let a_mask = match mul_mnemonic {
+            MultiplyMnemonic::MUL => 0,
+        };
+        encoding |= a_mask;
+
+        let s_mask = match set_condition_codes {
+            SetConditionCodes::SetCodes => 1 << 20,
+            SetConditionCodes::DontSetCodes => 0,
+        };
+        encoding |= s_mask;
+
+        let rd_mask = (rd.0 as u32) << 16;
+        encoding |= rd_mask;
+
+        let rn_mask = (rn.0 as u32) << 12;
+        encoding |= rn_mask;
+
+        let rs_mask = (rs.0 as u32) << 8;
+        encoding |= rs_mask;
+
+        let magic_bits = 0b1001_u32 << 4;
+        encoding |= magic_bits;
+
+        let rm_mask = rm.0 as u32;
+        encoding |= rm_mask;
+
+        encoding
+    }
+
+    fn encode_branch_inst(cond: Cond, b_mnemonic: BranchMnemonic, offset: BranchOffset) -> u32 {
+        let mut encoding: u32 = 0;
+
+        let cond_mask = (cond as u8 as u32) << 28;
+        encoding |= cond_mask;
+
+        let magic_bits = 0b101_u32 << 25;
+        encoding |= magic_bits;
+
+        let link_mask = match b_mnemonic {
+            BranchMnemonic::B => 0,
+            BranchMnemonic::BL => 1 << 24,
+        };
+        encoding |= link_mask;
+
+        let offset_mask = offset.bits();
+        encoding |= offset_mask;
+
+        encoding
+    }
+
+    fn encode_branch_exec_inst(cond: Cond, rn: Rn) -> u32 {
+        let mut encoding: u32 = 0;
+
+        let cond_mask = (cond as u8 as u32) << 28;
+        encoding |= cond_mask;
+
+        let magic_bits = 0b0001_0010_1111_1111_1111_0001 << 4;
+        encoding |= magic_bits;
+
+        encoding |= rn.0 as u32;
+
+        encoding
+    }
+
+    fn encode_move_wide_inst(cond: Cond, movw_mnemonic: MoveWideMnemonic, rd: Rd, imm: u16) -> u32 {
+        let mut encoding: u32 = 0;
+
+        let cond_mask = (cond as u8 as u32) << 28;
+        encoding |= cond_mask;
+
+        let magic_bits = 0b0011_u32 << 24;
+        encoding |= magic_bits;
+
+        let top_mask = match movw_mnemonic {
+            MoveWideMnemonic::MOVW => 0,
+            MoveWideMnemonic::MOVT => 1 << 22,
+        };
+        encoding |= top_mask;
+
+        // The immediate is split into imm4:imm12 around Rd
+        let imm4_mask = ((imm as u32) >> 12) << 16;
+        encoding |= imm4_mask;
+
+        let rd_mask = (rd.0 as u32) << 12;
+        encoding |= rd_mask;
+
+        let imm12_mask = (imm as u32) & 0x0FFF;
+        encoding |= imm12_mask;
+
+        encoding
+    }
+
+    fn encode_bitfield_inst(
+        cond: Cond,
+        bf_mnemonic: BitfieldMnemonic,
+        rd: Rd,
+        rn: Rn,
+        lsb: U5,
+        field: U5,
+    ) -> u32 {
+        let mut encoding: u32 = 0;
+
+        let cond_mask = (cond as u8 as u32) << 28;
+        encoding |= cond_mask;
+
+        let (op_bits, low_bits) = match bf_mnemonic {
+            BitfieldMnemonic::BFC | BitfieldMnemonic::BFI => (0b011_1110, 0b001),
+            BitfieldMnemonic::SBFX => (0b011_1101, 0b101),
+            BitfieldMnemonic::UBFX => (0b011_1111, 0b101),
+        };
+        encoding |= (op_bits as u32) << 21;
+
+        let width_mask = field.bits() << 16;
+        encoding |= width_mask;
+
+        let rd_mask = (rd.0 as u32) << 12;
+        encoding |= rd_mask;
+
+        let lsb_mask = lsb.bits() << 7;
+        encoding |= lsb_mask;
+
+        encoding |= (low_bits as u32) << 4;
+
+        encoding |= rn.0 as u32;
+
+        encoding
+    }
+
+    fn encode_reverse_inst(cond: Cond, rd: Rd, rm: Rm) -> u32 {
+        let mut encoding: u32 = 0;
+
+        let cond_mask = (cond as u8 as u32) << 28;
+        encoding |= cond_mask;
+
+        let magic_bits = 0x06_FF_0F_30;
+        encoding |= magic_bits;
 
-        encoding.swap_bytes()
+        let rd_mask = (rd.0 as u32) << 12;
+        encoding |= rd_mask;
+
+        encoding |= rm.0 as u32;
+
+        encoding
     }
 
-    fn encode_dp_inst(
-        cond: Cond,
-        dp_mnemonic: DataMnemonic,
-        set_condition_codes: SetConditionCodes,
-        rd: Rd,
-        rn: Rn,
-        op2: FlexibleOperand,
-    ) -> u32 {
+    fn encode_divide_inst(cond: Cond, div_mnemonic: DivideMnemonic, rd: Rd, rn: Rn, rm: Rm) -> u32 {
         let mut encoding: u32 = 0;
 
         let cond_mask = (cond as u8 as u32) << 28;
         encoding |= cond_mask;
 
-        let i_mask = match op2 {
-            FlexibleOperand::RegisterWithShift(_, _) => 0,
-            FlexibleOperand::ImmediateWithRotation(_, _) => 1 << 25,
+        let magic_bits = 0x07_10_F0_10;
+        encoding |= magic_bits;
+
+        let unsigned_mask = match div_mnemonic {
+            DivideMnemonic::SDIV => 0,
+            DivideMnemonic::UDIV => 1 << 21,
         };
-        encoding |= i_mask;
+        encoding |= unsigned_mask;
 
-        let dp_mnemonic_mask = (dp_mnemonic as u8 as u32) << 21;
-        encoding |= dp_mnemonic_mask;
+        // Note that division puts Rd where most instructions have Rn
+        let rd_mask = (rd.0 as u32) << 16;
+        encoding |= rd_mask;
 
-        let s_mask = match set_condition_codes {
-            SetConditionCodes::SetCodes => 1 << 20,
-            SetConditionCodes::DontSetCodes => 0,
-        };
-        encoding |= s_mask;
+        let rm_mask = (rm.0 as u32) << 8;
+        encoding |= rm_mask;
 
-        let rn_mask = (rn.0 as u32) << 16;
-        encoding |= rn_mask;
+        encoding |= rn.0 as u32;
 
-        let rd_mask = (rd.0 as u32) << 12;
-        encoding |= rd_mask;
+        encoding
+    }
 
-        let op2_mask = match op2 {
-            FlexibleOperand::RegisterWithShift(reg, shift) => {
-                ((shift.0 as u32) << 4) | (reg as u32)
-            }
-            FlexibleOperand::ImmediateWithRotation(imm, rotation) => {
-                ((rotation.0 as u32) << 8) | (imm as u32)
-            }
-        };
-        encoding |= op2_mask;
+    fn encode_barrier_inst(barrier_mnemonic: BarrierMnemonic, option: BarrierOption) -> u32 {
+        let mut encoding: u32 = 0xF5_7F_F0_00;
+
+        encoding |= (u8::from(barrier_mnemonic) as u32) << 4;
+        encoding |= u8::from(option) as u32;
 
         encoding
     }
 
-    fn encode_mem_inst(
+    fn encode_exclusive_inst(
         cond: Cond,
-        mem_mnemonic: MemoryMnemonic,
-        index_mode: IndexMode,
+        ex_mnemonic: ExclusiveMnemonic,
         rn: Rn,
-        rd: Rd,
-        offset: Offset,
+        rd: u8,
+        rt: u8,
     ) -> u32 {
         let mut encoding: u32 = 0;
 
         let cond_mask = (cond as u8 as u32) << 28;
         encoding |= cond_mask;
 
-        // not sure what to call this?
-        let random_bit_mask = 1 << 26;
-        encoding |= random_bit_mask;
+        encoding |= 1 << 24;
+        encoding |= (u8::from(ex_mnemonic) as u32) << 20;
 
-        let i_mask = match offset {
-            Offset::RegisterWithShift(..) => 1 << 25,
-            Offset::Immediate(..) => 0,
-        };
-        encoding |= i_mask;
+        let l_mask = if ex_mnemonic.is_load() { 1 << 20 } else { 0 };
+        encoding |= l_mask;
 
-        let p_mask = match index_mode {
-            IndexMode::PostIndex => 0,
-            IndexMode::Offset | IndexMode::PreIndex => 1 << 24,
-        };
-        encoding |= p_mask;
+        let rn_mask = (rn.0 as u32) << 16;
+        encoding |= rn_mask;
 
-        let u_mask = match offset {
-            Offset::RegisterWithShift(_, _, updown) | Offset::Immediate(_, updown) => {
-                match updown {
-                    UpDown::Up => 1 << 23,
-                    UpDown::Down => 0,
-                }
-            }
-        };
-        encoding |= u_mask;
+        let rd_mask = (rd as u32) << 12;
+        encoding |= rd_mask;
 
-        let b_mask = match mem_mnemonic {
-            MemoryMnemonic::STR | MemoryMnemonic::LDR => 0,
-            MemoryMnemonic::STRB | MemoryMnemonic::LDRB => 1 << 22,
-        };
-        encoding |= b_mask;
+        let magic_bits = 0b1111_1001 << 4;
+        encoding |= magic_bits;
 
-        let w_mask = match index_mode {
-            IndexMode::PostIndex | IndexMode::Offset => 0,
-            IndexMode::PreIndex => 1 << 21,
+        encoding |= rt as u32;
+
+        encoding
+    }
+
+    fn encode_preload_inst(pld_mnemonic: PreloadMnemonic, rn: Rn, offset: Offset) -> u32 {
+        let mut encoding: u32 = match pld_mnemonic {
+            PreloadMnemonic::PLD => 0xF5_50_F0_00,
+            PreloadMnemonic::PLDW => 0xF5_10_F0_00,
+            PreloadMnemonic::PLI => 0xF4_50_F0_00,
         };
-        encoding |= w_mask;
 
-        let l_mask = match mem_mnemonic {
-            MemoryMnemonic::STR | MemoryMnemonic::STRB => 0,
-            MemoryMnemonic::LDR | MemoryMnemonic::LDRB => 1 << 20,
+        let register_mask = match offset {
+            Offset::RegisterWithShift(..) => 1 << 25,
+            Offset::Immediate(..) => 0,
         };
-        encoding |= l_mask;
+        encoding |= register_mask;
+
+        let u_mask = offset.up_bit();
+        encoding |= u_mask;
 
         let rn_mask = (rn.0 as u32) << 16;
         encoding |= rn_mask;
 
-        let rd_mask = (rd.0 as u32) << 12;
-        encoding |= rd_mask;
-
-        let offset_mask = match offset {
-            Offset::RegisterWithShift(reg, shift, _) => ((shift.0 as u32) << 4) | (reg as u32),
-            // Truncate immediate value to 12 bits
-            Offset::Immediate(imm, _) => (imm & 0x0FFF).into(),
-        };
+        let offset_mask = offset.bits();
         encoding |= offset_mask;
 
         encoding
     }
 
-    fn encode_mul_inst(
-        cond: Cond,
-        mul_mnemonic: MultiplyMnemonic,
-        set_condition_codes: SetConditionCodes,
-        rd: Rd,
-        rn: Rn,
-        rs: Rs,
-        rm: Rm,
-    ) -> u32 {
+    fn encode_hint_inst(cond: Cond, hint_mnemonic: HintMnemonic) -> u32 {
         let mut encoding: u32 = 0;
 
         let cond_mask = (cond as u8 as u32) << 28;
         encoding |= cond_mask;
 
-        let a_mask = match mul_mnemonic {
-            MultiplyMnemonic::MUL => 0,
-        };
-        encoding |= a_mask;
-
-        let s_mask = match set_condition_codes {
-            SetConditionCodes::SetCodes => 1 << 20,
-            SetConditionCodes::DontSetCodes => 0,
-        };
-        encoding |= s_mask;
-
-        let rd_mask = (rd.0 as u32) << 16;
-        encoding |= rd_mask;
-
-        let rn_mask = (rn.0 as u32) << 12;
-        encoding |= rn_mask;
-
-        let rs_mask = (rs.0 as u32) << 8;
-        encoding |= rs_mask;
-
-        let magic_bits = (0b1001 as u32) << 4;
+        let magic_bits = 0x03_20_F0_00;
         encoding |= magic_bits;
 
-        let rm_mask = rm.0 as u32;
-        encoding |= rm_mask;
+        encoding |= u8::from(hint_mnemonic) as u32;
 
         encoding
     }
 
-    fn encode_branch_inst(cond: Cond, b_mnemonic: BranchMnemonic, offset: u32) -> u32 {
+    fn encode_exception_inst(cond: Cond, exc_mnemonic: ExceptionMnemonic, imm: u16) -> u32 {
         let mut encoding: u32 = 0;
 
         let cond_mask = (cond as u8 as u32) << 28;
         encoding |= cond_mask;
 
-        let magic_bits = (0b101 as u32) << 25;
+        let magic_bits = match exc_mnemonic {
+            ExceptionMnemonic::SMC => 0x01_60_00_70,
+            ExceptionMnemonic::HVC => 0x01_40_00_70,
+        };
         encoding |= magic_bits;
 
-        let link_mask = match b_mnemonic {
-            BranchMnemonic::B => 0,
-            BranchMnemonic::BL => 1 << 24,
+        // hvc splits its 16-bit immediate into imm12:imm4, smc only has the low imm4
+        let imm12_mask = ((imm as u32) >> 4) << 8;
+        encoding |= imm12_mask;
+
+        encoding |= (imm as u32) & 0x0F;
+
+        encoding
+    }
+
+    /// The condition field, which the `2` variants fill with ones.
+    fn coprocessor_cond_mask(cond: Cond, cp_mnemonic: CoprocessorMnemonic) -> u32 {
+        if cp_mnemonic.is_unconditional() {
+            0xF << 28
+        } else {
+            (cond as u32) << 28
+        }
+    }
+
+    /// `cdp` and `mcr`/`mrc`, where `rd` is CRd or Rt.
+    fn encode_coprocessor_inst(
+        cond: Cond,
+        cp_mnemonic: CoprocessorMnemonic,
+        coproc: Coproc,
+        (opc1, opc2): (u32, U3),
+        rd: u8,
+        crn: CReg,
+        crm: CReg,
+    ) -> u32 {
+        let mut encoding: u32 = 0x0E_00_00_00;
+        encoding |= Self::coprocessor_cond_mask(cond, cp_mnemonic);
+
+        let opcode_mask = match cp_mnemonic {
+            CoprocessorMnemonic::CDP | CoprocessorMnemonic::CDP2 => opc1 << 20,
+            // Register transfers have a 3-bit opc1, then the direction, and set bit 4
+            _ => (opc1 << 21) | ((cp_mnemonic.is_load() as u32) << 20) | (1 << 4),
         };
-        encoding |= link_mask;
+        encoding |= opcode_mask;
 
-        // Take lower 24 bits of offset
-        let offset_mask = offset & 0x00_FF_FF_FF;
-        encoding |= offset_mask;
+        encoding |= crn.0.bits() << 16;
+        encoding |= (rd as u32) << 12;
+        encoding |= coproc.0.bits() << 8;
+        encoding |= opc2.bits() << 5;
+        encoding |= crm.0.bits();
 
         encoding
     }
 
-    fn encode_branch_exec_inst(cond: Cond, rn: Rn) -> u32 {
-        let mut encoding: u32 = 0;
+    fn encode_coprocessor_pair_inst(
+        cond: Cond,
+        cp_mnemonic: CoprocessorMnemonic,
+        coproc: Coproc,
+        opc1: U4,
+        rt: Rd,
+        rt2: Rn,
+        crm: CReg,
+    ) -> u32 {
+        let mut encoding: u32 = 0x0C_40_00_00;
+        encoding |= Self::coprocessor_cond_mask(cond, cp_mnemonic);
+        encoding |= (cp_mnemonic.is_load() as u32) << 20;
+        encoding |= (rt2.0 as u32) << 16;
+        encoding |= (rt.0 as u32) << 12;
+        encoding |= coproc.0.bits() << 8;
+        encoding |= opc1.bits() << 4;
+        encoding |= crm.0.bits();
 
-        let cond_mask = (cond as u8 as u32) << 28;
-        encoding |= cond_mask;
+        encoding
+    }
 
-        let magic_bits = 0b0001_0010_1111_1111_1111_0001 << 4;
-        encoding |= magic_bits;
+    fn encode_coprocessor_mem_inst(
+        cond: Cond,
+        cp_mnemonic: CoprocessorMnemonic,
+        coproc: Coproc,
+        crd: CReg,
+        rn: Rn,
+        offset: CoprocessorOffset,
+    ) -> u32 {
+        let mut encoding: u32 = 0x0C_00_00_00;
+        encoding |= Self::coprocessor_cond_mask(cond, cp_mnemonic);
+
+        // P, U and W, then the 8-bit offset or option
+        let addressing_mask = match offset {
+            CoprocessorOffset::Immediate(index_mode, updown, imm) => {
+                let (p, w) = match index_mode {
+                    IndexMode::PostIndex => (0, 1),
+                    IndexMode::Offset => (1, 0),
+                    IndexMode::PreIndex => (1, 1),
+                };
+                let u = match updown {
+                    UpDown::Up => 1,
+                    UpDown::Down => 0,
+                };
+                (p << 24) | (u << 23) | (w << 21) | imm as u32
+            }
+            CoprocessorOffset::Unindexed(option) => (1 << 23) | option as u32,
+        };
+        encoding |= addressing_mask;
 
-        encoding |= rn.0 as u32;
+        encoding |= (cp_mnemonic.is_long() as u32) << 22;
+        encoding |= (cp_mnemonic.is_load() as u32) << 20;
+        encoding |= (rn.0 as u32) << 16;
+        encoding |= crd.0.bits() << 12;
+        encoding |= coproc.0.bits() << 8;
 
         encoding
     }
@@ -468,9 +1729,14 @@ impl Instruction {
 #[cfg(test)]
 pub mod tests {
     use crate::{
+        arch::{Arch, Feature, Target},
+        bitfield::{Imm8Rot, U12, U4, U5},
         cond::Cond,
-        instructions::{Offset, Rd, Rn, Rotation, UpDown},
-        mnemonics::{DataMnemonic, MemoryMnemonic, MultiplyMnemonic},
+        instructions::{parse_reg_id, Offset, Rd, Rn, Severity, UpDown},
+        mnemonics::{
+            BitfieldMnemonic, DataMnemonic, ExclusiveMnemonic, MemoryMnemonic, MoveWideMnemonic,
+            MultiplyMnemonic,
+        },
     };
 
     use super::{FlexibleOperand, IndexMode, Instruction, Rm, Rs, SetConditionCodes, Shift};
@@ -484,7 +1750,7 @@ pub mod tests {
             SetConditionCodes::DontSetCodes,
             Rd(4),
             Rn(3),
-            FlexibleOperand::RegisterWithShift(5, Shift(0)),
+            FlexibleOperand::RegisterWithShift(Rm(5), Shift::NONE),
         );
 
         assert_eq!(
@@ -492,12 +1758,12 @@ pub mod tests {
             add_inst_expected,
         );
 
-        let expected_be: u32 = 0x05_40_83_E0;
+        let expected: u32 = 0xE083_4005;
         let encoding = add_inst_expected.to_machine_code();
 
         assert_eq!(
-            encoding, expected_be,
-            "\nactual: {encoding:#08x} | expected: {expected_be:#08x}"
+            encoding, expected,
+            "\nactual: {encoding:#08x} | expected: {expected:#08x}"
         );
     }
 
@@ -510,7 +1776,7 @@ pub mod tests {
             SetConditionCodes::DontSetCodes,
             Rd(1),
             Rn(0),
-            FlexibleOperand::RegisterWithShift(2, Shift(0)),
+            FlexibleOperand::RegisterWithShift(Rm(2), Shift::NONE),
         );
 
         assert_eq!(
@@ -518,13 +1784,12 @@ pub mod tests {
             sub_inst_expected,
         );
 
-        let expected_be: u32 = 0x02_10_40_E0;
-        let expected_le = expected_be.to_le();
+        let expected: u32 = 0xE040_1002;
         let encoding = sub_inst_expected.to_machine_code();
 
         assert_eq!(
-            encoding, expected_le,
-            "\nactual: {encoding:#08x} | expected: {expected_le:#08x}"
+            encoding, expected,
+            "\nactual: {encoding:#08x} | expected: {expected:#08x}"
         );
     }
 
@@ -537,7 +1802,7 @@ pub mod tests {
             SetConditionCodes::DontSetCodes,
             Rd(0),
             Rn(0),
-            FlexibleOperand::ImmediateWithRotation(1, Rotation(0)),
+            FlexibleOperand::ImmediateWithRotation(Imm8Rot::new(1, U4::ZERO)),
         );
 
         assert_eq!(
@@ -545,13 +1810,19 @@ pub mod tests {
             mov_inst_expected,
         );
 
-        let expected_le = 0x01_00_a0_e3;
+        let expected = 0xE3A0_0001;
         let encoding = mov_inst_expected.to_machine_code();
 
         assert_eq!(
-            encoding, expected_le,
-            "\nactual: {encoding:#08x} | expected: {expected_le:#08x}"
+            encoding, expected,
+            "\nactual: {encoding:#08x} | expected: {expected:#08x}"
         );
+
+        // Immediates are rotated into place, or rejected if they can't be
+        let encode = |line| Instruction::try_from(line).map(|inst| inst.to_machine_code());
+        assert_eq!(encode("mov r0, #260").unwrap(), 0xE3A0_0F41);
+        assert_eq!(encode("mov r0, #0xF000000F").unwrap(), 0xE3A0_02FF);
+        assert!(encode("mov r0, #0x101").is_err());
     }
 
     #[test]
@@ -563,7 +1834,7 @@ pub mod tests {
             IndexMode::Offset,
             Rn(13),
             Rd(0),
-            Offset::Immediate(8, UpDown::Up),
+            Offset::Immediate(U12::new(8).unwrap(), UpDown::Up),
         );
 
         assert_eq!(
@@ -572,11 +1843,67 @@ pub mod tests {
         );
 
         let encoding = str_inst_expected.to_machine_code();
-        let expected = 0x08_00_8d_e5;
+        let expected = 0xE58D_0008;
         assert_eq!(
             encoding, expected,
             "actual: {encoding:#8X} | expected: {expected:#8X}"
         );
+
+        let ldr_inst = Instruction::try_from("ldr r0, [r1, #-4]").unwrap();
+        assert_eq!(ldr_inst.to_machine_code(), 0xE511_0004);
+        // Offsets are 12 bits
+        assert!(Instruction::try_from("ldr r0, [r1, #4096]").is_err());
+
+        // Encodings from llvm-mc -triple=armv7
+        for (source, expected) in [
+            ("str r0, [r1], #4", 0xE481_0004),
+            ("str r0, [r1, #-8]!", 0xE521_0008),
+            ("ldr r0, [r1, r2, lsl #2]!", 0xE7B1_0102),
+            ("ldr r0, [r1, -r2]", 0xE711_0002),
+            ("ldrb r3, [r4], -r5, asr #32", 0xE654_3045),
+            ("pld [r0, r1, lsl #2]", 0xF7D0_F101),
+        ] {
+            let encoding = Instruction::try_from(source).unwrap().to_machine_code();
+            assert_eq!(encoding, expected, "{source}: {encoding:#010X}");
+        }
+        for source in [
+            "ldr r0, [r1]!",
+            "ldr r0, [r1, #4], #4",
+            "ldr r0, [r1], r2, r3",
+            "pld [r0, #4]!",
+        ] {
+            assert!(Instruction::try_from(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_data_processing_forms() {
+        // Encodings from llvm-mc -triple=armv7
+        for (source, expected) in [
+            ("add r0, r1, r2, lsl #3", 0xE081_0182),
+            ("add r0, #1", 0xE280_0001),
+            ("add r0, r1, lsl #2", 0xE080_0101),
+            ("adds r0, r1, #1", 0xE291_0001),
+            ("subsne r2, r2, r3, rrx", 0x1052_2063),
+            ("movs r0, r1", 0xE1B0_0001),
+            ("mvn r0, r1", 0xE1E0_0001),
+            ("cmp r0, #1", 0xE350_0001),
+            ("tst r0, r1", 0xE110_0001),
+            ("muls r0, r1, r2", 0xE010_0291),
+            ("add r0, r1, r2, lsl r3", 0xE081_0312),
+            ("movs r4, r5, ror r6", 0xE1B0_4675),
+            ("cmp r0, r1, asr r2", 0xE150_0251),
+        ] {
+            let encoding = Instruction::try_from(source).unwrap().to_machine_code();
+            assert_eq!(encoding, expected, "{source}: {encoding:#010X}");
+        }
+        for source in [
+            "cmps r0, #1",
+            "add r0, r1, r2, r3",
+            "add r0, r1, r2, lsl #32",
+        ] {
+            assert!(Instruction::try_from(source).is_err(), "{source}");
+        }
     }
 
     #[test]
@@ -598,10 +1925,293 @@ pub mod tests {
         );
 
         let encoding = mul_inst_expected.to_machine_code();
-        let expected = 0x91_02_00_e0;
+        let expected = 0xE000_0291;
+        assert_eq!(
+            encoding, expected,
+            "actual: {encoding:#8X} | expected: {expected:#8X}"
+        );
+    }
+
+    #[test]
+    fn test_movw_movt() {
+        let movw_inst_str = "movw r0, #0x1234";
+        let movw_inst_expected =
+            Instruction::MoveWide(Cond::AL, MoveWideMnemonic::MOVW, Rd(0), 0x1234);
+
+        assert_eq!(
+            Instruction::try_from(movw_inst_str).unwrap(),
+            movw_inst_expected,
+        );
+
+        let encoding = movw_inst_expected.to_machine_code();
+        let expected = 0xE301_0234;
+        assert_eq!(
+            encoding, expected,
+            "actual: {encoding:#8X} | expected: {expected:#8X}"
+        );
+
+        let movt_inst = Instruction::try_from("movt r1, #:upper16:0xbeef0000").unwrap();
+        assert_eq!(
+            movt_inst,
+            Instruction::MoveWide(Cond::AL, MoveWideMnemonic::MOVT, Rd(1), 0xbeef)
+        );
+        assert_eq!(movt_inst.to_machine_code(), 0xE34B_1EEF);
+    }
+
+    #[test]
+    fn test_bitfield() {
+        let bfi_inst_str = "bfi r1, r2, #3, #4";
+        let bfi_inst_expected = Instruction::Bitfield(
+            Cond::AL,
+            BitfieldMnemonic::BFI,
+            Rd(1),
+            Rn(2),
+            U5::new(3).unwrap(),
+            U5::new(6).unwrap(),
+        );
+
+        assert_eq!(
+            Instruction::try_from(bfi_inst_str).unwrap(),
+            bfi_inst_expected,
+        );
+
+        let encoding = bfi_inst_expected.to_machine_code();
+        let expected = 0xE7C6_1192;
+        assert_eq!(
+            encoding, expected,
+            "actual: {encoding:#8X} | expected: {expected:#8X}"
+        );
+
+        // Fields must stay within the register
+        assert!(Instruction::try_from("ubfx r0, r1, #30, #4").is_err());
+        assert!(Instruction::try_from("bfc r0, #4, #0").is_err());
+    }
+
+    #[test]
+    fn test_exclusive() {
+        let strexd_inst_str = "strexd r0, r2, r3, [r4]";
+        let strexd_inst_expected =
+            Instruction::StoreExclusive(Cond::AL, ExclusiveMnemonic::STREXD, Rd(0), Rm(2), Rn(4));
+
+        assert_eq!(
+            Instruction::try_from(strexd_inst_str).unwrap(),
+            strexd_inst_expected,
+        );
+
+        let encoding = strexd_inst_expected.to_machine_code();
+        let expected = 0xE1A4_0F92;
         assert_eq!(
             encoding, expected,
             "actual: {encoding:#8X} | expected: {expected:#8X}"
         );
+
+        // The doubleword pair must be consecutive, starting at an even register
+        assert!(Instruction::try_from("ldrexd r1, r2, [r4]").is_err());
+        assert!(Instruction::try_from("ldrexd r2, r4, [r4]").is_err());
+    }
+
+    #[test]
+    fn test_armv7_encodings() {
+        let cases = [
+            ("bfc r0, #4, #8", 0xE7CB_021F),
+            ("sbfx r0, r1, #2, #5", 0xE7A4_0151),
+            ("ubfx r0, r1, #2, #5", 0xE7E4_0151),
+            ("rbit r3, r4", 0xE6FF_3F34),
+            ("udiv r0, r1, r2", 0xE730_F211),
+            ("sdiv r0, r1, r2", 0xE710_F211),
+            ("dmb ish", 0xF57F_F05B),
+            ("dsb", 0xF57F_F04F),
+            ("isb sy", 0xF57F_F06F),
+            ("ldrex r0, [r1]", 0xE191_0F9F),
+            ("strexb r0, r2, [r4]", 0xE1C4_0F92),
+            ("ldrexd r2, r3, [r4]", 0xE1B4_2F9F),
+            ("clrex", 0xF57F_F01F),
+            ("pld [r1, #-4]", 0xF551_F004),
+            ("pldw [r2, r3]", 0xF792_F003),
+            ("pli [r0]", 0xF4D0_F000),
+            ("wfi", 0xE320_F003),
+            ("yieldne", 0x1320_F001),
+            ("smc #3", 0xE160_0073),
+            ("hvc #0x12", 0xE140_0172),
+        ];
+
+        for (inst_str, expected) in cases {
+            let encoding = Instruction::try_from(inst_str).unwrap().to_machine_code();
+            assert_eq!(
+                encoding, expected,
+                "{inst_str}: actual: {encoding:#8X} | expected: {expected:#8X}"
+            );
+        }
+
+        assert!(Instruction::try_from("dmbeq ish").is_err());
+        assert!(Instruction::try_from("hvcne #1").is_err());
+    }
+
+    #[test]
+    fn test_coprocessor() {
+        let cases = [
+            ("mcr p15, 0, r0, c1, c0, 0", 0xEE01_0F10),
+            ("mrc p15, 0, r1, c0, c0, 5", 0xEE10_1FB0),
+            ("mrceq p14, 7, APSR_nzcv, c15, c14, 7", 0x0EFF_FEFE),
+            ("mcr2 p7, #1, r2, c3, c4, #5", 0xFE23_27B4),
+            ("cdp p5, 15, c1, c2, c3, 7", 0xEEF2_15E3),
+            ("cdp2 p5, 1, c1, c2, c3", 0xFE12_1503),
+            ("mcrr p15, 0, r0, r1, c2", 0xEC41_0F02),
+            ("mrrc2 p15, 3, r2, r3, c14", 0xFC53_2F3E),
+            ("ldc p5, c1, [r0, #4]", 0xED90_1501),
+            ("stcl p6, c2, [r1, #-8]!", 0xED61_2602),
+            ("ldc2l p7, c3, [r2], #16", 0xFCF2_3704),
+            ("stc2 p8, c4, [r3], {7}", 0xFC83_4807),
+            ("ldcne p9, c5, [r4]", 0x1D94_5900),
+        ];
+
+        for (inst_str, expected) in cases {
+            let encoding = Instruction::try_from(inst_str).unwrap().to_machine_code();
+            assert_eq!(
+                encoding, expected,
+                "{inst_str}: actual: {encoding:#8X} | expected: {expected:#8X}"
+            );
+        }
+
+        // Field widths and the unconditional `2` forms
+        assert!(Instruction::try_from("mcr p15, 8, r0, c1, c0, 0").is_err());
+        assert!(Instruction::try_from("mcr p16, 0, r0, c1, c0, 0").is_err());
+        assert!(Instruction::try_from("cdp p5, 16, c1, c2, c3").is_err());
+        assert!(Instruction::try_from("mrc p15, 0, r1, c0, c0, 8").is_err());
+        assert!(Instruction::try_from("mcr2eq p7, 1, r2, c3, c4").is_err());
+        assert!(Instruction::try_from("ldc p5, c1, [r0, #2]").is_err());
+        assert!(Instruction::try_from("ldc p5, c1, [r0, #1024]").is_err());
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let cases = [
+            ("push {fp, lr}", 0xE92D_4800),
+            ("popne {r4-r7, pc}", 0x18BD_80F0),
+            ("push {r4}", 0xE52D_4004),
+            ("pop {r0}", 0xE49D_0004),
+            ("push {lr}", 0xE52D_E004),
+            ("poplt {pc}", 0xB49D_F004),
+            ("push {r0-r12, lr}", 0xE92D_5FFF),
+            ("push {r0, r2, r4, r6, r8, r10}", 0xE92D_0555),
+        ];
+        for (inst_str, expected) in cases {
+            let encoding = Instruction::try_from(inst_str).unwrap().to_machine_code();
+            assert_eq!(encoding, expected, "{inst_str}");
+        }
+
+        assert!(Instruction::try_from("push {}").is_err());
+        assert!(Instruction::try_from("pop {r16}").is_err());
+        assert!(Instruction::try_from("push r0").is_err());
+    }
+
+    #[test]
+    fn test_branch_range() {
+        let cases = [
+            ("b -8", 0xEAFF_FFFE),
+            ("bl 33554428", 0xEB7F_FFFF),
+            ("blt -33554432", 0xBA80_0000),
+        ];
+        for (inst_str, expected) in cases {
+            let encoding = Instruction::try_from(inst_str).unwrap().to_machine_code();
+            assert_eq!(encoding, expected, "{inst_str}");
+        }
+
+        assert!(Instruction::try_from("b 33554432").is_err());
+        assert!(Instruction::try_from("bl -33554436").is_err());
+        assert!(Instruction::try_from("b 6").is_err());
+    }
+
+    #[test]
+    fn test_required_feature() {
+        let movw = Instruction::try_from("movw r0, #1").unwrap();
+        assert_eq!(movw.required_feature(), Some(Feature::V6T2));
+        assert!(Arch::V7A.supports(Feature::V6T2));
+        assert!(!Arch::V6K.supports(Feature::V6T2));
+
+        let sdiv = Instruction::try_from("sdiv r0, r1, r2").unwrap();
+        assert_eq!(sdiv.required_feature(), Some(Feature::Idiv));
+        assert!(!Arch::V7A.supports(Feature::Idiv));
+        assert!(Arch::V7R.supports(Feature::Idiv));
+
+        let add = Instruction::try_from("add r0, r1, r2").unwrap();
+        assert_eq!(add.required_feature(), None);
+
+        // Before the hints, nop is a move that does nothing
+        let v6 = Target::new(Arch::V6);
+        let nop = Instruction::try_from("nopeq").unwrap();
+        assert_eq!(nop.required_feature(), Some(Feature::V6K));
+        assert_eq!(nop.clone().for_target(&v6).to_machine_code(), 0x01A0_0000);
+        assert_eq!(
+            nop.for_target(&Target::new(Arch::V6K)).to_machine_code(),
+            0x0320_F000
+        );
+        let wfi = Instruction::try_from("wfi").unwrap();
+        assert_eq!(wfi.clone().for_target(&v6), wfi);
+    }
+
+    #[test]
+    fn test_registers() {
+        assert_eq!(Rd::new(15).unwrap().index(), 15);
+        assert!(Rm::new(16).is_err());
+        assert_eq!(parse_reg_id("R7").unwrap(), 7);
+        assert_eq!(parse_reg_id("SP").unwrap(), 13);
+        let aliases = ["a1", "A4", "v1", "wr", "v5", "sb", "SL", "v8"];
+        let ids = aliases.map(|alias| parse_reg_id(alias).unwrap());
+        assert_eq!(ids, [0, 3, 4, 7, 8, 9, 10, 11]);
+        for bad in ["r16", "r99", "r+1", "rsp", "[sp", "a0", "a5", "v9", "v01x"] {
+            assert!(parse_reg_id(bad).is_err(), "{bad}");
+        }
+        assert!(Instruction::try_from("add r0, r16, r1").is_err());
+    }
+
+    #[test]
+    fn test_unpredictable() {
+        let v7 = Target::new(Arch::V7A);
+        let v5 = Target::new(Arch::V5TE);
+        let check = |inst_str: &str, target: &Target| {
+            Instruction::try_from(inst_str)
+                .unwrap()
+                .unpredictable(target)
+                .map(|unpredictable| unpredictable.severity)
+        };
+        assert_eq!(check("mul pc, r0, r1", &v7), Some(Severity::Error));
+        assert_eq!(check("mul r0, r0, r1", &v7), None);
+        assert_eq!(check("mul r0, r0, r1", &v5), Some(Severity::Warning));
+        assert_eq!(check("push {sp, lr}", &v7), Some(Severity::Error));
+        assert_eq!(check("push {sp, lr}", &v5), None);
+        assert_eq!(check("strex r0, r0, [r1]", &v7), Some(Severity::Error));
+        assert_eq!(check("strex r0, r1, [r2]", &v7), None);
+        assert_eq!(check("movw pc, #1", &v7), Some(Severity::Error));
+
+        // A field may end at bit 31 however it's given
+        assert_eq!(check("bfi r0, r1, #16, #16", &v7), None);
+        assert_eq!(check("bfc r0, #0, #32", &v7), None);
+        assert_eq!(check("ubfx r0, r1, #31, #1", &v7), None);
+        let bfi = Instruction::try_from("bfi r0, r1, #16, #16").unwrap();
+        assert_eq!(bfi.to_machine_code(), 0xE7DF_0811);
+        let sbfx = Instruction::Bitfield(
+            Cond::AL,
+            BitfieldMnemonic::SBFX,
+            Rd(0),
+            Rn(1),
+            U5::new(16).unwrap(),
+            U5::new(16).unwrap(),
+        );
+        assert_eq!(
+            sbfx.unpredictable(&v7)
+                .map(|unpredictable| unpredictable.severity),
+            Some(Severity::Error)
+        );
+
+        assert_eq!(check("ldr r0, [r0], #4", &v7), Some(Severity::Warning));
+        assert_eq!(check("str r0, [r0, #4]!", &v7), Some(Severity::Warning));
+        assert_eq!(check("ldr r0, [pc, #4]!", &v7), Some(Severity::Error));
+        assert_eq!(check("ldr r0, [r1], #4", &v7), None);
+        assert_eq!(check("ldr r0, [r0, #4]", &v7), None);
+
+        assert_eq!(check("add r0, r1, r2, lsl pc", &v7), Some(Severity::Error));
+        assert_eq!(check("mov pc, r2, lsl r3", &v7), Some(Severity::Error));
+        assert_eq!(check("add r0, r1, r2, lsl r3", &v7), None);
     }
 }
@@ -0,0 +1,225 @@
+//! Running generated code in this process. An `ExecutableBuffer` holds a copy of the code in
+//! memory of its own, which is never writable and executable at the same time.
+
+use std::{collections::HashMap, io, mem, ptr};
+
+use crate::{
+    assembler::Assembler,
+    error::{AssemblerError, ParseError},
+};
+
+/// A function pointer type that code in an `ExecutableBuffer` can be called through.
+///
+/// # Safety
+///
+/// Implementors must be function pointers, which are the size of an address.
+pub unsafe trait FnPointer: Copy {}
+
+macro_rules! fn_pointers {
+    ($($arg:ident),*) => {
+        unsafe impl<R, $($arg),*> FnPointer for extern "C" fn($($arg),*) -> R {}
+        unsafe impl<R, $($arg),*> FnPointer for unsafe extern "C" fn($($arg),*) -> R {}
+    };
+}
+
+fn_pointers!();
+fn_pointers!(A);
+fn_pointers!(A, B);
+fn_pointers!(A, B, C);
+fn_pointers!(A, B, C, D);
+fn_pointers!(A, B, C, D, E);
+fn_pointers!(A, B, C, D, E, F);
+
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+extern "C" {
+    fn __clear_cache(begin: *mut libc::c_char, end: *mut libc::c_char);
+}
+
+/// Code copied into pages mapped for it, which are read-only and executable once it's there.
+#[derive(Debug)]
+pub struct ExecutableBuffer {
+    memory: *mut u8,
+    mapped: usize,
+    len: usize,
+    /// The offset of each label, with bit 0 set for Thumb functions.
+    labels: HashMap<String, u32>,
+}
+
+// The memory is never written after it's made executable.
+unsafe impl Send for ExecutableBuffer {}
+unsafe impl Sync for ExecutableBuffer {}
+
+impl ExecutableBuffer {
+    /// Copy `code` into executable memory.
+    pub fn new(code: &[u8]) -> Result<Self, AssemblerError> {
+        Self::with_labels(code, [])
+    }
+
+    /// Copy `code` into executable memory, where `labels` name offsets into it. The offset of a
+    /// Thumb function has bit 0 set, as in a symbol table.
+    pub fn with_labels(
+        code: &[u8],
+        labels: impl IntoIterator<Item = (String, u32)>,
+    ) -> Result<Self, AssemblerError> {
+        let page = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            size if size > 0 => size as usize,
+            _ => return Err(io::Error::last_os_error().into()),
+        };
+        // Even empty code gets a page, as mmap can't map nothing
+        let mapped = code.len().max(1).div_ceil(page) * page;
+        let memory = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                mapped,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if memory == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let buffer = Self {
+            memory: memory.cast(),
+            mapped,
+            len: code.len(),
+            labels: labels.into_iter().collect(),
+        };
+        unsafe {
+            ptr::copy_nonoverlapping(code.as_ptr(), buffer.memory, code.len());
+            if libc::mprotect(memory, mapped, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+            __clear_cache(memory.cast(), buffer.memory.add(mapped).cast());
+        }
+        Ok(buffer)
+    }
+
+    /// Copy the text section of `assembler` into executable memory, along with the labels
+    /// defined in it. Nothing in the section can be left for the linker to resolve.
+    pub fn from_assembler(assembler: &Assembler) -> Result<Self, AssemblerError> {
+        if let Some(name) = assembler.text_relocation() {
+            return Err(ParseError::Unrelocatable(name).into());
+        }
+        let labels = assembler
+            .symbol_table()
+            .into_iter()
+            .filter(|symbol| symbol.section.as_deref() == Some(".text"))
+            .map(|symbol| (symbol.name, symbol.value));
+        Self::with_labels(assembler.text(), labels)
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.memory
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The code, as copied in.
+    pub fn code(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.memory, self.len) }
+    }
+
+    /// The offset of the label `name`, with bit 0 set for a Thumb function.
+    pub fn offset(&self, name: &str) -> Option<u32> {
+        self.labels.get(name).copied()
+    }
+
+    /// The start of the code, called in ARM state.
+    ///
+    /// # Safety
+    ///
+    /// The code must be ARM code for this processor that follows the calling convention of `F`.
+    pub unsafe fn entry<F: FnPointer>(&self) -> F {
+        self.pointer(0)
+    }
+
+    /// The label `name`, called in Thumb state if it's a Thumb function.
+    ///
+    /// # Safety
+    ///
+    /// The code there must be for this processor and follow the calling convention of `F`.
+    pub unsafe fn function<F: FnPointer>(&self, name: &str) -> Option<F> {
+        let offset = self.offset(name)?;
+        ((offset & !1) < self.len as u32).then(|| self.pointer(offset as usize))
+    }
+
+    unsafe fn pointer<F: FnPointer>(&self, offset: usize) -> F {
+        // Bit 0 of a Thumb offset carries through to the address, which interworks
+        let address = self.memory.wrapping_add(offset);
+        mem::transmute_copy(&address)
+    }
+}
+
+impl Drop for ExecutableBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.memory.cast(), self.mapped);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::arch::{Arch, Target};
+
+    fn assemble(program: &[&str]) -> Assembler {
+        let mut assembler = Assembler::new(Target::new(Arch::V7A));
+        assembler.layout(program).unwrap();
+        for line in program {
+            assembler.assemble_line(line).unwrap();
+        }
+        assembler
+    }
+
+    #[test]
+    fn test_executable_buffer() {
+        let assembler = assemble(&[
+            ".syntax unified",
+            ".globl add",
+            "add:",
+            "add r0, r0, r1",
+            "bx lr",
+            ".thumb_func",
+            "sub:",
+            ".thumb",
+            "subs r0, r0, r1",
+            "bx lr",
+        ]);
+        let buffer = ExecutableBuffer::from_assembler(&assembler).unwrap();
+        assert_eq!(buffer.code(), assembler.text());
+        assert_eq!(buffer.offset("add"), Some(0));
+        assert_eq!(buffer.offset("sub"), Some(9));
+        assert_eq!(buffer.offset("mul"), None);
+
+        let add: extern "C" fn(u32, u32) -> u32 = unsafe { buffer.entry() };
+        assert_eq!(add as usize, buffer.as_ptr() as usize);
+        let sub: extern "C" fn(u32, u32) -> u32 = unsafe { buffer.function("sub").unwrap() };
+        assert_eq!(sub as usize, buffer.as_ptr() as usize + 9);
+        #[cfg(target_arch = "arm")]
+        {
+            assert_eq!(add(2, 3), 5);
+            assert_eq!(sub(5, 3), 2);
+        }
+
+        let empty = ExecutableBuffer::new(&[]).unwrap();
+        assert!(empty.is_empty());
+
+        let assembler = assemble(&["bl elsewhere"]);
+        let err = ExecutableBuffer::from_assembler(&assembler).unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Parse Error: elsewhere can't refer to a symbol defined in another section or file"
+        );
+    }
+}
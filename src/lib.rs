@@ -1,75 +1,48 @@
+pub mod alias;
+pub mod arch;
+pub mod assembler;
+pub mod attributes;
+pub mod bitfield;
+pub mod builder;
+pub mod cfi;
 pub mod cond;
+pub mod dwarf;
+pub mod ehabi;
+pub mod elf;
 pub mod error;
+pub mod formats;
 pub mod instructions;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod linker;
+pub mod linker_script;
+pub mod listing;
 pub mod mnemonics;
-
-use crate::{error::AssemblerError, instructions::Instruction, mnemonics::BranchMnemonic};
-use std::{
-    collections::{HashMap, HashSet},
-    fs::File,
-    io::{BufRead, BufReader},
+pub mod neon;
+pub mod source;
+pub mod thumb;
+pub mod thumb2;
+pub mod vfp;
+
+use crate::{
+    arch::Target, assembler::Assembler, error::AssemblerError, listing::Listing, source::Source,
 };
 
-const INST_SIZE: u32 = 0x20;
-
-// TODO: this should probably go into the linker
-fn preprocess_asm_file(filename: &str) -> Result<(Vec<String>, HashSet<String>), AssemblerError> {
-    let file = File::open(filename)?;
-
-    let mut label_map = HashMap::new();
-    let mut pc = 0x00;
-    let mut program = vec![];
-    let mut preprocessed_program = vec![];
-    let mut strings = HashSet::new();
-
-    // XXX: this is good enough for now but is probably inaccurate for some cases
-    let is_label = |l: &str| -> bool { l.ends_with(':') };
-
-    // Read in the file, figure out the mapping of label -> pc
-    for line in BufReader::new(&file).lines() {
-        let l = line?;
-        // Get the addr of the instruction following the label, but don't write the label to the
-        // program buffer since we don't need them for assembling
-        if is_label(&l) {
-            let label = l.replace(':', "");
-            label_map.insert(label.to_owned(), pc);
-            strings.insert(label.to_owned());
-        } else {
-            pc += INST_SIZE;
-        }
-
-        program.push((pc, l.to_owned()));
-    }
-
-    // Make a second pass now that we know where all the labels are located.
-    for (pc, inst) in program {
-        let modified_inst = if let Ok(_) = BranchMnemonic::try_from(inst.as_str()) {
-            let (_mnemonic, label) = inst.trim().split_once(' ').unwrap();
-            let label_addr = label_map.get(label).unwrap();
-            inst.replace(label, &(label_addr - pc).to_string())
-                .trim()
-                .to_string()
-        } else {
-            inst.trim().to_owned()
-        };
-
-        preprocessed_program.push(modified_inst);
-    }
-
-    Ok((preprocessed_program, strings))
+/// Assemble `filename` for `target`, returning the resulting ELF relocatable object.
+pub fn assemble_file(filename: &str, target: Target) -> Result<Vec<u8>, AssemblerError> {
+    Ok(assemble_file_with_listing(filename, target)?.0)
 }
 
-pub fn assemble_file(filename: &str) -> Result<(), AssemblerError> {
-    let (preprocessed_program, _strings) = preprocess_asm_file(&filename)?;
-
-    for inst in &preprocessed_program {
-        // Ignore labels and directives
-        if let Ok(parsed_instruction) = Instruction::try_from(inst.as_str()) {
-            // dbg!(&parsed_instruction);
-            let machine_code: u32 = parsed_instruction.to_machine_code();
-            println!("{inst} - {machine_code:08x}")
-        }
-    }
-
-    Ok(())
+/// Assemble `filename` for `target`, returning the resulting ELF relocatable object along with a
+/// listing of what each line assembled to.
+pub fn assemble_file_with_listing(
+    filename: &str,
+    target: Target,
+) -> Result<(Vec<u8>, Listing), AssemblerError> {
+    let mut source = Source::default();
+    source.add_file(filename)?;
+    let mut assembler = Assembler::new(target);
+    let listing = Listing::assemble(&mut assembler, filename, &source)?;
+
+    Ok((assembler.to_object(), listing))
 }
@@ -0,0 +1,1844 @@
+//! Combines relocatable objects into an executable: input sections are merged by kind, global
+//! symbols are resolved across objects and relocations are applied to the merged sections.
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use crate::{
+    attributes::{read_attributes, TAG_ARM_ISA_USE, TAG_CPU_ARCH, TAG_THUMB_ISA_USE},
+    elf::{
+        Endianness, ExecutableWriter, ObjectFile, ProgramHeader, Section, Symbol, EF_ARM_EABI_VER5,
+        PF_R, PF_W, PF_X, PT_LOAD, R_ARM_ABS32, R_ARM_CALL, R_ARM_JUMP24, R_ARM_MOVT_ABS,
+        R_ARM_MOVW_ABS_NC, R_ARM_NONE, R_ARM_PC24, R_ARM_PREL31, R_ARM_REL32, R_ARM_THM_CALL,
+        R_ARM_THM_JUMP19, R_ARM_THM_JUMP24, R_ARM_THM_MOVT_ABS, R_ARM_THM_MOVW_ABS_NC, R_ARM_V4BX,
+        SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHN_ABS, SHN_COMMON, SHN_UNDEF, SHT_NOBITS,
+        SHT_PROGBITS, STB_GLOBAL, STB_LOCAL, STB_WEAK, STT_FILE, STT_FUNC, STT_NOTYPE, STT_SECTION,
+        STV_DEFAULT, STV_HIDDEN, STV_INTERNAL,
+    },
+    error::LinkError,
+    formats::OutputFormat,
+    linker_script::{Command, Environment, Expr, LinkerScript, MemoryRegion, COMMON, DISCARD},
+};
+
+/// Where the first segment is loaded unless told otherwise, as with GNU ld on ARM Linux.
+pub const DEFAULT_BASE_ADDRESS: u32 = 0x10000;
+
+/// Segments start on a new page so that they can be mapped with different permissions.
+const PAGE_SIZE: u32 = 0x1000;
+
+/// Execution starts here unless another entry symbol is given.
+const DEFAULT_ENTRY: &str = "_start";
+
+/// Without a linker script, input sections whose names start with one of these, followed by a
+/// dot, are merged into the section of that name.
+const OUTPUT_SECTIONS: [&str; 4] = [".text", ".rodata", ".data", ".bss"];
+
+/// What veneers are put down as coming from in linker scripts' file patterns.
+const VENEER_FILE: &str = "linker stubs";
+
+/// Tag_CPU_arch for ARMv5T, the first with BLX.
+const CPU_ARCH_V5T: u32 = 3;
+
+/// How strongly a global symbol is defined. A stronger definition replaces a weaker one.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Strength {
+    Weak,
+    Common,
+    Strong,
+}
+
+/// The definition a global symbol resolved to.
+#[derive(Clone, Copy, Debug)]
+struct Definition {
+    object: usize,
+    symbol: usize,
+    strength: Strength,
+}
+
+/// A merged section of the output, with where it is loaded.
+#[derive(Clone, Debug)]
+struct OutputSection {
+    section: Section,
+    address: u32,
+    /// Where the section's contents are loaded, when that differs from where they are used.
+    load_address: u32,
+    offset: u32,
+}
+
+/// Identifies an input section: a section of one of the objects, or the space for a common
+/// symbol.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum InputKey {
+    Section(usize, usize),
+    Common(String),
+    /// The code that takes branches where they can't go by themselves.
+    Veneers,
+}
+
+#[derive(Clone, Debug)]
+struct Input {
+    key: InputKey,
+    /// The object the section or common symbol comes from.
+    object: usize,
+    section: Section,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Layout {
+    sections: Vec<OutputSection>,
+    /// The output section and offset within it of each input section, by object and index.
+    placements: HashMap<(usize, usize), (usize, u32)>,
+    /// The output section and offset within it of each common symbol, by name.
+    commons: HashMap<String, (usize, u32)>,
+    veneers: Option<(usize, u32)>,
+    /// Symbols defined by the linker script, in the order they were assigned.
+    symbols: Vec<(String, u32)>,
+    segments: Vec<ProgramHeader>,
+}
+
+impl Layout {
+    fn address(&self, (section, offset): (usize, u32)) -> u32 {
+        self.sections[section].address + offset
+    }
+
+    fn place(&mut self, input: &InputKey, placement: (usize, u32)) {
+        match input {
+            InputKey::Section(object, index) => {
+                self.placements.insert((*object, *index), placement);
+            }
+            InputKey::Common(name) => {
+                self.commons.insert(name.clone(), placement);
+            }
+            InputKey::Veneers => self.veneers = Some(placement),
+        }
+    }
+
+    fn script_symbol(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|(symbol, _)| symbol == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// Where the default layout puts an input section.
+fn default_output_name(input: &Input) -> &str {
+    match input.key {
+        InputKey::Common(_) => ".bss",
+        InputKey::Section(..) | InputKey::Veneers => OUTPUT_SECTIONS
+            .into_iter()
+            .find(|output| {
+                input
+                    .section
+                    .name
+                    .strip_prefix(output)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+            })
+            .unwrap_or(&input.section.name),
+    }
+}
+
+/// Append `input` to the output section called `name`, returning the index of that section and
+/// where `input` starts within it.
+fn place_section(outputs: &mut Vec<Section>, name: &str, input: &Section) -> (usize, u32) {
+    let index = match outputs.iter().position(|output| output.name == name) {
+        Some(index) => index,
+        None => {
+            outputs.push(Section::new(name, input.sh_type, 0, 1));
+            outputs.len() - 1
+        }
+    };
+
+    let output = &mut outputs[index];
+    // Any initialised input makes the whole section take up room in the file
+    if output.sh_type == SHT_NOBITS {
+        output.sh_type = input.sh_type;
+    }
+    output.flags |= input.flags & (SHF_WRITE | SHF_ALLOC | SHF_EXECINSTR);
+    output.align = output.align.max(input.align);
+    let start = output
+        .data
+        .len()
+        .next_multiple_of(input.align.max(1) as usize);
+    output.data.resize(start, 0);
+    output.data.extend_from_slice(&input.data);
+    (index, start as u32)
+}
+
+/// Group output sections into loadable segments, and give each section its offset in the file
+/// after the headers. A new segment starts wherever the sections can't be loaded as one: at a
+/// change of permissions or load offset, a large gap, or data after zeroed data. With
+/// `map_headers`, the first segment also covers the headers, which must fit before it.
+fn build_segments(sections: &mut [OutputSection], map_headers: bool) -> Vec<ProgramHeader> {
+    let mut groups: Vec<Vec<usize>> = vec![];
+    for (i, output) in sections.iter().enumerate() {
+        if output.section.data.is_empty() {
+            continue;
+        }
+        let previous = groups
+            .last()
+            .and_then(|group| group.last())
+            .map(|&last| &sections[last]);
+        let joins = previous.is_some_and(|previous| {
+            let end = previous.address + previous.section.data.len() as u32;
+            (previous.section.flags ^ output.section.flags) & SHF_WRITE == 0
+                && (end..end + PAGE_SIZE).contains(&output.address)
+                && previous.load_address.wrapping_sub(previous.address)
+                    == output.load_address.wrapping_sub(output.address)
+                && (previous.section.sh_type != SHT_NOBITS || output.section.sh_type == SHT_NOBITS)
+        });
+        match groups.last_mut() {
+            Some(group) if joins => group.push(i),
+            _ => groups.push(vec![i]),
+        }
+    }
+
+    let mut offset = ExecutableWriter::headers_size(groups.len());
+    let mut segments = vec![];
+    for group in &groups {
+        let (start, load_start) = (sections[group[0]].address, sections[group[0]].load_address);
+        // Keep file offsets congruent to addresses modulo the page size, so that they can be mapped
+        let file_start = offset + start.wrapping_sub(offset) % PAGE_SIZE;
+        let mut segment = ProgramHeader {
+            p_type: PT_LOAD,
+            offset: file_start,
+            vaddr: start,
+            paddr: load_start,
+            flags: PF_R,
+            align: PAGE_SIZE,
+            ..Default::default()
+        };
+        for &i in group {
+            let output = &mut sections[i];
+            let size = output.section.data.len() as u32;
+            output.offset = file_start + (output.address - start);
+            if output.section.sh_type != SHT_NOBITS {
+                segment.filesz = output.address + size - start;
+                offset = output.offset + size;
+            }
+            segment.memsz = output.address + size - start;
+            if output.section.flags & SHF_WRITE != 0 {
+                segment.flags |= PF_W;
+            }
+            if output.section.flags & SHF_EXECINSTR != 0 {
+                segment.flags |= PF_X;
+            }
+        }
+        segments.push(segment);
+    }
+
+    // Sections outside any segment take no room, so sit wherever the file has got to
+    let mut end = ExecutableWriter::headers_size(groups.len());
+    for output in sections.iter_mut() {
+        if output.section.data.is_empty() {
+            output.offset = end;
+        } else if output.section.sh_type != SHT_NOBITS {
+            end = output.offset + output.section.data.len() as u32;
+        }
+    }
+
+    if let Some(first) = segments.first_mut().filter(|_| map_headers) {
+        let headers = first.offset;
+        first.offset = 0;
+        first.vaddr -= headers;
+        first.paddr -= headers;
+        first.filesz += headers;
+        first.memsz += headers;
+    }
+
+    segments
+}
+
+/// Evaluation state while laying out with a linker script.
+struct ScriptState<'a> {
+    script: &'a LinkerScript,
+    globals: &'a HashMap<String, Definition>,
+    location: u32,
+    symbols: Vec<(String, u32)>,
+    /// The address, load address and size of each output section laid out so far.
+    sections: HashMap<String, (u32, u32, u32)>,
+    /// The next free address in each memory region.
+    cursors: HashMap<String, u32>,
+}
+
+impl ScriptState<'_> {
+    fn assign(&mut self, symbol: &str, expr: &Expr, provide: bool) -> Result<(), LinkError> {
+        if provide && (self.globals.contains_key(symbol) || self.symbol(symbol).is_some()) {
+            return Ok(());
+        }
+
+        let value = expr.evaluate(self)?;
+        match self.symbols.iter_mut().find(|(name, _)| name == symbol) {
+            Some(entry) => entry.1 = value,
+            None => self.symbols.push((symbol.to_owned(), value)),
+        }
+        Ok(())
+    }
+
+    fn cursor(&self, region: &str) -> Result<u32, LinkError> {
+        self.cursors
+            .get(region)
+            .copied()
+            .ok_or_else(|| LinkError::BadScript(format!("unknown memory region {region}")))
+    }
+
+    /// Use `region` up to `end`, checking that it fits.
+    fn advance(&mut self, region: &str, end: u32) -> Result<(), LinkError> {
+        let Some(limits) = self.script.region(region) else {
+            return Err(LinkError::BadScript(format!(
+                "unknown memory region {region}"
+            )));
+        };
+        let limit = u64::from(limits.origin) + u64::from(limits.length);
+        if u64::from(end) > limit {
+            return Err(LinkError::RegionOverflow(
+                region.to_owned(),
+                u64::from(end) - limit,
+            ));
+        }
+        self.cursors.insert(region.to_owned(), end);
+        Ok(())
+    }
+}
+
+impl Environment for ScriptState<'_> {
+    fn location(&self) -> u32 {
+        self.location
+    }
+
+    fn symbol(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|(symbol, _)| symbol == name)
+            .map(|(_, value)| *value)
+    }
+
+    fn region(&self, name: &str) -> Option<&MemoryRegion> {
+        self.script.region(name)
+    }
+
+    fn section(&self, name: &str) -> Option<(u32, u32, u32)> {
+        self.sections.get(name).copied()
+    }
+}
+
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    ((value << (32 - bits)) as i32) >> (32 - bits)
+}
+
+/// What the architectures of all the objects allow, which decides how veneers are written.
+#[derive(Clone, Copy, Debug)]
+struct Capabilities {
+    /// BLX, and loads to the PC that can change instruction set, from ARMv5T.
+    blx: bool,
+    /// Thumb-2, for a wide load to the PC.
+    thumb2: bool,
+    /// ARM code, which M-profile processors can't run.
+    arm: bool,
+}
+
+/// Code that takes a branch anywhere in the address space, in either instruction set.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Veneer {
+    /// Where the branch is going, with the Thumb bit set for Thumb code.
+    destination: u32,
+    /// Whether the branch comes from Thumb code, so that the veneer starts in Thumb state.
+    thumb: bool,
+}
+
+impl Veneer {
+    /// The veneer's instructions followed by its destination, or `None` if `capabilities` don't
+    /// allow one.
+    fn code(&self, capabilities: Capabilities, endianness: Endianness) -> Option<Vec<u8>> {
+        let mut bytes = vec![];
+        let code = endianness.code();
+        if self.thumb {
+            if capabilities.thumb2 {
+                // ldr.w pc, [pc]
+                bytes.extend(code.u16_bytes(0xF8DF));
+                bytes.extend(code.u16_bytes(0xF000));
+                bytes.extend(endianness.u32_bytes(self.destination));
+                return Some(bytes);
+            }
+            if !capabilities.arm {
+                return None;
+            }
+            // bx pc; nop, carrying on in ARM state with the next word
+            bytes.extend(code.u16_bytes(0x4778));
+            bytes.extend(code.u16_bytes(0x46C0));
+        }
+        if capabilities.blx || self.destination & 1 == 0 {
+            // ldr pc, [pc, #-4]
+            bytes.extend(code.u32_bytes(0xE51F_F004));
+        } else {
+            // ldr ip, [pc]; bx ip
+            bytes.extend(code.u32_bytes(0xE59F_C000));
+            bytes.extend(code.u32_bytes(0xE12F_FF1C));
+        }
+        bytes.extend(endianness.u32_bytes(self.destination));
+        Some(bytes)
+    }
+
+    /// A local function symbol for a veneer to `name` of `size` bytes at `address` in output
+    /// section `output`, and mapping symbols for its code and destination.
+    fn symbols(&self, name: &str, address: u32, size: u32, output: usize) -> Vec<Symbol> {
+        let symbol = |name: &str, value, sym_type, size| Symbol {
+            name: name.to_owned(),
+            value,
+            size,
+            binding: STB_LOCAL,
+            sym_type,
+            visibility: STV_DEFAULT,
+            // Writer section indices start at 1
+            section: output as u16 + 1,
+        };
+        let mut symbols = vec![symbol(
+            &format!("__{name}_veneer"),
+            address | u32::from(self.thumb),
+            STT_FUNC,
+            size,
+        )];
+        if self.thumb {
+            symbols.push(symbol("$t", address, STT_NOTYPE, 0));
+        }
+        // Thumb veneers that aren't one wide load carry on in ARM state
+        if !self.thumb || size > 8 {
+            let start = if self.thumb { 4 } else { 0 };
+            symbols.push(symbol("$a", address + start, STT_NOTYPE, 0));
+        }
+        symbols.push(symbol("$d", address + size - 4, STT_NOTYPE, 0));
+        symbols
+    }
+}
+
+/// A veneer some branch needs.
+struct NeededVeneer {
+    veneer: Veneer,
+    /// The symbol the first branch to need it went to, which names the veneer.
+    name: String,
+    code: Vec<u8>,
+    /// The output section of that first branch.
+    caller: usize,
+}
+
+/// One relocation, with its symbol resolved.
+struct Fixup<'a> {
+    object: &'a str,
+    symbol: String,
+    r_type: u8,
+    /// Address of the field being relocated, `P` in the ARM ELF specification.
+    place: u32,
+    /// Address of the symbol without the Thumb bit, `S`.
+    target: u32,
+    /// Whether the symbol is a Thumb function, `T`.
+    thumb: bool,
+    addend: Option<i32>,
+    endianness: Endianness,
+}
+
+impl Fixup<'_> {
+    fn out_of_range(&self) -> LinkError {
+        LinkError::RelocationOutOfRange(self.object.to_owned(), self.symbol.clone())
+    }
+
+    fn interworking(&self) -> LinkError {
+        LinkError::Interworking(self.object.to_owned(), self.symbol.clone())
+    }
+
+    /// `((S + A) | T) - P`, checked to fit in a signed `bits`-bit branch offset.
+    fn branch_offset(&self, addend: i32, bits: u32) -> Result<i64, LinkError> {
+        let offset = ((i64::from(self.target) + i64::from(addend)) | i64::from(self.thumb))
+            - i64::from(self.place);
+        let limit = 1i64 << (bits - 1);
+        if (-limit..limit).contains(&offset) {
+            Ok(offset)
+        } else {
+            Err(self.out_of_range())
+        }
+    }
+
+    /// Whether the field is a Thumb-2 instruction: two halfwords, most significant first.
+    fn is_thumb_pair(&self) -> bool {
+        matches!(
+            self.r_type,
+            R_ARM_THM_CALL
+                | R_ARM_THM_JUMP24
+                | R_ARM_THM_JUMP19
+                | R_ARM_THM_MOVW_ABS_NC
+                | R_ARM_THM_MOVT_ABS
+        )
+    }
+
+    fn order(&self) -> Endianness {
+        match self.r_type {
+            R_ARM_ABS32 | R_ARM_REL32 | R_ARM_PREL31 => self.endianness,
+            _ => self.endianness.code(),
+        }
+    }
+
+    /// The word at the start of `bytes`, with a Thumb-2 instruction's first halfword at the
+    /// bottom.
+    fn read(&self, bytes: &[u8]) -> u32 {
+        let order = self.order();
+        let read_u16 = |offset| order.read_u16(bytes, offset).unwrap_or_default();
+        if self.is_thumb_pair() {
+            (u32::from(read_u16(2)) << 16) | u32::from(read_u16(0))
+        } else {
+            order.read_u32(bytes, 0).unwrap_or_default()
+        }
+    }
+
+    /// `A` for a branch, from the relocation or the offset already in the instruction.
+    fn branch_addend(&self, word: u32) -> i32 {
+        let addend = match self.r_type {
+            R_ARM_THM_CALL | R_ARM_THM_JUMP24 => {
+                let (hw1, hw2) = (word & 0xFFFF, word >> 16);
+                let s = (hw1 >> 10) & 1;
+                let i1 = !((hw2 >> 13) ^ s) & 1;
+                let i2 = !((hw2 >> 11) ^ s) & 1;
+                let imm = (s << 24) | (i1 << 23) | (i2 << 22) | ((hw1 & 0x3FF) << 12);
+                sign_extend(imm | ((hw2 & 0x7FF) << 1), 25)
+            }
+            _ => sign_extend((word & 0x00FF_FFFF) << 2, 26),
+        };
+        self.addend.unwrap_or(addend)
+    }
+
+    /// The veneer a branch encoded as `word` has to go through, if it can't reach its target or
+    /// has to change instruction set without being a call that can become BLX.
+    fn veneer(&self, word: u32, blx: bool) -> Option<Veneer> {
+        let thumb = self.is_thumb_pair();
+        let (bits, call) = match self.r_type {
+            R_ARM_PC24 | R_ARM_JUMP24 => (26, false),
+            R_ARM_CALL => (26, true),
+            R_ARM_THM_JUMP24 => (25, false),
+            R_ARM_THM_CALL => (25, true),
+            _ => return None,
+        };
+        let addend = self.branch_addend(word);
+        let interworking = thumb != self.thumb;
+        // BLX from Thumb is relative to the word aligned PC
+        let place = if thumb && interworking {
+            self.place & !3
+        } else {
+            self.place
+        };
+        let fixup = Fixup {
+            place,
+            symbol: String::new(),
+            ..*self
+        };
+        if (interworking && !(call && blx)) || fixup.branch_offset(addend, bits).is_err() {
+            // The addend allows for the PC being ahead of the branch
+            let bias = if thumb { 4 } else { 8 };
+            let destination =
+                self.target.wrapping_add_signed(addend + bias) | u32::from(self.thumb);
+            Some(Veneer { destination, thumb })
+        } else {
+            None
+        }
+    }
+
+    /// Patch the field at the start of `field`.
+    fn apply(&self, field: &mut [u8]) -> Result<(), LinkError> {
+        let Some(bytes) = field.get_mut(..4) else {
+            return Err(LinkError::BadObject(format!(
+                "{}: relocation outside its section",
+                self.object
+            )));
+        };
+        let (thumb_pair, order) = (self.is_thumb_pair(), self.order());
+        let word = self.read(bytes);
+        let (hw1, hw2) = (word as u16, (word >> 16) as u16);
+        let t = u32::from(self.thumb);
+
+        let patched = match self.r_type {
+            R_ARM_ABS32 | R_ARM_REL32 => {
+                let value = self
+                    .target
+                    .wrapping_add_signed(self.addend.unwrap_or(word as i32))
+                    | t;
+                if self.r_type == R_ARM_ABS32 {
+                    value
+                } else {
+                    value.wrapping_sub(self.place)
+                }
+            }
+            R_ARM_PREL31 => {
+                let addend = self.addend.unwrap_or(sign_extend(word, 31));
+                let value = (self.target.wrapping_add_signed(addend) | t).wrapping_sub(self.place);
+                (word & 0x8000_0000) | (value & 0x7FFF_FFFF)
+            }
+            R_ARM_PC24 | R_ARM_CALL | R_ARM_JUMP24 => {
+                let addend = self.branch_addend(word);
+                let offset = self.branch_offset(addend, 26)? as u32;
+                match (self.r_type, self.thumb) {
+                    // Calls into Thumb code become BLX, which encodes bit 1 of the offset in H
+                    (R_ARM_CALL, true) => {
+                        0xFA00_0000 | ((offset & 2) << 23) | ((offset >> 2) & 0x00FF_FFFF)
+                    }
+                    (R_ARM_CALL, false) if word >> 28 == 0xF => {
+                        0xEB00_0000 | ((offset >> 2) & 0x00FF_FFFF)
+                    }
+                    (_, true) => return Err(self.interworking()),
+                    _ => (word & 0xFF00_0000) | ((offset >> 2) & 0x00FF_FFFF),
+                }
+            }
+            R_ARM_MOVW_ABS_NC | R_ARM_MOVT_ABS => {
+                let imm16 = ((word >> 4) & 0xF000) | (word & 0xFFF);
+                let value = self
+                    .target
+                    .wrapping_add_signed(self.addend.unwrap_or(imm16 as i16 as i32));
+                let imm16 = if self.r_type == R_ARM_MOVW_ABS_NC {
+                    (value | t) & 0xFFFF
+                } else {
+                    value >> 16
+                };
+                (word & 0xFFF0_F000) | ((imm16 & 0xF000) << 4) | (imm16 & 0xFFF)
+            }
+            R_ARM_THM_CALL | R_ARM_THM_JUMP24 => {
+                let (hw1, hw2) = (u32::from(hw1), u32::from(hw2));
+                let addend = self.branch_addend(word);
+                let (offset, hw2) = match (self.r_type, self.thumb) {
+                    (R_ARM_THM_CALL, true) => (self.branch_offset(addend, 25)?, hw2 | 0x1000),
+                    // Calls into ARM code become BLX, relative to the word aligned PC
+                    (R_ARM_THM_CALL, false) => {
+                        let fixup = Fixup {
+                            place: self.place & !3,
+                            symbol: self.symbol.clone(),
+                            ..*self
+                        };
+                        (fixup.branch_offset(addend, 25)?, hw2 & !0x1000)
+                    }
+                    (_, false) => return Err(self.interworking()),
+                    _ => (self.branch_offset(addend, 25)?, hw2),
+                };
+                let offset = offset as u32;
+                let s = (offset >> 24) & 1;
+                let j1 = !((offset >> 23) ^ s) & 1;
+                let j2 = !((offset >> 22) ^ s) & 1;
+                let hw1 = (hw1 & 0xF800) | (s << 10) | ((offset >> 12) & 0x3FF);
+                let hw2 = (hw2 & 0xD000) | (j1 << 13) | (j2 << 11) | ((offset >> 1) & 0x7FF);
+                (hw2 << 16) | hw1
+            }
+            R_ARM_THM_JUMP19 => {
+                let (hw1, hw2) = (u32::from(hw1), u32::from(hw2));
+                let imm = (((hw1 >> 10) & 1) << 20)
+                    | (((hw2 >> 11) & 1) << 19)
+                    | (((hw2 >> 13) & 1) << 18)
+                    | ((hw1 & 0x3F) << 12)
+                    | ((hw2 & 0x7FF) << 1);
+                let addend = self.addend.unwrap_or(sign_extend(imm, 21));
+                if !self.thumb {
+                    return Err(self.interworking());
+                }
+                let offset = self.branch_offset(addend, 21)? as u32;
+                let hw1 = (hw1 & 0xFBC0) | (((offset >> 20) & 1) << 10) | ((offset >> 12) & 0x3F);
+                let hw2 = (hw2 & 0xD000)
+                    | (((offset >> 18) & 1) << 13)
+                    | (((offset >> 19) & 1) << 11)
+                    | ((offset >> 1) & 0x7FF);
+                (hw2 << 16) | hw1
+            }
+            R_ARM_THM_MOVW_ABS_NC | R_ARM_THM_MOVT_ABS => {
+                let (hw1, hw2) = (u32::from(hw1), u32::from(hw2));
+                let imm16 = ((hw1 & 0xF) << 12)
+                    | (((hw1 >> 10) & 1) << 11)
+                    | (((hw2 >> 12) & 7) << 8)
+                    | (hw2 & 0xFF);
+                let value = self
+                    .target
+                    .wrapping_add_signed(self.addend.unwrap_or(imm16 as i16 as i32));
+                let imm16 = if self.r_type == R_ARM_THM_MOVW_ABS_NC {
+                    (value | t) & 0xFFFF
+                } else {
+                    value >> 16
+                };
+                let hw1 = (hw1 & 0xFBF0) | (imm16 >> 12) | (((imm16 >> 11) & 1) << 10);
+                let hw2 = (hw2 & 0x8F00) | (((imm16 >> 8) & 7) << 12) | (imm16 & 0xFF);
+                (hw2 << 16) | hw1
+            }
+            r_type => {
+                return Err(LinkError::UnsupportedRelocation(
+                    self.object.to_owned(),
+                    r_type,
+                ))
+            }
+        };
+
+        if thumb_pair {
+            bytes[..2].copy_from_slice(&order.u16_bytes(patched as u16));
+            bytes[2..].copy_from_slice(&order.u16_bytes((patched >> 16) as u16));
+        } else {
+            bytes.copy_from_slice(&order.u32_bytes(patched));
+        }
+        Ok(())
+    }
+}
+
+/// Links ELF relocatable objects into a statically linked executable.
+#[derive(Clone, Debug)]
+pub struct Linker {
+    entry: Option<String>,
+    base_address: u32,
+    script: Option<LinkerScript>,
+    format: OutputFormat,
+    objects: Vec<(String, ObjectFile)>,
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self {
+            entry: None,
+            base_address: DEFAULT_BASE_ADDRESS,
+            script: None,
+            format: OutputFormat::Elf,
+            objects: vec![],
+        }
+    }
+
+    /// Start execution at `symbol` rather than `_start` or the linker script's `ENTRY`.
+    pub fn set_entry(&mut self, symbol: &str) {
+        self.entry = Some(symbol.to_owned());
+    }
+
+    /// Load the first segment at `address` rather than [`DEFAULT_BASE_ADDRESS`]. Has no effect
+    /// with a linker script.
+    pub fn set_base_address(&mut self, address: u32) {
+        self.base_address = address;
+    }
+
+    /// Lay out the output as `script` describes.
+    pub fn set_script(&mut self, script: LinkerScript) {
+        self.script = Some(script);
+    }
+
+    /// Write a raw binary, Intel HEX or S-records instead of an ELF executable.
+    pub fn set_output_format(&mut self, format: OutputFormat) {
+        self.format = format;
+    }
+
+    /// Add the object in `bytes`, using `name` to refer to it in errors.
+    pub fn add_object(&mut self, name: &str, bytes: &[u8]) -> Result<(), LinkError> {
+        let object = ObjectFile::parse(bytes)?;
+        if let Some((_, first)) = self.objects.first() {
+            if first.endianness != object.endianness {
+                return Err(LinkError::MixedEndianness(name.to_owned()));
+            }
+        }
+        self.objects.push((name.to_owned(), object));
+        Ok(())
+    }
+
+    /// Link everything added so far into an `ET_EXEC` image.
+    pub fn link(&self) -> Result<Vec<u8>, LinkError> {
+        let globals = self.resolve_symbols()?;
+        let mut inputs = self.inputs(&globals);
+        let capabilities = self.capabilities();
+
+        // Veneers make room for each other and move what follows them, so lay everything out
+        // again until there is room for all the ones needed
+        let mut room = 0;
+        let mut caller = None;
+        let (mut layout, fixups, veneers) = loop {
+            let layout = match &self.script {
+                Some(script) => self.script_layout(script, &inputs, &globals, caller.as_deref())?,
+                None => self.default_layout(&inputs),
+            };
+            let fixups = self.fixups(&globals, &layout)?;
+            let veneers = self.veneers(&fixups, &layout, capabilities)?;
+            let size = veneers.iter().map(|needed| needed.code.len()).sum();
+            if size <= room {
+                break (layout, fixups, veneers);
+            }
+            room = size;
+            caller = veneers
+                .first()
+                .map(|needed| layout.sections[needed.caller].section.name.clone());
+            inputs.retain(|input| input.key != InputKey::Veneers);
+            let mut section = Section::new(".text.veneers", SHT_PROGBITS, 0, 4);
+            section.flags = SHF_ALLOC | SHF_EXECINSTR;
+            section.data = vec![0; room];
+            inputs.push(Input {
+                key: InputKey::Veneers,
+                object: 0,
+                section,
+            });
+        };
+        let veneer_symbols =
+            self.apply_relocations(&mut layout, fixups, &veneers, capabilities.blx)?;
+
+        let script_entry = self
+            .script
+            .as_ref()
+            .and_then(|script| script.entry.as_ref());
+        let entry = match self.entry.as_ref().or(script_entry) {
+            Some(name) => self
+                .global_address(&globals, &layout, name)
+                .ok_or_else(|| LinkError::UndefinedEntry(name.clone()))?,
+            // Like GNU ld, fall back to the start of the code
+            None => self
+                .global_address(&globals, &layout, DEFAULT_ENTRY)
+                .or_else(|| {
+                    layout
+                        .sections
+                        .iter()
+                        .find(|output| output.section.flags & SHF_EXECINSTR != 0)
+                        .map(|output| output.address)
+                })
+                .unwrap_or(self.base_address),
+        };
+
+        let flags = self
+            .objects
+            .first()
+            .map_or(EF_ARM_EABI_VER5, |(_, object)| object.flags);
+        let mut writer = ExecutableWriter::new(entry, flags);
+        writer.endianness = self.endianness();
+        for segment in &layout.segments {
+            writer.add_segment(*segment);
+        }
+        for output in &layout.sections {
+            writer.add_section(output.section.clone(), output.address, output.offset);
+        }
+        for symbol in self
+            .output_symbols(&globals, &layout)
+            .into_iter()
+            .chain(veneer_symbols)
+        {
+            writer.add_symbol(symbol);
+        }
+
+        self.format.convert(&writer.to_bytes())
+    }
+
+    fn endianness(&self) -> Endianness {
+        self.objects
+            .first()
+            .map_or(Endianness::Little, |(_, object)| object.endianness)
+    }
+
+    /// What every object's `.ARM.attributes` allows. Objects without them don't limit anything.
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities {
+            blx: true,
+            thumb2: true,
+            arm: true,
+        };
+        for (_, object) in &self.objects {
+            let Some(section) = object
+                .sections
+                .iter()
+                .find(|section| section.name == ".ARM.attributes")
+            else {
+                continue;
+            };
+            let attributes = read_attributes(&section.data, object.endianness);
+            let attribute = |tag| attributes.get(&tag).copied().unwrap_or_default();
+            capabilities.blx &= attribute(TAG_CPU_ARCH) >= CPU_ARCH_V5T;
+            capabilities.thumb2 &= attribute(TAG_THUMB_ISA_USE) >= 2;
+            capabilities.arm &= attribute(TAG_ARM_ISA_USE) != 0;
+        }
+        capabilities
+    }
+
+    fn symbol(&self, object: usize, index: usize) -> &Symbol {
+        &self.objects[object].1.symbols[index]
+    }
+
+    /// Pick the definition of each global symbol, checking that no symbol is defined twice.
+    fn resolve_symbols(&self) -> Result<HashMap<String, Definition>, LinkError> {
+        let mut globals: HashMap<String, Definition> = HashMap::new();
+        for (o, (_, object)) in self.objects.iter().enumerate() {
+            for (i, symbol) in object.symbols.iter().enumerate() {
+                if symbol.binding == STB_LOCAL || symbol.section == SHN_UNDEF {
+                    continue;
+                }
+
+                let strength = if symbol.binding == STB_WEAK {
+                    Strength::Weak
+                } else if symbol.section == SHN_COMMON {
+                    Strength::Common
+                } else {
+                    Strength::Strong
+                };
+                let definition = Definition {
+                    object: o,
+                    symbol: i,
+                    strength,
+                };
+                match globals.entry(symbol.name.clone()) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(definition);
+                    }
+                    Entry::Occupied(mut entry) => {
+                        let existing = *entry.get();
+                        let replace = match (existing.strength, strength) {
+                            (Strength::Strong, Strength::Strong) => {
+                                return Err(LinkError::DuplicateSymbol(symbol.name.clone()))
+                            }
+                            // The largest of several common symbols is kept
+                            (Strength::Common, Strength::Common) => {
+                                symbol.size > self.symbol(existing.object, existing.symbol).size
+                            }
+                            (existing, new) => new > existing,
+                        };
+                        if replace {
+                            entry.insert(definition);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(globals)
+    }
+
+    /// Every allocated input section, followed by the space for each common symbol.
+    fn inputs(&self, globals: &HashMap<String, Definition>) -> Vec<Input> {
+        let mut inputs = vec![];
+        for (o, (_, object)) in self.objects.iter().enumerate() {
+            for (i, section) in object.sections.iter().enumerate() {
+                if section.flags & SHF_ALLOC != 0 {
+                    inputs.push(Input {
+                        key: InputKey::Section(o, i),
+                        object: o,
+                        section: section.clone(),
+                    });
+                }
+            }
+        }
+
+        let mut commons: Vec<(&String, &Definition)> = globals
+            .iter()
+            .filter(|(_, definition)| definition.strength == Strength::Common)
+            .collect();
+        commons.sort_by_key(|(name, _)| *name);
+        for (name, definition) in commons {
+            let symbol = self.symbol(definition.object, definition.symbol);
+            // The value of a common symbol is its alignment
+            let mut section = Section::new(COMMON, SHT_NOBITS, SHF_ALLOC | SHF_WRITE, symbol.value);
+            section.data = vec![0; symbol.size as usize];
+            inputs.push(Input {
+                key: InputKey::Common(name.clone()),
+                object: definition.object,
+                section,
+            });
+        }
+
+        inputs
+    }
+
+    /// Merge input sections by name and place the results one after another: code first, then
+    /// read-only data in the first segment, followed by initialised and zeroed data in the second.
+    fn default_layout(&self, inputs: &[Input]) -> Layout {
+        let mut outputs = vec![];
+        let placed: Vec<(usize, u32)> = inputs
+            .iter()
+            .map(|input| place_section(&mut outputs, default_output_name(input), &input.section))
+            .collect();
+
+        let mut order: Vec<usize> = (0..outputs.len()).collect();
+        order.sort_by_key(|&i| {
+            let section = &outputs[i];
+            (
+                section.flags & SHF_WRITE != 0,
+                section.sh_type == SHT_NOBITS,
+                section.flags & SHF_EXECINSTR == 0,
+            )
+        });
+        let mut new_index = vec![0; outputs.len()];
+        for (position, &i) in order.iter().enumerate() {
+            new_index[i] = position;
+        }
+
+        let loaded: Vec<&Section> = order
+            .iter()
+            .map(|&i| &outputs[i])
+            .filter(|section| !section.data.is_empty())
+            .collect();
+        let segment_count = 1 + loaded
+            .windows(2)
+            .filter(|pair| (pair[0].flags ^ pair[1].flags) & SHF_WRITE != 0)
+            .count();
+
+        // The headers are loaded at the base address, just before the first section
+        let mut address = self.base_address + ExecutableWriter::headers_size(segment_count);
+        let mut writable = None;
+        let mut layout = Layout::default();
+        for i in order {
+            let section = outputs[i].clone();
+            if !section.data.is_empty() {
+                let section_writable = section.flags & SHF_WRITE != 0;
+                if writable.is_some_and(|writable| writable != section_writable) {
+                    // A new segment, on a new page but keeping the address congruent to the file
+                    // offset
+                    address = address.next_multiple_of(PAGE_SIZE) + address % PAGE_SIZE;
+                }
+                writable = Some(section_writable);
+            }
+
+            address = address.next_multiple_of(section.align.max(1));
+            let size = section.data.len() as u32;
+            layout.sections.push(OutputSection {
+                section,
+                address,
+                load_address: address,
+                offset: 0,
+            });
+            address += size;
+        }
+
+        for (input, (section, offset)) in inputs.iter().zip(placed) {
+            layout.place(&input.key, (new_index[section], offset));
+        }
+        layout.segments = build_segments(&mut layout.sections, true);
+        layout
+    }
+
+    /// Place input sections as `script` describes. Sections it doesn't mention are merged by name
+    /// and placed after everything else, except for veneers, which follow the last input sections
+    /// of `caller`, the output section of the first branch that needs one.
+    fn script_layout(
+        &self,
+        script: &LinkerScript,
+        inputs: &[Input],
+        globals: &HashMap<String, Definition>,
+        caller: Option<&str>,
+    ) -> Result<Layout, LinkError> {
+        // Each input section goes to the first description that matches it
+        let mut claimed = vec![false; inputs.len()];
+        let mut assignments: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (c, command) in script.commands.iter().enumerate() {
+            let Command::Section(description) = command else {
+                continue;
+            };
+            for (d, command) in description.commands.iter().enumerate() {
+                let Command::Input(pattern) = command else {
+                    continue;
+                };
+                let mut matched: Vec<usize> = (0..inputs.len())
+                    .filter(|&i| {
+                        let file = match inputs[i].key {
+                            InputKey::Veneers => VENEER_FILE,
+                            _ => &self.objects[inputs[i].object].0,
+                        };
+                        !claimed[i] && pattern.matches(file, &inputs[i].section.name)
+                    })
+                    .collect();
+                if pattern.sort {
+                    matched.sort_by(|&a, &b| inputs[a].section.name.cmp(&inputs[b].section.name));
+                }
+                for &i in &matched {
+                    claimed[i] = true;
+                }
+                assignments.insert((c, d), matched);
+            }
+        }
+        // Veneers have to stay within reach of the branches, which may not be true of orphans
+        let veneers = inputs
+            .iter()
+            .position(|input| input.key == InputKey::Veneers)
+            .filter(|&i| !claimed[i]);
+        if let (Some(i), Some(caller)) = (veneers, caller) {
+            let last_input = script.commands.iter().enumerate().find_map(|(c, command)| {
+                let Command::Section(description) = command else {
+                    return None;
+                };
+                if description.name != caller {
+                    return None;
+                }
+                let commands = &description.commands;
+                let d = commands
+                    .iter()
+                    .rposition(|command| matches!(command, Command::Input(_)))?;
+                Some((c, d))
+            });
+            if let Some(key) = last_input {
+                assignments.entry(key).or_default().push(i);
+                claimed[i] = true;
+            }
+        }
+
+        let mut state = ScriptState {
+            script,
+            globals,
+            location: 0,
+            symbols: vec![],
+            sections: HashMap::new(),
+            cursors: script
+                .regions
+                .iter()
+                .map(|region| (region.name.clone(), region.origin))
+                .collect(),
+        };
+        let mut layout = Layout::default();
+        // Assignments outside sections may refer to sections further on, so any that can't be
+        // evaluated yet are retried at the end, with the location counter they saw
+        let mut deferred = vec![];
+        for (c, command) in script.commands.iter().enumerate() {
+            let description = match command {
+                Command::SetLocation(expr) => {
+                    state.location = expr.evaluate(&state)?;
+                    continue;
+                }
+                Command::Assign {
+                    symbol,
+                    expr,
+                    provide,
+                } => {
+                    if state.assign(symbol, expr, *provide).is_err() {
+                        deferred.push((state.location, symbol, expr, *provide));
+                    }
+                    continue;
+                }
+                Command::Section(description) if description.name != DISCARD => description,
+                _ => continue,
+            };
+
+            let assigned = |d| assignments.get(&(c, d)).into_iter().flatten();
+            let mut align = (0..description.commands.len())
+                .flat_map(assigned)
+                .map(|&i| inputs[i].section.align)
+                .fold(1, u32::max);
+            if let Some(expr) = &description.align {
+                align = align.max(expr.evaluate(&state)?);
+            }
+            let address = match (&description.address, &description.region) {
+                (Some(expr), _) => expr.evaluate(&state)?,
+                (None, Some(region)) => state.cursor(region)?.next_multiple_of(align),
+                (None, None) => state.location.next_multiple_of(align),
+            };
+            let load_address = match (&description.load_address, &description.load_region) {
+                (Some(expr), _) => expr.evaluate(&state)?,
+                (None, Some(region)) => state.cursor(region)?.next_multiple_of(align),
+                (None, None) => address,
+            };
+
+            let index = layout.sections.len();
+            let mut section = Section::new(&description.name, SHT_NOBITS, 0, align);
+            let mut has_data = false;
+            state.location = address;
+            for (d, command) in description.commands.iter().enumerate() {
+                match command {
+                    Command::SetLocation(expr) => {
+                        let location = expr.evaluate(&state)?;
+                        if location < state.location {
+                            return Err(LinkError::BadScript(format!(
+                                "the location counter moves backwards in {}",
+                                description.name
+                            )));
+                        }
+                        state.location = location;
+                    }
+                    Command::Assign {
+                        symbol,
+                        expr,
+                        provide,
+                    } => state.assign(symbol, expr, *provide)?,
+                    Command::Input(_) => {
+                        for &i in assigned(d) {
+                            let input = &inputs[i].section;
+                            let start = state.location.next_multiple_of(input.align.max(1));
+                            let offset = start - address;
+                            section.data.resize(offset as usize, 0);
+                            section.data.extend_from_slice(&input.data);
+                            if input.sh_type != SHT_NOBITS {
+                                section.sh_type = input.sh_type;
+                                has_data = true;
+                            }
+                            section.flags |= input.flags & (SHF_WRITE | SHF_ALLOC | SHF_EXECINSTR);
+                            layout.place(&inputs[i].key, (index, offset));
+                            state.location = start + input.data.len() as u32;
+                        }
+                    }
+                    Command::Section(_) => {}
+                }
+            }
+
+            let size = state.location - address;
+            section.data.resize(size as usize, 0);
+            section.flags |= SHF_ALLOC;
+            if description.noload {
+                section.sh_type = SHT_NOBITS;
+            } else if !has_data && section.flags & SHF_WRITE == 0 {
+                // Padding and symbols alone still take up room in the file
+                section.sh_type = SHT_PROGBITS;
+            }
+
+            if let Some(region) = &description.region {
+                state.advance(region, address + size)?;
+            }
+            if let Some(region) = &description.load_region {
+                if section.sh_type != SHT_NOBITS {
+                    state.advance(region, load_address + size)?;
+                }
+            }
+            state
+                .sections
+                .insert(description.name.clone(), (address, load_address, size));
+            layout.sections.push(OutputSection {
+                section,
+                address,
+                load_address,
+                offset: 0,
+            });
+        }
+
+        // Anything the script doesn't mention follows, merged by name
+        let mut orphans = vec![];
+        let mut placed = vec![];
+        for (input, _) in inputs
+            .iter()
+            .zip(&claimed)
+            .filter(|(_, claimed)| !**claimed)
+        {
+            let placement = place_section(&mut orphans, default_output_name(input), &input.section);
+            placed.push((&input.key, placement));
+        }
+        let first_orphan = layout.sections.len();
+        for section in orphans {
+            let address = state.location.next_multiple_of(section.align.max(1));
+            state.location = address + section.data.len() as u32;
+            layout.sections.push(OutputSection {
+                section,
+                address,
+                load_address: address,
+                offset: 0,
+            });
+        }
+        for (key, (section, offset)) in placed {
+            layout.place(key, (first_orphan + section, offset));
+        }
+
+        for (location, symbol, expr, provide) in deferred {
+            state.location = location;
+            state.assign(symbol, expr, provide)?;
+        }
+
+        layout.symbols = state.symbols;
+        layout.segments = build_segments(&mut layout.sections, false);
+        Ok(layout)
+    }
+
+    /// The address of symbol `index` as defined in `object`, without the Thumb bit, and whether
+    /// it is a Thumb function. `None` if its section was not allocated.
+    fn symbol_address(&self, layout: &Layout, object: usize, index: usize) -> Option<(u32, bool)> {
+        let symbol = self.symbol(object, index);
+        let thumb = symbol.is_thumb_func();
+        let value = symbol.value & !u32::from(thumb);
+        let address = match symbol.section {
+            SHN_UNDEF => return None,
+            SHN_ABS => value,
+            SHN_COMMON => layout.address(*layout.commons.get(&symbol.name)?),
+            section => layout.address(*layout.placements.get(&(object, section.into()))?) + value,
+        };
+        Some((address, thumb))
+    }
+
+    /// The address of global symbol `name`, with the Thumb bit set for Thumb functions.
+    fn global_address(
+        &self,
+        globals: &HashMap<String, Definition>,
+        layout: &Layout,
+        name: &str,
+    ) -> Option<u32> {
+        if let Some(value) = layout.script_symbol(name) {
+            return Some(value);
+        }
+        let definition = globals.get(name)?;
+        let (address, thumb) = self.symbol_address(layout, definition.object, definition.symbol)?;
+        Some(address | u32::from(thumb))
+    }
+
+    /// The name to report a symbol by. Section symbols are named after their section.
+    fn symbol_name(&self, object: usize, symbol: &Symbol) -> String {
+        let sections = &self.objects[object].1.sections;
+        match sections.get(symbol.section as usize) {
+            Some(section) if symbol.sym_type == STT_SECTION => section.name.clone(),
+            _ => symbol.name.clone(),
+        }
+    }
+
+    /// Find what symbol `index` of `object` refers to, for a relocation. `None` for an undefined
+    /// weak symbol.
+    fn resolve(
+        &self,
+        globals: &HashMap<String, Definition>,
+        layout: &Layout,
+        object: usize,
+        index: usize,
+    ) -> Result<Option<(u32, bool)>, LinkError> {
+        let (name, symbols) = (&self.objects[object].0, &self.objects[object].1.symbols);
+        let Some(symbol) = symbols.get(index) else {
+            return Err(LinkError::BadObject(format!(
+                "{name}: relocation against symbol {index}, which does not exist"
+            )));
+        };
+
+        // Symbols the linker script assigns take precedence over the objects' definitions
+        let address = match globals.get(&symbol.name) {
+            _ if symbol.binding == STB_LOCAL => self.symbol_address(layout, object, index),
+            _ if layout.script_symbol(&symbol.name).is_some() => layout
+                .script_symbol(&symbol.name)
+                .map(|value| (value, false)),
+            Some(definition) => self.symbol_address(layout, definition.object, definition.symbol),
+            None if symbol.binding == STB_WEAK => return Ok(None),
+            None => None,
+        };
+        match address {
+            Some(address) => Ok(Some(address)),
+            None => Err(LinkError::UndefinedSymbol(
+                name.clone(),
+                self.symbol_name(object, symbol),
+            )),
+        }
+    }
+
+    /// Every relocation of the loaded sections with its symbol resolved, and the output section
+    /// and offset within it of the field it patches.
+    fn fixups(
+        &self,
+        globals: &HashMap<String, Definition>,
+        layout: &Layout,
+    ) -> Result<Vec<(usize, u32, Fixup<'_>)>, LinkError> {
+        let mut fixups = vec![];
+        for (o, (name, object)) in self.objects.iter().enumerate() {
+            for (section, relocations) in &object.relocations {
+                // Relocations for sections that aren't loaded, such as debug info, are dropped
+                let Some(&(output, start)) = layout.placements.get(&(o, (*section).into())) else {
+                    continue;
+                };
+
+                for relocation in relocations {
+                    if matches!(relocation.r_type, R_ARM_NONE | R_ARM_V4BX) {
+                        continue;
+                    }
+
+                    let index = relocation.symbol as usize;
+                    let offset = start + relocation.offset;
+                    let place = layout.address((output, offset));
+                    let (target, thumb, addend) =
+                        match (self.resolve(globals, layout, o, index)?, relocation.r_type) {
+                            (Some((target, thumb)), _) => (target, thumb, relocation.addend),
+                            // Branches to undefined weak symbols go to the next instruction instead
+                            (None, R_ARM_PC24 | R_ARM_CALL | R_ARM_JUMP24) => {
+                                (place + 4, false, Some(-8))
+                            }
+                            (None, R_ARM_THM_CALL | R_ARM_THM_JUMP24 | R_ARM_THM_JUMP19) => {
+                                (place + 4, true, Some(-4))
+                            }
+                            // Anything else sees zero
+                            (None, _) => (0, false, Some(0)),
+                        };
+                    let fixup = Fixup {
+                        object: name,
+                        symbol: self.symbol_name(o, &object.symbols[index]),
+                        r_type: relocation.r_type,
+                        place,
+                        target,
+                        thumb,
+                        addend,
+                        endianness: object.endianness,
+                    };
+                    fixups.push((output, offset, fixup));
+                }
+            }
+        }
+
+        Ok(fixups)
+    }
+
+    /// The veneers the branches among `fixups` need with `layout`.
+    fn veneers(
+        &self,
+        fixups: &[(usize, u32, Fixup)],
+        layout: &Layout,
+        capabilities: Capabilities,
+    ) -> Result<Vec<NeededVeneer>, LinkError> {
+        let mut veneers: Vec<NeededVeneer> = vec![];
+        for (output, offset, fixup) in fixups {
+            let data = &layout.sections[*output].section.data;
+            let field = data.get(*offset as usize..).unwrap_or_default();
+            let Some(veneer) = fixup.veneer(fixup.read(field), capabilities.blx) else {
+                continue;
+            };
+            if veneers.iter().all(|needed| needed.veneer != veneer) {
+                let code = veneer
+                    .code(capabilities, self.endianness())
+                    .ok_or_else(|| fixup.out_of_range())?;
+                veneers.push(NeededVeneer {
+                    veneer,
+                    name: fixup.symbol.clone(),
+                    code,
+                    caller: *output,
+                });
+            }
+        }
+        Ok(veneers)
+    }
+
+    /// Write `veneers` into the room made for them and patch every field, sending branches that
+    /// need it through a veneer. Returns the symbols for the veneers.
+    fn apply_relocations(
+        &self,
+        layout: &mut Layout,
+        fixups: Vec<(usize, u32, Fixup)>,
+        veneers: &[NeededVeneer],
+        blx: bool,
+    ) -> Result<Vec<Symbol>, LinkError> {
+        let mut addresses = vec![];
+        let mut symbols = vec![];
+        if let Some((output, mut offset)) = layout.veneers {
+            for needed in veneers {
+                let (start, size) = (offset as usize, needed.code.len());
+                let data = &mut layout.sections[output].section.data;
+                data[start..start + size].copy_from_slice(&needed.code);
+                let address = layout.address((output, offset));
+                addresses.push((needed.veneer, address));
+                let veneer_symbols =
+                    needed
+                        .veneer
+                        .symbols(&needed.name, address, size as u32, output);
+                symbols.extend(veneer_symbols);
+                offset += size as u32;
+            }
+        }
+
+        for (output, offset, fixup) in fixups {
+            let data = &mut layout.sections[output].section.data;
+            let field = data.get_mut(offset as usize..).unwrap_or_default();
+            let fixup = match fixup.veneer(fixup.read(field), blx) {
+                Some(veneer) => {
+                    let (_, address) = addresses
+                        .iter()
+                        .find(|(existing, _)| *existing == veneer)
+                        .ok_or_else(|| fixup.out_of_range())?;
+                    Fixup {
+                        target: *address,
+                        thumb: veneer.thumb,
+                        ..fixup
+                    }
+                }
+                None => fixup,
+            };
+            fixup.apply(field)?;
+        }
+
+        Ok(symbols)
+    }
+
+    /// The symbols of the executable: every named local symbol, and the chosen definition of each
+    /// global one.
+    fn output_symbols(
+        &self,
+        globals: &HashMap<String, Definition>,
+        layout: &Layout,
+    ) -> Vec<Symbol> {
+        let mut symbols = vec![];
+        for (o, (_, object)) in self.objects.iter().enumerate() {
+            for (i, symbol) in object.symbols.iter().enumerate() {
+                if symbol.name.is_empty() || matches!(symbol.sym_type, STT_SECTION | STT_FILE) {
+                    continue;
+                }
+                // Only the chosen definition of a global symbol is kept
+                let chosen = globals
+                    .get(&symbol.name)
+                    .is_some_and(|definition| (definition.object, definition.symbol) == (o, i));
+                if symbol.binding != STB_LOCAL
+                    && (!chosen || layout.script_symbol(&symbol.name).is_some())
+                {
+                    continue;
+                }
+                let Some((address, thumb)) = self.symbol_address(layout, o, i) else {
+                    continue;
+                };
+
+                // Writer section indices start at 1
+                let section = match symbol.section {
+                    SHN_ABS => SHN_ABS,
+                    SHN_COMMON => layout.commons[&symbol.name].0 as u16 + 1,
+                    section => layout.placements[&(o, section.into())].0 as u16 + 1,
+                };
+                // Hidden symbols are only visible within the image
+                let binding = match symbol.visibility {
+                    STV_HIDDEN | STV_INTERNAL => STB_LOCAL,
+                    _ => symbol.binding,
+                };
+                symbols.push(Symbol {
+                    value: address | u32::from(thumb),
+                    binding,
+                    section,
+                    ..symbol.clone()
+                });
+            }
+        }
+
+        for (name, value) in &layout.symbols {
+            symbols.push(Symbol {
+                name: name.clone(),
+                value: *value,
+                size: 0,
+                binding: STB_GLOBAL,
+                sym_type: STT_NOTYPE,
+                visibility: STV_DEFAULT,
+                section: SHN_ABS,
+            });
+        }
+
+        symbols
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::Linker;
+    use crate::{
+        arch::{Arch, Target},
+        assembler::Assembler,
+        elf::{
+            Endianness, ExecutableFile, ObjectWriter, Relocation, Section, Symbol, R_ARM_ABS32,
+            R_ARM_CALL, R_ARM_MOVT_ABS, R_ARM_MOVW_ABS_NC, R_ARM_THM_CALL, SHF_ALLOC,
+            SHF_EXECINSTR, SHF_WRITE, SHT_PROGBITS, STB_GLOBAL, STB_WEAK, STT_FUNC, STT_NOTYPE,
+            STV_DEFAULT,
+        },
+        error::LinkError,
+        formats::{BinaryWriter, OutputFormat},
+        linker_script::LinkerScript,
+    };
+
+    /// An object with a `.text` and a `.data` section, which are sections 1 and 2. Symbols are
+    /// given as `(name, section, value, binding)`, and relocations as
+    /// `(section, offset, symbol, type)`.
+    fn object(
+        text: &[u8],
+        data: &[u8],
+        symbols: &[(&str, u16, u32, u8)],
+        relocations: &[(u16, u32, &str, u8)],
+    ) -> Vec<u8> {
+        let mut writer = ObjectWriter::new(0);
+        let mut section = Section::new(".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 4);
+        section.data = text.to_vec();
+        writer.add_section(section);
+        let mut section = Section::new(".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 4);
+        section.data = data.to_vec();
+        writer.add_section(section);
+
+        let indices: Vec<u32> = symbols
+            .iter()
+            .map(|&(name, section, value, binding)| {
+                writer.add_symbol(Symbol {
+                    name: name.to_owned(),
+                    value,
+                    size: 0,
+                    binding,
+                    sym_type: if value & 1 == 1 { STT_FUNC } else { STT_NOTYPE },
+                    visibility: STV_DEFAULT,
+                    section,
+                })
+            })
+            .collect();
+        for &(section, offset, name, r_type) in relocations {
+            let symbol = symbols.iter().position(|symbol| symbol.0 == name).unwrap();
+            let relocation = Relocation {
+                offset,
+                symbol: indices[symbol],
+                r_type,
+                addend: None,
+            };
+            writer.add_relocation(section, relocation);
+        }
+        writer.to_bytes()
+    }
+
+    #[test]
+    fn test_link() {
+        // _start: bl func; movw r0, #:lower16:value; movt r0, #:upper16:value; svc #0
+        let arm = object(
+            &[
+                0xFE, 0xFF, 0xFF, 0xEB, 0x00, 0x00, 0x00, 0xE3, 0x00, 0x00, 0x40, 0xE3, 0x00, 0x00,
+                0x00, 0xEF,
+            ],
+            &[],
+            &[
+                ("_start", 1, 0, STB_GLOBAL),
+                ("func", 0, 0, STB_GLOBAL),
+                ("value", 0, 0, STB_GLOBAL),
+            ],
+            &[
+                (1, 0, "func", R_ARM_CALL),
+                (1, 4, "value", R_ARM_MOVW_ABS_NC),
+                (1, 8, "value", R_ARM_MOVT_ABS),
+            ],
+        );
+        // func: bl _start; bx lr, and value: .word func
+        let thumb = object(
+            &[0xFF, 0xF7, 0xFE, 0xFF, 0x70, 0x47],
+            &[0, 0, 0, 0],
+            &[
+                ("func", 1, 1, STB_GLOBAL),
+                ("value", 2, 0, STB_GLOBAL),
+                ("_start", 0, 0, STB_GLOBAL),
+            ],
+            &[
+                (1, 0, "_start", R_ARM_THM_CALL),
+                (2, 0, "func", R_ARM_ABS32),
+            ],
+        );
+
+        let mut linker = Linker::new();
+        linker.add_object("arm.o", &arm).unwrap();
+        linker.add_object("thumb.o", &thumb).unwrap();
+        let image = linker.link().unwrap();
+
+        // ET_EXEC, entered at _start just after the ELF header and two program headers
+        assert_eq!(&image[16..18], &[2, 0]);
+        assert_eq!(&image[24..28], &0x10074u32.to_le_bytes());
+        // The calls switch to BLX, and value is placed in the data segment at 0x1108c
+        assert_eq!(
+            &image[0x74..0x8A],
+            &[
+                0x02, 0x00, 0x00, 0xFA, 0x8C, 0x00, 0x01, 0xE3, 0x01, 0x00, 0x40, 0xE3, 0x00, 0x00,
+                0x00, 0xEF, 0xFF, 0xF7, 0xF6, 0xEF, 0x70, 0x47,
+            ]
+        );
+        assert_eq!(&image[0x8C..0x90], &0x10085u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_symbol_resolution() {
+        let strong = object(&[0; 4], &[], &[("_start", 1, 0, STB_GLOBAL)], &[]);
+        let weak = object(
+            &[0; 4],
+            &[0xFF; 4],
+            &[("_start", 1, 0, STB_WEAK), ("missing", 0, 0, STB_WEAK)],
+            &[(2, 0, "missing", R_ARM_ABS32)],
+        );
+
+        // The strong definition wins, and the undefined weak reference is zero
+        let mut linker = Linker::new();
+        linker.add_object("weak.o", &weak).unwrap();
+        linker.add_object("strong.o", &strong).unwrap();
+        let image = linker.link().unwrap();
+        assert_eq!(&image[24..28], &0x10078u32.to_le_bytes());
+        assert_eq!(&image[0x7C..0x80], &[0; 4]);
+
+        linker.add_object("again.o", &strong).unwrap();
+        assert!(matches!(linker.link(), Err(LinkError::DuplicateSymbol(_))));
+
+        let undefined = object(
+            &[0; 4],
+            &[],
+            &[("elsewhere", 0, 0, STB_GLOBAL)],
+            &[(1, 0, "elsewhere", R_ARM_ABS32)],
+        );
+        let mut linker = Linker::new();
+        linker.add_object("undefined.o", &undefined).unwrap();
+        assert_eq!(
+            linker.link().unwrap_err().to_string(),
+            "undefined.o: undefined reference to elsewhere"
+        );
+
+        let mut linker = Linker::new();
+        linker.add_object("strong.o", &strong).unwrap();
+        linker.set_entry("main");
+        assert!(matches!(linker.link(), Err(LinkError::UndefinedEntry(_))));
+        assert!(linker.add_object("bad.o", b"not an object").is_err());
+    }
+
+    #[test]
+    fn test_script() {
+        let script = "
+            ENTRY(reset)
+            MEMORY { FLASH (rx) : ORIGIN = 0x8000000, LENGTH = 16  RAM : ORIGIN = 0x20000000, LENGTH = 1K }
+            SECTIONS {
+                .text : { *(.text*) } > FLASH
+                .data : { _sdata = .; *(.data*) _edata = .; } > RAM AT> FLASH
+                _sidata = LOADADDR(.data);
+            }";
+        // reset: .word _sidata; .word _sdata, and some initialised data
+        let firmware = object(
+            &[0; 8],
+            &[1, 2, 3, 4],
+            &[
+                ("reset", 1, 0, STB_GLOBAL),
+                ("_sidata", 0, 0, STB_GLOBAL),
+                ("_sdata", 0, 0, STB_GLOBAL),
+            ],
+            &[
+                (1, 0, "_sidata", R_ARM_ABS32),
+                (1, 4, "_sdata", R_ARM_ABS32),
+            ],
+        );
+
+        let mut linker = Linker::new();
+        linker.set_script(LinkerScript::parse(script).unwrap());
+        linker.add_object("firmware.o", &firmware).unwrap();
+        let image = linker.link().unwrap();
+
+        assert_eq!(&image[24..28], &0x8000000u32.to_le_bytes());
+        // .data runs in RAM but is loaded into flash after .text
+        let data_segment = &image[84..116];
+        assert_eq!(&data_segment[8..16], &[0, 0, 0, 0x20, 8, 0, 0, 0x08]);
+        assert_eq!(&image[0x1000..0x1008], &[8, 0, 0, 0x08, 0, 0, 0, 0x20]);
+        assert_eq!(&image[0x2000..0x2004], &[1, 2, 3, 4]);
+
+        // A raw binary has .data right after .text, where it is loaded
+        linker.set_output_format(OutputFormat::Binary(BinaryWriter::default()));
+        assert_eq!(
+            linker.link().unwrap(),
+            &[8, 0, 0, 0x08, 0, 0, 0, 0x20, 1, 2, 3, 4]
+        );
+
+        // Another copy no longer fits in flash
+        linker
+            .add_object("again.o", &object(&[0; 8], &[], &[], &[]))
+            .unwrap();
+        assert_eq!(
+            linker.link().unwrap_err().to_string(),
+            "Region FLASH overflowed by 4 bytes"
+        );
+    }
+
+    #[test]
+    fn test_veneers() {
+        let script = "SECTIONS { .text 0x10000 : { *(.text*) } .far 0x4000000 : { *(.far) } }";
+        let arm = [
+            ".global _start",
+            "_start: bl far",
+            "b func",
+            ".section .far, \"ax\"",
+            ".global far",
+            "far: bx lr",
+        ];
+        let thumb = [
+            ".global func",
+            ".thumb",
+            ".thumb_func",
+            "func: bl far",
+            "bx lr",
+        ];
+        let link_with = |arch, script| {
+            let mut linker = Linker::new();
+            linker.set_script(LinkerScript::parse(script).unwrap());
+            for program in [&arm[..], &thumb[..]] {
+                let mut assembler = Assembler::new(Target::new(arch));
+                assembler.layout(program).unwrap();
+                for line in program {
+                    assembler.assemble_line(line).unwrap();
+                }
+                linker.add_object("t.o", &assembler.to_object()).unwrap();
+            }
+            let executable = ExecutableFile::parse(&linker.link().unwrap()).unwrap();
+            let (_, address, text) = executable.sections.into_iter().next().unwrap();
+            assert_eq!(address, 0x10000);
+            text
+        };
+        let link = |arch| link_with(arch, script);
+        let words = |text: &[u8]| -> Vec<u32> {
+            text.chunks(4)
+                .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+                .collect()
+        };
+
+        // Each branch goes to a veneer after the code: the calls are too far, and b can't
+        // switch to Thumb
+        let text = link(Arch::V7A);
+        assert_eq!(words(&text[..8]), [0xEB00_0002, 0xEA00_0003],);
+        assert_eq!(&text[8..12], &[0x00, 0xF0, 0x0A, 0xF8]);
+        assert_eq!(
+            words(&text[16..]),
+            [
+                0xE51F_F004,
+                0x0400_0000,
+                0xE51F_F004,
+                0x0001_0009,
+                0xF000_F8DF,
+                0x0400_0000
+            ]
+        );
+
+        // Veneers that no rule asks for stay in .text with the branches rather than becoming an
+        // orphan out of their reach
+        let script =
+            "SECTIONS { .text 0x10000 : { *(.text) _etext = .; } .far 0x4000000 : { *(.far) } }";
+        assert_eq!(link_with(Arch::V7A, script), text);
+
+        // Without BLX or Thumb-2, the Thumb veneer switches to ARM first
+        let text = link(Arch::V4T);
+        assert_eq!(
+            words(&text[16..]),
+            [
+                0xE51F_F004,
+                0x0400_0000,
+                0xE59F_C000,
+                0xE12F_FF1C,
+                0x0001_0009,
+                0x46C0_4778,
+                0xE51F_F004,
+                0x0400_0000
+            ]
+        );
+    }
+
+    #[test]
+    fn test_big_endian() {
+        let arm = [".global _start", "_start: bl func", ".data", ".word func"];
+        let thumb = [
+            ".global func",
+            ".thumb",
+            ".thumb_func",
+            "func: bl _start",
+            "bx lr",
+        ];
+        let objects = |endianness| {
+            [&arm[..], &thumb[..]].map(|program| {
+                let mut assembler = Assembler::new(Target::new(Arch::V7A));
+                assembler.set_endianness(endianness).unwrap();
+                assembler.layout(program).unwrap();
+                for line in program {
+                    assembler.assemble_line(line).unwrap();
+                }
+                assembler.to_object()
+            })
+        };
+        let link = |endianness| {
+            let mut linker = Linker::new();
+            for object in objects(endianness) {
+                linker.add_object("t.o", &object).unwrap();
+            }
+            let executable = ExecutableFile::parse(&linker.link().unwrap()).unwrap();
+            assert_eq!(executable.endianness, endianness);
+            executable.sections
+        };
+
+        // Only data is byte swapped in BE-8, and instructions as well in BE-32
+        let little = link(Endianness::Little);
+        let be8 = link(Endianness::Be8);
+        let be32 = link(Endianness::Be32);
+        let swap = |data: &[u8], size| -> Vec<u8> {
+            data.chunks(size)
+                .flat_map(|chunk| chunk.iter().rev().copied())
+                .collect()
+        };
+        assert_eq!(be8[0].2, little[0].2);
+        assert_eq!(be8[1].2, swap(&little[1].2, 4));
+        assert_eq!(be32[0].2[..4], swap(&little[0].2[..4], 4));
+        assert_eq!(be32[0].2[4..], swap(&little[0].2[4..], 2));
+        assert_eq!(be32[1].2, be8[1].2);
+
+        let mut linker = Linker::new();
+        let [arm, _] = objects(Endianness::Little);
+        let [_, thumb] = objects(Endianness::Be8);
+        linker.add_object("arm.o", &arm).unwrap();
+        assert!(matches!(
+            linker.add_object("thumb.o", &thumb),
+            Err(LinkError::MixedEndianness(_))
+        ));
+    }
+}
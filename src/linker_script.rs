@@ -0,0 +1,829 @@
+//! Parses the subset of GNU ld's linker script language used to lay out bare-metal images: `ENTRY`,
+//! `MEMORY` regions, and `SECTIONS` with output section descriptions, input section wildcards and
+//! symbol and location counter assignments.
+
+use crate::error::LinkError;
+
+/// Name of the output section whose input sections are dropped.
+pub const DISCARD: &str = "/DISCARD/";
+
+/// Name matching common symbols in input section descriptions.
+pub const COMMON: &str = "COMMON";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub origin: u32,
+    pub length: u32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Shl,
+    Shr,
+}
+
+/// Binary operators from loosest to tightest binding.
+const PRECEDENCE: [&[(&str, BinaryOp)]; 5] = [
+    &[("|", BinaryOp::Or)],
+    &[("&", BinaryOp::And)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Rem),
+    ],
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Expr {
+    Number(u32),
+    /// The location counter, `.`.
+    Location,
+    Symbol(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// `ALIGN(align)` aligns the location counter, `ALIGN(expr, align)` any value.
+    Align(Option<Box<Expr>>, Box<Expr>),
+    Origin(String),
+    Length(String),
+    Addr(String),
+    LoadAddr(String),
+    SizeOf(String),
+}
+
+/// What expressions can refer to.
+pub trait Environment {
+    fn location(&self) -> u32;
+    fn symbol(&self, name: &str) -> Option<u32>;
+    fn region(&self, name: &str) -> Option<&MemoryRegion>;
+    /// The address, load address and size of an output section that has been laid out.
+    fn section(&self, name: &str) -> Option<(u32, u32, u32)>;
+}
+
+fn bad_script(message: String) -> LinkError {
+    LinkError::BadScript(message)
+}
+
+impl Expr {
+    pub fn evaluate(&self, env: &impl Environment) -> Result<u32, LinkError> {
+        let region = |name: &String| {
+            env.region(name)
+                .ok_or_else(|| bad_script(format!("unknown memory region {name}")))
+        };
+        let section = |name: &String| {
+            env.section(name)
+                .ok_or_else(|| bad_script(format!("section {name} has not been laid out")))
+        };
+
+        Ok(match self {
+            Self::Number(value) => *value,
+            Self::Location => env.location(),
+            Self::Symbol(name) => env
+                .symbol(name)
+                .ok_or_else(|| bad_script(format!("undefined symbol {name} in expression")))?,
+            Self::Unary(UnaryOp::Neg, operand) => operand.evaluate(env)?.wrapping_neg(),
+            Self::Unary(UnaryOp::Not, operand) => !operand.evaluate(env)?,
+            Self::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.evaluate(env)?, rhs.evaluate(env)?);
+                match op {
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div | BinaryOp::Rem if rhs == 0 => {
+                        return Err(bad_script("division by zero".to_owned()))
+                    }
+                    BinaryOp::Div => lhs / rhs,
+                    BinaryOp::Rem => lhs % rhs,
+                    BinaryOp::And => lhs & rhs,
+                    BinaryOp::Or => lhs | rhs,
+                    BinaryOp::Shl => lhs.wrapping_shl(rhs),
+                    BinaryOp::Shr => lhs.wrapping_shr(rhs),
+                }
+            }
+            Self::Align(value, align) => {
+                let value = match value {
+                    Some(value) => value.evaluate(env)?,
+                    None => env.location(),
+                };
+                value.next_multiple_of(align.evaluate(env)?.max(1))
+            }
+            Self::Origin(name) => region(name)?.origin,
+            Self::Length(name) => region(name)?.length,
+            Self::Addr(name) => section(name)?.0,
+            Self::LoadAddr(name) => section(name)?.1,
+            Self::SizeOf(name) => section(name)?.2,
+        })
+    }
+}
+
+/// Whether `text` matches the wildcard `pattern`, which may use `*`, `?` and `[...]`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), text.chars().collect());
+
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern.first() {
+            None => text.is_empty(),
+            Some('*') => (0..=text.len()).any(|skip| matches(&pattern[1..], &text[skip..])),
+            Some('?') => !text.is_empty() && matches(&pattern[1..], &text[1..]),
+            Some('[') => {
+                let Some(end) = pattern.iter().position(|&c| c == ']') else {
+                    return text.first() == Some(&'[') && matches(&pattern[1..], &text[1..]);
+                };
+                let Some(&c) = text.first() else {
+                    return false;
+                };
+                let set = &pattern[1..end];
+                let found = set
+                    .iter()
+                    .enumerate()
+                    .any(|(i, &start)| match set.get(i + 1..i + 3) {
+                        Some(['-', end]) => (start..=*end).contains(&c),
+                        _ => start == c,
+                    });
+                found && matches(&pattern[end + 1..], &text[1..])
+            }
+            Some(&c) => text.first() == Some(&c) && matches(&pattern[1..], &text[1..]),
+        }
+    }
+
+    matches(&pattern, &text)
+}
+
+/// Selects input sections, as in `KEEP(*crt0.o(.text .text.*))`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InputSectionDescription {
+    pub file: String,
+    pub sections: Vec<String>,
+    /// Set by `KEEP`. Sections are never garbage collected, so this has no effect yet.
+    pub keep: bool,
+    /// Set by `SORT`/`SORT_BY_NAME`: matching sections are ordered by name rather than as found.
+    pub sort: bool,
+}
+
+impl InputSectionDescription {
+    /// Whether this selects section `section` of the object `file`.
+    pub fn matches(&self, file: &str, section: &str) -> bool {
+        let base = file.rsplit('/').next().unwrap_or(file);
+        (glob_match(&self.file, file) || glob_match(&self.file, base))
+            && self
+                .sections
+                .iter()
+                .any(|pattern| glob_match(pattern, section))
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OutputSectionDescription {
+    pub name: String,
+    pub address: Option<Expr>,
+    /// Set by `AT(expr)`.
+    pub load_address: Option<Expr>,
+    pub align: Option<Expr>,
+    /// Set by `(NOLOAD)`: the section takes no room in the file.
+    pub noload: bool,
+    /// Set by `> REGION`.
+    pub region: Option<String>,
+    /// Set by `AT> REGION`.
+    pub load_region: Option<String>,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// `. = expr`
+    SetLocation(Expr),
+    /// `symbol = expr`, or with `provide` only when no object defines `symbol`.
+    Assign {
+        symbol: String,
+        expr: Expr,
+        provide: bool,
+    },
+    /// Only inside output sections.
+    Input(InputSectionDescription),
+    /// Only at the top level of `SECTIONS`.
+    Section(OutputSectionDescription),
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LinkerScript {
+    pub entry: Option<String>,
+    pub regions: Vec<MemoryRegion>,
+    /// Assignments outside `SECTIONS` and everything inside it, in order.
+    pub commands: Vec<Command>,
+}
+
+impl LinkerScript {
+    pub fn parse(source: &str) -> Result<Self, LinkError> {
+        let mut parser = Parser { source, pos: 0 };
+        let mut script = Self::default();
+
+        while parser.peek().is_some() {
+            if parser.keyword("ENTRY") {
+                script.entry = Some(parser.parenthesized_name()?);
+            } else if parser.keyword("MEMORY") {
+                parser.memory(&mut script.regions)?;
+            } else if parser.keyword("SECTIONS") {
+                parser.expect('{')?;
+                while !parser.eat('}') {
+                    if parser.keyword("ENTRY") {
+                        script.entry = Some(parser.parenthesized_name()?);
+                    } else if let Some(command) = parser.command(true)? {
+                        script.commands.push(command);
+                    }
+                }
+            } else if ["OUTPUT_FORMAT", "OUTPUT_ARCH", "SEARCH_DIR"]
+                .iter()
+                .any(|keyword| parser.keyword(keyword))
+            {
+                // Only ever ARM ELF output, and inputs are given explicitly
+                parser.parenthesized_name()?;
+            } else if let Some(command) = parser.command(false)? {
+                script.commands.push(command);
+            }
+        }
+
+        Ok(script)
+    }
+
+    pub fn region(&self, name: &str) -> Option<&MemoryRegion> {
+        self.regions.iter().find(|region| region.name == name)
+    }
+}
+
+/// Lets `MEMORY` refer to the regions defined before.
+struct Regions<'a>(&'a [MemoryRegion]);
+
+impl Environment for Regions<'_> {
+    fn location(&self) -> u32 {
+        0
+    }
+
+    fn symbol(&self, _name: &str) -> Option<u32> {
+        None
+    }
+
+    fn region(&self, name: &str) -> Option<&MemoryRegion> {
+        self.0.iter().find(|region| region.name == name)
+    }
+
+    fn section(&self, _name: &str) -> Option<(u32, u32, u32)> {
+        None
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+fn is_name_char(c: char) -> bool {
+    !c.is_whitespace() && !"(){};,=:<>!+\"".contains(c)
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.$".contains(c)
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> LinkError {
+        let line = self.source[..self.pos].matches('\n').count() + 1;
+        bad_script(format!("line {line}: {message}"))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.pos..]
+    }
+
+    /// Skip whitespace and `/* */` comments.
+    fn skip_space(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if !trimmed.starts_with("/*") {
+                return;
+            }
+            self.pos += trimmed.find("*/").map_or(trimmed.len(), |end| end + 2);
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_space();
+        self.rest().chars().next()
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        self.skip_space();
+        let found = self.rest().starts_with(s);
+        if found {
+            self.pos += s.len();
+        }
+        found
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.eat_str(c.encode_utf8(&mut [0; 4]))
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), LinkError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {c}")))
+        }
+    }
+
+    /// Consume `keyword` if it comes next as a whole word.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        self.skip_space();
+        let found = self.rest().strip_prefix(keyword).is_some_and(|rest| {
+            !rest.starts_with(|c: char| is_identifier_char(c) || c == '*' || c == '?')
+        });
+        if found {
+            self.pos += keyword.len();
+        }
+        found
+    }
+
+    /// A section name, symbol or wildcard pattern.
+    fn name(&mut self) -> Result<String, LinkError> {
+        self.skip_space();
+        let rest = self.rest();
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted
+                .find('"')
+                .ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += end + 2;
+            return Ok(quoted[..end].to_owned());
+        }
+
+        let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        if end == 0 {
+            return Err(self.error("expected a name"));
+        }
+        self.pos += end;
+        Ok(rest[..end].to_owned())
+    }
+
+    fn parenthesized_name(&mut self) -> Result<String, LinkError> {
+        self.expect('(')?;
+        let name = self.name()?;
+        while self.eat(',') {
+            self.name()?;
+        }
+        self.expect(')')?;
+        Ok(name)
+    }
+
+    fn memory(&mut self, regions: &mut Vec<MemoryRegion>) -> Result<(), LinkError> {
+        self.expect('{')?;
+        while !self.eat('}') {
+            let name = self.name()?;
+            // Attributes only matter for placing orphan sections
+            if self.eat('(') {
+                self.name()?;
+                self.expect(')')?;
+            }
+            self.expect(':')?;
+
+            let field = |parser: &mut Self, names: [&str; 3]| {
+                if !names.iter().any(|name| parser.keyword(name)) {
+                    return Err(parser.error(&format!("expected {}", names[0])));
+                }
+                parser.expect('=')?;
+                parser.expr()?.evaluate(&Regions(regions))
+            };
+            let origin = field(self, ["ORIGIN", "org", "o"])?;
+            self.eat(',');
+            let length = field(self, ["LENGTH", "len", "l"])?;
+            regions.push(MemoryRegion {
+                name,
+                origin,
+                length,
+            });
+        }
+        Ok(())
+    }
+
+    /// `PROVIDE(symbol = expr)`, with the keyword already consumed.
+    fn provide(&mut self) -> Result<Command, LinkError> {
+        self.expect('(')?;
+        let symbol = self.name()?;
+        self.expect('=')?;
+        let expr = self.expr()?;
+        self.expect(')')?;
+        self.eat(';');
+        Ok(Command::Assign {
+            symbol,
+            expr,
+            provide: true,
+        })
+    }
+
+    /// The rest of an assignment to `target`, or `None` if no assignment operator follows.
+    fn assignment(&mut self, target: String) -> Result<Option<Command>, LinkError> {
+        let compound = if self.eat_str("+=") {
+            Some(BinaryOp::Add)
+        } else if self.eat_str("-=") {
+            Some(BinaryOp::Sub)
+        } else if self.rest().starts_with('=') && !self.rest().starts_with("==") {
+            self.pos += 1;
+            None
+        } else {
+            return Ok(None);
+        };
+
+        let mut expr = self.expr()?;
+        let current = match target.as_str() {
+            "." => Expr::Location,
+            _ => Expr::Symbol(target.clone()),
+        };
+        if let Some(op) = compound {
+            expr = Expr::Binary(op, Box::new(current), Box::new(expr));
+        }
+        self.expect(';')?;
+
+        Ok(Some(match target.as_str() {
+            "." => Command::SetLocation(expr),
+            _ => Command::Assign {
+                symbol: target,
+                expr,
+                provide: false,
+            },
+        }))
+    }
+
+    /// A command in `SECTIONS`, or in an output section unless `top_level`. `None` for a stray
+    /// semicolon.
+    fn command(&mut self, top_level: bool) -> Result<Option<Command>, LinkError> {
+        if self.eat(';') {
+            return Ok(None);
+        }
+        if self.keyword("PROVIDE") || self.keyword("PROVIDE_HIDDEN") {
+            return self.provide().map(Some);
+        }
+        if !top_level && self.keyword("KEEP") {
+            self.expect('(')?;
+            let file = self.name()?;
+            let mut input = self.input_section(file)?;
+            input.keep = true;
+            self.expect(')')?;
+            return Ok(Some(Command::Input(input)));
+        }
+
+        let name = self.name()?;
+        self.skip_space();
+        if let Some(command) = self.assignment(name.clone())? {
+            return Ok(Some(command));
+        }
+        if top_level {
+            return self
+                .output_section(name)
+                .map(|section| Some(Command::Section(section)));
+        }
+        if self.peek() == Some('(') {
+            return self
+                .input_section(name)
+                .map(|input| Some(Command::Input(input)));
+        }
+        Err(self.error(&format!("unexpected {name}")))
+    }
+
+    /// The section patterns after the file pattern `file`.
+    fn input_section(&mut self, file: String) -> Result<InputSectionDescription, LinkError> {
+        let mut input = InputSectionDescription {
+            file,
+            sections: vec![],
+            keep: false,
+            sort: false,
+        };
+        self.expect('(')?;
+        while !self.eat(')') {
+            if self.keyword("SORT") || self.keyword("SORT_BY_NAME") {
+                input.sort = true;
+                self.expect('(')?;
+                while !self.eat(')') {
+                    input.sections.push(self.name()?);
+                    self.eat(',');
+                }
+            } else {
+                input.sections.push(self.name()?);
+            }
+            self.eat(',');
+        }
+        Ok(input)
+    }
+
+    fn output_section(&mut self, name: String) -> Result<OutputSectionDescription, LinkError> {
+        let mut section = OutputSectionDescription {
+            name,
+            address: None,
+            load_address: None,
+            align: None,
+            noload: false,
+            region: None,
+            load_region: None,
+            commands: vec![],
+        };
+
+        if !self.rest().starts_with(':') && !self.rest().starts_with('(') {
+            section.address = Some(self.expr()?);
+        }
+        if self.eat('(') {
+            if self.keyword("NOLOAD") {
+                section.noload = true;
+            } else if !self.keyword("READONLY") {
+                return Err(self.error("unsupported output section type"));
+            }
+            self.expect(')')?;
+        }
+        self.expect(':')?;
+
+        loop {
+            if self.keyword("AT") {
+                self.expect('(')?;
+                section.load_address = Some(self.expr()?);
+                self.expect(')')?;
+            } else if self.keyword("ALIGN") {
+                self.expect('(')?;
+                section.align = Some(self.expr()?);
+                self.expect(')')?;
+            } else {
+                break;
+            }
+        }
+
+        self.expect('{')?;
+        while !self.eat('}') {
+            if let Some(command) = self.command(false)? {
+                section.commands.push(command);
+            }
+        }
+
+        loop {
+            if self.eat('>') {
+                section.region = Some(self.name()?);
+            } else if self.keyword("AT") {
+                self.expect('>')?;
+                section.load_region = Some(self.name()?);
+            } else {
+                break;
+            }
+        }
+        self.eat(',');
+
+        Ok(section)
+    }
+
+    fn expr(&mut self) -> Result<Expr, LinkError> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, LinkError> {
+        let Some(operators) = PRECEDENCE.get(level) else {
+            return self.unary();
+        };
+
+        let mut lhs = self.binary(level + 1)?;
+        'operators: loop {
+            self.skip_space();
+            for (token, op) in *operators {
+                // Don't take the start of `<<`, `+=` or `&&` for a shorter operator
+                let rest = &self.rest()[token.len().min(self.rest().len())..];
+                if self.rest().starts_with(token) && !rest.starts_with(['=', '<', '>', '&', '|']) {
+                    self.pos += token.len();
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'operators;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, LinkError> {
+        if self.eat('-') {
+            return Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?)));
+        }
+        if self.eat('~') {
+            return Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?)));
+        }
+        if self.eat('(') {
+            let expr = self.expr()?;
+            self.expect(')')?;
+            return Ok(expr);
+        }
+
+        let rest = self.rest();
+        let end = rest.find(|c| !is_identifier_char(c)).unwrap_or(rest.len());
+        let token = &rest[..end];
+        if token.is_empty() {
+            return Err(self.error("expected an expression"));
+        }
+        self.pos += end;
+
+        if token.starts_with(|c: char| c.is_ascii_digit()) {
+            return self.number(token);
+        }
+        if token == "." {
+            return Ok(Expr::Location);
+        }
+        if !self.eat('(') {
+            return Ok(Expr::Symbol(token.to_owned()));
+        }
+
+        let expr = match token {
+            "ALIGN" => {
+                let first = self.expr()?;
+                match self.eat(',') {
+                    true => Expr::Align(Some(Box::new(first)), Box::new(self.expr()?)),
+                    false => Expr::Align(None, Box::new(first)),
+                }
+            }
+            "ABSOLUTE" => self.expr()?,
+            "ORIGIN" | "org" => Expr::Origin(self.name()?),
+            "LENGTH" | "len" => Expr::Length(self.name()?),
+            "ADDR" => Expr::Addr(self.name()?),
+            "LOADADDR" => Expr::LoadAddr(self.name()?),
+            "SIZEOF" => Expr::SizeOf(self.name()?),
+            _ => return Err(self.error(&format!("unsupported function {token}"))),
+        };
+        self.expect(')')?;
+        Ok(expr)
+    }
+
+    /// A number with an optional `K` or `M` suffix.
+    fn number(&self, token: &str) -> Result<Expr, LinkError> {
+        let (digits, scale) = match token.strip_suffix(['K', 'k']) {
+            Some(digits) => (digits, 1024),
+            None => match token.strip_suffix(['M', 'm']) {
+                Some(digits) => (digits, 1024 * 1024),
+                None => (token, 1),
+            },
+        };
+        let value = match digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+        {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => digits.parse(),
+        };
+        value
+            .ok()
+            .and_then(|value| value.checked_mul(scale))
+            .map(Expr::Number)
+            .ok_or_else(|| self.error(&format!("bad number {token}")))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{glob_match, Command, Environment, Expr, LinkerScript, MemoryRegion};
+
+    const SCRIPT: &str = "
+        ENTRY(Reset_Handler)
+        MEMORY
+        {
+            FLASH (rx) : ORIGIN = 0x08000000, LENGTH = 512K
+            RAM (rwx)  : ORIGIN = 0x20000000, LENGTH = 128K
+        }
+        _estack = ORIGIN(RAM) + LENGTH(RAM);
+
+        SECTIONS
+        {
+            .vectors : { KEEP(*(.vectors)) } > FLASH
+            .text :
+            {
+                *(.text .text.*)   /* code */
+                *(SORT(.rodata*))
+                . = ALIGN(4);
+                _etext = .;
+            } > FLASH
+            _sidata = LOADADDR(.data);
+            .data : AT(_etext) { _sdata = .; *(.data*) _edata = .; } > RAM
+            .bss (NOLOAD) : { _sbss = .; *(.bss*) *(COMMON) _ebss = .; } > RAM AT> FLASH
+            PROVIDE(end = _ebss);
+            /DISCARD/ : { *(.ARM.exidx*) }
+        }
+    ";
+
+    struct Env;
+
+    impl Environment for Env {
+        fn location(&self) -> u32 {
+            0x1003
+        }
+
+        fn symbol(&self, name: &str) -> Option<u32> {
+            (name == "base").then_some(0x100)
+        }
+
+        fn region(&self, _name: &str) -> Option<&MemoryRegion> {
+            None
+        }
+
+        fn section(&self, _name: &str) -> Option<(u32, u32, u32)> {
+            None
+        }
+    }
+
+    fn evaluate(source: &str) -> u32 {
+        let script = LinkerScript::parse(&format!("x = {source};")).unwrap();
+        let Command::Assign { expr, .. } = &script.commands[0] else {
+            panic!("{source} is not an assignment");
+        };
+        expr.evaluate(&Env).unwrap()
+    }
+
+    #[test]
+    fn test_expressions() {
+        assert_eq!(evaluate("1 + 2 * 3"), 7);
+        assert_eq!(evaluate("(1 + 2) * 3"), 9);
+        assert_eq!(evaluate("0x10 << 2 | 1"), 0x41);
+        assert_eq!(evaluate("4K - 1 & ~0xF"), 0xFF0);
+        assert_eq!(evaluate("ALIGN(8)"), 0x1008);
+        assert_eq!(
+            evaluate("ALIGN(base + 1, 0x40) - ."),
+            0x140u32.wrapping_sub(0x1003)
+        );
+        assert_eq!(evaluate("-1"), u32::MAX);
+        assert_eq!(evaluate("2M / 3 % 5"), 2 * 1024 * 1024 / 3 % 5);
+
+        let script = LinkerScript::parse("x = 1 / 0;").unwrap();
+        let Command::Assign { expr, .. } = &script.commands[0] else {
+            unreachable!()
+        };
+        assert!(expr.evaluate(&Env).is_err());
+        assert!(Expr::Symbol("missing".to_owned()).evaluate(&Env).is_err());
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match(".text*", ".text"));
+        assert!(glob_match(".text.*", ".text.main"));
+        assert!(!glob_match(".text.*", ".text"));
+        assert!(glob_match("*crt?.o", "lib/crt0.o"));
+        assert!(glob_match(".data[0-9]", ".data7"));
+        assert!(!glob_match(".data[0-9]", ".datax"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let script = LinkerScript::parse(SCRIPT).unwrap();
+        assert_eq!(script.entry.as_deref(), Some("Reset_Handler"));
+        assert_eq!(
+            script.regions[0],
+            MemoryRegion {
+                name: "FLASH".to_owned(),
+                origin: 0x0800_0000,
+                length: 0x80000,
+            }
+        );
+        assert_eq!(script.regions[1].length, 0x20000);
+        assert_eq!(script.commands.len(), 8);
+
+        let Command::Section(text) = &script.commands[2] else {
+            panic!("expected .text");
+        };
+        assert_eq!(text.name, ".text");
+        assert_eq!(text.region.as_deref(), Some("FLASH"));
+        let Command::Input(input) = &text.commands[1] else {
+            panic!("expected the .rodata input");
+        };
+        assert!(input.sort && input.matches("main.o", ".rodata.str1.1"));
+        assert!(matches!(text.commands[2], Command::SetLocation(_)));
+
+        let Command::Section(bss) = &script.commands[5] else {
+            panic!("expected .bss");
+        };
+        assert!(bss.noload);
+        assert_eq!(bss.load_region.as_deref(), Some("FLASH"));
+        assert!(matches!(
+            script.commands[6],
+            Command::Assign { provide: true, .. }
+        ));
+
+        let err = LinkerScript::parse("SECTIONS {\n .text : { *(.text) \n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Bad linker script: line 3: expected a name"
+        );
+    }
+}
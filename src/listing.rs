@@ -0,0 +1,418 @@
+//! Assembly listings in the style of `as -al`: each source line next to the offset and bytes it
+//! assembled to, followed by the symbol table and a cross-reference of where each symbol is
+//! defined and used.
+//!
+//! Lines are listed as they were written, so what a `.macro` or the `arm!` macro expands to
+//! isn't shown, only the line that used it.
+
+use std::fmt::Write;
+
+use crate::{
+    assembler::{is_symbol_name, strip_comment, Assembler},
+    elf::{STB_GLOBAL, STB_LOCAL, STB_WEAK},
+    error::AssemblerError,
+    source::{Include, Location, Source},
+};
+
+/// How many bytes are shown next to each line. Longer data wraps onto continuation lines.
+const BYTES_PER_LINE: usize = 4;
+/// Continuation lines shown before the rest of a long run of data is left out, as `as` does.
+const MAX_CONTINUATION_LINES: usize = 4;
+
+/// A symbol as it appears in a listing's symbol table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListedSymbol {
+    pub name: String,
+    /// `None` for symbols that are left for the linker to define.
+    pub section: Option<String>,
+    /// With the low bit set for Thumb functions.
+    pub value: u32,
+    pub binding: u8,
+    /// The source line it was defined on.
+    pub line: Option<usize>,
+}
+
+/// One source line and what it assembled to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListingLine {
+    /// Counted from 1 across every file, as the assembler counts lines. `None` for an
+    /// `.include`, which the assembler never sees.
+    pub number: Option<usize>,
+    /// Where it was written, which for an included line is in the file that included it.
+    pub location: Location,
+    /// Where the bytes start in the section being assembled into.
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+    /// As written, with its label and comments.
+    pub source: String,
+}
+
+/// Which parts of a listing to render, after the `-al` and `-as` options of `as`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ListingOptions {
+    pub assembly: bool,
+    pub symbols: bool,
+}
+
+impl Default for ListingOptions {
+    fn default() -> Self {
+        Self {
+            assembly: true,
+            symbols: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Listing {
+    /// The file being assembled. Lines from any other file are cross-referenced with its name.
+    pub file: String,
+    pub lines: Vec<ListingLine>,
+    pub symbols: Vec<ListedSymbol>,
+}
+
+impl Listing {
+    /// Lay out and assemble `source` with `assembler`, recording what each line emitted.
+    pub fn assemble(
+        assembler: &mut Assembler,
+        file: &str,
+        source: &Source,
+    ) -> Result<Self, AssemblerError> {
+        let lines = &source.lines;
+        assembler.layout(lines)?;
+        let mut listing = Self {
+            file: file.to_owned(),
+            ..Self::default()
+        };
+        let mut includes = source.includes.iter().peekable();
+        for (number, line) in (1..).zip(lines) {
+            while let Some(include) = includes.next_if(|include| include.before < number) {
+                listing.push_include(include, assembler.address());
+            }
+            let section = assembler.current_section().to_owned();
+            let offset = assembler.address();
+            assembler.assemble_line(line)?;
+
+            // A line that switches section can't also emit anything
+            let bytes = match assembler.section(&section) {
+                Some(data) if assembler.current_section() == section => {
+                    data[offset as usize..].to_vec()
+                }
+                _ => vec![],
+            };
+            let location = source.location(number).cloned().unwrap_or(Location {
+                file: file.to_owned(),
+                line: number,
+            });
+            listing.lines.push(ListingLine {
+                number: Some(number),
+                location,
+                offset,
+                bytes,
+                source: line.clone(),
+            });
+        }
+        for include in includes {
+            listing.push_include(include, assembler.address());
+        }
+        assembler.finish()?;
+        listing.symbols = assembler.symbol_table();
+
+        Ok(listing)
+    }
+
+    fn push_include(&mut self, include: &Include, offset: u32) {
+        self.lines.push(ListingLine {
+            number: None,
+            location: include.location.clone(),
+            offset,
+            bytes: vec![],
+            source: include.source.clone(),
+        });
+    }
+
+    pub fn render(&self, options: ListingOptions) -> String {
+        let mut out = String::new();
+        if options.assembly {
+            self.render_lines(&mut out);
+        }
+        if options.symbols {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            self.render_symbols(&mut out);
+        }
+        out
+    }
+
+    fn render_lines(&self, out: &mut String) {
+        let mut file = &self.file;
+        for line in &self.lines {
+            // Name the file each run of lines is from, once one has been included
+            if line.location.file != *file {
+                file = &line.location.file;
+                writeln!(out, "==== {file}").unwrap();
+            }
+            let mut chunks = line.bytes.chunks(BYTES_PER_LINE);
+            let (offset, bytes) = match chunks.next() {
+                Some(chunk) => (format!("{:04x}", line.offset), hex(chunk)),
+                None => (String::new(), String::new()),
+            };
+            let number = line.location.line;
+            let text = format!("{number:4} {offset:<4} {bytes:<8} {}", line.source);
+            writeln!(out, "{}", text.trim_end()).unwrap();
+            for chunk in chunks.take(MAX_CONTINUATION_LINES) {
+                writeln!(out, "{number:4}      {}", hex(chunk)).unwrap();
+            }
+        }
+    }
+
+    fn render_symbols(&self, out: &mut String) {
+        let (defined, undefined): (Vec<_>, Vec<_>) = self
+            .symbols
+            .iter()
+            .partition(|symbol| symbol.section.is_some());
+
+        writeln!(out, "DEFINED SYMBOLS").unwrap();
+        for symbol in &defined {
+            let location = symbol
+                .line
+                .and_then(|number| self.line(number))
+                .map_or_else(
+                    || format!("{}:0", self.file),
+                    |line| line.location.to_string(),
+                );
+            writeln!(
+                out,
+                "{location:>20} {:>12}:{:08x} {:<6} {}",
+                symbol.section.as_deref().unwrap_or(""),
+                symbol.value,
+                binding_name(symbol.binding),
+                symbol.name
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\nUNDEFINED SYMBOLS").unwrap();
+        for symbol in &undefined {
+            writeln!(out, "{}", symbol.name).unwrap();
+        }
+
+        writeln!(out, "\nCROSS REFERENCE").unwrap();
+        writeln!(out, "{:<24} {:>7}  USED", "SYMBOL", "DEFINED").unwrap();
+        for symbol in self.sorted_symbols() {
+            let defined = symbol
+                .line
+                .and_then(|number| self.line(number))
+                .map_or("-".to_owned(), |line| self.line_name(line));
+            let uses: Vec<String> = self
+                .lines
+                .iter()
+                .filter(|line| mentions(&line.source).contains(&symbol.name.as_str()))
+                .map(|line| self.line_name(line))
+                .collect();
+            let text = format!("{:<24} {defined:>7}  {}", symbol.name, uses.join(" "));
+            writeln!(out, "{}", text.trim_end()).unwrap();
+        }
+    }
+
+    /// Line `number`, counting across every file.
+    fn line(&self, number: usize) -> Option<&ListingLine> {
+        self.lines.iter().find(|line| line.number == Some(number))
+    }
+
+    /// The line's number, with the file it's in if that isn't the one being assembled.
+    fn line_name(&self, line: &ListingLine) -> String {
+        if line.location.file == self.file {
+            line.location.line.to_string()
+        } else {
+            line.location.to_string()
+        }
+    }
+
+    fn sorted_symbols(&self) -> Vec<&ListedSymbol> {
+        let mut symbols: Vec<_> = self.symbols.iter().collect();
+        symbols.sort_by(|a, b| a.name.cmp(&b.name));
+        symbols
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+fn binding_name(binding: u8) -> &'static str {
+    match binding {
+        STB_LOCAL => "LOCAL",
+        STB_GLOBAL => "GLOBAL",
+        STB_WEAK => "WEAK",
+        _ => "?",
+    }
+}
+
+/// The symbol names in the operands of a source line, leaving out the label it defines, its
+/// mnemonic and anything in a string literal.
+fn mentions(source: &str) -> Vec<&str> {
+    let mut line = strip_comment(source).trim();
+    if let Some((label, rest)) = line.split_once(':') {
+        if is_symbol_name(label) {
+            line = rest.trim();
+        }
+    }
+    let operands = line
+        .split_once(char::is_whitespace)
+        .map_or("", |(_, rest)| rest);
+
+    let mut names = vec![];
+    let mut start = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in operands.char_indices().chain([(operands.len(), ' ')]) {
+        if !in_string && (c.is_ascii_alphanumeric() || "_.$".contains(c)) {
+            start.get_or_insert(i);
+            continue;
+        }
+        if let Some(begin) = start.take() {
+            let token = &operands[begin..i];
+            if is_symbol_name(token) {
+                names.push(token);
+            }
+        }
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            _ => (),
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::arch::Target;
+
+    #[test]
+    fn test_listing() {
+        let program = [
+            "    .global _start",
+            "_start: mov r0, #1    @ first",
+            "loop:",
+            "    bl external",
+            "    b loop",
+            "    .data",
+            "table: .word 1, 2, 3, 4, 5, loop",
+            "    .asciz \"_start\"",
+        ];
+        let mut source = Source::default();
+        source.add_text("t.s", &program.join("\n")).unwrap();
+        let mut assembler = Assembler::new(Target::default());
+        let listing = Listing::assemble(&mut assembler, "t.s", &source).unwrap();
+        let text = listing.render(ListingOptions::default());
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines[0], "   1                   .global _start");
+        assert_eq!(lines[1], "   2 0000 0100A0E3 _start: mov r0, #1    @ first");
+        assert_eq!(lines[2], "   3               loop:");
+        assert_eq!(lines[3], "   4 0004 FEFFFFEB     bl external");
+        assert_eq!(lines[4], "   5 0008 FDFFFFEA     b loop");
+        assert_eq!(
+            lines[6],
+            "   7 0000 01000000 table: .word 1, 2, 3, 4, 5, loop"
+        );
+        assert_eq!(lines[7], "   7      02000000");
+        // The sixth word is past the continuation lines that are shown
+        assert_eq!(lines[10], "   7      05000000");
+        assert_eq!(lines[11], "   8 0018 5F737461     .asciz \"_start\"");
+        assert_eq!(lines[12], "   8      727400");
+
+        assert!(text.contains("             t.s:2        .text:00000000 GLOBAL _start\n"));
+        assert!(text.contains("\nUNDEFINED SYMBOLS\nexternal\n"));
+        assert!(text.contains("\n_start                         2  1\n"));
+        assert!(text.contains("\nexternal                       -  4\n"));
+        assert!(text.contains("\nloop                           3  5 7\n"));
+
+        let symbols = listing.render(ListingOptions {
+            assembly: false,
+            symbols: true,
+        });
+        assert!(symbols.starts_with("DEFINED SYMBOLS\n"));
+    }
+
+    #[test]
+    fn test_listing_data() {
+        let program = [
+            ".byte 1, 2, 3",
+            ".hword 0x1234, 5",
+            ".space 6, 0xAA",
+            ".balign 8",
+            ".ascii \"abcd\"",
+            ".word 0x11223344",
+            ".zero 4",
+        ];
+        let mut source = Source::default();
+        source.add_text("t.s", &program.join("\n")).unwrap();
+        let mut assembler = Assembler::new(Target::default());
+        let listing = Listing::assemble(&mut assembler, "t.s", &source).unwrap();
+        let text = listing.render(ListingOptions {
+            assembly: true,
+            symbols: false,
+        });
+
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            [
+                "   1 0000 010203   .byte 1, 2, 3",
+                "   2 0003 34120500 .hword 0x1234, 5",
+                "   3 0007 AAAAAAAA .space 6, 0xAA",
+                "   3      AAAA",
+                "   4 000d 000000   .balign 8",
+                "   5 0010 61626364 .ascii \"abcd\"",
+                "   6 0014 44332211 .word 0x11223344",
+                "   7 0018 00000000 .zero 4",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listing_include() {
+        let dir = std::env::temp_dir().join(format!("listing-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.s"), "@ helpers\nhelper: bx lr\n").unwrap();
+        let lib = dir.join("lib.s").display().to_string();
+
+        let mut source = Source::new(vec![dir.clone()]);
+        source
+            .add_text(
+                "t.s",
+                "start: mov r0, #1\n  .include \"lib.s\"\n  bl helper\n",
+            )
+            .unwrap();
+        let mut assembler = Assembler::new(Target::default());
+        let listing = Listing::assemble(&mut assembler, "t.s", &source).unwrap();
+        let text = listing.render(ListingOptions::default());
+        let lines: Vec<&str> = text.lines().collect();
+
+        // Included lines are numbered within their own file, which is named before them
+        assert_eq!(
+            lines[..7],
+            [
+                "   1 0000 0100A0E3 start: mov r0, #1",
+                "   2                 .include \"lib.s\"",
+                &format!("==== {lib}"),
+                "   1               @ helpers",
+                "   2 0004 1EFF2FE1 helper: bx lr",
+                "==== t.s",
+                "   3 0008 FDFFFFEB   bl helper",
+            ]
+        );
+
+        assert!(text.contains(&format!("{lib}:2        .text:00000004 LOCAL  helper\n")));
+        assert!(text.contains("             t.s:1        .text:00000000 LOCAL  start\n"));
+        assert!(text.contains(&format!("\n{:<24} {lib}:2  3\n", "helper")));
+        assert!(text.contains("\nstart                          1\n"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
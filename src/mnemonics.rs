@@ -1,33 +1,80 @@
 use strum::IntoEnumIterator;
 
-use crate::error::{AssemblerError, ParseError};
+use crate::{
+    cond::Cond,
+    error::{AssemblerError, ParseError},
+};
 
 #[derive(Clone, Copy, Debug)]
 pub enum Mnemonic {
     Data(DataMnemonic),
     Mem(MemoryMnemonic),
+    Stack(StackMnemonic),
     Mul(MultiplyMnemonic),
     Branch(BranchMnemonic),
     BranchExec(BranchExecMnemonic),
+    MoveWide(MoveWideMnemonic),
+    Bitfield(BitfieldMnemonic),
+    Reverse(ReverseMnemonic),
+    Divide(DivideMnemonic),
+    Barrier(BarrierMnemonic),
+    Exclusive(ExclusiveMnemonic),
+    Preload(PreloadMnemonic),
+    Hint(HintMnemonic),
+    Exception(ExceptionMnemonic),
+    Coprocessor(CoprocessorMnemonic),
 }
 
 impl TryFrom<&str> for Mnemonic {
     type Error = AssemblerError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        if let Ok(data_mnemonic) = DataMnemonic::try_from(value) {
-            Ok(Mnemonic::Data(data_mnemonic))
-        } else if let Ok(mem_mnemonic) = MemoryMnemonic::try_from(value) {
-            Ok(Mnemonic::Mem(mem_mnemonic))
-        } else if let Ok(mul_mnemonic) = MultiplyMnemonic::try_from(value) {
-            Ok(Mnemonic::Mul(mul_mnemonic))
-        } else if let Ok(bx_mnemonic) = BranchExecMnemonic::try_from(value) {
-            Ok(Mnemonic::BranchExec(bx_mnemonic))
-        } else if let Ok(b_mnemonic) = BranchMnemonic::try_from(value) {
-            Ok(Mnemonic::Branch(b_mnemonic))
-        } else {
-            Err(ParseError::BadMnemonic(value.to_owned()).into())
-        }
+        // Several families share prefixes (`b`/`bfi`, `ldr`/`ldrex`, `mov`/`movw`), so ask every
+        // family and keep the longest match.
+        let candidates = [
+            DataMnemonic::try_from(value).map(Mnemonic::Data),
+            MemoryMnemonic::try_from(value).map(Mnemonic::Mem),
+            StackMnemonic::try_from(value).map(Mnemonic::Stack),
+            MultiplyMnemonic::try_from(value).map(Mnemonic::Mul),
+            BranchExecMnemonic::try_from(value).map(Mnemonic::BranchExec),
+            BranchMnemonic::try_from(value).map(Mnemonic::Branch),
+            MoveWideMnemonic::try_from(value).map(Mnemonic::MoveWide),
+            BitfieldMnemonic::try_from(value).map(Mnemonic::Bitfield),
+            ReverseMnemonic::try_from(value).map(Mnemonic::Reverse),
+            DivideMnemonic::try_from(value).map(Mnemonic::Divide),
+            BarrierMnemonic::try_from(value).map(Mnemonic::Barrier),
+            ExclusiveMnemonic::try_from(value).map(Mnemonic::Exclusive),
+            PreloadMnemonic::try_from(value).map(Mnemonic::Preload),
+            HintMnemonic::try_from(value).map(Mnemonic::Hint),
+            ExceptionMnemonic::try_from(value).map(Mnemonic::Exception),
+            CoprocessorMnemonic::try_from(value).map(Mnemonic::Coprocessor),
+        ];
+
+        candidates
+            .into_iter()
+            .flatten()
+            .max_by_key(|mnemonic| mnemonic.to_string().len())
+            .ok_or_else(|| ParseError::BadMnemonic(value.to_owned()).into())
+    }
+}
+
+impl Mnemonic {
+    /// Parse a data-processing or multiply mnemonic with an `s` suffix ahead of any condition,
+    /// such as `adds` or `mulseq`. Compares always set the flags, so they don't take one.
+    pub(crate) fn parse_set_flags(value: &str) -> Option<Self> {
+        let data = DataMnemonic::iter()
+            .filter(|mnemonic| !mnemonic.is_compare())
+            .map(Mnemonic::Data);
+        let mul = MultiplyMnemonic::iter().map(Mnemonic::Mul);
+        data.chain(mul).find(|mnemonic| {
+            let name = mnemonic.to_string();
+            value
+                .get(..name.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&name))
+                && value[name.len()..]
+                    .strip_prefix(['s', 'S'])
+                    .is_some_and(is_cond_suffix)
+        })
     }
 }
 
@@ -36,13 +83,48 @@ impl std::fmt::Display for Mnemonic {
         match self {
             Mnemonic::Data(data) => write!(f, "{data}"),
             Mnemonic::Mem(mem) => write!(f, "{mem}"),
+            Mnemonic::Stack(stack) => write!(f, "{stack}"),
             Mnemonic::Mul(mul) => write!(f, "{mul}"),
             Mnemonic::Branch(b) => write!(f, "{b}"),
             Mnemonic::BranchExec(bx) => write!(f, "{bx}"),
+            Mnemonic::MoveWide(movw) => write!(f, "{movw}"),
+            Mnemonic::Bitfield(bf) => write!(f, "{bf}"),
+            Mnemonic::Reverse(rev) => write!(f, "{rev}"),
+            Mnemonic::Divide(div) => write!(f, "{div}"),
+            Mnemonic::Barrier(barrier) => write!(f, "{barrier}"),
+            Mnemonic::Exclusive(ex) => write!(f, "{ex}"),
+            Mnemonic::Preload(pld) => write!(f, "{pld}"),
+            Mnemonic::Hint(hint) => write!(f, "{hint}"),
+            Mnemonic::Exception(exc) => write!(f, "{exc}"),
+            Mnemonic::Coprocessor(cp) => write!(f, "{cp}"),
         }
     }
 }
 
+/// Whether `suffix` may follow a mnemonic: either nothing or a condition code.
+fn is_cond_suffix(suffix: &str) -> bool {
+    suffix.is_empty() || Cond::try_from(suffix).is_ok()
+}
+
+/// Match the longest mnemonic of `T` that `value` starts with, provided that whatever follows it
+/// is a valid condition code. Matching is case-insensitive.
+pub(crate) fn parse_mnemonic<T>(value: &str) -> Result<T, AssemblerError>
+where
+    T: IntoEnumIterator + std::fmt::Display,
+{
+    T::iter()
+        .filter_map(|mnemonic| {
+            let name = mnemonic.to_string();
+            let prefix = value.get(..name.len())?;
+            let matches =
+                prefix.eq_ignore_ascii_case(&name) && is_cond_suffix(&value[name.len()..]);
+            matches.then_some((name.len(), mnemonic))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, mnemonic)| mnemonic)
+        .ok_or_else(|| ParseError::BadMnemonic(value.to_owned()).into())
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
 pub enum DataMnemonic {
     AND,
@@ -86,19 +168,23 @@ impl std::fmt::Display for DataMnemonic {
     }
 }
 
+impl DataMnemonic {
+    /// Whether this only sets the flags, without a destination register.
+    pub fn is_compare(self) -> bool {
+        matches!(self, Self::TST | Self::TEQ | Self::CMP | Self::CMN)
+    }
+
+    /// Whether this moves its second operand, without a first operand register.
+    pub fn is_move(self) -> bool {
+        matches!(self, Self::MOV | Self::MVN)
+    }
+}
+
 impl TryFrom<&str> for DataMnemonic {
     type Error = AssemblerError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        for mnemonic in DataMnemonic::iter() {
-            let mnemonic_upper = mnemonic.to_string().to_uppercase();
-            let mnemonic_lower = mnemonic.to_string().to_lowercase();
-            if value.starts_with(&mnemonic_upper) || value.starts_with(&mnemonic_lower) {
-                return Ok(mnemonic);
-            }
-        }
-
-        Err(ParseError::BadMnemonic(value.to_owned()).into())
+        parse_mnemonic(value)
     }
 }
 
@@ -126,7 +212,7 @@ impl From<DataMnemonic> for u8 {
 }
 
 // TODO: L, B bits
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
 pub enum MemoryMnemonic {
     STR,
     STRB,
@@ -149,30 +235,34 @@ impl TryFrom<&str> for MemoryMnemonic {
     type Error = AssemblerError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        // TODO: there should be a better way of doing this that doesn't rely on the correct
-        // ordering of the MemoryMnemonic enum variants. Probably just store the length of the
-        // matching str, but I don't want to do that rn.
-        let strb = MemoryMnemonic::STRB.to_string();
-        let str = MemoryMnemonic::STR.to_string();
-        let ldrb = MemoryMnemonic::LDRB.to_string();
-        let ldr = MemoryMnemonic::LDR.to_string();
-
-        if value.starts_with(&strb.to_uppercase()) || value.starts_with(&strb.to_lowercase()) {
-            Ok(MemoryMnemonic::STRB)
-        } else if value.starts_with(&str.to_uppercase()) || value.starts_with(&str.to_lowercase()) {
-            Ok(MemoryMnemonic::STR)
-        } else if value.starts_with(&ldrb.to_uppercase()) || value.starts_with(&ldrb.to_lowercase())
-        {
-            Ok(MemoryMnemonic::LDRB)
-        } else if value.starts_with(&ldr.to_uppercase()) || value.starts_with(&ldr.to_lowercase()) {
-            Ok(MemoryMnemonic::LDR)
-        } else {
-            Err(ParseError::BadMnemonic(value.to_owned()).into())
+        parse_mnemonic(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum StackMnemonic {
+    PUSH,
+    POP,
+}
+
+impl std::fmt::Display for StackMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StackMnemonic::PUSH => write!(f, "push"),
+            StackMnemonic::POP => write!(f, "pop"),
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+impl TryFrom<&str> for StackMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
 pub enum BranchMnemonic {
     B,
     BL,
@@ -191,20 +281,11 @@ impl TryFrom<&str> for BranchMnemonic {
     type Error = AssemblerError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let bl = BranchMnemonic::BL.to_string();
-        let b = BranchMnemonic::B.to_string();
-
-        if value.starts_with(&bl.to_uppercase()) || value.starts_with(&bl.to_lowercase()) {
-            Ok(BranchMnemonic::BL)
-        } else if value.starts_with(&b.to_uppercase()) || value.starts_with(&b.to_lowercase()) {
-            Ok(BranchMnemonic::B)
-        } else {
-            Err(ParseError::BadMnemonic(value.to_owned()).into())
-        }
+        parse_mnemonic(value)
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
 pub enum BranchExecMnemonic {
     BX,
 }
@@ -221,17 +302,11 @@ impl TryFrom<&str> for BranchExecMnemonic {
     type Error = AssemblerError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let bx = Self::BX.to_string();
-
-        if value.starts_with(&bx.to_uppercase()) || value.starts_with(&bx.to_lowercase()) {
-            Ok(Self::BX)
-        } else {
-            Err(ParseError::BadMnemonic(value.to_owned()).into())
-        }
+        parse_mnemonic(value)
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
 pub enum MultiplyMnemonic {
     MUL,
 }
@@ -248,12 +323,382 @@ impl TryFrom<&str> for MultiplyMnemonic {
     type Error = AssemblerError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let mul = MultiplyMnemonic::MUL.to_string();
+        parse_mnemonic(value)
+    }
+}
+
+/// 16-bit immediate moves (ARMv6T2+).
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum MoveWideMnemonic {
+    MOVW,
+    MOVT,
+}
+
+impl std::fmt::Display for MoveWideMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MOVW => write!(f, "movw"),
+            Self::MOVT => write!(f, "movt"),
+        }
+    }
+}
+
+impl TryFrom<&str> for MoveWideMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+/// Bitfield insert/clear/extract (ARMv6T2+).
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum BitfieldMnemonic {
+    BFC,
+    BFI,
+    SBFX,
+    UBFX,
+}
+
+impl std::fmt::Display for BitfieldMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BFC => write!(f, "bfc"),
+            Self::BFI => write!(f, "bfi"),
+            Self::SBFX => write!(f, "sbfx"),
+            Self::UBFX => write!(f, "ubfx"),
+        }
+    }
+}
+
+impl TryFrom<&str> for BitfieldMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum ReverseMnemonic {
+    RBIT,
+}
+
+impl std::fmt::Display for ReverseMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RBIT => write!(f, "rbit"),
+        }
+    }
+}
+
+impl TryFrom<&str> for ReverseMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum DivideMnemonic {
+    SDIV,
+    UDIV,
+}
+
+impl std::fmt::Display for DivideMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SDIV => write!(f, "sdiv"),
+            Self::UDIV => write!(f, "udiv"),
+        }
+    }
+}
+
+impl TryFrom<&str> for DivideMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum BarrierMnemonic {
+    DMB,
+    DSB,
+    ISB,
+}
+
+impl std::fmt::Display for BarrierMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DMB => write!(f, "dmb"),
+            Self::DSB => write!(f, "dsb"),
+            Self::ISB => write!(f, "isb"),
+        }
+    }
+}
+
+impl TryFrom<&str> for BarrierMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+impl From<BarrierMnemonic> for u8 {
+    fn from(value: BarrierMnemonic) -> Self {
+        match value {
+            BarrierMnemonic::DSB => 0b0100,
+            BarrierMnemonic::DMB => 0b0101,
+            BarrierMnemonic::ISB => 0b0110,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum ExclusiveMnemonic {
+    LDREX,
+    LDREXB,
+    LDREXH,
+    LDREXD,
+    STREX,
+    STREXB,
+    STREXH,
+    STREXD,
+    CLREX,
+}
+
+impl ExclusiveMnemonic {
+    pub fn is_load(self) -> bool {
+        matches!(
+            self,
+            Self::LDREX | Self::LDREXB | Self::LDREXH | Self::LDREXD
+        )
+    }
+
+    pub fn is_doubleword(self) -> bool {
+        matches!(self, Self::LDREXD | Self::STREXD)
+    }
+}
+
+impl std::fmt::Display for ExclusiveMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LDREX => write!(f, "ldrex"),
+            Self::LDREXB => write!(f, "ldrexb"),
+            Self::LDREXH => write!(f, "ldrexh"),
+            Self::LDREXD => write!(f, "ldrexd"),
+            Self::STREX => write!(f, "strex"),
+            Self::STREXB => write!(f, "strexb"),
+            Self::STREXH => write!(f, "strexh"),
+            Self::STREXD => write!(f, "strexd"),
+            Self::CLREX => write!(f, "clrex"),
+        }
+    }
+}
+
+impl TryFrom<&str> for ExclusiveMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+/// Bits 23..20 of the exclusive load/store encodings, without the L bit.
+impl From<ExclusiveMnemonic> for u8 {
+    fn from(value: ExclusiveMnemonic) -> Self {
+        match value {
+            ExclusiveMnemonic::LDREX | ExclusiveMnemonic::STREX => 0b1000,
+            ExclusiveMnemonic::LDREXD | ExclusiveMnemonic::STREXD => 0b1010,
+            ExclusiveMnemonic::LDREXB | ExclusiveMnemonic::STREXB => 0b1100,
+            ExclusiveMnemonic::LDREXH | ExclusiveMnemonic::STREXH => 0b1110,
+            ExclusiveMnemonic::CLREX => 0b0000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum PreloadMnemonic {
+    PLD,
+    PLDW,
+    PLI,
+}
+
+impl std::fmt::Display for PreloadMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PLD => write!(f, "pld"),
+            Self::PLDW => write!(f, "pldw"),
+            Self::PLI => write!(f, "pli"),
+        }
+    }
+}
+
+impl TryFrom<&str> for PreloadMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum HintMnemonic {
+    NOP,
+    YIELD,
+    WFE,
+    WFI,
+    SEV,
+}
+
+impl std::fmt::Display for HintMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NOP => write!(f, "nop"),
+            Self::YIELD => write!(f, "yield"),
+            Self::WFE => write!(f, "wfe"),
+            Self::WFI => write!(f, "wfi"),
+            Self::SEV => write!(f, "sev"),
+        }
+    }
+}
+
+impl TryFrom<&str> for HintMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
 
-        if value.starts_with(&mul.to_uppercase()) || value.starts_with(&mul.to_lowercase()) {
-            Ok(MultiplyMnemonic::MUL)
-        } else {
-            Err(ParseError::BadMnemonic(value.to_owned()).into())
+impl From<HintMnemonic> for u8 {
+    fn from(value: HintMnemonic) -> Self {
+        match value {
+            HintMnemonic::NOP => 0,
+            HintMnemonic::YIELD => 1,
+            HintMnemonic::WFE => 2,
+            HintMnemonic::WFI => 3,
+            HintMnemonic::SEV => 4,
         }
     }
 }
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum ExceptionMnemonic {
+    SMC,
+    HVC,
+}
+
+impl std::fmt::Display for ExceptionMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SMC => write!(f, "smc"),
+            Self::HVC => write!(f, "hvc"),
+        }
+    }
+}
+
+impl TryFrom<&str> for ExceptionMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum CoprocessorMnemonic {
+    CDP,
+    CDP2,
+    LDC,
+    LDC2,
+    LDC2L,
+    LDCL,
+    MCR,
+    MCR2,
+    MCRR,
+    MCRR2,
+    MRC,
+    MRC2,
+    MRRC,
+    MRRC2,
+    STC,
+    STC2,
+    STC2L,
+    STCL,
+}
+
+impl std::fmt::Display for CoprocessorMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CDP => write!(f, "cdp"),
+            Self::CDP2 => write!(f, "cdp2"),
+            Self::LDC => write!(f, "ldc"),
+            Self::LDC2 => write!(f, "ldc2"),
+            Self::LDC2L => write!(f, "ldc2l"),
+            Self::LDCL => write!(f, "ldcl"),
+            Self::MCR => write!(f, "mcr"),
+            Self::MCR2 => write!(f, "mcr2"),
+            Self::MCRR => write!(f, "mcrr"),
+            Self::MCRR2 => write!(f, "mcrr2"),
+            Self::MRC => write!(f, "mrc"),
+            Self::MRC2 => write!(f, "mrc2"),
+            Self::MRRC => write!(f, "mrrc"),
+            Self::MRRC2 => write!(f, "mrrc2"),
+            Self::STC => write!(f, "stc"),
+            Self::STC2 => write!(f, "stc2"),
+            Self::STC2L => write!(f, "stc2l"),
+            Self::STCL => write!(f, "stcl"),
+        }
+    }
+}
+
+impl TryFrom<&str> for CoprocessorMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+impl CoprocessorMnemonic {
+    /// The `2` variants, which use the unconditional encoding space.
+    pub fn is_unconditional(self) -> bool {
+        matches!(
+            self,
+            Self::CDP2
+                | Self::LDC2
+                | Self::LDC2L
+                | Self::MCR2
+                | Self::MCRR2
+                | Self::MRC2
+                | Self::MRRC2
+                | Self::STC2
+                | Self::STC2L
+        )
+    }
+
+    /// Transfers from the coprocessor to the core or memory.
+    pub fn is_load(self) -> bool {
+        matches!(
+            self,
+            Self::LDC
+                | Self::LDC2
+                | Self::LDC2L
+                | Self::LDCL
+                | Self::MRC
+                | Self::MRC2
+                | Self::MRRC
+                | Self::MRRC2
+        )
+    }
+
+    /// The `l` variants of `ldc`/`stc`, which set the D bit for a long transfer.
+    pub fn is_long(self) -> bool {
+        matches!(self, Self::LDC2L | Self::LDCL | Self::STC2L | Self::STCL)
+    }
+}
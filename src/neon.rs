@@ -0,0 +1,1736 @@
+//! Advanced SIMD (NEON) instructions. These are written with their ARM encodings; Thumb state
+//! uses the same fields behind a different prefix, see [`NeonInstruction::to_thumb_machine_code`].
+
+use crate::{
+    arch::Feature,
+    cond::Cond,
+    error::{AssemblerError, ParseError},
+    instructions::{
+        parse_bounded_immediate, parse_immediate, parse_reg_id, split_operands, Rd, Rm, Rn,
+    },
+    mnemonics::parse_mnemonic,
+    vfp::{encode_float_immediate, parse_float_immediate, VfpRegister},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum NeonMnemonic {
+    VABA,
+    VABD,
+    VABS,
+    VADD,
+    VADDL,
+    VADDW,
+    VAND,
+    VBIC,
+    VBIF,
+    VBIT,
+    VBSL,
+    VCEQ,
+    VCGE,
+    VCGT,
+    VCNT,
+    VCVT,
+    VDUP,
+    VEOR,
+    VEXT,
+    VHADD,
+    VHSUB,
+    VLD1,
+    VLD2,
+    VLD3,
+    VLD4,
+    VMAX,
+    VMIN,
+    VMLA,
+    VMLAL,
+    VMLS,
+    VMLSL,
+    VMOV,
+    VMOVL,
+    VMOVN,
+    VMUL,
+    VMULL,
+    VMVN,
+    VNEG,
+    VORN,
+    VORR,
+    VPADD,
+    VPMAX,
+    VPMIN,
+    VQADD,
+    VQMOVN,
+    VQMOVUN,
+    VQSUB,
+    VRECPE,
+    VREV16,
+    VREV32,
+    VREV64,
+    VRHADD,
+    VRSHR,
+    VRSHRN,
+    VRSQRTE,
+    VRSRA,
+    VSHL,
+    VSHLL,
+    VSHR,
+    VSHRN,
+    VSLI,
+    VSRA,
+    VSRI,
+    VST1,
+    VST2,
+    VST3,
+    VST4,
+    VSUB,
+    VSUBL,
+    VSUBW,
+    VSWP,
+    VTBL,
+    VTBX,
+    VTRN,
+    VTST,
+    VUZP,
+    VZIP,
+}
+
+impl std::fmt::Display for NeonMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::VABA => "vaba",
+            Self::VABD => "vabd",
+            Self::VABS => "vabs",
+            Self::VADD => "vadd",
+            Self::VADDL => "vaddl",
+            Self::VADDW => "vaddw",
+            Self::VAND => "vand",
+            Self::VBIC => "vbic",
+            Self::VBIF => "vbif",
+            Self::VBIT => "vbit",
+            Self::VBSL => "vbsl",
+            Self::VCEQ => "vceq",
+            Self::VCGE => "vcge",
+            Self::VCGT => "vcgt",
+            Self::VCNT => "vcnt",
+            Self::VCVT => "vcvt",
+            Self::VDUP => "vdup",
+            Self::VEOR => "veor",
+            Self::VEXT => "vext",
+            Self::VHADD => "vhadd",
+            Self::VHSUB => "vhsub",
+            Self::VLD1 => "vld1",
+            Self::VLD2 => "vld2",
+            Self::VLD3 => "vld3",
+            Self::VLD4 => "vld4",
+            Self::VMAX => "vmax",
+            Self::VMIN => "vmin",
+            Self::VMLA => "vmla",
+            Self::VMLAL => "vmlal",
+            Self::VMLS => "vmls",
+            Self::VMLSL => "vmlsl",
+            Self::VMOV => "vmov",
+            Self::VMOVL => "vmovl",
+            Self::VMOVN => "vmovn",
+            Self::VMUL => "vmul",
+            Self::VMULL => "vmull",
+            Self::VMVN => "vmvn",
+            Self::VNEG => "vneg",
+            Self::VORN => "vorn",
+            Self::VORR => "vorr",
+            Self::VPADD => "vpadd",
+            Self::VPMAX => "vpmax",
+            Self::VPMIN => "vpmin",
+            Self::VQADD => "vqadd",
+            Self::VQMOVN => "vqmovn",
+            Self::VQMOVUN => "vqmovun",
+            Self::VQSUB => "vqsub",
+            Self::VRECPE => "vrecpe",
+            Self::VREV16 => "vrev16",
+            Self::VREV32 => "vrev32",
+            Self::VREV64 => "vrev64",
+            Self::VRHADD => "vrhadd",
+            Self::VRSHR => "vrshr",
+            Self::VRSHRN => "vrshrn",
+            Self::VRSQRTE => "vrsqrte",
+            Self::VRSRA => "vrsra",
+            Self::VSHL => "vshl",
+            Self::VSHLL => "vshll",
+            Self::VSHR => "vshr",
+            Self::VSHRN => "vshrn",
+            Self::VSLI => "vsli",
+            Self::VSRA => "vsra",
+            Self::VSRI => "vsri",
+            Self::VST1 => "vst1",
+            Self::VST2 => "vst2",
+            Self::VST3 => "vst3",
+            Self::VST4 => "vst4",
+            Self::VSUB => "vsub",
+            Self::VSUBL => "vsubl",
+            Self::VSUBW => "vsubw",
+            Self::VSWP => "vswp",
+            Self::VTBL => "vtbl",
+            Self::VTBX => "vtbx",
+            Self::VTRN => "vtrn",
+            Self::VTST => "vtst",
+            Self::VUZP => "vuzp",
+            Self::VZIP => "vzip",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl TryFrom<&str> for NeonMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+impl NeonMnemonic {
+    /// `vldN`/`vstN` with N and whether it loads.
+    fn structure(self) -> Option<(u8, bool)> {
+        match self {
+            Self::VLD1 => Some((1, true)),
+            Self::VLD2 => Some((2, true)),
+            Self::VLD3 => Some((3, true)),
+            Self::VLD4 => Some((4, true)),
+            Self::VST1 => Some((1, false)),
+            Self::VST2 => Some((2, false)),
+            Self::VST3 => Some((3, false)),
+            Self::VST4 => Some((4, false)),
+            _ => None,
+        }
+    }
+
+    fn is_shift(self) -> bool {
+        matches!(
+            self,
+            Self::VSHR
+                | Self::VSRA
+                | Self::VRSHR
+                | Self::VRSRA
+                | Self::VSRI
+                | Self::VSHL
+                | Self::VSLI
+                | Self::VSHRN
+                | Self::VRSHRN
+                | Self::VSHLL
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ElementKind {
+    /// A bare size such as `.32`.
+    Untyped,
+    Integer,
+    Signed,
+    Unsigned,
+    Float,
+    Polynomial,
+}
+
+/// The element data type suffix, e.g. `.i8`, `.u16`, `.s32`, `.f32` or `.p8`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ElementType {
+    pub kind: ElementKind,
+    pub size: u8,
+}
+
+impl TryFrom<&str> for ElementType {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let bad_type = || ParseError::BadDataType(value.to_owned());
+        let lower = value.trim().to_ascii_lowercase();
+        let (kind, size) =
+            match lower.split_at(lower.find(|c: char| c.is_ascii_digit()).unwrap_or(0)) {
+                ("", size) => (ElementKind::Untyped, size),
+                ("i", size) => (ElementKind::Integer, size),
+                ("s", size) => (ElementKind::Signed, size),
+                ("u", size) => (ElementKind::Unsigned, size),
+                ("f", size) => (ElementKind::Float, size),
+                ("p", size) => (ElementKind::Polynomial, size),
+                _ => return Err(bad_type().into()),
+            };
+        let size = match size {
+            "8" => 8,
+            "16" => 16,
+            "32" => 32,
+            "64" => 64,
+            _ => return Err(bad_type().into()),
+        };
+
+        let valid = match kind {
+            ElementKind::Float => size == 32,
+            ElementKind::Polynomial => size == 8,
+            _ => true,
+        };
+        if !valid {
+            return Err(bad_type().into());
+        }
+
+        Ok(Self { kind, size })
+    }
+}
+
+impl std::fmt::Display for ElementType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let prefix = match self.kind {
+            ElementKind::Untyped => "",
+            ElementKind::Integer => "i",
+            ElementKind::Signed => "s",
+            ElementKind::Unsigned => "u",
+            ElementKind::Float => "f",
+            ElementKind::Polynomial => "p",
+        };
+        write!(f, "{prefix}{}", self.size)
+    }
+}
+
+impl ElementType {
+    /// The 2-bit size field for 8, 16, 32 and 64-bit elements.
+    fn size_field(self) -> u32 {
+        match self.size {
+            8 => 0,
+            16 => 1,
+            32 => 2,
+            _ => 3,
+        }
+    }
+
+    fn is_unsigned(self) -> bool {
+        self.kind == ElementKind::Unsigned
+    }
+
+    /// Signed or unsigned, as needed by operations whose result depends on the sign.
+    fn is_signed_or_unsigned(self) -> bool {
+        matches!(self.kind, ElementKind::Signed | ElementKind::Unsigned)
+    }
+
+    /// Any integer type, for operations that don't care about the sign.
+    fn is_integer(self) -> bool {
+        matches!(
+            self.kind,
+            ElementKind::Untyped
+                | ElementKind::Integer
+                | ElementKind::Signed
+                | ElementKind::Unsigned
+        )
+    }
+}
+
+/// A 64-bit `d0`-`d31` or 128-bit `q0`-`q15` register.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NeonRegister {
+    Double(u8),
+    Quad(u8),
+}
+
+impl TryFrom<&str> for NeonRegister {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let bad_register = || ParseError::BadRegister(value.to_owned());
+        let value = value.trim();
+        let number = value
+            .get(1..)
+            .and_then(|number| number.parse::<u8>().ok())
+            .ok_or_else(bad_register)?;
+
+        match value.get(..1) {
+            Some("d" | "D") if number < 32 => Ok(Self::Double(number)),
+            Some("q" | "Q") if number < 16 => Ok(Self::Quad(number)),
+            _ => Err(bad_register().into()),
+        }
+    }
+}
+
+impl NeonRegister {
+    pub fn is_quad(self) -> bool {
+        matches!(self, Self::Quad(_))
+    }
+
+    /// The first double register, which is how quad registers are encoded.
+    fn as_double(self) -> VfpRegister {
+        match self {
+            Self::Double(number) => VfpRegister::Double(number),
+            Self::Quad(number) => VfpRegister::Double(2 * number),
+        }
+    }
+
+    /// The `Q` bit, bit 6.
+    fn q(self) -> u32 {
+        u32::from(self.is_quad()) << 6
+    }
+
+    fn vd(self) -> u32 {
+        self.as_double().vd()
+    }
+
+    fn vn(self) -> u32 {
+        self.as_double().vn()
+    }
+
+    fn vm(self) -> u32 {
+        self.as_double().vm()
+    }
+}
+
+/// One element of a double register, such as `d0[1]`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Scalar {
+    pub reg: u8,
+    pub index: u8,
+}
+
+impl TryFrom<&str> for Scalar {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let bad_scalar = || ParseError::BadRegister(value.to_owned());
+        let (reg, index) = value
+            .trim()
+            .strip_suffix(']')
+            .and_then(|scalar| scalar.split_once('['))
+            .ok_or_else(bad_scalar)?;
+        let NeonRegister::Double(reg) = NeonRegister::try_from(reg)? else {
+            return Err(bad_scalar().into());
+        };
+        let index = index.trim().parse::<u8>().map_err(|_| bad_scalar())?;
+
+        Ok(Self { reg, index })
+    }
+}
+
+impl Scalar {
+    /// Check that the index fits elements of `size` bits.
+    fn check(self, size: u8) -> Result<Self, AssemblerError> {
+        if u32::from(self.index) < 64 / u32::from(size) {
+            Ok(self)
+        } else {
+            Err(ParseError::BadRegister(format!("d{}[{}]", self.reg, self.index)).into())
+        }
+    }
+
+    /// Encoded in the Vm position for multiplies, where 16-bit scalars only reach `d0`-`d7` and
+    /// borrow the top bits for the index.
+    fn vm(self, size: u8) -> u32 {
+        let (reg, index) = (u32::from(self.reg), u32::from(self.index));
+        if size == 16 {
+            reg | ((index & 1) << 3) | ((index >> 1) << 5)
+        } else {
+            reg | (index << 5)
+        }
+    }
+}
+
+/// Find the `cmode`, `op` and 8-bit value with which `vmov` (or `vmvn`, with `invert`) produces
+/// `value` in every element of type `et`.
+pub fn encode_simd_immediate(value: u64, et: ElementType, invert: bool) -> Option<(u8, u8, u8)> {
+    let op = u8::from(invert);
+    let byte_at = |shift: u32| ((value >> shift) & 0xFF) as u8;
+    let only_byte = |shift: u32| value & !(0xFF << shift) == 0;
+
+    match (et.size, et.kind) {
+        (8, _) if !invert && value <= 0xFF => Some((0b1110, 0, value as u8)),
+        (16, _) => [0, 8]
+            .into_iter()
+            .find(|shift| only_byte(*shift))
+            .map(|shift| (0b1000 | (shift / 4) as u8, op, byte_at(shift))),
+        (32, ElementKind::Float) if !invert => {
+            let value = f32::from_bits(value as u32);
+            encode_float_immediate(value.into()).map(|imm| (0b1111, 0, imm))
+        }
+        (32, _) => {
+            if let Some(shift) = [0, 8, 16, 24].into_iter().find(|shift| only_byte(*shift)) {
+                Some(((shift / 4) as u8, op, byte_at(shift)))
+            } else if value & 0xFFFF_00FF == 0xFF {
+                Some((0b1100, op, byte_at(8)))
+            } else if value & 0xFF00_FFFF == 0xFFFF {
+                Some((0b1101, op, byte_at(16)))
+            } else {
+                None
+            }
+        }
+        // Each bit of the immediate expands to a byte of all zeros or all ones
+        (64, _) if !invert => (0..8)
+            .try_fold(0, |imm, i| match byte_at(8 * i) {
+                0x00 => Some(imm),
+                0xFF => Some(imm | (1 << i)),
+                _ => None,
+            })
+            .map(|imm| (0b1110, 1, imm)),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NeonInstruction {
+    /// `op.dt Vd, Vn, Vm` with all three registers the same width
+    ThreeSame(
+        NeonMnemonic,
+        ElementType,
+        NeonRegister,
+        NeonRegister,
+        NeonRegister,
+    ),
+    /// `vmul/vmla/vmls.dt Vd, Vn, Dm[x]`
+    ByScalar(
+        NeonMnemonic,
+        ElementType,
+        NeonRegister,
+        NeonRegister,
+        Scalar,
+    ),
+    /// `op.dt Qd, Vn, Dm` where `vaddw/vsubw` take a quad Vn and the others a double one
+    ThreeDifferent(
+        NeonMnemonic,
+        ElementType,
+        NeonRegister,
+        NeonRegister,
+        NeonRegister,
+    ),
+    /// `vabs/vneg/vmvn/vmovn/vcnt/vrev64/vswp/vrecpe/vzip/vuzp/vtrn.dt Vd, Vm` and the like
+    TwoMisc(NeonMnemonic, ElementType, NeonRegister, NeonRegister),
+    /// `vcvt.f32.s32 Vd, Vm{, #fbits}` and the other directions between integers and floats,
+    /// with the `op` field and the fraction bits of a fixed point conversion, 0 for integers
+    Convert(NeonRegister, NeonRegister, u8, u8),
+    /// `op.dt Vd, Vm, #shift`, including `vmovl` as a long shift by 0
+    ShiftImmediate(NeonMnemonic, ElementType, NeonRegister, NeonRegister, u8),
+    /// `vmov/vmvn.dt Vd, #imm` with its `cmode`, `op` and 8-bit value
+    ModifiedImmediate(NeonRegister, u8, u8, u8),
+    /// `vdup.size Vd, Rt`
+    DupCore(ElementType, NeonRegister, Rd),
+    /// `vdup.size Vd, Dm[x]`
+    DupScalar(ElementType, NeonRegister, Scalar),
+    /// `vmov.dt Rt, Dn[x]`
+    ScalarToCore(ElementType, Rd, Scalar),
+    /// `vmov.size Dd[x], Rt`
+    CoreToScalar(ElementType, Scalar, Rd),
+    /// `vext.size Vd, Vn, Vm, #index`, with the index already scaled to bytes
+    Extract(NeonRegister, NeonRegister, NeonRegister, u8),
+    /// `vtbl/vtbx.8 Dd, {list}, Dm` with the first list register and the list length
+    TableLookup(NeonMnemonic, NeonRegister, NeonRegister, u8, NeonRegister),
+    /// `vldN/vstN.size {list}, [Rn{:align}]{!}` or `..., Rm` with the first list register, the
+    /// `type` field describing the list, the `align` field and Rm as sp for `!` and pc for none
+    LoadStore(NeonMnemonic, ElementType, NeonRegister, u8, Rn, u8, Rm),
+    /// `vldN/vstN.size {Dd[x], ...}, [Rn{:align}]...` with the first list register and the
+    /// `index_align` field
+    LoadStoreLane(NeonMnemonic, ElementType, NeonRegister, u8, Rn, Rm),
+    /// `vldN.size {Dd[], ...}, [Rn{:align}]...` with the size field, the first list register and
+    /// the `T` and `a` bits
+    LoadAllLanes(NeonMnemonic, u8, NeonRegister, u8, Rn, Rm),
+}
+
+/// Parse a list of double or quad registers into its double registers, e.g. `{q0, q1}` into
+/// d0-d3.
+fn parse_neon_reg_list(value: &str) -> Result<Vec<u8>, AssemblerError> {
+    let bad_list = || ParseError::BadRegister(value.to_owned());
+    let inner = value
+        .trim()
+        .strip_prefix('{')
+        .and_then(|list| list.strip_suffix('}'))
+        .ok_or_else(bad_list)?;
+
+    let mut regs = vec![];
+    for item in inner.split(',') {
+        let (first, last) = match item.split_once('-') {
+            Some((first, last)) => (
+                NeonRegister::try_from(first)?,
+                NeonRegister::try_from(last)?,
+            ),
+            None => {
+                let reg = NeonRegister::try_from(item)?;
+                (reg, reg)
+            }
+        };
+        let (VfpRegister::Double(first_d), VfpRegister::Double(last_d)) =
+            (first.as_double(), last.as_double())
+        else {
+            return Err(bad_list().into());
+        };
+        if first.is_quad() != last.is_quad() || first_d > last_d {
+            return Err(bad_list().into());
+        }
+        let last_d = if last.is_quad() { last_d + 1 } else { last_d };
+        regs.extend(first_d..=last_d);
+    }
+
+    Ok(regs)
+}
+
+/// Parse a list of lanes of double registers, e.g. `{d0[1], d2[1]}` into d0 and d2 and lane 1,
+/// or `{d0[], d1[]}` into d0 and d1 with no lane for all of them.
+fn parse_lane_list(value: &str) -> Result<(Vec<u8>, Option<u8>), AssemblerError> {
+    let bad_list = || ParseError::BadRegister(value.to_owned());
+    let inner = value
+        .trim()
+        .strip_prefix('{')
+        .and_then(|list| list.strip_suffix('}'))
+        .ok_or_else(bad_list)?;
+
+    let mut regs = vec![];
+    let mut lanes = vec![];
+    for item in inner.split(',') {
+        let (reg, lane) = item
+            .trim()
+            .strip_suffix(']')
+            .and_then(|item| item.split_once('['))
+            .ok_or_else(bad_list)?;
+        let NeonRegister::Double(reg) = NeonRegister::try_from(reg)? else {
+            return Err(bad_list().into());
+        };
+        let lane = match lane.trim() {
+            "" => None,
+            lane => Some(lane.parse::<u8>().map_err(|_| bad_list())?),
+        };
+        regs.push(reg);
+        lanes.push(lane);
+    }
+    if lanes.windows(2).any(|pair| pair[0] != pair[1]) {
+        return Err(bad_list().into());
+    }
+
+    Ok((regs, lanes[0]))
+}
+
+/// Parse the immediate of a 64-bit `vmov`, which may not fit an `i64`.
+fn parse_wide_immediate(value: &str) -> Result<u64, AssemblerError> {
+    let digits = value.trim().trim_start_matches('#');
+    let hex = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"));
+    match hex.map(|hex| u64::from_str_radix(hex, 16)) {
+        Some(Ok(value)) => Ok(value),
+        _ => Ok(parse_immediate(value)? as u64),
+    }
+}
+
+impl NeonInstruction {
+    /// Parse a whole NEON instruction. Only the core register transfers can be conditional in ARM
+    /// state, so any other condition is rejected.
+    pub fn parse(value: &str) -> Result<(Cond, Self), AssemblerError> {
+        let value = value.trim();
+        let (opcode, rest) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+        let (name, types) = opcode.split_once('.').unwrap_or((opcode, ""));
+        let types = types
+            .split('.')
+            .filter(|t| !t.is_empty())
+            .map(ElementType::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mnemonic = NeonMnemonic::try_from(name)?;
+        let cond_maybe = &name[mnemonic.to_string().len()..];
+        let cond = if cond_maybe.is_empty() {
+            Cond::AL
+        } else {
+            Cond::try_from(cond_maybe)?
+        };
+
+        let operands = split_operands(rest);
+        let instruction = Self::parse_operands(mnemonic, &types, &operands)?;
+
+        let conditional = matches!(
+            instruction,
+            Self::DupCore(..) | Self::ScalarToCore(..) | Self::CoreToScalar(..)
+        );
+        if cond != Cond::AL && !conditional {
+            return Err(ParseError::UnexpectedCond(mnemonic.to_string()).into());
+        }
+
+        Ok((cond, instruction))
+    }
+
+    fn parse_operands(
+        mnemonic: NeonMnemonic,
+        types: &[ElementType],
+        operands: &[&str],
+    ) -> Result<Self, AssemblerError> {
+        let bad_operands = || ParseError::BadFlexOperand(operands.join(", "));
+        let bad_types = || {
+            let names: Vec<String> = types.iter().map(ElementType::to_string).collect();
+            ParseError::BadDataType(names.join("."))
+        };
+        let untyped = ElementType {
+            kind: ElementKind::Untyped,
+            size: 8,
+        };
+        if mnemonic == NeonMnemonic::VCVT {
+            return Self::parse_convert(types, operands);
+        }
+        let et = match types {
+            [] => None,
+            [et] => Some(*et),
+            _ => return Err(bad_types().into()),
+        };
+        let reg = |i: usize| -> Result<NeonRegister, AssemblerError> {
+            NeonRegister::try_from(*operands.get(i).ok_or(ParseError::RanOutOfOperands)?)
+        };
+
+        if let Some((n, _)) = mnemonic.structure() {
+            let et = et.ok_or_else(bad_types)?;
+            return Self::parse_load_store(mnemonic, n, et, operands);
+        }
+
+        let instruction = match (mnemonic, operands) {
+            (NeonMnemonic::VMOV | NeonMnemonic::VMVN, [vd, imm]) if imm.starts_with('#') => {
+                let et = et.ok_or_else(bad_types)?;
+                let vd = NeonRegister::try_from(*vd)?;
+                let mask = u64::MAX >> (64 - et.size);
+                let value = if et.kind == ElementKind::Float {
+                    u64::from((parse_float_immediate(imm)? as f32).to_bits())
+                } else {
+                    parse_wide_immediate(imm)? & mask
+                };
+                let invert = mnemonic == NeonMnemonic::VMVN;
+                // Fall back to the opposite instruction with the inverted value
+                let (cmode, op, imm8) = encode_simd_immediate(value, et, invert)
+                    .or_else(|| encode_simd_immediate(!value & mask, et, !invert))
+                    .ok_or_else(|| ParseError::BadImmediate(imm.to_string()))?;
+                Self::ModifiedImmediate(vd, cmode, op, imm8)
+            }
+            (NeonMnemonic::VMOV, [rt, scalar]) if scalar.ends_with(']') => {
+                let et = et.ok_or_else(bad_types)?;
+                let narrow = et.size < 32;
+                if et.size == 64 || (narrow && !et.is_signed_or_unsigned()) {
+                    return Err(bad_types().into());
+                }
+                let scalar = Scalar::try_from(*scalar)?.check(et.size)?;
+                Self::ScalarToCore(et, Rd(parse_reg_id(rt)?), scalar)
+            }
+            (NeonMnemonic::VMOV, [scalar, rt]) if scalar.ends_with(']') => {
+                let et = et.ok_or_else(bad_types)?;
+                if et.size == 64 || et.kind == ElementKind::Float {
+                    return Err(bad_types().into());
+                }
+                let scalar = Scalar::try_from(*scalar)?.check(et.size)?;
+                Self::CoreToScalar(et, scalar, Rd(parse_reg_id(rt)?))
+            }
+            (NeonMnemonic::VMOV, [vd, vm]) => {
+                let (vd, vm) = (NeonRegister::try_from(*vd)?, NeonRegister::try_from(*vm)?);
+                if vd.is_quad() != vm.is_quad() {
+                    return Err(bad_operands().into());
+                }
+                // vmov is vorr with both sources the same
+                Self::ThreeSame(NeonMnemonic::VORR, et.unwrap_or(untyped), vd, vm, vm)
+            }
+            (NeonMnemonic::VDUP, [vd, source]) => {
+                let et = et.ok_or_else(bad_types)?;
+                if et.size == 64 {
+                    return Err(bad_types().into());
+                }
+                let vd = NeonRegister::try_from(*vd)?;
+                if source.ends_with(']') {
+                    Self::DupScalar(et, vd, Scalar::try_from(*source)?.check(et.size)?)
+                } else {
+                    Self::DupCore(et, vd, Rd(parse_reg_id(source)?))
+                }
+            }
+            (NeonMnemonic::VEXT, [vd, vn, vm, index]) => {
+                let et = et.ok_or_else(bad_types)?;
+                let (vd, vn, vm) = (
+                    NeonRegister::try_from(*vd)?,
+                    NeonRegister::try_from(*vn)?,
+                    NeonRegister::try_from(*vm)?,
+                );
+                if vd.is_quad() != vn.is_quad() || vd.is_quad() != vm.is_quad() {
+                    return Err(bad_operands().into());
+                }
+                let bytes = if vd.is_quad() { 16 } else { 8 };
+                let elements = bytes / u32::from(et.size / 8);
+                let index = parse_bounded_immediate(index, elements - 1)?;
+                Self::Extract(vd, vn, vm, (index * u32::from(et.size / 8)) as u8)
+            }
+            (NeonMnemonic::VTBL | NeonMnemonic::VTBX, [vd, list, vm]) => {
+                if et.is_some_and(|et| et.size != 8) {
+                    return Err(bad_types().into());
+                }
+                let regs = parse_neon_reg_list(list)?;
+                let consecutive = regs.windows(2).all(|pair| pair[1] == pair[0] + 1);
+                let (vd, vm) = (NeonRegister::try_from(*vd)?, NeonRegister::try_from(*vm)?);
+                if !consecutive || regs.len() > 4 || vd.is_quad() || vm.is_quad() {
+                    return Err(bad_operands().into());
+                }
+                let first = NeonRegister::Double(regs[0]);
+                Self::TableLookup(mnemonic, vd, first, regs.len() as u8, vm)
+            }
+            (NeonMnemonic::VMOVL, [vd, vm]) => {
+                let et = et.filter(|et| et.is_signed_or_unsigned() && et.size < 64);
+                let et = et.ok_or_else(bad_types)?;
+                let (vd, vm) = (NeonRegister::try_from(*vd)?, NeonRegister::try_from(*vm)?);
+                if !vd.is_quad() || vm.is_quad() {
+                    return Err(bad_operands().into());
+                }
+                Self::ShiftImmediate(NeonMnemonic::VSHLL, et, vd, vm, 0)
+            }
+            (_, [vd, vm, shift]) if mnemonic.is_shift() => {
+                let et = et.ok_or_else(bad_types)?;
+                let (vd, vm) = (NeonRegister::try_from(*vd)?, NeonRegister::try_from(*vm)?);
+                Self::parse_shift(mnemonic, et, vd, vm, shift)?
+            }
+            (_, [vd, vn, scalar])
+                if scalar.ends_with(']')
+                    && matches!(
+                        mnemonic,
+                        NeonMnemonic::VMUL | NeonMnemonic::VMLA | NeonMnemonic::VMLS
+                    ) =>
+            {
+                let et = et.ok_or_else(bad_types)?;
+                let valid_type = match et.kind {
+                    ElementKind::Float => true,
+                    ElementKind::Polynomial => false,
+                    _ => et.size == 16 || et.size == 32,
+                };
+                let (vd, vn) = (NeonRegister::try_from(*vd)?, NeonRegister::try_from(*vn)?);
+                let scalar = Scalar::try_from(*scalar)?.check(et.size)?;
+                let in_range = et.size == 32 || scalar.reg < 8;
+                if !valid_type || !in_range || vd.is_quad() != vn.is_quad() {
+                    return Err(bad_operands().into());
+                }
+                Self::ByScalar(mnemonic, et, vd, vn, scalar)
+            }
+            (
+                NeonMnemonic::VABS
+                | NeonMnemonic::VNEG
+                | NeonMnemonic::VMVN
+                | NeonMnemonic::VMOVN
+                | NeonMnemonic::VQMOVN
+                | NeonMnemonic::VQMOVUN
+                | NeonMnemonic::VCNT
+                | NeonMnemonic::VREV16
+                | NeonMnemonic::VREV32
+                | NeonMnemonic::VREV64
+                | NeonMnemonic::VSWP
+                | NeonMnemonic::VRECPE
+                | NeonMnemonic::VRSQRTE
+                | NeonMnemonic::VZIP
+                | NeonMnemonic::VUZP
+                | NeonMnemonic::VTRN,
+                [vd, vm],
+            ) => {
+                let (vd, vm) = (NeonRegister::try_from(*vd)?, NeonRegister::try_from(*vm)?);
+                let et = match mnemonic {
+                    NeonMnemonic::VMVN | NeonMnemonic::VSWP => et.unwrap_or(untyped),
+                    _ => et.ok_or_else(bad_types)?,
+                };
+                let valid = match mnemonic {
+                    NeonMnemonic::VABS | NeonMnemonic::VNEG => {
+                        et.kind == ElementKind::Float
+                            || (matches!(et.kind, ElementKind::Signed) && et.size < 64)
+                    }
+                    NeonMnemonic::VMVN => vd.is_quad() == vm.is_quad(),
+                    NeonMnemonic::VMOVN => {
+                        et.is_integer() && et.size > 8 && !vd.is_quad() && vm.is_quad()
+                    }
+                    NeonMnemonic::VQMOVN | NeonMnemonic::VQMOVUN => {
+                        let signed = et.kind == ElementKind::Signed;
+                        let valid_type = signed || mnemonic == NeonMnemonic::VQMOVN;
+                        valid_type
+                            && et.is_signed_or_unsigned()
+                            && et.size > 8
+                            && !vd.is_quad()
+                            && vm.is_quad()
+                    }
+                    NeonMnemonic::VCNT => et.is_integer() && et.size == 8,
+                    // Reversing within regions of 16, 32 or 64 bits
+                    NeonMnemonic::VREV16 => et.size == 8,
+                    NeonMnemonic::VREV32 => et.size < 32,
+                    NeonMnemonic::VREV64 => et.size < 64,
+                    NeonMnemonic::VSWP => true,
+                    NeonMnemonic::VRECPE | NeonMnemonic::VRSQRTE => {
+                        matches!(et.kind, ElementKind::Float | ElementKind::Unsigned)
+                            && et.size == 32
+                    }
+                    // Two 32-bit elements in a double register can only be transposed
+                    NeonMnemonic::VZIP | NeonMnemonic::VUZP => {
+                        et.kind != ElementKind::Float
+                            && (et.size < 32 || (et.size == 32 && vd.is_quad()))
+                    }
+                    _ => et.kind != ElementKind::Float && et.size < 64,
+                };
+                let narrowing = matches!(
+                    mnemonic,
+                    NeonMnemonic::VMOVN | NeonMnemonic::VQMOVN | NeonMnemonic::VQMOVUN
+                );
+                let same_width = narrowing || vd.is_quad() == vm.is_quad();
+                if !valid || !same_width {
+                    return Err(bad_operands().into());
+                }
+                Self::TwoMisc(mnemonic, et, vd, vm)
+            }
+            (
+                NeonMnemonic::VADDL
+                | NeonMnemonic::VADDW
+                | NeonMnemonic::VSUBL
+                | NeonMnemonic::VSUBW
+                | NeonMnemonic::VMLAL
+                | NeonMnemonic::VMLSL
+                | NeonMnemonic::VMULL,
+                [vd, vn, vm],
+            ) => {
+                let et = et.ok_or_else(bad_types)?;
+                let valid_type = match et.kind {
+                    ElementKind::Polynomial => mnemonic == NeonMnemonic::VMULL,
+                    _ => et.is_signed_or_unsigned() && et.size < 64,
+                };
+                let (vd, vn, vm) = (
+                    NeonRegister::try_from(*vd)?,
+                    NeonRegister::try_from(*vn)?,
+                    NeonRegister::try_from(*vm)?,
+                );
+                let wide = matches!(mnemonic, NeonMnemonic::VADDW | NeonMnemonic::VSUBW);
+                if !valid_type || !vd.is_quad() || vn.is_quad() != wide || vm.is_quad() {
+                    return Err(bad_operands().into());
+                }
+                Self::ThreeDifferent(mnemonic, et, vd, vn, vm)
+            }
+            (_, [_, _] | [_, _, _]) => {
+                // The two operand form uses Vd as the first source
+                let (vd, vn, vm) = match operands.len() {
+                    2 => (reg(0)?, reg(0)?, reg(1)?),
+                    _ => (reg(0)?, reg(1)?, reg(2)?),
+                };
+                // The pairwise operations only work on double registers
+                let pairwise = matches!(
+                    mnemonic,
+                    NeonMnemonic::VPADD | NeonMnemonic::VPMAX | NeonMnemonic::VPMIN
+                );
+                if vd.is_quad() != vn.is_quad()
+                    || vd.is_quad() != vm.is_quad()
+                    || (pairwise && vd.is_quad())
+                {
+                    return Err(bad_operands().into());
+                }
+                let et = et.unwrap_or(untyped);
+                let instruction = Self::ThreeSame(mnemonic, et, vd, vn, vm);
+                instruction.three_same_bits().ok_or_else(bad_types)?;
+                instruction
+            }
+            _ => return Err(bad_operands().into()),
+        };
+
+        Ok(instruction)
+    }
+
+    /// Parse `vcvt.<to>.<from> Vd, Vm{, #fbits}` between 32-bit floats and signed or unsigned
+    /// integers, or fixed point numbers with `fbits` fraction bits.
+    fn parse_convert(types: &[ElementType], operands: &[&str]) -> Result<Self, AssemblerError> {
+        let bad_operands = || ParseError::BadFlexOperand(operands.join(", "));
+        let bad_types = || {
+            let names: Vec<String> = types.iter().map(ElementType::to_string).collect();
+            ParseError::BadDataType(names.join("."))
+        };
+        let is_integer = |et: ElementType| et.is_signed_or_unsigned() && et.size == 32;
+        let op = match *types {
+            [to, from] if to.kind == ElementKind::Float && is_integer(from) => {
+                u8::from(from.is_unsigned())
+            }
+            [to, from] if is_integer(to) && from.kind == ElementKind::Float => {
+                0b10 | u8::from(to.is_unsigned())
+            }
+            _ => return Err(bad_types().into()),
+        };
+
+        let (vd, vm, fbits) = match *operands {
+            [vd, vm] => (vd, vm, 0),
+            [vd, vm, fbits] => (vd, vm, parse_bounded_immediate(fbits, 32)?),
+            _ => return Err(bad_operands().into()),
+        };
+        let (vd, vm) = (NeonRegister::try_from(vd)?, NeonRegister::try_from(vm)?);
+        if vd.is_quad() != vm.is_quad() || (operands.len() == 3 && fbits == 0) {
+            return Err(bad_operands().into());
+        }
+
+        Ok(Self::Convert(vd, vm, op, fbits as u8))
+    }
+
+    fn parse_shift(
+        mnemonic: NeonMnemonic,
+        et: ElementType,
+        vd: NeonRegister,
+        vm: NeonRegister,
+        shift: &str,
+    ) -> Result<Self, AssemblerError> {
+        let bad_operands = || ParseError::BadFlexOperand(format!("{vd:?}, {vm:?}, {shift}"));
+        let size = u32::from(et.size);
+        let (min, max) = match mnemonic {
+            NeonMnemonic::VSHL | NeonMnemonic::VSLI => (0, size - 1),
+            NeonMnemonic::VSHRN | NeonMnemonic::VRSHRN => (1, size / 2),
+            NeonMnemonic::VSHLL => (1, size - 1),
+            _ => (1, size),
+        };
+        let amount = parse_bounded_immediate(shift, max)?;
+
+        let valid_type = match mnemonic {
+            NeonMnemonic::VSHR | NeonMnemonic::VSRA | NeonMnemonic::VRSHR | NeonMnemonic::VRSRA => {
+                et.is_signed_or_unsigned()
+            }
+            NeonMnemonic::VSHRN | NeonMnemonic::VRSHRN => et.is_integer() && et.size > 8,
+            NeonMnemonic::VSHLL => et.is_signed_or_unsigned() && et.size < 64,
+            _ => et.is_integer(),
+        };
+        let widths = match mnemonic {
+            NeonMnemonic::VSHRN | NeonMnemonic::VRSHRN => !vd.is_quad() && vm.is_quad(),
+            NeonMnemonic::VSHLL => vd.is_quad() && !vm.is_quad(),
+            _ => vd.is_quad() == vm.is_quad(),
+        };
+        if amount < min || !valid_type || !widths {
+            return Err(bad_operands().into());
+        }
+
+        Ok(Self::ShiftImmediate(mnemonic, et, vd, vm, amount as u8))
+    }
+
+    fn parse_load_store(
+        mnemonic: NeonMnemonic,
+        n: u8,
+        et: ElementType,
+        operands: &[&str],
+    ) -> Result<Self, AssemblerError> {
+        let bad_operands = || ParseError::BadFlexOperand(operands.join(", "));
+        let (list, address, rm) = match *operands {
+            [list, address] => (list, address, None),
+            [list, address, rm] => (list, address, Some(rm)),
+            _ => return Err(bad_operands().into()),
+        };
+        let (rn, align, rm) = Self::parse_structure_address(address, rm)?;
+        if list.contains('[') {
+            return Self::parse_lanes(mnemonic, n, et, list, rn, align, rm);
+        }
+
+        let regs = parse_neon_reg_list(list)?;
+        let spacing = match regs.as_slice() {
+            [first, second, ..] => second.wrapping_sub(*first),
+            _ => 1,
+        };
+        let evenly_spaced = regs
+            .windows(2)
+            .all(|pair| pair[1] == pair[0].wrapping_add(spacing));
+        let list_type = match (n, regs.len(), spacing) {
+            (1, 1, _) => 0b0111,
+            (1, 2, 1) => 0b1010,
+            (1, 3, 1) => 0b0110,
+            (1, 4, 1) => 0b0010,
+            (2, 2, 1) => 0b1000,
+            (2, 2, 2) => 0b1001,
+            (2, 4, 1) => 0b0011,
+            (3, 3, 1) => 0b0100,
+            (3, 3, 2) => 0b0101,
+            (4, 4, 1) => 0b0000,
+            (4, 4, 2) => 0b0001,
+            _ => return Err(bad_operands().into()),
+        };
+        let last = regs.last().copied().unwrap_or_default();
+        if !evenly_spaced || last > 31 || (et.size == 64 && n != 1) {
+            return Err(bad_operands().into());
+        }
+
+        // The widest alignment each list allows
+        let max_align = match (n, regs.len()) {
+            (1, 2) | (2, 2) => 128,
+            (1, 4) | (2, 4) | (4, _) => 256,
+            _ => 64,
+        };
+        let align = match align {
+            None => 0,
+            Some(64) => 1,
+            Some(128) if max_align >= 128 => 2,
+            Some(256) if max_align >= 256 => 3,
+            Some(_) => return Err(bad_operands().into()),
+        };
+
+        Ok(Self::LoadStore(
+            mnemonic,
+            et,
+            NeonRegister::Double(regs[0]),
+            list_type,
+            rn,
+            align,
+            rm,
+        ))
+    }
+
+    /// Parse the `[Rn{:align}]{!}` address and optional Rm of a structure load or store, giving
+    /// Rm as sp for `!` and pc for neither.
+    fn parse_structure_address(
+        address: &str,
+        rm: Option<&str>,
+    ) -> Result<(Rn, Option<u32>, Rm), AssemblerError> {
+        let bad_address = || ParseError::BadFlexOperand(address.to_owned());
+        let (inner, writeback) = match address.trim().strip_suffix('!') {
+            Some(address) => (address, true),
+            None => (address, false),
+        };
+        let inner = inner
+            .trim()
+            .strip_prefix('[')
+            .and_then(|address| address.strip_suffix(']'))
+            .ok_or_else(bad_address)?;
+        let (base, align) = match inner.split_once(':') {
+            Some((base, align)) => {
+                let align = align.trim().parse::<u32>().map_err(|_| bad_address())?;
+                (base, Some(align))
+            }
+            None => (inner, None),
+        };
+
+        let rm = match (writeback, rm) {
+            (false, None) => 15,
+            (true, None) => 13,
+            (false, Some(rm)) => match parse_reg_id(rm)? {
+                13 | 15 => return Err(bad_address().into()),
+                rm => rm,
+            },
+            (true, Some(_)) => return Err(bad_address().into()),
+        };
+
+        Ok((Rn(parse_reg_id(base.trim())?), align, Rm(rm)))
+    }
+
+    /// Parse the single lane and all lanes forms, whose list names one lane of each register.
+    fn parse_lanes(
+        mnemonic: NeonMnemonic,
+        n: u8,
+        et: ElementType,
+        list: &str,
+        rn: Rn,
+        align: Option<u32>,
+        rm: Rm,
+    ) -> Result<Self, AssemblerError> {
+        let bad_list = || ParseError::BadRegister(list.to_owned());
+        let (regs, lane) = parse_lane_list(list)?;
+        let spacing = match regs.as_slice() {
+            [first, second, ..] => second.wrapping_sub(*first),
+            _ => 1,
+        };
+        let evenly_spaced = regs
+            .windows(2)
+            .all(|pair| pair[1] == pair[0].wrapping_add(spacing));
+        let last = regs.last().copied().unwrap_or_default();
+        if !evenly_spaced || !(1..=2).contains(&spacing) || last > 31 || et.size == 64 {
+            return Err(bad_list().into());
+        }
+        let (size, n32) = (u32::from(et.size), u32::from(n));
+        let first = NeonRegister::Double(regs[0]);
+
+        let Some(lane) = lane else {
+            // vld1 may fill one or two registers, the others one per structure element
+            let load = mnemonic.structure().is_some_and(|(_, load)| load);
+            if !load {
+                return Err(bad_list().into());
+            }
+            let t = match (n, regs.len(), spacing) {
+                (1, 1 | 2, 1) => regs.len() - 1,
+                (_, len, _) if n > 1 && len == usize::from(n) => usize::from(spacing - 1),
+                _ => return Err(bad_list().into()),
+            };
+            // Four 32-bit elements aligned to 128 bits borrow the unused size 64
+            let (size_field, a) = match align {
+                None => (et.size_field(), 0),
+                Some(64) if n == 4 && size == 32 => (et.size_field(), 1),
+                Some(128) if n == 4 && size == 32 => (0b11, 1),
+                Some(align) if n != 3 && size * n32 > 8 && align == size * n32 => {
+                    (et.size_field(), 1)
+                }
+                Some(_) => return Err(bad_list().into()),
+            };
+            return Ok(Self::LoadAllLanes(
+                mnemonic,
+                size_field as u8,
+                first,
+                ((t as u8) << 1) | a,
+                rn,
+                rm,
+            ));
+        };
+
+        // The lane sits above the register spacing and alignment bits
+        let lane = u32::from(lane);
+        if regs.len() != usize::from(n) || lane >= 64 / size || (spacing == 2 && size == 8) {
+            return Err(bad_list().into());
+        }
+        let align = match (n, align) {
+            (_, None) => 0,
+            (1, Some(16)) if size == 16 => 0b01,
+            (1, Some(32)) if size == 32 => 0b11,
+            (2, Some(align)) if align == 2 * size => 0b01,
+            (4, Some(64)) if size == 32 => 0b01,
+            (4, Some(128)) if size == 32 => 0b10,
+            (4, Some(align)) if align == 4 * size => 0b01,
+            _ => return Err(bad_list().into()),
+        };
+        let size_field = et.size_field();
+        let index_align =
+            (lane << (size_field + 1)) | (u32::from(spacing - 1) << size_field) | align;
+
+        Ok(Self::LoadStoreLane(
+            mnemonic,
+            et,
+            first,
+            index_align as u8,
+            rn,
+            rm,
+        ))
+    }
+
+    /// The `U` bit, `opc` field, `B` bit and size field of a three register same length
+    /// instruction, or `None` if the data type doesn't suit the operation.
+    fn three_same_bits(&self) -> Option<(u32, u32, u32, u32)> {
+        let Self::ThreeSame(mnemonic, et, ..) = *self else {
+            return None;
+        };
+        let unsigned = u32::from(et.is_unsigned());
+        let size = et.size_field();
+
+        if et.kind == ElementKind::Float {
+            // Bit 21 selects between pairs of operations, bit 20 is the precision
+            let (u, opc, b, op) = match mnemonic {
+                NeonMnemonic::VADD => (0, 0b1101, 0, 0),
+                NeonMnemonic::VSUB => (0, 0b1101, 0, 1),
+                NeonMnemonic::VABD => (1, 0b1101, 0, 1),
+                NeonMnemonic::VMLA => (0, 0b1101, 1, 0),
+                NeonMnemonic::VMLS => (0, 0b1101, 1, 1),
+                NeonMnemonic::VMUL => (1, 0b1101, 1, 0),
+                NeonMnemonic::VCEQ => (0, 0b1110, 0, 0),
+                NeonMnemonic::VCGE => (1, 0b1110, 0, 0),
+                NeonMnemonic::VCGT => (1, 0b1110, 0, 1),
+                NeonMnemonic::VMAX => (0, 0b1111, 0, 0),
+                NeonMnemonic::VMIN => (0, 0b1111, 0, 1),
+                NeonMnemonic::VPADD => (1, 0b1101, 0, 0),
+                NeonMnemonic::VPMAX => (1, 0b1111, 0, 0),
+                NeonMnemonic::VPMIN => (1, 0b1111, 0, 1),
+                _ => return None,
+            };
+            return Some((u, opc, b, op << 1));
+        }
+
+        // The bitwise operations use the size field to pick the operation
+        let bitwise = match mnemonic {
+            NeonMnemonic::VAND => Some((0, 0b00)),
+            NeonMnemonic::VBIC => Some((0, 0b01)),
+            NeonMnemonic::VORR => Some((0, 0b10)),
+            NeonMnemonic::VORN => Some((0, 0b11)),
+            NeonMnemonic::VEOR => Some((1, 0b00)),
+            NeonMnemonic::VBSL => Some((1, 0b01)),
+            NeonMnemonic::VBIT => Some((1, 0b10)),
+            NeonMnemonic::VBIF => Some((1, 0b11)),
+            _ => None,
+        };
+        if let Some((u, size)) = bitwise {
+            return et.is_integer().then_some((u, 0b0001, 1, size));
+        }
+
+        if et.kind == ElementKind::Polynomial {
+            return (mnemonic == NeonMnemonic::VMUL).then_some((1, 0b1001, 1, 0));
+        }
+
+        // Operations that depend on the sign, then those that don't
+        let (u, opc, b, allow_64) = match mnemonic {
+            NeonMnemonic::VHADD => (unsigned, 0b0000, 0, false),
+            NeonMnemonic::VQADD => (unsigned, 0b0000, 1, true),
+            NeonMnemonic::VRHADD => (unsigned, 0b0001, 0, false),
+            NeonMnemonic::VHSUB => (unsigned, 0b0010, 0, false),
+            NeonMnemonic::VQSUB => (unsigned, 0b0010, 1, true),
+            NeonMnemonic::VCGT => (unsigned, 0b0011, 0, false),
+            NeonMnemonic::VCGE => (unsigned, 0b0011, 1, false),
+            NeonMnemonic::VMAX => (unsigned, 0b0110, 0, false),
+            NeonMnemonic::VMIN => (unsigned, 0b0110, 1, false),
+            NeonMnemonic::VABD => (unsigned, 0b0111, 0, false),
+            NeonMnemonic::VABA => (unsigned, 0b0111, 1, false),
+            NeonMnemonic::VADD => (0, 0b1000, 0, true),
+            NeonMnemonic::VSUB => (1, 0b1000, 0, true),
+            NeonMnemonic::VTST => (0, 0b1000, 1, false),
+            NeonMnemonic::VCEQ => (1, 0b1000, 1, false),
+            NeonMnemonic::VMLA => (0, 0b1001, 0, false),
+            NeonMnemonic::VMLS => (1, 0b1001, 0, false),
+            NeonMnemonic::VMUL => (0, 0b1001, 1, false),
+            NeonMnemonic::VPMAX => (unsigned, 0b1010, 0, false),
+            NeonMnemonic::VPMIN => (unsigned, 0b1010, 1, false),
+            NeonMnemonic::VPADD => (0, 0b1011, 1, false),
+            _ => return None,
+        };
+        let needs_sign = opc < 0b1000 || opc == 0b1010;
+        let valid = if needs_sign {
+            et.is_signed_or_unsigned()
+        } else {
+            et.is_integer() && et.kind != ElementKind::Untyped
+        };
+        if !valid || (et.size == 64 && !allow_64) {
+            return None;
+        }
+
+        Some((u, opc, b, size))
+    }
+
+    /// The feature needed: NEON, except for 32-bit scalar moves which VFP already provides.
+    pub fn required_feature(&self) -> Feature {
+        match self {
+            Self::ScalarToCore(et, ..) | Self::CoreToScalar(et, ..) if et.size == 32 => {
+                Feature::Vfpv2
+            }
+            _ => Feature::Neon,
+        }
+    }
+
+    /// The ARM encoding, in which `cond` only applies to the core register transfers.
+    pub fn to_machine_code(&self, cond: Cond) -> u32 {
+        match *self {
+            Self::ThreeSame(_, _, vd, vn, vm) => {
+                let (u, opc, b, size) = self.three_same_bits().unwrap_or_default();
+                0xF200_0000
+                    | (u << 24)
+                    | (size << 20)
+                    | (opc << 8)
+                    | (b << 4)
+                    | vd.q()
+                    | vd.vd()
+                    | vn.vn()
+                    | vm.vm()
+            }
+            Self::ByScalar(mnemonic, et, vd, vn, scalar) => {
+                let op = match mnemonic {
+                    NeonMnemonic::VMLA => 0b0000,
+                    NeonMnemonic::VMLS => 0b0100,
+                    _ => 0b1000,
+                };
+                let float = u32::from(et.kind == ElementKind::Float);
+                0xF280_0040
+                    | (u32::from(vd.is_quad()) << 24)
+                    | (et.size_field() << 20)
+                    | ((op | float) << 8)
+                    | vd.vd()
+                    | vn.vn()
+                    | scalar.vm(et.size)
+            }
+            Self::ThreeDifferent(mnemonic, et, vd, vn, vm) => {
+                let opc = match mnemonic {
+                    NeonMnemonic::VADDL => 0b0000,
+                    NeonMnemonic::VADDW => 0b0001,
+                    NeonMnemonic::VSUBL => 0b0010,
+                    NeonMnemonic::VSUBW => 0b0011,
+                    NeonMnemonic::VMLAL => 0b1000,
+                    NeonMnemonic::VMLSL => 0b1010,
+                    _ if et.kind == ElementKind::Polynomial => 0b1110,
+                    _ => 0b1100,
+                };
+                0xF280_0000
+                    | (u32::from(et.is_unsigned()) << 24)
+                    | (et.size_field() << 20)
+                    | (opc << 8)
+                    | vd.vd()
+                    | vn.vn()
+                    | vm.vm()
+            }
+            Self::TwoMisc(mnemonic, et, vd, vm) => {
+                let float = u32::from(et.kind == ElementKind::Float);
+                // The A field in bits 17-16 and B in bits 10-7, with Q in bit 6
+                let (a, b, size) = match mnemonic {
+                    NeonMnemonic::VREV64 => (0b00, 0b0000, et.size_field()),
+                    NeonMnemonic::VREV32 => (0b00, 0b0001, et.size_field()),
+                    NeonMnemonic::VREV16 => (0b00, 0b0010, et.size_field()),
+                    NeonMnemonic::VCNT => (0b00, 0b1010, 0),
+                    NeonMnemonic::VMVN => (0b00, 0b1011, 0),
+                    NeonMnemonic::VABS => (0b01, (float << 3) | 0b0110, et.size_field()),
+                    NeonMnemonic::VNEG => (0b01, (float << 3) | 0b0111, et.size_field()),
+                    NeonMnemonic::VSWP => (0b10, 0b0000, 0),
+                    NeonMnemonic::VTRN => (0b10, 0b0001, et.size_field()),
+                    NeonMnemonic::VUZP => (0b10, 0b0010, et.size_field()),
+                    NeonMnemonic::VZIP => (0b10, 0b0011, et.size_field()),
+                    NeonMnemonic::VQMOVN => (0b10, 0b0101, et.size_field() - 1),
+                    NeonMnemonic::VRECPE => (0b11, 0b1000 | (float << 1), 0b10),
+                    NeonMnemonic::VRSQRTE => (0b11, 0b1001 | (float << 1), 0b10),
+                    _ => (0b10, 0b0100, et.size_field() - 1),
+                };
+                // The narrowing moves read a quad register, so bit 6 instead picks the
+                // saturation: none, unsigned from signed, or the same as the source
+                let q = match mnemonic {
+                    NeonMnemonic::VMOVN => 0,
+                    NeonMnemonic::VQMOVUN => 1 << 6,
+                    NeonMnemonic::VQMOVN => u32::from(et.is_unsigned()) << 6,
+                    _ => vm.q(),
+                };
+                0xF3B0_0000 | (size << 18) | (a << 16) | (b << 7) | q | vd.vd() | vm.vm()
+            }
+            Self::Convert(vd, vm, op, 0) => {
+                0xF3BB_0600 | (u32::from(op) << 7) | vm.q() | vd.vd() | vm.vm()
+            }
+            // The fixed point form is a shift by 64 - fbits with the sign in U
+            Self::Convert(vd, vm, op, fbits) => {
+                let op = u32::from(op);
+                0xF280_0E10
+                    | ((op & 1) << 24)
+                    | ((64 - u32::from(fbits)) << 16)
+                    | ((op >> 1) << 8)
+                    | vm.q()
+                    | vd.vd()
+                    | vm.vm()
+            }
+            Self::ShiftImmediate(mnemonic, et, vd, vm, shift) => {
+                let (size, shift) = (u32::from(et.size), u32::from(shift));
+                let (u, opc, b) = match mnemonic {
+                    NeonMnemonic::VSHR => (u32::from(et.is_unsigned()), 0b0000, 0),
+                    NeonMnemonic::VSRA => (u32::from(et.is_unsigned()), 0b0001, 0),
+                    NeonMnemonic::VRSHR => (u32::from(et.is_unsigned()), 0b0010, 0),
+                    NeonMnemonic::VRSRA => (u32::from(et.is_unsigned()), 0b0011, 0),
+                    NeonMnemonic::VSRI => (1, 0b0100, 0),
+                    NeonMnemonic::VSHL => (0, 0b0101, 0),
+                    NeonMnemonic::VSLI => (1, 0b0101, 0),
+                    NeonMnemonic::VSHRN => (0, 0b1000, 0),
+                    NeonMnemonic::VRSHRN => (0, 0b1000, 1),
+                    _ => (u32::from(et.is_unsigned()), 0b1010, 0),
+                };
+                // L:imm6 holds the element size plus a left shift, or twice the element size
+                // minus a right shift. Narrowing shifts name the wider source elements.
+                let imm7 = match mnemonic {
+                    NeonMnemonic::VSHL | NeonMnemonic::VSLI | NeonMnemonic::VSHLL => size + shift,
+                    NeonMnemonic::VSHRN | NeonMnemonic::VRSHRN => size - shift,
+                    _ => 2 * size - shift,
+                };
+                let q = match mnemonic {
+                    NeonMnemonic::VSHRN | NeonMnemonic::VRSHRN | NeonMnemonic::VSHLL => 0,
+                    _ => vd.q(),
+                };
+                0xF280_0010
+                    | (u << 24)
+                    | ((imm7 & 0x3F) << 16)
+                    | (opc << 8)
+                    | ((imm7 >> 6) << 7)
+                    | (b << 6)
+                    | q
+                    | vd.vd()
+                    | vm.vm()
+            }
+            Self::ModifiedImmediate(vd, cmode, op, imm8) => {
+                let imm8 = u32::from(imm8);
+                0xF280_0010
+                    | ((imm8 >> 7) << 24)
+                    | (((imm8 >> 4) & 0b111) << 16)
+                    | (u32::from(cmode) << 8)
+                    | (u32::from(op) << 5)
+                    | (imm8 & 0xF)
+                    | vd.q()
+                    | vd.vd()
+            }
+            Self::DupCore(et, vd, rt) => {
+                let (b, e) = match et.size {
+                    8 => (1, 0),
+                    16 => (0, 1),
+                    _ => (0, 0),
+                };
+                ((cond as u32) << 28)
+                    | 0x0E80_0B10
+                    | (b << 22)
+                    | (u32::from(vd.is_quad()) << 21)
+                    | (u32::from(rt.0) << 12)
+                    | (e << 5)
+                    | vd.vn()
+            }
+            Self::DupScalar(et, vd, scalar) => {
+                // The lowest set bit of imm4 gives the size, the bits above it the index
+                let size_bit = u32::from(et.size / 8);
+                let imm4 = size_bit | (u32::from(scalar.index) * 2 * size_bit);
+                0xF3B0_0C00 | (imm4 << 16) | vd.q() | vd.vd() | VfpRegister::Double(scalar.reg).vm()
+            }
+            Self::ScalarToCore(et, rt, scalar) => {
+                let (opc1, opc2) = Self::scalar_opcodes(et, scalar);
+                let unsigned = u32::from(et.is_unsigned() && et.size < 32);
+                ((cond as u32) << 28)
+                    | 0x0E10_0B10
+                    | (unsigned << 23)
+                    | (opc1 << 21)
+                    | (u32::from(rt.0) << 12)
+                    | (opc2 << 5)
+                    | VfpRegister::Double(scalar.reg).vn()
+            }
+            Self::CoreToScalar(et, scalar, rt) => {
+                let (opc1, opc2) = Self::scalar_opcodes(et, scalar);
+                ((cond as u32) << 28)
+                    | 0x0E00_0B10
+                    | (opc1 << 21)
+                    | (u32::from(rt.0) << 12)
+                    | (opc2 << 5)
+                    | VfpRegister::Double(scalar.reg).vn()
+            }
+            Self::Extract(vd, vn, vm, index) => {
+                0xF2B0_0000 | (u32::from(index) << 8) | vd.q() | vd.vd() | vn.vn() | vm.vm()
+            }
+            Self::TableLookup(mnemonic, vd, first, len, vm) => {
+                let extension = u32::from(mnemonic == NeonMnemonic::VTBX);
+                0xF3B0_0800
+                    | (u32::from(len - 1) << 8)
+                    | (extension << 6)
+                    | vd.vd()
+                    | first.vn()
+                    | vm.vm()
+            }
+            Self::LoadStore(mnemonic, et, first, list_type, rn, align, rm) => {
+                let load = mnemonic.structure().is_some_and(|(_, load)| load);
+                0xF400_0000
+                    | (u32::from(load) << 21)
+                    | (u32::from(rn.0) << 16)
+                    | (u32::from(list_type) << 8)
+                    | (et.size_field() << 6)
+                    | (u32::from(align) << 4)
+                    | u32::from(rm.0)
+                    | first.vd()
+            }
+            Self::LoadStoreLane(mnemonic, et, first, index_align, rn, rm) => {
+                let (n, load) = mnemonic.structure().unwrap_or_default();
+                0xF480_0000
+                    | (u32::from(load) << 21)
+                    | (u32::from(rn.0) << 16)
+                    | (et.size_field() << 10)
+                    | (u32::from(n - 1) << 8)
+                    | (u32::from(index_align) << 4)
+                    | u32::from(rm.0)
+                    | first.vd()
+            }
+            Self::LoadAllLanes(mnemonic, size, first, t_a, rn, rm) => {
+                let (n, _) = mnemonic.structure().unwrap_or_default();
+                0xF4A0_0C00
+                    | (u32::from(rn.0) << 16)
+                    | (u32::from(n - 1) << 8)
+                    | (u32::from(size) << 6)
+                    | (u32::from(t_a) << 4)
+                    | u32::from(rm.0)
+                    | first.vd()
+            }
+        }
+    }
+
+    /// The `opc1` and `opc2` fields giving the size and index of a core register transfer.
+    fn scalar_opcodes(et: ElementType, scalar: Scalar) -> (u32, u32) {
+        let index = u32::from(scalar.index);
+        match et.size {
+            8 => (0b10 | (index >> 2), index & 0b11),
+            16 => (index >> 1, ((index & 1) << 1) | 1),
+            _ => (index, 0),
+        }
+    }
+
+    /// The Thumb encoding: data processing moves the `U` bit from bit 24 to 28 behind an `0xEF`
+    /// prefix, loads and stores swap `0xF4` for `0xF9`, and core transfers use the `al` form.
+    pub fn to_thumb_machine_code(&self) -> u32 {
+        let arm = self.to_machine_code(Cond::AL);
+        match arm >> 24 {
+            0xF2 | 0xF3 => 0xEF00_0000 | (((arm >> 24) & 1) << 28) | (arm & 0x00FF_FFFF),
+            0xF4 => 0xF900_0000 | (arm & 0x00FF_FFFF),
+            _ => arm,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{encode_simd_immediate, ElementKind, ElementType, NeonInstruction};
+
+    #[test]
+    fn test_simd_immediate() {
+        let i32 = ElementType {
+            kind: ElementKind::Integer,
+            size: 32,
+        };
+        assert_eq!(
+            encode_simd_immediate(0xAB00, i32, false),
+            Some((0b0010, 0, 0xAB))
+        );
+        assert_eq!(
+            encode_simd_immediate(0xABFF, i32, false),
+            Some((0b1100, 0, 0xAB))
+        );
+        assert_eq!(encode_simd_immediate(0x1234, i32, false), None);
+
+        let i64 = ElementType {
+            kind: ElementKind::Integer,
+            size: 64,
+        };
+        assert_eq!(
+            encode_simd_immediate(0xFF00_0000_0000_00FF, i64, false),
+            Some((0b1110, 1, 0x81))
+        );
+    }
+
+    #[test]
+    fn test_neon_encodings() {
+        // Checked against llvm-mc -triple=armv7a -mattr=+neon
+        let cases = [
+            ("vadd.i32 q0, q1, q2", 0xF222_0844),
+            ("vadd.f32 d0, d1, d2", 0xF201_0D02),
+            ("vsub.i64 d16, d17, d18", 0xF371_08A2),
+            ("vmul.f32 q8, q9, q10", 0xF342_0DF4),
+            ("vmul.p8 d0, d1, d2", 0xF301_0912),
+            ("vmla.i16 d0, d1, d2", 0xF211_0902),
+            ("vqadd.u8 q0, q1, q2", 0xF302_0054),
+            ("vhadd.s16 d0, d1, d2", 0xF211_0002),
+            ("vmax.s32 d0, d1, d2", 0xF221_0602),
+            ("vmin.f32 q0, q1, q2", 0xF222_0F44),
+            ("vabd.u16 d0, d1, d2", 0xF311_0702),
+            ("vceq.i8 d0, d1, d2", 0xF301_0812),
+            ("vcgt.f32 d0, d1, d2", 0xF321_0E02),
+            ("vand q0, q1, q2", 0xF202_0154),
+            ("veor d0, d1, d2", 0xF301_0112),
+            ("vbsl q0, q1, q2", 0xF312_0154),
+            ("vmov q0, q1", 0xF222_0152),
+            ("vmul.f32 q0, q1, d2[1]", 0xF3A2_0962),
+            ("vmla.i16 d0, d1, d2[3]", 0xF291_006A),
+            ("vmls.i32 q0, q1, d15[0]", 0xF3A2_044F),
+            ("vmov.i32 q0, #0", 0xF280_0050),
+            ("vmov.i8 d0, #0xff", 0xF387_0E1F),
+            ("vmov.i16 q1, #0x1200", 0xF281_2A52),
+            ("vmov.i32 d0, #0xffffff00", 0xF387_003F),
+            ("vmvn.i32 d0, #0x12ffff", 0xF281_0D32),
+            ("vmov.i64 d0, #0xff0000ff", 0xF280_0E39),
+            ("vmov.i64 q1, #0xffffffffffffffff", 0xF387_2E7F),
+            ("vmov.i64 d2, #0xff00ff00ff00ff00", 0xF382_2E3A),
+            ("vmov.f32 q0, #1.0", 0xF287_0F50),
+            ("vdup.32 q0, r1", 0xEEA0_1B10),
+            ("vdup.8 d17, r2", 0xEEC1_2B90),
+            ("vdupne.16 d0, r3", 0x1E80_3B30),
+            ("vdup.16 q0, d1[3]", 0xF3BE_0C41),
+            ("vmov.32 r0, d1[1]", 0xEE31_0B10),
+            ("vmov.u8 r0, d1[7]", 0xEEF1_0B70),
+            ("vmov.s16 r0, d1[2]", 0xEE31_0B30),
+            ("vmov.8 d1[5], r0", 0xEE61_0B30),
+            ("vext.8 q0, q1, q2, #3", 0xF2B2_0344),
+            ("vext.32 d0, d1, d2, #1", 0xF2B1_0402),
+            ("vtbl.8 d0, {d1, d2}, d3", 0xF3B1_0903),
+            ("vtbx.8 d0, {d1-d4}, d5", 0xF3B1_0B45),
+            ("vzip.16 q0, q1", 0xF3B6_01C2),
+            ("vuzp.8 d0, d1", 0xF3B2_0101),
+            ("vtrn.32 d0, d1", 0xF3BA_0081),
+            ("vmovn.i32 d0, q1", 0xF3B6_0202),
+            ("vmovl.u8 q0, d1", 0xF388_0A11),
+            ("vmovl.s16 q1, d2", 0xF290_2A12),
+            ("vabs.s8 d0, d1", 0xF3B1_0301),
+            ("vneg.f32 q0, q1", 0xF3B9_07C2),
+            ("vmvn q0, q1", 0xF3B0_05C2),
+            ("vshr.s32 q0, q1, #5", 0xF2BB_0052),
+            ("vshr.u64 d0, d1, #64", 0xF380_0091),
+            ("vshl.i16 d0, d1, #3", 0xF293_0511),
+            ("vsra.u8 d0, d1, #1", 0xF38F_0111),
+            ("vrshr.s16 q0, q1, #16", 0xF290_0252),
+            ("vsri.32 d0, d1, #8", 0xF3B8_0411),
+            ("vsli.64 q0, q1, #63", 0xF3BF_05D2),
+            ("vshrn.i32 d0, q1, #16", 0xF290_0812),
+            ("vrshrn.i16 d0, q1, #1", 0xF28F_0852),
+            ("vshll.s8 q0, d1, #3", 0xF28B_0A11),
+            ("vld1.8 {d0}, [r0]", 0xF420_070F),
+            ("vld1.32 {d0-d1}, [r1:128]!", 0xF421_0AAD),
+            ("vld1.64 {d0-d3}, [r2:256], r3", 0xF422_02F3),
+            ("vst1.16 {q8}, [r0]", 0xF440_0A4F),
+            ("vld2.8 {d0, d2}, [r0]", 0xF420_090F),
+            ("vld2.16 {q0, q1}, [r0:64]", 0xF420_035F),
+            ("vst3.32 {d0, d1, d2}, [r4]!", 0xF404_048D),
+            ("vld4.8 {d0-d3}, [r5]", 0xF425_000F),
+            ("vst4.16 {d1, d3, d5, d7}, [r6:128]", 0xF406_116F),
+            ("vld1.32 {d0[1]}, [r0]", 0xF4A0_088F),
+            ("vld1.8 {d3[7]}, [r1]!", 0xF4A1_30ED),
+            ("vld1.16 {d2[2]}, [r2:16], r3", 0xF4A2_2493),
+            ("vst1.32 {d5[0]}, [r4:32]", 0xF484_583F),
+            ("vld2.32 {d0[1], d2[1]}, [r0]", 0xF4A0_09CF),
+            ("vld2.8 {d0[1], d1[1]}, [r0:16]", 0xF4A0_013F),
+            ("vst3.8 {d0[3], d1[3], d2[3]}, [r1]", 0xF481_026F),
+            ("vld4.16 {d0[2], d2[2], d4[2], d6[2]}, [r0]", 0xF4A0_07AF),
+            (
+                "vld4.32 {d0[1], d1[1], d2[1], d3[1]}, [r0:128]",
+                0xF4A0_0BAF,
+            ),
+            ("vld1.32 {d0[]}, [r0]", 0xF4A0_0C8F),
+            ("vld1.8 {d0[], d1[]}, [r1]!", 0xF4A1_0C2D),
+            ("vld1.32 {d0[], d1[]}, [r0:32]", 0xF4A0_0CBF),
+            ("vld2.16 {d0[], d2[]}, [r0]", 0xF4A0_0D6F),
+            ("vld3.32 {d0[], d1[], d2[]}, [r0], r2", 0xF4A0_0E82),
+            ("vld4.16 {d0[], d1[], d2[], d3[]}, [r0:64]", 0xF4A0_0F5F),
+            ("vld4.32 {d0[], d1[], d2[], d3[]}, [r0:128]", 0xF4A0_0FDF),
+            ("vcnt.8 q0, q1", 0xF3B0_0542),
+            ("vpadd.i16 d0, d1, d2", 0xF211_0B12),
+            ("vpadd.f32 d0, d1, d2", 0xF301_0D02),
+            ("vpmax.s8 d0, d1, d2", 0xF201_0A02),
+            ("vpmin.u32 d0, d1, d2", 0xF321_0A12),
+            ("vpmin.f32 d0, d1, d2", 0xF321_0F02),
+            ("vrev64.32 q0, q1", 0xF3B8_0042),
+            ("vrev32.16 d0, d1", 0xF3B4_0081),
+            ("vrev16.8 q0, q1", 0xF3B0_0142),
+            ("vswp d0, d1", 0xF3B2_0001),
+            ("vrecpe.u32 d0, d1", 0xF3BB_0401),
+            ("vrecpe.f32 q0, q1", 0xF3BB_0542),
+            ("vrsqrte.f32 d0, d1", 0xF3BB_0581),
+            ("vcvt.f32.s32 q0, q1", 0xF3BB_0642),
+            ("vcvt.f32.u32 d0, d1", 0xF3BB_0681),
+            ("vcvt.s32.f32 q2, q3", 0xF3BB_4746),
+            ("vcvt.u32.f32 d4, d5", 0xF3BB_4785),
+            ("vcvt.f32.s32 q0, q1, #16", 0xF2B0_0E52),
+            ("vmull.s16 q0, d1, d2", 0xF291_0C02),
+            ("vmull.u8 q1, d2, d3", 0xF382_2C03),
+            ("vmull.p8 q0, d1, d2", 0xF281_0E02),
+            ("vaddl.s32 q0, d1, d2", 0xF2A1_0002),
+            ("vaddw.s16 q0, q1, d2", 0xF292_0102),
+            ("vsubl.u16 q0, d1, d2", 0xF391_0202),
+            ("vsubw.u32 q0, q1, d4", 0xF3A2_0304),
+            ("vmlal.s8 q0, d1, d2", 0xF281_0802),
+            ("vmlsl.u32 q0, d1, d2", 0xF3A1_0A02),
+            ("vqmovn.s32 d0, q1", 0xF3B6_0282),
+            ("vqmovn.u16 d0, q1", 0xF3B2_02C2),
+            ("vqmovn.s64 d0, q1", 0xF3BA_0282),
+            ("vqmovun.s32 d0, q1", 0xF3B6_0242),
+        ];
+
+        for (source, expected) in cases {
+            let (cond, instruction) = NeonInstruction::parse(source).unwrap();
+            assert_eq!(
+                instruction.to_machine_code(cond),
+                expected,
+                "{source}: {instruction:?}"
+            );
+        }
+
+        for bad in [
+            "vadd.i32 q0, q1, d2",
+            "vhadd.i16 d0, d1, d2",
+            "vmul.p16 d0, d1, d2",
+            "vmov.i32 q0, #0x12345",
+            "vaddeq.i32 d0, d1, d2",
+            "vshr.s8 d0, d1, #9",
+            "vld1.8 {d0}, [r0:128]",
+            "vld3.8 {d0-d3}, [r0]",
+            "vmul.i16 d0, d1, d8[0]",
+            "vmov.8 r0, d0[0]",
+            "vld1.32 {d0[2]}, [r0]",
+            "vld2.8 {d0[1], d2[1]}, [r0]",
+            "vld2.16 {d0[1], d1[2]}, [r0]",
+            "vld1.8 {d0[1]}, [r0:16]",
+            "vst1.32 {d0[]}, [r0]",
+            "vld3.8 {d0[], d1[], d2[]}, [r0:32]",
+            "vld2.16 {d2, d0}, [r0]",
+            "vcnt.16 d0, d1",
+            "vpadd.i16 q0, q1, q2",
+            "vrev32.32 d0, d1",
+            "vrecpe.s32 d0, d1",
+            "vcvt.f32.f32 q0, q1",
+            "vcvt.s16.f32 d0, d1",
+            "vcvt.f32.s32 q0, d1",
+            "vmull.s16 d0, d1, d2",
+            "vaddw.u8 q0, d1, d2",
+            "vmull.s64 q0, d1, d2",
+            "vqmovun.u32 d0, q1",
+            "vqmovn.s32 q0, q1",
+        ] {
+            assert!(NeonInstruction::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_thumb_encoding() {
+        let thumb = |source| {
+            NeonInstruction::parse(source)
+                .unwrap()
+                .1
+                .to_thumb_machine_code()
+        };
+
+        assert_eq!(thumb("vadd.i32 q0, q1, q2"), 0xEF22_0844);
+        assert_eq!(thumb("vqadd.u8 q0, q1, q2"), 0xFF02_0054);
+        assert_eq!(thumb("vld1.8 {d0}, [r0]"), 0xF920_070F);
+        assert_eq!(thumb("vdup.32 q0, r1"), 0xEEA0_1B10);
+    }
+}
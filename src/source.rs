@@ -0,0 +1,174 @@
+//! Reading assembly source, with each `.include "file"` replaced by the lines of the file it
+//! names.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    assembler::strip_comment,
+    error::{AssemblerError, ParseError},
+};
+
+/// How deeply `.include`s may nest, to catch files that include themselves.
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// Where a source line came from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Location {
+    pub file: String,
+    /// Counted from 1.
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// An `.include` directive, which is replaced by the lines of its file rather than assembled.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Include {
+    /// How many of [`Source::lines`] come before it.
+    pub before: usize,
+    pub location: Location,
+    /// As written.
+    pub source: String,
+}
+
+/// The lines to assemble, from any number of files read one after the other.
+#[derive(Clone, Debug, Default)]
+pub struct Source {
+    pub lines: Vec<String>,
+    /// Where each of `lines` came from.
+    pub locations: Vec<Location>,
+    /// The `.include`s that were read, in the order they were written.
+    pub includes: Vec<Include>,
+    /// Searched for included files that aren't found relative to the current directory.
+    pub include_dirs: Vec<PathBuf>,
+}
+
+impl Source {
+    pub fn new(include_dirs: Vec<PathBuf>) -> Self {
+        Self {
+            include_dirs,
+            ..Self::default()
+        }
+    }
+
+    /// Append the lines of the file at `path`.
+    pub fn add_file(&mut self, path: &str) -> Result<(), AssemblerError> {
+        let text = fs::read_to_string(path)?;
+        self.add(path, &text, 0)
+    }
+
+    /// Append the lines of `text`, which came from `file`.
+    pub fn add_text(&mut self, file: &str, text: &str) -> Result<(), AssemblerError> {
+        self.add(file, text, 0)
+    }
+
+    /// Where line `number` came from, counting from 1 as [`crate::assembler::Assembler`] does.
+    pub fn location(&self, number: usize) -> Option<&Location> {
+        self.locations.get(number.checked_sub(1)?)
+    }
+
+    fn add(&mut self, file: &str, text: &str, depth: usize) -> Result<(), AssemblerError> {
+        for (number, line) in (1..).zip(text.lines()) {
+            if let Some(name) = included_file(line) {
+                if depth == MAX_INCLUDE_DEPTH {
+                    return Err(ParseError::BadDirective(line.trim().to_owned()).into());
+                }
+                let path = self.find(name)?;
+                self.includes.push(Include {
+                    before: self.lines.len(),
+                    location: Location {
+                        file: file.to_owned(),
+                        line: number,
+                    },
+                    source: line.trim_end().to_owned(),
+                });
+                let text = fs::read_to_string(&path)?;
+                self.add(&path.display().to_string(), &text, depth + 1)?;
+                continue;
+            }
+
+            self.lines.push(line.trim_end().to_owned());
+            self.locations.push(Location {
+                file: file.to_owned(),
+                line: number,
+            });
+        }
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Result<PathBuf, AssemblerError> {
+        std::iter::once(Path::new(name).to_path_buf())
+            .chain(self.include_dirs.iter().map(|dir| dir.join(name)))
+            .find(|path| path.is_file())
+            .ok_or_else(|| AssemblerError::MissingInclude(name.to_owned()))
+    }
+}
+
+/// The file named by an `.include` directive on `line`, if it is one.
+fn included_file(line: &str) -> Option<&str> {
+    let args = strip_comment(line).trim().strip_prefix(".include")?;
+    if !args.starts_with(char::is_whitespace) {
+        return None;
+    }
+    args.trim().strip_prefix('"')?.strip_suffix('"')
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("source-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("inc")).unwrap();
+        fs::write(dir.join("inc/defs.s"), ".set ONE, 1\n.include \"more.s\"\n").unwrap();
+        fs::write(dir.join("inc/more.s"), ".set TWO, 2\n").unwrap();
+        fs::write(dir.join("inc/loop.s"), ".include \"loop.s\"\n").unwrap();
+
+        let mut source = Source::new(vec![dir.join("inc")]);
+        source
+            .add_text(
+                "t.s",
+                "start:\n  .include \"defs.s\" @ both\nmov r0, #ONE\n",
+            )
+            .unwrap();
+        assert_eq!(
+            source.lines,
+            ["start:", ".set ONE, 1", ".set TWO, 2", "mov r0, #ONE"]
+        );
+        assert_eq!(
+            source.location(3).unwrap().to_string(),
+            format!("{}:1", dir.join("inc").join("more.s").display())
+        );
+        assert_eq!(source.location(4).unwrap().to_string(), "t.s:3");
+        assert_eq!(
+            source
+                .includes
+                .iter()
+                .map(|include| (include.before, include.location.to_string()))
+                .collect::<Vec<_>>(),
+            [
+                (1, "t.s:2".to_owned()),
+                (2, format!("{}:2", dir.join("inc").join("defs.s").display()))
+            ]
+        );
+        assert_eq!(source.includes[0].source, "  .include \"defs.s\" @ both");
+        assert!(source.location(0).is_none());
+
+        assert!(matches!(
+            Source::new(vec![]).add_text("t.s", ".include \"defs.s\""),
+            Err(AssemblerError::MissingInclude(_))
+        ));
+        let mut source = Source::new(vec![dir.join("inc")]);
+        assert!(source.add_text("t.s", ".include \"loop.s\"").is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1368 @@
+//! The 16-bit Thumb instruction set, one variant per encoding format.
+
+use crate::{
+    arch::{Feature, Target},
+    bitfield::{U11, U5, U6, U8},
+    cond::Cond,
+    error::{AssemblerError, ParseError},
+    instructions::{
+        parse_bounded_immediate, parse_immediate, parse_reg_id, parse_reg_list, split_operands, Rd,
+        Rm, Rn,
+    },
+    mnemonics::parse_mnemonic,
+};
+use strum::IntoEnumIterator;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum ThumbMnemonic {
+    ADC,
+    ADD,
+    ADDW,
+    ADR,
+    AND,
+    ASR,
+    B,
+    BFC,
+    BFI,
+    BIC,
+    BKPT,
+    BL,
+    BLX,
+    BX,
+    CBNZ,
+    CBZ,
+    CLREX,
+    CLZ,
+    CMN,
+    CMP,
+    CPSID,
+    CPSIE,
+    DMB,
+    DSB,
+    EOR,
+    ISB,
+    LDM,
+    LDMDB,
+    LDMIA,
+    LDR,
+    LDRB,
+    LDRD,
+    LDREX,
+    LDRH,
+    LDRSB,
+    LDRSH,
+    LSL,
+    LSR,
+    MLA,
+    MLS,
+    MOV,
+    MOVT,
+    MOVW,
+    MRS,
+    MSR,
+    MUL,
+    MVN,
+    NEG,
+    NOP,
+    ORN,
+    ORR,
+    PLD,
+    POP,
+    PUSH,
+    RBIT,
+    REV,
+    REV16,
+    ROR,
+    RSB,
+    SBC,
+    SBFX,
+    SDIV,
+    SEV,
+    SMULL,
+    STM,
+    STMDB,
+    STMIA,
+    STR,
+    STRB,
+    STRD,
+    STREX,
+    STRH,
+    SUB,
+    SUBW,
+    SVC,
+    SWI,
+    SXTB,
+    SXTH,
+    TBB,
+    TBH,
+    TEQ,
+    TST,
+    UBFX,
+    UDIV,
+    UMULL,
+    UXTB,
+    UXTH,
+    WFE,
+    WFI,
+    YIELD,
+}
+
+impl std::fmt::Display for ThumbMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::ADC => "adc",
+            Self::ADD => "add",
+            Self::ADDW => "addw",
+            Self::ADR => "adr",
+            Self::AND => "and",
+            Self::ASR => "asr",
+            Self::B => "b",
+            Self::BFC => "bfc",
+            Self::BFI => "bfi",
+            Self::BIC => "bic",
+            Self::BKPT => "bkpt",
+            Self::BL => "bl",
+            Self::BLX => "blx",
+            Self::BX => "bx",
+            Self::CBNZ => "cbnz",
+            Self::CBZ => "cbz",
+            Self::CLREX => "clrex",
+            Self::CLZ => "clz",
+            Self::CMN => "cmn",
+            Self::CMP => "cmp",
+            Self::CPSID => "cpsid",
+            Self::CPSIE => "cpsie",
+            Self::DMB => "dmb",
+            Self::DSB => "dsb",
+            Self::EOR => "eor",
+            Self::ISB => "isb",
+            Self::LDM => "ldm",
+            Self::LDMDB => "ldmdb",
+            Self::LDMIA => "ldmia",
+            Self::LDR => "ldr",
+            Self::LDRB => "ldrb",
+            Self::LDRD => "ldrd",
+            Self::LDREX => "ldrex",
+            Self::LDRH => "ldrh",
+            Self::LDRSB => "ldrsb",
+            Self::LDRSH => "ldrsh",
+            Self::LSL => "lsl",
+            Self::LSR => "lsr",
+            Self::MLA => "mla",
+            Self::MLS => "mls",
+            Self::MOV => "mov",
+            Self::MOVT => "movt",
+            Self::MOVW => "movw",
+            Self::MRS => "mrs",
+            Self::MSR => "msr",
+            Self::MUL => "mul",
+            Self::MVN => "mvn",
+            Self::NEG => "neg",
+            Self::NOP => "nop",
+            Self::ORN => "orn",
+            Self::ORR => "orr",
+            Self::PLD => "pld",
+            Self::POP => "pop",
+            Self::PUSH => "push",
+            Self::RBIT => "rbit",
+            Self::REV => "rev",
+            Self::REV16 => "rev16",
+            Self::ROR => "ror",
+            Self::RSB => "rsb",
+            Self::SBC => "sbc",
+            Self::SBFX => "sbfx",
+            Self::SDIV => "sdiv",
+            Self::SEV => "sev",
+            Self::SMULL => "smull",
+            Self::STM => "stm",
+            Self::STMDB => "stmdb",
+            Self::STMIA => "stmia",
+            Self::STR => "str",
+            Self::STRB => "strb",
+            Self::STRD => "strd",
+            Self::STREX => "strex",
+            Self::STRH => "strh",
+            Self::SUB => "sub",
+            Self::SUBW => "subw",
+            Self::SVC => "svc",
+            Self::SWI => "swi",
+            Self::SXTB => "sxtb",
+            Self::SXTH => "sxth",
+            Self::TBB => "tbb",
+            Self::TBH => "tbh",
+            Self::TEQ => "teq",
+            Self::TST => "tst",
+            Self::UBFX => "ubfx",
+            Self::UDIV => "udiv",
+            Self::UMULL => "umull",
+            Self::UXTB => "uxtb",
+            Self::UXTH => "uxth",
+            Self::WFE => "wfe",
+            Self::WFI => "wfi",
+            Self::YIELD => "yield",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl TryFrom<&str> for ThumbMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+impl ThumbMnemonic {
+    /// Whether the mnemonic can take an `s` suffix in unified syntax.
+    pub fn accepts_s(self) -> bool {
+        matches!(
+            self,
+            Self::ADC
+                | Self::ADD
+                | Self::AND
+                | Self::ASR
+                | Self::BIC
+                | Self::EOR
+                | Self::LSL
+                | Self::LSR
+                | Self::MOV
+                | Self::MUL
+                | Self::MVN
+                | Self::NEG
+                | Self::ORN
+                | Self::ORR
+                | Self::ROR
+                | Self::RSB
+                | Self::SBC
+                | Self::SUB
+        )
+    }
+
+    /// Whether the mnemonic changes the flow of control, and so has to end an IT block.
+    pub fn is_branch(self) -> bool {
+        matches!(
+            self,
+            Self::B | Self::BL | Self::BLX | Self::BX | Self::TBB | Self::TBH
+        )
+    }
+
+    /// The hint number of `nop` and the other hints.
+    pub(crate) fn hint(self) -> Option<u16> {
+        match self {
+            Self::NOP => Some(0),
+            Self::YIELD => Some(1),
+            Self::WFE => Some(2),
+            Self::WFI => Some(3),
+            Self::SEV => Some(4),
+            _ => None,
+        }
+    }
+
+    /// The opcode field of the format 4 ALU operations.
+    fn alu_opcode(self) -> Option<u16> {
+        match self {
+            Self::AND => Some(0x0),
+            Self::EOR => Some(0x1),
+            Self::LSL => Some(0x2),
+            Self::LSR => Some(0x3),
+            Self::ASR => Some(0x4),
+            Self::ADC => Some(0x5),
+            Self::SBC => Some(0x6),
+            Self::ROR => Some(0x7),
+            Self::TST => Some(0x8),
+            Self::NEG => Some(0x9),
+            Self::CMP => Some(0xA),
+            Self::CMN => Some(0xB),
+            Self::ORR => Some(0xC),
+            Self::MUL => Some(0xD),
+            Self::BIC => Some(0xE),
+            Self::MVN => Some(0xF),
+            _ => None,
+        }
+    }
+}
+
+/// An explicit `.n` or `.w` qualifier on a mnemonic.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Width {
+    Narrow,
+    Wide,
+}
+
+/// Which flag-setting behaviour a narrow encoding has to have. Outside an IT block, unified syntax
+/// only allows the encodings that set the flags when the mnemonic has an `s` suffix, and divided
+/// syntax doesn't care.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FlagSetting {
+    Any,
+    Set,
+    Preserve,
+}
+
+/// The mnemonic of a Thumb instruction with its suffixes split off.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ThumbOpcode {
+    pub mnemonic: ThumbMnemonic,
+    pub set_flags: bool,
+    pub cond: Cond,
+    pub width: Option<Width>,
+}
+
+impl TryFrom<&str> for ThumbOpcode {
+    type Error = AssemblerError;
+
+    /// Parse `mnemonic{s}{cond}{.n|.w}`, matching the longest mnemonic for which the rest is valid.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let (name, width) = match value.rsplit_once('.') {
+            Some((name, qualifier)) if qualifier.eq_ignore_ascii_case("n") => {
+                (name, Some(Width::Narrow))
+            }
+            Some((name, qualifier)) if qualifier.eq_ignore_ascii_case("w") => {
+                (name, Some(Width::Wide))
+            }
+            _ => (value, None),
+        };
+
+        let parse_cond = |suffix: &str| -> Option<Cond> {
+            if suffix.is_empty() {
+                Some(Cond::AL)
+            } else {
+                Cond::try_from(suffix).ok().filter(|cond| *cond != Cond::NV)
+            }
+        };
+
+        ThumbMnemonic::iter()
+            .filter_map(|mnemonic| {
+                let prefix_len = mnemonic.to_string().len();
+                let prefix = name.get(..prefix_len)?;
+                if !prefix.eq_ignore_ascii_case(&mnemonic.to_string()) {
+                    return None;
+                }
+
+                let suffix = &name[prefix_len..];
+                let (set_flags, cond) = match parse_cond(suffix) {
+                    Some(cond) => (false, cond),
+                    None if mnemonic.accepts_s() => {
+                        let rest = suffix.strip_prefix(['s', 'S'])?;
+                        (true, parse_cond(rest)?)
+                    }
+                    None => return None,
+                };
+
+                Some((
+                    prefix_len,
+                    Self {
+                        mnemonic,
+                        set_flags,
+                        cond,
+                        width,
+                    },
+                ))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, opcode)| opcode)
+            .ok_or_else(|| ParseError::BadMnemonic(value.to_owned()).into())
+    }
+}
+
+/// An `IT` instruction, which makes up to four following instructions conditional.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ItBlock {
+    pub first_cond: Cond,
+    pub mask: u8,
+    /// The condition each instruction in the block must have, in order.
+    pub conds: Vec<Cond>,
+}
+
+impl ItBlock {
+    /// Parse `it{x{y{z}}} cond`, where each of x, y and z is `t` or `e`. Returns `None` if `line`
+    /// is not an IT instruction.
+    pub fn parse(line: &str) -> Result<Option<Self>, AssemblerError> {
+        let (opcode, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let Some(pattern) = opcode
+            .get(..2)
+            .filter(|it| it.eq_ignore_ascii_case("it"))
+            .map(|_| opcode[2..].to_ascii_lowercase())
+        else {
+            return Ok(None);
+        };
+        if pattern.len() > 3 || !pattern.chars().all(|c| c == 't' || c == 'e') {
+            return Ok(None);
+        }
+
+        let first_cond = Cond::try_from(rest.trim())?;
+        if first_cond == Cond::NV || (first_cond == Cond::AL && pattern.contains('e')) {
+            return Err(ParseError::BadItBlock(line.to_owned()).into());
+        }
+
+        let low_bit = u8::from(first_cond) & 1;
+        let mut mask = 0;
+        let mut conds = vec![first_cond];
+        for (i, c) in pattern.chars().enumerate() {
+            let (bit, cond) = if c == 't' {
+                (low_bit, first_cond)
+            } else {
+                (low_bit ^ 1, first_cond.inverse())
+            };
+            mask |= bit << (3 - i);
+            conds.push(cond);
+        }
+        // The lowest set bit marks the end of the block
+        mask |= 1 << (3 - pattern.len());
+
+        Ok(Some(Self {
+            first_cond,
+            mask,
+            conds,
+        }))
+    }
+
+    pub fn to_machine_code(&self) -> u16 {
+        0xBF00 | ((self.first_cond as u16) << 4) | self.mask as u16
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ThumbOperand {
+    Register(Rm),
+    Immediate(u8),
+}
+
+/// The base register of a format 12 address calculation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AddressBase {
+    Pc,
+    Sp,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ThumbInstruction {
+    /// Format 1: `lsl/lsr/asr Rd, Rm, #imm5`, where 0 stands for a right shift by 32
+    MoveShifted(ThumbMnemonic, Rd, Rm, U5),
+    /// Format 2: `add/sub Rd, Rn, Rm|#imm3`
+    AddSubtract(ThumbMnemonic, Rd, Rn, ThumbOperand),
+    /// Format 3: `mov/cmp/add/sub Rd, #imm8`
+    Immediate(ThumbMnemonic, Rd, u8),
+    /// Format 4: `op Rd, Rm` on low registers
+    Alu(ThumbMnemonic, Rd, Rm),
+    /// Format 5: `add/cmp/mov Rd, Rm` where either may be a high register
+    HiRegister(ThumbMnemonic, Rd, Rm),
+    /// Format 5: `bx/blx Rm`
+    BranchExchange(ThumbMnemonic, Rm),
+    /// Format 6: `ldr Rd, [pc, #imm]`
+    PcRelativeLoad(Rd, u16),
+    /// Formats 7 and 8: `op Rd, [Rn, Rm]`
+    LoadStoreRegister(ThumbMnemonic, Rd, Rn, Rm),
+    /// Formats 9 and 10: `op Rd, [Rn, #imm]` with a byte offset
+    LoadStoreImmediate(ThumbMnemonic, Rd, Rn, u8),
+    /// Format 11: `ldr/str Rd, [sp, #imm]`
+    SpRelative(ThumbMnemonic, Rd, u16),
+    /// Format 12: `add Rd, pc|sp, #imm`
+    LoadAddress(Rd, AddressBase, u16),
+    /// Format 13: `add sp, #imm`, with negative values subtracting
+    AdjustSp(i16),
+    /// Format 14: `push/pop {rlist}`, where bit 8 of the list stands for lr or pc
+    PushPop(ThumbMnemonic, u16),
+    /// Format 15: `ldmia/stmia Rn!, {rlist}`, where a load of Rn itself doesn't write it back
+    Multiple(ThumbMnemonic, Rn, u8),
+    /// Format 16, with the halfword offset from the PC (the branch address plus 4)
+    CondBranch(Cond, U8),
+    /// Format 17
+    SoftwareInterrupt(u8),
+    /// Format 18, with the halfword offset from the PC
+    Branch(U11),
+    /// Format 19, encoded as a pair of halfwords
+    BranchLink(i32),
+    /// `cbz/cbnz Rn, offset`, which can only branch forwards, with the halfword offset
+    CompareBranch(ThumbMnemonic, Rn, U6),
+    /// `nop`, `yield`, `wfe`, `wfi` and `sev`
+    Hint(ThumbMnemonic),
+    /// `cpsid/cpsie iflags`, with the `a`, `i` and `f` bits
+    ChangeState(ThumbMnemonic, u8),
+    /// `sxtb/sxth/uxtb/uxth Rd, Rm` without a rotation
+    Extend(ThumbMnemonic, Rd, Rm),
+    /// `rev/rev16 Rd, Rm`
+    Reverse(ThumbMnemonic, Rd, Rm),
+    /// `bkpt #imm8`
+    Breakpoint(u8),
+}
+
+/// Check that a branch offset is even and lies within `min..=max`, which `range` describes.
+pub(crate) fn check_branch_offset(
+    offset: i64,
+    min: i64,
+    max: i64,
+    range: &'static str,
+) -> Result<i32, AssemblerError> {
+    if offset % 2 != 0 || offset < min || offset > max {
+        return Err(ParseError::BranchOutOfRange(offset.to_string(), range).into());
+    }
+
+    Ok(offset as i32)
+}
+
+/// The halfword offset of a branch by `offset` bytes as a field made by `new`, which checks that
+/// it's in the range `range` describes.
+fn halfword_offset<T>(
+    offset: i64,
+    range: &'static str,
+    new: impl Fn(i32) -> Option<T>,
+) -> Result<T, AssemblerError> {
+    i32::try_from(offset)
+        .ok()
+        .filter(|offset| offset % 2 == 0)
+        .and_then(|offset| new(offset >> 1))
+        .ok_or_else(|| ParseError::BranchOutOfRange(offset.to_string(), range).into())
+}
+
+/// Encode the 25-bit offset shared by `bl` and the wide `b`, with `second` holding the fixed bits
+/// of the second halfword.
+pub(crate) fn encode_branch24(offset: i32, second: u16) -> Vec<u16> {
+    let offset = offset as u32;
+    let s = (offset >> 24) & 1;
+    let j1 = (!(offset >> 23) ^ s) & 1;
+    let j2 = (!(offset >> 22) ^ s) & 1;
+    let imm10 = (offset >> 12) & 0x3FF;
+    let imm11 = (offset >> 1) & 0x7FF;
+    vec![
+        0xF000 | (s << 10) as u16 | imm10 as u16,
+        second | (j1 << 13) as u16 | (j2 << 11) as u16 | imm11 as u16,
+    ]
+}
+
+/// Parse a register that must be one of r0-r7.
+pub(crate) fn parse_low_reg(value: &str) -> Result<u8, AssemblerError> {
+    let reg = parse_reg_id(value)?;
+    if reg < 8 {
+        Ok(reg)
+    } else {
+        Err(ParseError::BadRegister(value.to_owned()).into())
+    }
+}
+
+/// Parse an immediate that must be a multiple of `scale`, returning it already divided down.
+fn parse_scaled_immediate(value: &str, max: u32, scale: u32) -> Result<u32, AssemblerError> {
+    let imm = parse_bounded_immediate(value, max * scale)?;
+    if imm % scale != 0 {
+        return Err(ParseError::BadImmediate(value.to_owned()).into());
+    }
+
+    Ok(imm / scale)
+}
+
+/// Split `[Rn, offset]` into the base register text and the optional offset text.
+pub(crate) fn split_address(value: &str) -> Result<(&str, Option<&str>), AssemblerError> {
+    let inner = value
+        .trim()
+        .strip_prefix('[')
+        .and_then(|address| address.strip_suffix(']'))
+        .ok_or_else(|| ParseError::BadFlexOperand(value.to_owned()))?;
+
+    Ok(match inner.split_once(',') {
+        Some((base, offset)) => (base.trim(), Some(offset.trim())),
+        None => (inner.trim(), None),
+    })
+}
+
+impl TryFrom<&str> for ThumbInstruction {
+    type Error = AssemblerError;
+
+    /// Parse a narrow instruction in divided syntax.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        let (opcode, rest) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+        let opcode = ThumbOpcode::try_from(opcode)?;
+        if opcode.set_flags || opcode.width == Some(Width::Wide) {
+            return Err(ParseError::BadMnemonic(value.to_owned()).into());
+        }
+
+        Self::parse(opcode.mnemonic, opcode.cond, rest, FlagSetting::Any)
+    }
+}
+
+impl ThumbInstruction {
+    /// Parse the operands of a narrow instruction, picking an encoding with the requested
+    /// flag-setting behaviour.
+    pub fn parse(
+        mnemonic: ThumbMnemonic,
+        cond: Cond,
+        operands: &str,
+        flags: FlagSetting,
+    ) -> Result<Self, AssemblerError> {
+        // Only branches are conditional outside of an IT block
+        if cond != Cond::AL && mnemonic != ThumbMnemonic::B {
+            return Err(ParseError::UnexpectedCond(mnemonic.to_string()).into());
+        }
+
+        let instruction = Self::parse_operands(mnemonic, cond, operands, flags)?;
+        let matches_flags = match flags {
+            FlagSetting::Any => true,
+            FlagSetting::Set => instruction.sets_flags() != Some(false),
+            FlagSetting::Preserve => instruction.sets_flags() != Some(true),
+        };
+        if !matches_flags {
+            return Err(ParseError::BadMnemonic(mnemonic.to_string()).into());
+        }
+
+        Ok(instruction)
+    }
+
+    fn parse_operands(
+        mnemonic: ThumbMnemonic,
+        cond: Cond,
+        rest: &str,
+        flags: FlagSetting,
+    ) -> Result<Self, AssemblerError> {
+        let operands = split_operands(rest);
+        let operand = |i: usize| -> Result<&str, AssemblerError> {
+            operands
+                .get(i)
+                .copied()
+                .ok_or_else(|| ParseError::RanOutOfOperands.into())
+        };
+        let is_imm = |op: &str| op.starts_with('#');
+
+        match mnemonic {
+            ThumbMnemonic::LSL | ThumbMnemonic::LSR | ThumbMnemonic::ASR if operands.len() == 3 => {
+                let rd = Rd(parse_low_reg(operand(0)?)?);
+                let rm = Rm(parse_low_reg(operand(1)?)?);
+                let max = if mnemonic == ThumbMnemonic::LSL {
+                    31
+                } else {
+                    32
+                };
+                // A shift by 32 is encoded as 0
+                let imm = parse_bounded_immediate(operand(2)?, max)? % 32;
+                let imm = U5::new(imm as u8).unwrap_or(U5::ZERO);
+                Ok(Self::MoveShifted(mnemonic, rd, rm, imm))
+            }
+            ThumbMnemonic::ADD | ThumbMnemonic::SUB => {
+                Self::parse_add_sub(mnemonic, &operands, flags)
+            }
+            ThumbMnemonic::MOV | ThumbMnemonic::CMP => {
+                let (rd_op, rm_op) = (operand(0)?, operand(1)?);
+                if is_imm(rm_op) {
+                    let rd = Rd(parse_low_reg(rd_op)?);
+                    let imm = parse_bounded_immediate(rm_op, 0xFF)?;
+                    return Ok(Self::Immediate(mnemonic, rd, imm as u8));
+                }
+
+                let (rd, rm) = (parse_reg_id(rd_op)?, parse_reg_id(rm_op)?);
+                match (mnemonic, rd < 8 && rm < 8) {
+                    // Unified syntax spells the flag-setting move between low registers `lsls #0`
+                    (ThumbMnemonic::MOV, true) if flags == FlagSetting::Set => Ok(
+                        Self::MoveShifted(ThumbMnemonic::LSL, Rd(rd), Rm(rm), U5::ZERO),
+                    ),
+                    (ThumbMnemonic::MOV, true) if flags == FlagSetting::Preserve => {
+                        Ok(Self::HiRegister(mnemonic, Rd(rd), Rm(rm)))
+                    }
+                    // Divided syntax moves between low registers with `add Rd, Rm, #0`
+                    (ThumbMnemonic::MOV, true) => Ok(Self::AddSubtract(
+                        ThumbMnemonic::ADD,
+                        Rd(rd),
+                        Rn(rm),
+                        ThumbOperand::Immediate(0),
+                    )),
+                    (ThumbMnemonic::CMP, true) => Ok(Self::Alu(mnemonic, Rd(rd), Rm(rm))),
+                    _ => Ok(Self::HiRegister(mnemonic, Rd(rd), Rm(rm))),
+                }
+            }
+            ThumbMnemonic::BX | ThumbMnemonic::BLX => {
+                let rm = Rm(parse_reg_id(operand(0)?)?);
+                Ok(Self::BranchExchange(mnemonic, rm))
+            }
+            ThumbMnemonic::MUL => {
+                // Accept the three operand form as long as it matches what the encoding does
+                let rd = parse_low_reg(operand(0)?)?;
+                let rm = parse_low_reg(operand(1)?)?;
+                if let Ok(third) = operand(2) {
+                    if parse_low_reg(third)? != rd {
+                        return Err(ParseError::BadRegister(third.to_owned()).into());
+                    }
+                }
+                Ok(Self::Alu(mnemonic, Rd(rd), Rm(rm)))
+            }
+            ThumbMnemonic::AND
+            | ThumbMnemonic::EOR
+            | ThumbMnemonic::LSL
+            | ThumbMnemonic::LSR
+            | ThumbMnemonic::ASR
+            | ThumbMnemonic::ADC
+            | ThumbMnemonic::SBC
+            | ThumbMnemonic::ROR
+            | ThumbMnemonic::TST
+            | ThumbMnemonic::NEG
+            | ThumbMnemonic::CMN
+            | ThumbMnemonic::ORR
+            | ThumbMnemonic::BIC
+            | ThumbMnemonic::MVN => {
+                let rd = Rd(parse_low_reg(operand(0)?)?);
+                let rm = Rm(parse_low_reg(operand(1)?)?);
+                Ok(Self::Alu(mnemonic, rd, rm))
+            }
+            ThumbMnemonic::LDR
+            | ThumbMnemonic::STR
+            | ThumbMnemonic::LDRB
+            | ThumbMnemonic::STRB
+            | ThumbMnemonic::LDRH
+            | ThumbMnemonic::STRH
+            | ThumbMnemonic::LDRSB
+            | ThumbMnemonic::LDRSH => Self::parse_load_store(mnemonic, &operands),
+            ThumbMnemonic::PUSH | ThumbMnemonic::POP => {
+                let list_op = operand(0)?;
+                let list = parse_reg_list(list_op)?;
+                // Besides the low registers, push can save lr and pop can load pc
+                let extra = if mnemonic == ThumbMnemonic::PUSH {
+                    14
+                } else {
+                    15
+                };
+                if list == 0 || list & !(0xFF | (1 << extra)) != 0 {
+                    return Err(ParseError::BadRegister(list_op.to_owned()).into());
+                }
+                let extra_bit = if list & (1 << extra) != 0 { 1 << 8 } else { 0 };
+                Ok(Self::PushPop(mnemonic, (list & 0xFF) | extra_bit))
+            }
+            ThumbMnemonic::LDMIA
+            | ThumbMnemonic::STMIA
+            | ThumbMnemonic::LDM
+            | ThumbMnemonic::STM => {
+                let base_op = operand(0)?;
+                let writeback = base_op.ends_with('!');
+                let rn = parse_low_reg(base_op.trim_end_matches('!'))?;
+                let list_op = operand(1)?;
+                let list = parse_reg_list(list_op)?;
+                if list & !0xFF != 0 || list == 0 {
+                    return Err(ParseError::BadRegister(list_op.to_owned()).into());
+                }
+                let mnemonic = match mnemonic {
+                    ThumbMnemonic::LDM => ThumbMnemonic::LDMIA,
+                    ThumbMnemonic::STM => ThumbMnemonic::STMIA,
+                    _ => mnemonic,
+                };
+                // Stores always write back, and loads do unless they load the base register,
+                // so anything else needs the wide encoding
+                let loads_base = mnemonic == ThumbMnemonic::LDMIA && list & (1 << rn) != 0;
+                if writeback == loads_base {
+                    return Err(ParseError::BadWriteback(rest.trim().to_owned()).into());
+                }
+                Ok(Self::Multiple(mnemonic, Rn(rn), list as u8))
+            }
+            ThumbMnemonic::B => {
+                let offset = parse_immediate(operand(0)?)?;
+                if cond == Cond::AL {
+                    Ok(Self::Branch(halfword_offset(
+                        offset,
+                        "±2 KiB",
+                        U11::new_signed,
+                    )?))
+                } else {
+                    Ok(Self::CondBranch(
+                        cond,
+                        halfword_offset(offset, "±256 bytes", U8::new_signed)?,
+                    ))
+                }
+            }
+            ThumbMnemonic::BL => {
+                let offset = parse_immediate(operand(0)?)?;
+                Ok(Self::BranchLink(check_branch_offset(
+                    offset,
+                    -(1 << 24),
+                    (1 << 24) - 2,
+                    "±16 MiB",
+                )?))
+            }
+            ThumbMnemonic::CBZ | ThumbMnemonic::CBNZ => {
+                let rn = Rn(parse_low_reg(operand(0)?)?);
+                let offset = parse_immediate(operand(1)?)?;
+                let offset = halfword_offset(offset, "0 to 126 bytes forward", |offset| {
+                    U6::new(u8::try_from(offset).ok()?)
+                })?;
+                Ok(Self::CompareBranch(mnemonic, rn, offset))
+            }
+            ThumbMnemonic::SWI | ThumbMnemonic::SVC => {
+                let imm = parse_bounded_immediate(operand(0)?, 0xFF)?;
+                Ok(Self::SoftwareInterrupt(imm as u8))
+            }
+            _ if mnemonic.hint().is_some() && operands.is_empty() => Ok(Self::Hint(mnemonic)),
+            ThumbMnemonic::CPSID | ThumbMnemonic::CPSIE if operands.len() == 1 => {
+                let iflags = operand(0)?;
+                let mut flags = 0;
+                for flag in iflags.chars() {
+                    let bit = match flag.to_ascii_lowercase() {
+                        'a' => 0b100,
+                        'i' => 0b010,
+                        'f' => 0b001,
+                        _ => return Err(ParseError::BadFlexOperand(iflags.to_owned()).into()),
+                    };
+                    if flags & bit != 0 {
+                        return Err(ParseError::BadFlexOperand(iflags.to_owned()).into());
+                    }
+                    flags |= bit;
+                }
+                Ok(Self::ChangeState(mnemonic, flags))
+            }
+            ThumbMnemonic::SXTB
+            | ThumbMnemonic::SXTH
+            | ThumbMnemonic::UXTB
+            | ThumbMnemonic::UXTH
+                if operands.len() == 2 =>
+            {
+                let rd = Rd(parse_low_reg(operand(0)?)?);
+                let rm = Rm(parse_low_reg(operand(1)?)?);
+                Ok(Self::Extend(mnemonic, rd, rm))
+            }
+            ThumbMnemonic::REV | ThumbMnemonic::REV16 => {
+                let rd = Rd(parse_low_reg(operand(0)?)?);
+                let rm = Rm(parse_low_reg(operand(1)?)?);
+                Ok(Self::Reverse(mnemonic, rd, rm))
+            }
+            // `adr` is `add Rd, pc, #imm` with the offset from the word-aligned PC
+            ThumbMnemonic::ADR => {
+                let rd = Rd(parse_low_reg(operand(0)?)?);
+                let imm = parse_scaled_immediate(operand(1)?, 0xFF, 4)? as u16 * 4;
+                Ok(Self::LoadAddress(rd, AddressBase::Pc, imm))
+            }
+            ThumbMnemonic::BKPT => {
+                let imm = match operands.first() {
+                    Some(imm) => parse_bounded_immediate(imm, 0xFF)?,
+                    None => 0,
+                };
+                Ok(Self::Breakpoint(imm as u8))
+            }
+            _ => Err(ParseError::BadMnemonic(mnemonic.to_string()).into()),
+        }
+    }
+
+    fn parse_add_sub(
+        mnemonic: ThumbMnemonic,
+        operands: &[&str],
+        flags: FlagSetting,
+    ) -> Result<Self, AssemblerError> {
+        let operand = |i: usize| -> Result<&str, AssemblerError> {
+            operands
+                .get(i)
+                .copied()
+                .ok_or_else(|| ParseError::RanOutOfOperands.into())
+        };
+        let is_sub = mnemonic == ThumbMnemonic::SUB;
+
+        // `add sp, #imm` and `add sp, sp, #imm`
+        let rd = parse_reg_id(operand(0)?)?;
+        if rd == 13 && operands.last().is_some_and(|op| op.starts_with('#')) {
+            let imm_op = operands.last().copied().unwrap_or_default();
+            let imm = parse_scaled_immediate(imm_op, 0x7F, 4)? as i16 * 4;
+            return Ok(Self::AdjustSp(if is_sub { -imm } else { imm }));
+        }
+
+        match operands.len() {
+            2 => {
+                let src = operand(1)?;
+                if src.starts_with('#') {
+                    let imm = parse_bounded_immediate(src, 0xFF)?;
+                    Ok(Self::Immediate(
+                        mnemonic,
+                        Rd(parse_low_reg(operand(0)?)?),
+                        imm as u8,
+                    ))
+                } else if !is_sub
+                    && (rd > 7 || parse_reg_id(src)? > 7 || flags == FlagSetting::Preserve)
+                {
+                    Ok(Self::HiRegister(mnemonic, Rd(rd), Rm(parse_reg_id(src)?)))
+                } else {
+                    let rd = parse_low_reg(operand(0)?)?;
+                    let rm = Rm(parse_low_reg(src)?);
+                    Ok(Self::AddSubtract(
+                        mnemonic,
+                        Rd(rd),
+                        Rn(rd),
+                        ThumbOperand::Register(rm),
+                    ))
+                }
+            }
+            3 => {
+                let (rn_op, src) = (operand(1)?, operand(2)?);
+                let rn = parse_reg_id(rn_op)?;
+
+                if !is_sub && (rn == 13 || rn == 15) {
+                    let imm = parse_scaled_immediate(src, 0xFF, 4)? as u16 * 4;
+                    let base = if rn == 13 {
+                        AddressBase::Sp
+                    } else {
+                        AddressBase::Pc
+                    };
+                    return Ok(Self::LoadAddress(
+                        Rd(parse_low_reg(operand(0)?)?),
+                        base,
+                        imm,
+                    ));
+                }
+
+                // `add Rd, Rd, Rm` can use the form that leaves the flags alone
+                if !is_sub && flags == FlagSetting::Preserve && !src.starts_with('#') {
+                    let rm = parse_reg_id(src)?;
+                    if rd == rn {
+                        return Ok(Self::HiRegister(mnemonic, Rd(rd), Rm(rm)));
+                    } else if rd == rm {
+                        return Ok(Self::HiRegister(mnemonic, Rd(rd), Rm(rn)));
+                    }
+                }
+
+                let rd = Rd(parse_low_reg(operand(0)?)?);
+                let rn = Rn(parse_low_reg(rn_op)?);
+                if src.starts_with('#') {
+                    let imm = parse_bounded_immediate(src, 0xFF)?;
+                    if imm > 7 && rd.0 == rn.0 {
+                        Ok(Self::Immediate(mnemonic, rd, imm as u8))
+                    } else if imm <= 7 {
+                        Ok(Self::AddSubtract(
+                            mnemonic,
+                            rd,
+                            rn,
+                            ThumbOperand::Immediate(imm as u8),
+                        ))
+                    } else {
+                        Err(ParseError::BadImmediate(src.to_owned()).into())
+                    }
+                } else {
+                    let rm = Rm(parse_low_reg(src)?);
+                    Ok(Self::AddSubtract(
+                        mnemonic,
+                        rd,
+                        rn,
+                        ThumbOperand::Register(rm),
+                    ))
+                }
+            }
+            _ => Err(ParseError::RanOutOfOperands.into()),
+        }
+    }
+
+    fn parse_load_store(
+        mnemonic: ThumbMnemonic,
+        operands: &[&str],
+    ) -> Result<Self, AssemblerError> {
+        let rd_op = operands.first().ok_or(ParseError::RanOutOfOperands)?;
+        let address_op = operands.get(1).ok_or(ParseError::RanOutOfOperands)?;
+        // Writeback is only in the wide encodings
+        if operands.len() > 2 || address_op.ends_with('!') {
+            return Err(ParseError::BadFlexOperand(operands[1..].join(", ")).into());
+        }
+        let rd = Rd(parse_low_reg(rd_op)?);
+        let (base_op, offset_op) = split_address(address_op)?;
+        let base = parse_reg_id(base_op)?;
+        let offset_op = offset_op.unwrap_or("#0");
+        let is_word = matches!(mnemonic, ThumbMnemonic::LDR | ThumbMnemonic::STR);
+
+        if base == 15 && mnemonic == ThumbMnemonic::LDR {
+            let imm = parse_scaled_immediate(offset_op, 0xFF, 4)? as u16 * 4;
+            return Ok(Self::PcRelativeLoad(rd, imm));
+        }
+        if base == 13 && is_word {
+            let imm = parse_scaled_immediate(offset_op, 0xFF, 4)? as u16 * 4;
+            return Ok(Self::SpRelative(mnemonic, rd, imm));
+        }
+
+        let rn = Rn(parse_low_reg(base_op)?);
+        if offset_op.starts_with('#') {
+            let scale = match mnemonic {
+                ThumbMnemonic::LDR | ThumbMnemonic::STR => 4,
+                ThumbMnemonic::LDRH | ThumbMnemonic::STRH => 2,
+                ThumbMnemonic::LDRB | ThumbMnemonic::STRB => 1,
+                // Sign-extending loads only have a register offset form
+                _ => return Err(ParseError::BadFlexOperand(offset_op.to_owned()).into()),
+            };
+            let imm = parse_scaled_immediate(offset_op, 31, scale)? * scale;
+            Ok(Self::LoadStoreImmediate(mnemonic, rd, rn, imm as u8))
+        } else {
+            let rm = Rm(parse_low_reg(offset_op)?);
+            Ok(Self::LoadStoreRegister(mnemonic, rd, rn, rm))
+        }
+    }
+
+    /// Whether the encoding sets the flags when outside an IT block, or `None` for instructions
+    /// where that isn't optional.
+    pub fn sets_flags(&self) -> Option<bool> {
+        match self {
+            Self::MoveShifted(..) | Self::AddSubtract(..) => Some(true),
+            Self::Immediate(mnemonic, ..) => Some(*mnemonic != ThumbMnemonic::CMP),
+            Self::Alu(mnemonic, ..) => Some(!matches!(
+                mnemonic,
+                ThumbMnemonic::TST | ThumbMnemonic::CMP | ThumbMnemonic::CMN
+            )),
+            Self::HiRegister(mnemonic, ..) => (*mnemonic != ThumbMnemonic::CMP).then_some(false),
+            Self::AdjustSp(..) | Self::LoadAddress(..) => Some(false),
+            _ => None,
+        }
+    }
+
+    /// What to assemble for this on `target`. Without the architectural hints, `nop` becomes
+    /// `mov r8, r8` as in GNU as.
+    pub fn for_target(self, target: &Target) -> Self {
+        match self {
+            Self::Hint(ThumbMnemonic::NOP) if !target.supports(Feature::ThumbSystem) => {
+                Self::HiRegister(ThumbMnemonic::MOV, Rd(8), Rm(8))
+            }
+            instruction => instruction,
+        }
+    }
+
+    /// The architecture feature this instruction needs.
+    pub fn required_feature(&self) -> Feature {
+        match self {
+            Self::BranchExchange(ThumbMnemonic::BLX, _) => Feature::V5TE,
+            // Before ARMv6 the high register operations needed at least one high register
+            Self::HiRegister(_, rd, rm) if rd.0 < 8 && rm.0 < 8 => Feature::V6,
+            Self::CompareBranch(..) => Feature::Thumb2,
+            Self::Hint(..) => Feature::ThumbSystem,
+            Self::ChangeState(..) | Self::Extend(..) | Self::Reverse(..) => Feature::V6,
+            Self::Breakpoint(..) => Feature::V5TE,
+            _ => Feature::V4T,
+        }
+    }
+
+    /// Encode as one halfword, or two for `bl`, in the order they appear in memory.
+    pub fn to_machine_code(self) -> Vec<u16> {
+        match self {
+            Self::MoveShifted(mnemonic, rd, rm, imm) => {
+                let op = match mnemonic {
+                    ThumbMnemonic::LSL => 0b00,
+                    ThumbMnemonic::LSR => 0b01,
+                    _ => 0b10,
+                };
+                let imm5 = imm.get() as u16;
+                vec![(op << 11) | (imm5 << 6) | ((rm.0 as u16) << 3) | rd.0 as u16]
+            }
+            Self::AddSubtract(mnemonic, rd, rn, operand) => {
+                let mut encoding = 0b00011 << 11;
+                if mnemonic == ThumbMnemonic::SUB {
+                    encoding |= 1 << 9;
+                }
+                encoding |= match operand {
+                    ThumbOperand::Register(rm) => (rm.0 as u16) << 6,
+                    ThumbOperand::Immediate(imm) => (1 << 10) | ((imm as u16) << 6),
+                };
+                vec![encoding | ((rn.0 as u16) << 3) | rd.0 as u16]
+            }
+            Self::Immediate(mnemonic, rd, imm) => {
+                let op = match mnemonic {
+                    ThumbMnemonic::MOV => 0b00,
+                    ThumbMnemonic::CMP => 0b01,
+                    ThumbMnemonic::ADD => 0b10,
+                    _ => 0b11,
+                };
+                vec![(0b001 << 13) | (op << 11) | ((rd.0 as u16) << 8) | imm as u16]
+            }
+            Self::Alu(mnemonic, rd, rm) => {
+                let op = mnemonic.alu_opcode().unwrap_or_default();
+                vec![(0b010000 << 10) | (op << 6) | ((rm.0 as u16) << 3) | rd.0 as u16]
+            }
+            Self::HiRegister(mnemonic, rd, rm) => {
+                let op = match mnemonic {
+                    ThumbMnemonic::ADD => 0b00,
+                    ThumbMnemonic::CMP => 0b01,
+                    _ => 0b10,
+                };
+                let h1 = (rd.0 as u16 >> 3) << 7;
+                let h2 = (rm.0 as u16 >> 3) << 6;
+                vec![
+                    (0b010001 << 10)
+                        | (op << 8)
+                        | h1
+                        | h2
+                        | (((rm.0 & 7) as u16) << 3)
+                        | (rd.0 & 7) as u16,
+                ]
+            }
+            Self::BranchExchange(mnemonic, rm) => {
+                let link = if mnemonic == ThumbMnemonic::BLX {
+                    1 << 7
+                } else {
+                    0
+                };
+                vec![0x4700 | link | ((rm.0 as u16) << 3)]
+            }
+            Self::PcRelativeLoad(rd, imm) => vec![0x4800 | ((rd.0 as u16) << 8) | (imm >> 2)],
+            Self::LoadStoreRegister(mnemonic, rd, rn, rm) => {
+                // Format 7 is 0101_LB0, format 8 is 0101_HS1
+                let op: u16 = match mnemonic {
+                    ThumbMnemonic::STR => 0b000,
+                    ThumbMnemonic::STRH => 0b001,
+                    ThumbMnemonic::STRB => 0b010,
+                    ThumbMnemonic::LDRSB => 0b011,
+                    ThumbMnemonic::LDR => 0b100,
+                    ThumbMnemonic::LDRH => 0b101,
+                    ThumbMnemonic::LDRB => 0b110,
+                    _ => 0b111,
+                };
+                vec![
+                    (0b0101 << 12)
+                        | (op << 9)
+                        | ((rm.0 as u16) << 6)
+                        | ((rn.0 as u16) << 3)
+                        | rd.0 as u16,
+                ]
+            }
+            Self::LoadStoreImmediate(mnemonic, rd, rn, imm) => {
+                let (encoding, imm5) = match mnemonic {
+                    ThumbMnemonic::STR => (0x6000, imm >> 2),
+                    ThumbMnemonic::LDR => (0x6800, imm >> 2),
+                    ThumbMnemonic::STRB => (0x7000, imm),
+                    ThumbMnemonic::LDRB => (0x7800, imm),
+                    ThumbMnemonic::STRH => (0x8000, imm >> 1),
+                    _ => (0x8800, imm >> 1),
+                };
+                vec![encoding | ((imm5 as u16) << 6) | ((rn.0 as u16) << 3) | rd.0 as u16]
+            }
+            Self::SpRelative(mnemonic, rd, imm) => {
+                let load = if mnemonic == ThumbMnemonic::LDR {
+                    1 << 11
+                } else {
+                    0
+                };
+                vec![0x9000 | load | ((rd.0 as u16) << 8) | (imm >> 2)]
+            }
+            Self::LoadAddress(rd, base, imm) => {
+                let sp = if base == AddressBase::Sp { 1 << 11 } else { 0 };
+                vec![0xA000 | sp | ((rd.0 as u16) << 8) | (imm >> 2)]
+            }
+            Self::AdjustSp(imm) => {
+                let sign = if imm < 0 { 1 << 7 } else { 0 };
+                vec![0xB000 | sign | (imm.unsigned_abs() >> 2)]
+            }
+            Self::PushPop(mnemonic, list) => {
+                let load = if mnemonic == ThumbMnemonic::POP {
+                    1 << 11
+                } else {
+                    0
+                };
+                vec![0xB400 | load | list]
+            }
+            Self::Multiple(mnemonic, rn, list) => {
+                let load = if mnemonic == ThumbMnemonic::LDMIA {
+                    1 << 11
+                } else {
+                    0
+                };
+                vec![0xC000 | load | ((rn.0 as u16) << 8) | list as u16]
+            }
+            Self::CondBranch(cond, offset) => {
+                vec![0xD000 | ((cond as u16) << 8) | offset.get() as u16]
+            }
+            Self::SoftwareInterrupt(imm) => vec![0xDF00 | imm as u16],
+            Self::Branch(offset) => vec![0xE000 | offset.get()],
+            Self::BranchLink(offset) => encode_branch24(offset, 0xD000),
+            Self::CompareBranch(mnemonic, rn, offset) => {
+                let nonzero = if mnemonic == ThumbMnemonic::CBNZ {
+                    1 << 11
+                } else {
+                    0
+                };
+                // The top bit of the offset goes in i, apart from the other five
+                let offset = offset.get() as u16;
+                let (i, imm5) = (offset >> 5, offset & 0x1F);
+                vec![0xB100 | nonzero | (i << 9) | (imm5 << 3) | rn.0 as u16]
+            }
+            Self::Hint(mnemonic) => vec![0xBF00 | (mnemonic.hint().unwrap_or_default() << 4)],
+            Self::ChangeState(mnemonic, flags) => {
+                let disable = (mnemonic == ThumbMnemonic::CPSID) as u16;
+                vec![0xB660 | (disable << 4) | flags as u16]
+            }
+            Self::Extend(mnemonic, rd, rm) => {
+                let op = match mnemonic {
+                    ThumbMnemonic::SXTH => 0b00,
+                    ThumbMnemonic::SXTB => 0b01,
+                    ThumbMnemonic::UXTH => 0b10,
+                    _ => 0b11,
+                };
+                vec![0xB200 | (op << 6) | ((rm.0 as u16) << 3) | rd.0 as u16]
+            }
+            Self::Reverse(mnemonic, rd, rm) => {
+                let op = (mnemonic == ThumbMnemonic::REV16) as u16;
+                vec![0xBA00 | (op << 6) | ((rm.0 as u16) << 3) | rd.0 as u16]
+            }
+            Self::Breakpoint(imm) => vec![0xBE00 | imm as u16],
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{ItBlock, ThumbInstruction, ThumbMnemonic, ThumbOpcode, ThumbOperand, Width};
+    use crate::{
+        cond::Cond,
+        instructions::{Rd, Rm, Rn},
+    };
+
+    #[test]
+    fn test_add_formats() {
+        let add_inst_str = "add r0, r1, r2";
+        let add_inst_expected = ThumbInstruction::AddSubtract(
+            ThumbMnemonic::ADD,
+            Rd(0),
+            Rn(1),
+            ThumbOperand::Register(Rm(2)),
+        );
+
+        assert_eq!(
+            ThumbInstruction::try_from(add_inst_str).unwrap(),
+            add_inst_expected,
+        );
+        assert_eq!(add_inst_expected.to_machine_code(), vec![0x1888]);
+
+        let cases = [
+            ("add r0, r1, #3", 0x1cc8),
+            ("add r3, #200", 0x33c8),
+            ("add r0, r8", 0x4440),
+            ("add r2, sp, #16", 0xaa04),
+            ("add r2, pc, #8", 0xa202),
+            ("add sp, #16", 0xb004),
+            ("sub sp, sp, #16", 0xb084),
+            ("sub r0, r1, r2", 0x1a88),
+        ];
+        for (inst_str, expected) in cases {
+            let encoding = ThumbInstruction::try_from(inst_str)
+                .unwrap()
+                .to_machine_code();
+            assert_eq!(encoding, vec![expected], "{inst_str}");
+        }
+    }
+
+    #[test]
+    fn test_thumb_encodings() {
+        let cases: &[(&str, &[u16])] = &[
+            ("lsl r0, r1, #2", &[0x0088]),
+            ("asr r2, r3, #32", &[0x101a]),
+            ("lsr r0, r1, #32", &[0x0808]),
+            ("lsl r0, r1, #31", &[0x07c8]),
+            ("mov r0, #1", &[0x2001]),
+            ("mov r0, r1", &[0x1c08]),
+            ("mov r8, r1", &[0x4688]),
+            ("cmp r0, #10", &[0x280a]),
+            ("cmp r0, r1", &[0x4288]),
+            ("and r0, r1", &[0x4008]),
+            ("mul r0, r1", &[0x4348]),
+            ("mvn r7, r6", &[0x43f7]),
+            ("bx lr", &[0x4770]),
+            ("blx r3", &[0x4798]),
+            ("ldr r0, [pc, #8]", &[0x4802]),
+            ("str r0, [r1, r2]", &[0x5088]),
+            ("ldrsh r0, [r1, r2]", &[0x5e88]),
+            ("ldr r0, [r1, #4]", &[0x6848]),
+            ("strb r0, [r1, #3]", &[0x70c8]),
+            ("ldrh r0, [r1, #6]", &[0x88c8]),
+            ("str r0, [sp, #12]", &[0x9003]),
+            ("push {r4-r7, lr}", &[0xb5f0]),
+            ("pop {r4, pc}", &[0xbd10]),
+            ("stmia r0!, {r1, r2}", &[0xc006]),
+            ("ldm r0!, {r1, r2}", &[0xc806]),
+            ("ldm r0, {r0, r1}", &[0xc803]),
+            ("ldm r3!, {r4}", &[0xcb10]),
+            ("stm r0!, {r1}", &[0xc002]),
+            ("beq -4", &[0xd0fe]),
+            ("bne 254", &[0xd17f]),
+            ("bne -256", &[0xd180]),
+            ("swi #0x12", &[0xdf12]),
+            ("b 8", &[0xe004]),
+            ("b 2046", &[0xe3ff]),
+            ("b -2048", &[0xe400]),
+            ("bl 0x1000", &[0xf001, 0xf800]),
+            ("bl -4", &[0xf7ff, 0xfffe]),
+            ("cbz r0, 0", &[0xb100]),
+            ("cbnz r7, 124", &[0xbbf7]),
+            ("cbz r1, 126", &[0xb3f9]),
+            ("nop", &[0xbf00]),
+            ("yield", &[0xbf10]),
+            ("wfe", &[0xbf20]),
+            ("wfi", &[0xbf30]),
+            ("sev", &[0xbf40]),
+            ("cpsid i", &[0xb672]),
+            ("cpsie if", &[0xb663]),
+            ("cpsid f", &[0xb671]),
+            ("rev r0, r1", &[0xba08]),
+            ("rev16 r2, r3", &[0xba5a]),
+            ("sxtb r0, r1", &[0xb248]),
+            ("sxth r0, r1", &[0xb208]),
+            ("uxtb r0, r1", &[0xb2c8]),
+            ("uxth r7, r6", &[0xb2b7]),
+            ("adr r0, #12", &[0xa003]),
+            ("bkpt #255", &[0xbeff]),
+            ("bkpt", &[0xbe00]),
+        ];
+        for (inst_str, expected) in cases {
+            let encoding = ThumbInstruction::try_from(*inst_str)
+                .unwrap()
+                .to_machine_code();
+            assert_eq!(encoding, *expected, "{inst_str}");
+        }
+
+        assert!(ThumbInstruction::try_from("add r0, r1, #8").is_err());
+        assert!(ThumbInstruction::try_from("ldr r0, [r1, #3]").is_err());
+        assert!(ThumbInstruction::try_from("ldr r0, [r1], #4").is_err());
+        assert!(ThumbInstruction::try_from("ldr r0, [r1, #4]!").is_err());
+        assert!(ThumbInstruction::try_from("addeq r0, r1").is_err());
+        assert!(ThumbInstruction::try_from("push {r8}").is_err());
+        // The narrow encoding writes back exactly when the base register isn't loaded
+        assert!(ThumbInstruction::try_from("ldm r0, {r1, r2}").is_err());
+        assert!(ThumbInstruction::try_from("ldmia r0!, {r0, r1}").is_err());
+        assert!(ThumbInstruction::try_from("stmia r0, {r1}").is_err());
+        assert!(ThumbInstruction::try_from("lsl r0, r1, #32").is_err());
+        assert!(ThumbInstruction::try_from("b 2048").is_err());
+        assert!(ThumbInstruction::try_from("b -2050").is_err());
+        assert!(ThumbInstruction::try_from("b 3").is_err());
+        assert!(ThumbInstruction::try_from("bne 256").is_err());
+        assert!(ThumbInstruction::try_from("bne -258").is_err());
+        assert!(ThumbInstruction::try_from("cbz r0, -2").is_err());
+        assert!(ThumbInstruction::try_from("cbz r0, 128").is_err());
+        assert!(ThumbInstruction::try_from("nop r0").is_err());
+        assert!(ThumbInstruction::try_from("cpsid ii").is_err());
+        assert!(ThumbInstruction::try_from("cpsie x").is_err());
+        assert!(ThumbInstruction::try_from("rev r8, r1").is_err());
+        assert!(ThumbInstruction::try_from("sxth r0, r1, ror #8").is_err());
+        assert!(ThumbInstruction::try_from("adr r0, #2").is_err());
+        assert!(ThumbInstruction::try_from("adr r0, #-4").is_err());
+        assert!(ThumbInstruction::try_from("bkpt #256").is_err());
+    }
+
+    #[test]
+    fn test_opcode_suffixes() {
+        let opcode = |value: &str| {
+            let opcode = ThumbOpcode::try_from(value).unwrap();
+            (opcode.mnemonic, opcode.set_flags, opcode.cond, opcode.width)
+        };
+
+        assert_eq!(
+            opcode("addseq.w"),
+            (ThumbMnemonic::ADD, true, Cond::EQ, Some(Width::Wide))
+        );
+        assert_eq!(opcode("bls"), (ThumbMnemonic::B, false, Cond::LS, None));
+        assert_eq!(opcode("lsls"), (ThumbMnemonic::LSL, true, Cond::AL, None));
+        assert_eq!(
+            opcode("ldrsh.n"),
+            (ThumbMnemonic::LDRSH, false, Cond::AL, Some(Width::Narrow))
+        );
+        assert!(ThumbOpcode::try_from("ldrs").is_err());
+        assert!(ThumbOpcode::try_from("bls.x").is_err());
+    }
+
+    #[test]
+    fn test_it_blocks() {
+        let cases = [
+            ("it al", 0xbfe8),
+            ("ite eq", 0xbf0c),
+            ("itet lt", 0xbfb6),
+            ("itee gt", 0xbfce),
+            ("itttt ne", 0xbf1f),
+        ];
+        for (inst_str, expected) in cases {
+            let block = ItBlock::parse(inst_str).unwrap().unwrap();
+            assert_eq!(block.to_machine_code(), expected, "{inst_str}");
+        }
+
+        let block = ItBlock::parse("itet lt").unwrap().unwrap();
+        assert_eq!(block.conds, vec![Cond::LT, Cond::GE, Cond::LT]);
+        assert!(ItBlock::parse("ite al").is_err());
+        assert!(ItBlock::parse("itx eq").unwrap().is_none());
+    }
+}
@@ -0,0 +1,1259 @@
+//! The 32-bit Thumb-2 encodings.
+
+use crate::{
+    arch::Feature,
+    bitfield::U5,
+    cond::Cond,
+    error::{AssemblerError, ParseError},
+    instructions::{
+        parse_bounded_immediate, parse_immediate, parse_move_wide_immediate, parse_reg_id,
+        parse_reg_list, split_operands, BarrierOption, IndexMode, Ra, Rd, Rm, Rn, Shift,
+        Unpredictable, UpDown, PC, SP,
+    },
+    thumb::{check_branch_offset, encode_branch24, split_address, ThumbMnemonic},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ShiftType {
+    LSL,
+    LSR,
+    ASR,
+    ROR,
+}
+
+impl From<ShiftType> for u16 {
+    fn from(value: ShiftType) -> Self {
+        match value {
+            ShiftType::LSL => 0b00,
+            ShiftType::LSR => 0b01,
+            ShiftType::ASR => 0b10,
+            ShiftType::ROR => 0b11,
+        }
+    }
+}
+
+impl ShiftType {
+    pub(crate) fn from_mnemonic(mnemonic: ThumbMnemonic) -> Option<Self> {
+        match mnemonic {
+            ThumbMnemonic::LSL => Some(Self::LSL),
+            ThumbMnemonic::LSR => Some(Self::LSR),
+            ThumbMnemonic::ASR => Some(Self::ASR),
+            ThumbMnemonic::ROR => Some(Self::ROR),
+            _ => None,
+        }
+    }
+}
+
+/// Encode `value` as a Thumb-2 modified immediate, returning the 12-bit `i:imm3:imm8` field.
+/// Unlike ARM's rotated 8-bit values, this also covers a byte repeated across halfwords or words.
+pub fn encode_modified_immediate(value: u32) -> Option<u16> {
+    let low = value & 0xFF;
+    let high = (value >> 8) & 0xFF;
+
+    if value <= 0xFF {
+        return Some(value as u16);
+    } else if value == (low << 16) | low {
+        return Some(0x100 | low as u16);
+    } else if value == (high << 24) | (high << 8) {
+        return Some(0x200 | high as u16);
+    } else if value == low * 0x0101_0101 {
+        return Some(0x300 | low as u16);
+    }
+
+    // Otherwise an 8-bit value with its top bit set, rotated right by 8 to 31
+    (8..32).find_map(|rotation: u32| {
+        let unrotated = value.rotate_left(rotation);
+        (unrotated & !0xFF == 0 && unrotated & 0x80 != 0)
+            .then_some(((rotation << 7) | (unrotated & 0x7F)) as u16)
+    })
+}
+
+/// The second operand of a data processing instruction.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Thumb2Operand {
+    /// An already encoded modified immediate.
+    Immediate(u16),
+    Register(Rm, Shift),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Thumb2Address {
+    Immediate(IndexMode, UpDown, u16),
+    /// A register offset shifted left by 0 to 3.
+    Register(Rm, u8),
+}
+
+/// The M-profile special registers, by their SYSm numbers.
+const SPECIAL_REGISTERS: &[(&str, u8)] = &[
+    ("apsr", 0),
+    ("iapsr", 1),
+    ("eapsr", 2),
+    ("xpsr", 3),
+    ("ipsr", 5),
+    ("epsr", 6),
+    ("iepsr", 7),
+    ("msp", 8),
+    ("psp", 9),
+    ("primask", 16),
+    ("basepri", 17),
+    ("basepri_max", 18),
+    ("faultmask", 19),
+    ("control", 20),
+];
+
+/// A status register as `mrs` and `msr` name it: whether it's the SPSR, the fields `msr` writes,
+/// and the M-profile SYSm number. The M-profile registers write the same fields as `apsr_nzcvq`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SpecialRegister {
+    pub spsr: bool,
+    pub mask: u8,
+    pub sysm: u8,
+}
+
+impl TryFrom<&str> for SpecialRegister {
+    type Error = AssemblerError;
+
+    /// Parse `apsr_nzcvq`, `cpsr_fc`, `spsr` or an M-profile register such as `primask`.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let bad = || ParseError::BadFlexOperand(value.to_owned());
+        let lower = value.trim().to_ascii_lowercase();
+        if let Some(&(_, sysm)) = SPECIAL_REGISTERS.iter().find(|(name, _)| *name == lower) {
+            return Ok(Self {
+                spsr: false,
+                mask: 0b1000,
+                sysm,
+            });
+        }
+
+        let (name, fields) = lower.split_once('_').unwrap_or((&lower, ""));
+        let (spsr, mask) = match name {
+            "apsr" => match fields {
+                "nzcvq" => (false, 0b1000),
+                "g" => (false, 0b0100),
+                "nzcvqg" => (false, 0b1100),
+                _ => return Err(bad().into()),
+            },
+            "cpsr" | "spsr" => {
+                let mut mask = 0;
+                for field in fields.chars() {
+                    let bit = match field {
+                        'c' => 0b0001,
+                        'x' => 0b0010,
+                        's' => 0b0100,
+                        'f' => 0b1000,
+                        _ => return Err(bad().into()),
+                    };
+                    if mask & bit != 0 {
+                        return Err(bad().into());
+                    }
+                    mask |= bit;
+                }
+                (name == "spsr", mask)
+            }
+            _ => return Err(bad().into()),
+        };
+        Ok(Self {
+            spsr,
+            mask,
+            sysm: 0,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Thumb2Instruction {
+    /// `op{s} Rd, Rn, #imm|Rm{, shift}`, with Rd as pc for compares and Rn as pc for moves
+    DataProcessing(ThumbMnemonic, bool, Rd, Rn, Thumb2Operand),
+    /// `addw/subw Rd, Rn, #imm12` and `movw/movt Rd, #imm16`, which ignore Rn
+    PlainImmediate(ThumbMnemonic, Rd, Rn, u16),
+    /// `lsl{s}/lsr{s}/asr{s}/ror{s} Rd, Rn, Rm`
+    RegisterShift(ShiftType, bool, Rd, Rn, Rm),
+    /// `mul/mla/mls Rd, Rn, Rm, Ra`, with Ra as pc for `mul`
+    Multiply(ThumbMnemonic, Rd, Rn, Rm, Ra),
+    Divide(ThumbMnemonic, Rd, Rn, Rm),
+    LoadStore(ThumbMnemonic, Rd, Rn, Thumb2Address),
+    /// `ldrd/strd Rt, Rt2, address` with a byte offset
+    LoadStoreDual(ThumbMnemonic, Rd, Rd, Rn, IndexMode, UpDown, u16),
+    /// `ldm/stm{db} Rn{!}, {rlist}`, with a flag for writeback
+    Multiple(ThumbMnemonic, Rn, bool, u16),
+    /// A branch with the byte offset from the PC, conditional unless the condition is `al`
+    Branch(Cond, i32),
+    TableBranch(ThumbMnemonic, Rn, Rm),
+    /// The wide `nop.w` and other hints
+    Hint(ThumbMnemonic),
+    /// `dmb/dsb/isb {option}`
+    Barrier(ThumbMnemonic, BarrierOption),
+    /// `mrs Rd, spec_reg`
+    MoveFromSpecial(Rd, SpecialRegister),
+    /// `msr spec_reg, Rn`
+    MoveToSpecial(SpecialRegister, Rn),
+    /// `bfc/bfi/sbfx/ubfx`, with Rn as pc for `bfc`, the lsb, and the msb for inserts and clears
+    /// or the width minus one for extracts
+    Bitfield(ThumbMnemonic, Rd, Rn, U5, U5),
+    /// `rbit/rev/rev16/clz Rd, Rm`
+    Reverse(ThumbMnemonic, Rd, Rm),
+    /// `sxtb/sxth/uxtb/uxth Rd, Rm{, ror #n}`, with the rotation in bytes
+    Extend(ThumbMnemonic, Rd, Rm, u8),
+    /// `smull/umull RdLo, RdHi, Rn, Rm`
+    LongMultiply(ThumbMnemonic, Rd, Rd, Rn, Rm),
+    /// `ldrex/strex Rd, Rt, [Rn{, #imm}]`, with Rd as pc for `ldrex` and a byte offset
+    Exclusive(ThumbMnemonic, Rd, Rd, Rn, u16),
+    ClearExclusive,
+    /// `pld [Rn, offset]`
+    Preload(Rn, Thumb2Address),
+}
+
+/// Parse an optional `lsl #n`-style shift following a register operand.
+pub(crate) fn parse_shift(value: Option<&str>) -> Result<(ShiftType, u8), AssemblerError> {
+    let Some(value) = value else {
+        return Ok((ShiftType::LSL, 0));
+    };
+    if value.eq_ignore_ascii_case("rrx") {
+        return Ok((ShiftType::ROR, 0));
+    }
+
+    let (name, amount) = value
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| ParseError::BadFlexOperand(value.to_owned()))?;
+    let mnemonic = ThumbMnemonic::try_from(name)?;
+    let shift = ShiftType::from_mnemonic(mnemonic)
+        .ok_or_else(|| ParseError::BadFlexOperand(value.to_owned()))?;
+    let (min, max) = match shift {
+        ShiftType::LSL => (0, 31),
+        ShiftType::LSR | ShiftType::ASR => (1, 32),
+        ShiftType::ROR => (1, 31),
+    };
+    let amount = parse_bounded_immediate(amount, max)?;
+    if amount < min {
+        return Err(ParseError::BadImmediate(value.to_owned()).into());
+    }
+
+    Ok((shift, amount as u8))
+}
+
+/// Split `[Rn{, offset}]{!}`, with `post` as any offset after the brackets, into the base register,
+/// indexing and offset text.
+fn split_indexed<'a>(
+    address_op: &'a str,
+    post: Option<&&'a str>,
+) -> Result<(Rn, IndexMode, Option<&'a str>), AssemblerError> {
+    let writeback = address_op.ends_with('!');
+    let (base, offset) = split_address(address_op.trim_end_matches('!'))?;
+    let rn = Rn(parse_reg_id(base)?);
+
+    let (index_mode, offset) = match (post, writeback) {
+        (Some(post), false) if offset.is_none() => (IndexMode::PostIndex, Some(*post)),
+        (None, true) => (IndexMode::PreIndex, offset),
+        (None, false) => (IndexMode::Offset, offset),
+        _ => return Err(ParseError::BadFlexOperand(address_op.to_string()).into()),
+    };
+    Ok((rn, index_mode, offset))
+}
+
+impl Thumb2Instruction {
+    /// Parse the operands of a wide instruction.
+    pub fn parse(
+        mnemonic: ThumbMnemonic,
+        set_flags: bool,
+        cond: Cond,
+        rest: &str,
+    ) -> Result<Self, AssemblerError> {
+        if cond != Cond::AL && mnemonic != ThumbMnemonic::B {
+            return Err(ParseError::UnexpectedCond(mnemonic.to_string()).into());
+        }
+
+        let operands = split_operands(rest);
+        let operand = |i: usize| -> Result<&str, AssemblerError> {
+            operands
+                .get(i)
+                .copied()
+                .ok_or_else(|| ParseError::RanOutOfOperands.into())
+        };
+        let reg = |i: usize| -> Result<u8, AssemblerError> { parse_reg_id(operand(i)?) };
+
+        match mnemonic {
+            ThumbMnemonic::TST | ThumbMnemonic::TEQ | ThumbMnemonic::CMP | ThumbMnemonic::CMN => {
+                let rn = Rn(reg(0)?);
+                let op2 = Self::parse_operand2(operands.get(1..).unwrap_or_default())?;
+                Ok(Self::DataProcessing(mnemonic, true, Rd(15), rn, op2))
+            }
+            ThumbMnemonic::MOV | ThumbMnemonic::MVN => {
+                let rd = Rd(reg(0)?);
+                match Self::parse_operand2(operands.get(1..).unwrap_or_default()) {
+                    Ok(op2) => Ok(Self::DataProcessing(mnemonic, set_flags, rd, Rn(15), op2)),
+                    Err(_) if mnemonic == ThumbMnemonic::MOV && !set_flags => {
+                        let imm = parse_move_wide_immediate(operand(1)?)?;
+                        Ok(Self::PlainImmediate(ThumbMnemonic::MOVW, rd, Rn(15), imm))
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            ThumbMnemonic::NEG => {
+                let (rd, rm) = (Rd(reg(0)?), Rn(reg(1)?));
+                let op2 = Thumb2Operand::Immediate(0);
+                Ok(Self::DataProcessing(mnemonic, set_flags, rd, rm, op2))
+            }
+            ThumbMnemonic::LSL | ThumbMnemonic::LSR | ThumbMnemonic::ASR | ThumbMnemonic::ROR => {
+                let shift = ShiftType::from_mnemonic(mnemonic)
+                    .ok_or_else(|| ParseError::BadMnemonic(mnemonic.to_string()))?;
+                let rd = Rd(reg(0)?);
+                let last = operands.len().max(2) - 1;
+                if operand(last)?.starts_with('#') {
+                    // An immediate shift is a move with a shifted register
+                    let rm = Rm(reg(last - 1)?);
+                    let shift = Shift::parse(Some(&format!("{mnemonic} {}", operand(last)?)))?;
+                    let op2 = Thumb2Operand::Register(rm, shift);
+                    Ok(Self::DataProcessing(
+                        ThumbMnemonic::MOV,
+                        set_flags,
+                        rd,
+                        Rn(15),
+                        op2,
+                    ))
+                } else {
+                    let rn = Rn(reg(last - 1)?);
+                    let rm = Rm(reg(last)?);
+                    Ok(Self::RegisterShift(shift, set_flags, rd, rn, rm))
+                }
+            }
+            ThumbMnemonic::ADD
+            | ThumbMnemonic::SUB
+            | ThumbMnemonic::ADC
+            | ThumbMnemonic::SBC
+            | ThumbMnemonic::RSB
+            | ThumbMnemonic::AND
+            | ThumbMnemonic::ORR
+            | ThumbMnemonic::ORN
+            | ThumbMnemonic::EOR
+            | ThumbMnemonic::BIC => {
+                let rd = Rd(reg(0)?);
+                // The two operand form uses Rd as the first source
+                let (rn, src) = match operands.len() {
+                    0..=2 => (Rn(rd.0), 1),
+                    _ if operand(2)?.starts_with('#') => (Rn(reg(1)?), 2),
+                    // With a register operand, a shift means there's one fewer register
+                    _ if parse_reg_id(operand(2)?).is_err() => (Rn(rd.0), 1),
+                    _ => (Rn(reg(1)?), 2),
+                };
+
+                match Self::parse_operand2(operands.get(src..).unwrap_or_default()) {
+                    Ok(op2) => Ok(Self::DataProcessing(mnemonic, set_flags, rd, rn, op2)),
+                    // Fall back to the plain 12-bit immediate forms
+                    Err(_)
+                        if !set_flags
+                            && matches!(mnemonic, ThumbMnemonic::ADD | ThumbMnemonic::SUB) =>
+                    {
+                        let imm = parse_bounded_immediate(operand(src)?, 0xFFF)?;
+                        let wide = if mnemonic == ThumbMnemonic::ADD {
+                            ThumbMnemonic::ADDW
+                        } else {
+                            ThumbMnemonic::SUBW
+                        };
+                        Ok(Self::PlainImmediate(wide, rd, rn, imm as u16))
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            ThumbMnemonic::ADDW | ThumbMnemonic::SUBW => {
+                let rd = Rd(reg(0)?);
+                let (rn, src) = if operands.len() == 2 {
+                    (Rn(rd.0), 1)
+                } else {
+                    (Rn(reg(1)?), 2)
+                };
+                let imm = parse_bounded_immediate(operand(src)?, 0xFFF)?;
+                Ok(Self::PlainImmediate(mnemonic, rd, rn, imm as u16))
+            }
+            ThumbMnemonic::MOVW | ThumbMnemonic::MOVT => {
+                let rd = Rd(reg(0)?);
+                let imm = parse_move_wide_immediate(operand(1)?)?;
+                Ok(Self::PlainImmediate(mnemonic, rd, Rn(15), imm))
+            }
+            ThumbMnemonic::MUL => {
+                let rd = Rd(reg(0)?);
+                let (rn, rm) = if operands.len() == 2 {
+                    (Rn(rd.0), Rm(reg(1)?))
+                } else {
+                    (Rn(reg(1)?), Rm(reg(2)?))
+                };
+                Ok(Self::Multiply(mnemonic, rd, rn, rm, Ra(15)))
+            }
+            ThumbMnemonic::MLA | ThumbMnemonic::MLS => {
+                let (rd, rn, rm, ra) = (Rd(reg(0)?), Rn(reg(1)?), Rm(reg(2)?), Ra(reg(3)?));
+                Ok(Self::Multiply(mnemonic, rd, rn, rm, ra))
+            }
+            ThumbMnemonic::SDIV | ThumbMnemonic::UDIV => {
+                let (rd, rn, rm) = (Rd(reg(0)?), Rn(reg(1)?), Rm(reg(2)?));
+                Ok(Self::Divide(mnemonic, rd, rn, rm))
+            }
+            ThumbMnemonic::LDR
+            | ThumbMnemonic::LDRB
+            | ThumbMnemonic::LDRH
+            | ThumbMnemonic::LDRSB
+            | ThumbMnemonic::LDRSH
+            | ThumbMnemonic::STR
+            | ThumbMnemonic::STRB
+            | ThumbMnemonic::STRH => Self::parse_load_store(mnemonic, &operands),
+            ThumbMnemonic::LDM
+            | ThumbMnemonic::LDMIA
+            | ThumbMnemonic::LDMDB
+            | ThumbMnemonic::STM
+            | ThumbMnemonic::STMIA
+            | ThumbMnemonic::STMDB => {
+                let base = operand(0)?;
+                let writeback = base.ends_with('!');
+                let rn = Rn(parse_reg_id(base.trim_end_matches('!'))?);
+                let list = parse_reg_list(operand(1)?)?;
+                let mnemonic = match mnemonic {
+                    ThumbMnemonic::LDMIA => ThumbMnemonic::LDM,
+                    ThumbMnemonic::STMIA => ThumbMnemonic::STM,
+                    _ => mnemonic,
+                };
+                Ok(Self::Multiple(mnemonic, rn, writeback, list))
+            }
+            ThumbMnemonic::PUSH | ThumbMnemonic::POP => {
+                let list = parse_reg_list(operand(0)?)?;
+                let mnemonic = if mnemonic == ThumbMnemonic::PUSH {
+                    ThumbMnemonic::STMDB
+                } else {
+                    ThumbMnemonic::LDM
+                };
+                Ok(Self::Multiple(mnemonic, Rn(13), true, list))
+            }
+            ThumbMnemonic::B => {
+                let offset = parse_immediate(operand(0)?)?;
+                let offset = if cond == Cond::AL {
+                    check_branch_offset(offset, -(1 << 24), (1 << 24) - 2, "±16 MiB")?
+                } else {
+                    check_branch_offset(offset, -(1 << 20), (1 << 20) - 2, "±1 MiB")?
+                };
+                Ok(Self::Branch(cond, offset))
+            }
+            ThumbMnemonic::TBB | ThumbMnemonic::TBH => {
+                let (base, index) = split_address(operand(0)?)?;
+                let index = index.ok_or(ParseError::RanOutOfOperands)?;
+                let (rm, shift) = index.split_once(',').unwrap_or((index, ""));
+                let rn = Rn(parse_reg_id(base)?);
+                let rm = Rm(parse_reg_id(rm.trim())?);
+                // tbh indexes halfwords, and has to say so
+                let expected_shift = if mnemonic == ThumbMnemonic::TBH {
+                    "lsl #1"
+                } else {
+                    ""
+                };
+                if !shift.trim().eq_ignore_ascii_case(expected_shift) {
+                    return Err(ParseError::BadFlexOperand(shift.to_owned()).into());
+                }
+                Ok(Self::TableBranch(mnemonic, rn, rm))
+            }
+            ThumbMnemonic::LDRD | ThumbMnemonic::STRD => {
+                let (rt, rt2) = (Rd(reg(0)?), Rd(reg(1)?));
+                let (rn, index_mode, offset) = split_indexed(operand(2)?, operands.get(3))?;
+                let imm = match offset {
+                    Some(offset) => parse_immediate(offset)?,
+                    None => 0,
+                };
+                let up_down = if imm < 0 { UpDown::Down } else { UpDown::Up };
+                // The offset is counted in words
+                let magnitude = imm.unsigned_abs();
+                if magnitude > 1020 || magnitude % 4 != 0 || operands.len() > 4 {
+                    return Err(
+                        ParseError::BadImmediate(offset.unwrap_or_default().to_owned()).into(),
+                    );
+                }
+                Ok(Self::LoadStoreDual(
+                    mnemonic,
+                    rt,
+                    rt2,
+                    rn,
+                    index_mode,
+                    up_down,
+                    magnitude as u16,
+                ))
+            }
+            _ if mnemonic.hint().is_some() && operands.is_empty() => Ok(Self::Hint(mnemonic)),
+            ThumbMnemonic::DMB | ThumbMnemonic::DSB | ThumbMnemonic::ISB => {
+                let option = match operands.first() {
+                    None => BarrierOption::SY,
+                    Some(option) => BarrierOption::try_from(*option)?,
+                };
+                if operands.len() > 1
+                    || mnemonic == ThumbMnemonic::ISB && option != BarrierOption::SY
+                {
+                    return Err(ParseError::BadFlexOperand(rest.trim().to_owned()).into());
+                }
+                Ok(Self::Barrier(mnemonic, option))
+            }
+            ThumbMnemonic::CLREX if operands.is_empty() => Ok(Self::ClearExclusive),
+            ThumbMnemonic::BFC | ThumbMnemonic::BFI | ThumbMnemonic::SBFX | ThumbMnemonic::UBFX => {
+                let rd = Rd(reg(0)?);
+                let (rn, lsb_index) = if mnemonic == ThumbMnemonic::BFC {
+                    (Rn(PC), 1)
+                } else {
+                    (Rn(reg(1)?), 2)
+                };
+                let (lsb_op, width_op) = (operand(lsb_index)?, operand(lsb_index + 1)?);
+                let lsb = parse_bounded_immediate(lsb_op, 31)?;
+                let width = parse_bounded_immediate(width_op, 32 - lsb)?;
+                // Insert/clear encode the msb, extracts encode width - 1
+                let field = match mnemonic {
+                    ThumbMnemonic::BFC | ThumbMnemonic::BFI => (lsb + width).checked_sub(1),
+                    _ => width.checked_sub(1),
+                };
+                let field = field
+                    .filter(|_| width > 0)
+                    .and_then(|field| U5::new(field as u8))
+                    .ok_or_else(|| ParseError::BadImmediate(width_op.to_owned()))?;
+                let lsb = U5::new(lsb as u8)
+                    .ok_or_else(|| ParseError::BadImmediate(lsb_op.to_owned()))?;
+                Ok(Self::Bitfield(mnemonic, rd, rn, lsb, field))
+            }
+            ThumbMnemonic::RBIT
+            | ThumbMnemonic::REV
+            | ThumbMnemonic::REV16
+            | ThumbMnemonic::CLZ => Ok(Self::Reverse(mnemonic, Rd(reg(0)?), Rm(reg(1)?))),
+            ThumbMnemonic::SXTB
+            | ThumbMnemonic::SXTH
+            | ThumbMnemonic::UXTB
+            | ThumbMnemonic::UXTH => {
+                let rotation = match operands.get(2) {
+                    None => 0,
+                    Some(rotation) => match parse_shift(Some(rotation))? {
+                        (ShiftType::ROR, amount @ (8 | 16 | 24)) => amount / 8,
+                        _ => return Err(ParseError::BadFlexOperand((*rotation).to_owned()).into()),
+                    },
+                };
+                Ok(Self::Extend(mnemonic, Rd(reg(0)?), Rm(reg(1)?), rotation))
+            }
+            ThumbMnemonic::SMULL | ThumbMnemonic::UMULL => {
+                let (rd_lo, rd_hi) = (Rd(reg(0)?), Rd(reg(1)?));
+                let (rn, rm) = (Rn(reg(2)?), Rm(reg(3)?));
+                Ok(Self::LongMultiply(mnemonic, rd_lo, rd_hi, rn, rm))
+            }
+            ThumbMnemonic::LDREX | ThumbMnemonic::STREX => {
+                let (rd, first) = if mnemonic == ThumbMnemonic::STREX {
+                    (Rd(reg(0)?), 1)
+                } else {
+                    (Rd(PC), 0)
+                };
+                let rt = Rd(reg(first)?);
+                let (base, offset) = split_address(operand(first + 1)?)?;
+                // The offset is counted in words
+                let imm = match offset {
+                    Some(offset) => parse_bounded_immediate(offset, 1020)
+                        .ok()
+                        .filter(|imm| imm % 4 == 0)
+                        .ok_or_else(|| ParseError::BadImmediate(offset.to_owned()))?,
+                    None => 0,
+                };
+                Ok(Self::Exclusive(
+                    mnemonic,
+                    rd,
+                    rt,
+                    Rn(parse_reg_id(base)?),
+                    imm as u16,
+                ))
+            }
+            ThumbMnemonic::PLD => {
+                // A preload is a byte load into pc, without the writeback forms
+                let address_op = operand(0)?;
+                match Self::parse_load_store(ThumbMnemonic::LDRB, &["pc", address_op])? {
+                    Self::LoadStore(_, _, rn, address)
+                        if operands.len() == 1
+                            && !matches!(
+                                address,
+                                Thumb2Address::Immediate(
+                                    IndexMode::PreIndex | IndexMode::PostIndex,
+                                    ..
+                                )
+                            ) =>
+                    {
+                        Ok(Self::Preload(rn, address))
+                    }
+                    _ => Err(ParseError::BadFlexOperand(rest.trim().to_owned()).into()),
+                }
+            }
+            // `adr` is `addw/subw Rd, pc, #imm` with the offset from the word-aligned PC
+            ThumbMnemonic::ADR => {
+                let (rd, imm_op) = (Rd(reg(0)?), operand(1)?);
+                let imm = parse_immediate(imm_op)?;
+                let wide = if imm < 0 {
+                    ThumbMnemonic::SUBW
+                } else {
+                    ThumbMnemonic::ADDW
+                };
+                let imm = parse_bounded_immediate(&imm.unsigned_abs().to_string(), 0xFFF)
+                    .map_err(|_| ParseError::BadImmediate(imm_op.to_owned()))?;
+                Ok(Self::PlainImmediate(wide, rd, Rn(PC), imm as u16))
+            }
+            ThumbMnemonic::MRS => {
+                let spec_reg = SpecialRegister::try_from(operand(1)?)?;
+                Ok(Self::MoveFromSpecial(Rd(reg(0)?), spec_reg))
+            }
+            ThumbMnemonic::MSR => {
+                let spec_reg = SpecialRegister::try_from(operand(0)?)?;
+                if spec_reg.mask == 0 {
+                    return Err(ParseError::BadFlexOperand(operand(0)?.to_owned()).into());
+                }
+                Ok(Self::MoveToSpecial(spec_reg, Rn(reg(1)?)))
+            }
+            _ => Err(ParseError::BadMnemonic(mnemonic.to_string()).into()),
+        }
+    }
+
+    /// Parse `#imm` or `Rm{, shift}`.
+    fn parse_operand2(operands: &[&str]) -> Result<Thumb2Operand, AssemblerError> {
+        let first = operands.first().ok_or(ParseError::RanOutOfOperands)?;
+        if first.starts_with('#') {
+            let imm = parse_immediate(first)?;
+            return u32::try_from(imm)
+                .ok()
+                .and_then(encode_modified_immediate)
+                .map(Thumb2Operand::Immediate)
+                .ok_or_else(|| ParseError::BadImmediate((*first).to_owned()).into());
+        }
+
+        let rm = Rm(parse_reg_id(first)?);
+        let shift = Shift::parse(operands.get(1).copied())?;
+        Ok(Thumb2Operand::Register(rm, shift))
+    }
+
+    fn parse_load_store(
+        mnemonic: ThumbMnemonic,
+        operands: &[&str],
+    ) -> Result<Self, AssemblerError> {
+        let rt_op = operands.first().ok_or(ParseError::RanOutOfOperands)?;
+        let address_op = operands.get(1).ok_or(ParseError::RanOutOfOperands)?;
+        let rt = Rd(parse_reg_id(rt_op)?);
+        let (rn, index_mode, offset) = split_indexed(address_op, operands.get(2))?;
+
+        let address = match offset {
+            None => Thumb2Address::Immediate(index_mode, UpDown::Up, 0),
+            Some(offset) if offset.starts_with('#') => {
+                let imm = parse_immediate(offset)?;
+                let up_down = if imm < 0 { UpDown::Down } else { UpDown::Up };
+                let max =
+                    if index_mode == IndexMode::Offset && (up_down == UpDown::Up || rn.0 == 15) {
+                        0xFFF
+                    } else {
+                        0xFF
+                    };
+                let imm = parse_bounded_immediate(&imm.unsigned_abs().to_string(), max)
+                    .map_err(|_| ParseError::BadImmediate(offset.to_owned()))?;
+                Thumb2Address::Immediate(index_mode, up_down, imm as u16)
+            }
+            Some(offset) if index_mode == IndexMode::Offset => {
+                let (rm, shift) = offset.split_once(',').unwrap_or((offset, ""));
+                let rm = Rm(parse_reg_id(rm.trim())?);
+                let shift = shift.trim();
+                let amount = if shift.is_empty() {
+                    0
+                } else {
+                    match parse_shift(Some(shift))? {
+                        (ShiftType::LSL, amount) if amount <= 3 => amount,
+                        _ => return Err(ParseError::BadFlexOperand(shift.to_owned()).into()),
+                    }
+                };
+                Thumb2Address::Register(rm, amount)
+            }
+            Some(offset) => return Err(ParseError::BadFlexOperand(offset.to_owned()).into()),
+        };
+
+        // Literal loads only have the offset form
+        if rn.0 == 15 && !matches!(address, Thumb2Address::Immediate(IndexMode::Offset, ..)) {
+            return Err(ParseError::BadFlexOperand(address_op.to_string()).into());
+        }
+
+        Ok(Self::LoadStore(mnemonic, rt, rn, address))
+    }
+
+    /// The architecture feature this instruction needs.
+    pub fn required_feature(&self) -> Feature {
+        match self {
+            Self::Divide(..) => Feature::Idiv,
+            Self::Barrier(..) => Feature::Barrier,
+            Self::MoveFromSpecial(..) | Self::MoveToSpecial(..) => Feature::ThumbSystem,
+            Self::ClearExclusive => Feature::V7,
+            _ => Feature::Thumb2,
+        }
+    }
+
+    /// Whether what this does with its registers is UNPREDICTABLE, which for most instructions
+    /// includes using sp.
+    pub fn unpredictable(&self) -> Option<Unpredictable> {
+        let bad = |reg: u8| reg == SP || reg == PC;
+        match *self {
+            Self::DataProcessing(mnemonic, set_flags, rd, rn, op2) => {
+                let compare = matches!(
+                    mnemonic,
+                    ThumbMnemonic::TST
+                        | ThumbMnemonic::TEQ
+                        | ThumbMnemonic::CMP
+                        | ThumbMnemonic::CMN
+                );
+                let moves = matches!(mnemonic, ThumbMnemonic::MOV | ThumbMnemonic::MVN);
+                let rm = match op2 {
+                    Thumb2Operand::Register(rm, ..) => Some(rm.0),
+                    Thumb2Operand::Immediate(_) => None,
+                };
+                // Only add and sub can work on sp, compares can read it, and a plain mov can
+                // copy between it and another register
+                let sp_arithmetic = matches!(mnemonic, ThumbMnemonic::ADD | ThumbMnemonic::SUB);
+                let plain_move = mnemonic == ThumbMnemonic::MOV && !set_flags;
+                let destination = compare
+                    || !bad(rd.0)
+                    || rd.0 == SP && (sp_arithmetic && rn.0 == SP || plain_move);
+                let first = moves || rn.0 != PC && (rn.0 != SP || sp_arithmetic || compare);
+                let second = match rm {
+                    Some(PC) => false,
+                    Some(SP) => plain_move && rd.0 != SP,
+                    _ => true,
+                };
+                if !destination {
+                    Unpredictable::error("sp and pc can't be the destination")
+                } else if !first {
+                    Unpredictable::error("sp and pc can't be the first operand")
+                } else if !second {
+                    Unpredictable::error("sp and pc can't be the second operand")
+                } else {
+                    None
+                }
+            }
+            Self::PlainImmediate(mnemonic, rd, rn, _) => {
+                let sp_arithmetic = matches!(mnemonic, ThumbMnemonic::ADDW | ThumbMnemonic::SUBW)
+                    && rd.0 == SP
+                    && rn.0 == SP;
+                if bad(rd.0) && !sp_arithmetic {
+                    Unpredictable::error("sp and pc can't be the destination")
+                } else {
+                    None
+                }
+            }
+            Self::RegisterShift(_, _, rd, rn, rm) if [rd.0, rn.0, rm.0].into_iter().any(bad) => {
+                Unpredictable::error("sp and pc can't be used in a register-controlled shift")
+            }
+            Self::Multiply(_, rd, rn, rm, _) if [rd.0, rn.0, rm.0].into_iter().any(bad) => {
+                Unpredictable::error("sp and pc can't be operands of a multiply")
+            }
+            Self::Multiply(.., ra) if ra.0 == SP => {
+                Unpredictable::error("sp can't be the accumulator")
+            }
+            Self::Divide(_, rd, rn, rm) if [rd.0, rn.0, rm.0].into_iter().any(bad) => {
+                Unpredictable::error("sp and pc can't be operands of a divide")
+            }
+            Self::LoadStore(mnemonic, rt, rn, address) => {
+                let word = matches!(mnemonic, ThumbMnemonic::LDR | ThumbMnemonic::STR);
+                let load = !matches!(
+                    mnemonic,
+                    ThumbMnemonic::STR | ThumbMnemonic::STRB | ThumbMnemonic::STRH
+                );
+                let writeback = matches!(
+                    address,
+                    Thumb2Address::Immediate(IndexMode::PreIndex | IndexMode::PostIndex, ..)
+                );
+                if !word && bad(rt.0) || !load && rt.0 == PC {
+                    Unpredictable::error("sp and pc can't be transferred by this instruction")
+                } else if matches!(address, Thumb2Address::Register(rm, _) if bad(rm.0)) {
+                    Unpredictable::error("sp and pc can't be an offset register")
+                } else if writeback && rn.0 == rt.0 {
+                    Unpredictable::warning("the base register is written back and transferred")
+                } else {
+                    None
+                }
+            }
+            Self::Multiple(mnemonic, rn, writeback, list) => {
+                let load = matches!(mnemonic, ThumbMnemonic::LDM | ThumbMnemonic::LDMDB);
+                if rn.0 == PC {
+                    Unpredictable::error("pc can't be the base register")
+                } else if list & (1 << SP) != 0 {
+                    Unpredictable::error("sp can't be in the register list")
+                } else if !load && list & (1 << PC) != 0 {
+                    Unpredictable::error("pc can't be stored")
+                } else if load && list & (1 << PC) != 0 && list & (1 << 14) != 0 {
+                    Unpredictable::error("lr and pc can't both be loaded")
+                } else if writeback && list & (1 << rn.0) != 0 {
+                    Unpredictable::error("the base register can't be written back and transferred")
+                } else {
+                    None
+                }
+            }
+            Self::TableBranch(_, rn, rm) if rn.0 == SP || bad(rm.0) => {
+                Unpredictable::error("sp and pc can't be the index register or sp the base")
+            }
+            Self::LoadStoreDual(mnemonic, rt, rt2, rn, index_mode, ..) => {
+                let writeback = index_mode != IndexMode::Offset;
+                if bad(rt.0) || bad(rt2.0) {
+                    Unpredictable::error("sp and pc can't be transferred by this instruction")
+                } else if mnemonic == ThumbMnemonic::LDRD && rt.0 == rt2.0 {
+                    Unpredictable::error("the two registers loaded must differ")
+                } else if writeback && (rn.0 == PC || rn.0 == rt.0 || rn.0 == rt2.0) {
+                    Unpredictable::error("the base register can't be pc or transferred")
+                } else {
+                    None
+                }
+            }
+            Self::MoveFromSpecial(rd, _) if bad(rd.0) => {
+                Unpredictable::error("sp and pc can't be the destination")
+            }
+            Self::MoveToSpecial(_, rn) if bad(rn.0) => {
+                Unpredictable::error("sp and pc can't be written to a status register")
+            }
+            Self::Bitfield(_, rd, rn, ..) if bad(rd.0) || rn.0 == SP => {
+                Unpredictable::error("sp and pc can't be operands of a bitfield instruction")
+            }
+            Self::Reverse(_, rd, rm) | Self::Extend(_, rd, rm, _) if bad(rd.0) || bad(rm.0) => {
+                Unpredictable::error("sp and pc can't be operands of this instruction")
+            }
+            Self::LongMultiply(_, rd_lo, rd_hi, rn, rm) => {
+                if [rd_lo.0, rd_hi.0, rn.0, rm.0].into_iter().any(bad) {
+                    Unpredictable::error("sp and pc can't be operands of a multiply")
+                } else if rd_lo.0 == rd_hi.0 {
+                    Unpredictable::error("the two destination registers must differ")
+                } else {
+                    None
+                }
+            }
+            Self::Exclusive(mnemonic, rd, rt, rn, _) => {
+                let store = mnemonic == ThumbMnemonic::STREX;
+                if bad(rt.0) || store && bad(rd.0) || rn.0 == PC {
+                    Unpredictable::error("sp and pc can't be transferred, or pc be the base")
+                } else if store && (rd.0 == rt.0 || rd.0 == rn.0) {
+                    Unpredictable::error("the status register can't be transferred or the base")
+                } else {
+                    None
+                }
+            }
+            Self::Preload(_, Thumb2Address::Register(rm, _)) if bad(rm.0) => {
+                Unpredictable::error("sp and pc can't be an offset register")
+            }
+            _ => None,
+        }
+    }
+
+    /// Encode as a pair of halfwords, in the order they appear in memory.
+    pub fn to_machine_code(self) -> Vec<u16> {
+        match self {
+            Self::DataProcessing(mnemonic, set_flags, rd, rn, op2) => {
+                let op: u16 = match mnemonic {
+                    ThumbMnemonic::AND | ThumbMnemonic::TST => 0b0000,
+                    ThumbMnemonic::BIC => 0b0001,
+                    ThumbMnemonic::ORR | ThumbMnemonic::MOV => 0b0010,
+                    ThumbMnemonic::ORN | ThumbMnemonic::MVN => 0b0011,
+                    ThumbMnemonic::EOR | ThumbMnemonic::TEQ => 0b0100,
+                    ThumbMnemonic::ADD | ThumbMnemonic::CMN => 0b1000,
+                    ThumbMnemonic::ADC => 0b1010,
+                    ThumbMnemonic::SBC => 0b1011,
+                    ThumbMnemonic::SUB | ThumbMnemonic::CMP => 0b1101,
+                    _ => 0b1110,
+                };
+                let s = (set_flags as u16) << 4;
+
+                match op2 {
+                    Thumb2Operand::Immediate(imm12) => vec![
+                        0xF000 | ((imm12 >> 11) << 10) | (op << 5) | s | rn.0 as u16,
+                        (((imm12 >> 8) & 0x7) << 12) | ((rd.0 as u16) << 8) | (imm12 & 0xFF),
+                    ],
+                    Thumb2Operand::Register(rm, Shift(shift, amount)) => {
+                        let amount = amount.get() as u16;
+                        vec![
+                            0xEA00 | (op << 5) | s | rn.0 as u16,
+                            ((amount >> 2) << 12)
+                                | ((rd.0 as u16) << 8)
+                                | ((amount & 0x3) << 6)
+                                | (u16::from(shift) << 4)
+                                | rm.0 as u16,
+                        ]
+                    }
+                }
+            }
+            Self::PlainImmediate(mnemonic, rd, rn, imm) => {
+                let (encoding, high) = match mnemonic {
+                    ThumbMnemonic::ADDW => (0xF200, rn.0 as u16),
+                    ThumbMnemonic::SUBW => (0xF2A0, rn.0 as u16),
+                    ThumbMnemonic::MOVW => (0xF240, imm >> 12),
+                    _ => (0xF2C0, imm >> 12),
+                };
+                vec![
+                    encoding | (((imm >> 11) & 1) << 10) | high,
+                    (((imm >> 8) & 0x7) << 12) | ((rd.0 as u16) << 8) | (imm & 0xFF),
+                ]
+            }
+            Self::RegisterShift(shift, set_flags, rd, rn, rm) => vec![
+                0xFA00 | (u16::from(shift) << 5) | ((set_flags as u16) << 4) | rn.0 as u16,
+                0xF000 | ((rd.0 as u16) << 8) | rm.0 as u16,
+            ],
+            Self::Multiply(mnemonic, rd, rn, rm, ra) => {
+                let subtract = if mnemonic == ThumbMnemonic::MLS {
+                    0x10
+                } else {
+                    0
+                };
+                vec![
+                    0xFB00 | rn.0 as u16,
+                    ((ra.0 as u16) << 12) | ((rd.0 as u16) << 8) | subtract | rm.0 as u16,
+                ]
+            }
+            Self::Divide(mnemonic, rd, rn, rm) => {
+                let encoding = if mnemonic == ThumbMnemonic::UDIV {
+                    0xFBB0
+                } else {
+                    0xFB90
+                };
+                vec![
+                    encoding | rn.0 as u16,
+                    0xF0F0 | ((rd.0 as u16) << 8) | rm.0 as u16,
+                ]
+            }
+            Self::LoadStore(mnemonic, rt, rn, address) => {
+                let (sign, size, load): (u16, u16, u16) = match mnemonic {
+                    ThumbMnemonic::STRB => (0, 0b00, 0),
+                    ThumbMnemonic::STRH => (0, 0b01, 0),
+                    ThumbMnemonic::STR => (0, 0b10, 0),
+                    ThumbMnemonic::LDRB => (0, 0b00, 1),
+                    ThumbMnemonic::LDRH => (0, 0b01, 1),
+                    ThumbMnemonic::LDR => (0, 0b10, 1),
+                    ThumbMnemonic::LDRSB => (1, 0b00, 1),
+                    _ => (1, 0b01, 1),
+                };
+                let first = 0xF800 | (sign << 8) | (size << 5) | (load << 4) | rn.0 as u16;
+                let rt = (rt.0 as u16) << 12;
+
+                match address {
+                    Thumb2Address::Immediate(IndexMode::Offset, up_down, imm)
+                        if rn.0 == 15 || up_down == UpDown::Up && imm > 0xFF =>
+                    {
+                        let up = (up_down == UpDown::Up) as u16;
+                        vec![first | (up << 7), rt | imm]
+                    }
+                    Thumb2Address::Immediate(IndexMode::Offset, UpDown::Up, imm) => {
+                        vec![first | (1 << 7), rt | imm]
+                    }
+                    Thumb2Address::Immediate(index_mode, up_down, imm) => {
+                        let pre = (index_mode != IndexMode::PostIndex) as u16;
+                        let up = (up_down == UpDown::Up) as u16;
+                        let writeback = (index_mode != IndexMode::Offset) as u16;
+                        vec![
+                            first,
+                            rt | 0x800 | (pre << 10) | (up << 9) | (writeback << 8) | imm,
+                        ]
+                    }
+                    Thumb2Address::Register(rm, shift) => {
+                        vec![first, rt | ((shift as u16) << 4) | rm.0 as u16]
+                    }
+                }
+            }
+            Self::Multiple(mnemonic, rn, writeback, list) => {
+                let (encoding, load) = match mnemonic {
+                    ThumbMnemonic::STM => (0xE880, 0),
+                    ThumbMnemonic::LDM => (0xE880, 1),
+                    ThumbMnemonic::STMDB => (0xE900, 0),
+                    _ => (0xE900, 1),
+                };
+                vec![
+                    encoding | ((writeback as u16) << 5) | (load << 4) | rn.0 as u16,
+                    list,
+                ]
+            }
+            Self::Branch(Cond::AL, offset) => encode_branch24(offset, 0x9000),
+            Self::Branch(cond, offset) => {
+                let offset = offset as u32;
+                let s = (offset >> 20) & 1;
+                let j2 = (offset >> 19) & 1;
+                let j1 = (offset >> 18) & 1;
+                let imm6 = (offset >> 12) & 0x3F;
+                let imm11 = (offset >> 1) & 0x7FF;
+                vec![
+                    0xF000 | (s << 10) as u16 | ((cond as u16) << 6) | imm6 as u16,
+                    0x8000 | (j1 << 13) as u16 | (j2 << 11) as u16 | imm11 as u16,
+                ]
+            }
+            Self::TableBranch(mnemonic, rn, rm) => {
+                let halfword = if mnemonic == ThumbMnemonic::TBH {
+                    1 << 4
+                } else {
+                    0
+                };
+                vec![0xE8D0 | rn.0 as u16, 0xF000 | halfword | rm.0 as u16]
+            }
+            Self::LoadStoreDual(mnemonic, rt, rt2, rn, index_mode, up_down, imm) => {
+                let pre = (index_mode != IndexMode::PostIndex) as u16;
+                let up = (up_down == UpDown::Up) as u16;
+                let writeback = (index_mode != IndexMode::Offset) as u16;
+                let load = (mnemonic == ThumbMnemonic::LDRD) as u16;
+                vec![
+                    0xE840 | (pre << 8) | (up << 7) | (writeback << 5) | (load << 4) | rn.0 as u16,
+                    ((rt.0 as u16) << 12) | ((rt2.0 as u16) << 8) | (imm >> 2),
+                ]
+            }
+            Self::Hint(mnemonic) => vec![0xF3AF, 0x8000 | mnemonic.hint().unwrap_or_default()],
+            Self::Barrier(mnemonic, option) => {
+                let op = match mnemonic {
+                    ThumbMnemonic::DSB => 0x4,
+                    ThumbMnemonic::DMB => 0x5,
+                    _ => 0x6,
+                };
+                vec![0xF3BF, 0x8F00 | (op << 4) | u8::from(option) as u16]
+            }
+            Self::MoveFromSpecial(rd, spec_reg) => vec![
+                0xF3EF | ((spec_reg.spsr as u16) << 4),
+                0x8000 | ((rd.0 as u16) << 8) | spec_reg.sysm as u16,
+            ],
+            Self::MoveToSpecial(spec_reg, rn) => vec![
+                0xF380 | ((spec_reg.spsr as u16) << 4) | rn.0 as u16,
+                0x8000 | ((spec_reg.mask as u16) << 8) | spec_reg.sysm as u16,
+            ],
+            Self::Bitfield(mnemonic, rd, rn, lsb, field) => {
+                let encoding = match mnemonic {
+                    ThumbMnemonic::SBFX => 0xF340,
+                    ThumbMnemonic::UBFX => 0xF3C0,
+                    _ => 0xF360,
+                };
+                let lsb = lsb.get() as u16;
+                vec![
+                    encoding | rn.0 as u16,
+                    ((lsb >> 2) << 12)
+                        | ((rd.0 as u16) << 8)
+                        | ((lsb & 0x3) << 6)
+                        | field.get() as u16,
+                ]
+            }
+            Self::Reverse(mnemonic, rd, rm) => {
+                let (op1, op2) = match mnemonic {
+                    ThumbMnemonic::REV => (0b01, 0b00),
+                    ThumbMnemonic::REV16 => (0b01, 0b01),
+                    ThumbMnemonic::RBIT => (0b01, 0b10),
+                    _ => (0b11, 0b00),
+                };
+                vec![
+                    0xFA80 | (op1 << 4) | rm.0 as u16,
+                    0xF080 | ((rd.0 as u16) << 8) | (op2 << 4) | rm.0 as u16,
+                ]
+            }
+            Self::Extend(mnemonic, rd, rm, rotation) => {
+                let op = match mnemonic {
+                    ThumbMnemonic::SXTH => 0b000,
+                    ThumbMnemonic::UXTH => 0b001,
+                    ThumbMnemonic::SXTB => 0b100,
+                    _ => 0b101,
+                };
+                vec![
+                    0xFA0F | (op << 4),
+                    0xF080 | ((rd.0 as u16) << 8) | ((rotation as u16) << 4) | rm.0 as u16,
+                ]
+            }
+            Self::LongMultiply(mnemonic, rd_lo, rd_hi, rn, rm) => {
+                let encoding = if mnemonic == ThumbMnemonic::UMULL {
+                    0xFBA0
+                } else {
+                    0xFB80
+                };
+                vec![
+                    encoding | rn.0 as u16,
+                    ((rd_lo.0 as u16) << 12) | ((rd_hi.0 as u16) << 8) | rm.0 as u16,
+                ]
+            }
+            Self::Exclusive(mnemonic, rd, rt, rn, imm) => {
+                let load = (mnemonic == ThumbMnemonic::LDREX) as u16;
+                vec![
+                    0xE840 | (load << 4) | rn.0 as u16,
+                    ((rt.0 as u16) << 12) | ((rd.0 as u16) << 8) | (imm >> 2),
+                ]
+            }
+            Self::ClearExclusive => vec![0xF3BF, 0x8F2F],
+            Self::Preload(rn, address) => {
+                Self::LoadStore(ThumbMnemonic::LDRB, Rd(PC), rn, address).to_machine_code()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{encode_modified_immediate, Thumb2Instruction};
+    use crate::{cond::Cond, instructions::Severity, thumb::ThumbOpcode};
+
+    fn assemble(value: &str) -> Vec<u16> {
+        let (opcode, rest) = value.split_once(' ').unwrap_or((value, ""));
+        let opcode = ThumbOpcode::try_from(opcode).unwrap();
+        Thumb2Instruction::parse(opcode.mnemonic, opcode.set_flags, opcode.cond, rest)
+            .unwrap()
+            .to_machine_code()
+    }
+
+    #[test]
+    fn test_modified_immediate() {
+        assert_eq!(encode_modified_immediate(0xAB), Some(0x0AB));
+        assert_eq!(encode_modified_immediate(0x00AB_00AB), Some(0x1AB));
+        assert_eq!(encode_modified_immediate(0xAB00_AB00), Some(0x2AB));
+        assert_eq!(encode_modified_immediate(0xABAB_ABAB), Some(0x3AB));
+        assert_eq!(encode_modified_immediate(0x8000_0000), Some(0x400));
+        assert_eq!(encode_modified_immediate(0x0000_0FF0), Some(0xE7F));
+        assert_eq!(encode_modified_immediate(0x0000_0101), None);
+        assert_eq!(encode_modified_immediate(0x0001_FE00), Some(0xBFF));
+        assert_eq!(encode_modified_immediate(0x00FF_00FE), None);
+    }
+
+    #[test]
+    fn test_thumb2_encodings() {
+        let cases: &[(&str, [u16; 2])] = &[
+            ("add.w r0, r1, #1", [0xf101, 0x0001]),
+            ("adds.w r0, r1, r2, lsl #3", [0xeb11, 0x00c2]),
+            ("addw r0, r1, #0xfff", [0xf601, 0x70ff]),
+            ("sub.w sp, sp, #0x10", [0xf1ad, 0x0d10]),
+            ("orr.w r0, r0, #0xff00ff00", [0xf040, 0x20ff]),
+            ("mov.w r0, #0x1234", [0xf241, 0x2034]),
+            ("mvn.w r1, #0", [0xf06f, 0x0100]),
+            ("cmp.w r0, #0x100", [0xf5b0, 0x7f80]),
+            ("tst.w r0, r1, lsr #2", [0xea10, 0x0f91]),
+            ("add.w r0, r1, r2, asr #32", [0xeb01, 0x0022]),
+            ("lsr.w r3, r4, #32", [0xea4f, 0x0314]),
+            ("orr.w r0, r1, r2, lsl #31", [0xea41, 0x70c2]),
+            ("lsl.w r0, r1, #3", [0xea4f, 0x00c1]),
+            ("asrs.w r0, r1, r2", [0xfa51, 0xf002]),
+            ("movt r0, #0xffff", [0xf6cf, 0x70ff]),
+            ("mul.w r0, r1, r2", [0xfb01, 0xf002]),
+            ("mls.w r0, r1, r2, r3", [0xfb01, 0x3012]),
+            ("udiv.w r0, r1, r2", [0xfbb1, 0xf0f2]),
+            ("ldr.w r0, [r1, #4]", [0xf8d1, 0x0004]),
+            ("ldr.w r0, [r1, #-4]", [0xf851, 0x0c04]),
+            ("ldrb.w r0, [r1], #1", [0xf811, 0x0b01]),
+            ("str.w r0, [r1, #8]!", [0xf841, 0x0f08]),
+            ("ldrsh.w r0, [r1, r2, lsl #1]", [0xf931, 0x0012]),
+            ("ldr.w r0, [pc, #-8]", [0xf85f, 0x0008]),
+            ("ldm.w r0!, {r1, r2}", [0xe8b0, 0x0006]),
+            ("push.w {r4-r11, lr}", [0xe92d, 0x4ff0]),
+            ("pop.w {r4-r11, pc}", [0xe8bd, 0x8ff0]),
+            ("b.w 0x1000", [0xf001, 0xb800]),
+            ("b.w -4", [0xf7ff, 0xbffe]),
+            ("bne.w -0x100000", [0xf440, 0x8000]),
+            ("tbb.w [r0, r1]", [0xe8d0, 0xf001]),
+            ("tbh.w [pc, r1, lsl #1]", [0xe8df, 0xf011]),
+            ("nop.w", [0xf3af, 0x8000]),
+            ("sev.w", [0xf3af, 0x8004]),
+            ("dmb", [0xf3bf, 0x8f5f]),
+            ("dmb ish", [0xf3bf, 0x8f5b]),
+            ("dsb sy", [0xf3bf, 0x8f4f]),
+            ("isb", [0xf3bf, 0x8f6f]),
+            ("mrs r3, cpsr", [0xf3ef, 0x8300]),
+            ("mrs r1, spsr", [0xf3ff, 0x8100]),
+            ("mrs r2, control", [0xf3ef, 0x8214]),
+            ("msr apsr_nzcvq, r1", [0xf381, 0x8800]),
+            ("msr apsr_g, r3", [0xf383, 0x8400]),
+            ("msr cpsr_fc, r2", [0xf382, 0x8900]),
+            ("msr spsr_fsxc, r4", [0xf394, 0x8f00]),
+            ("msr basepri_max, r1", [0xf381, 0x8812]),
+            ("ldrd r0, r1, [r2]", [0xe9d2, 0x0100]),
+            ("ldrd r0, r1, [r2, #-8]!", [0xe972, 0x0102]),
+            ("strd r2, r3, [r4], #16", [0xe8e4, 0x2304]),
+            ("strd r0, r2, [sp, #1020]", [0xe9cd, 0x02ff]),
+            ("bfi r0, r1, #4, #8", [0xf361, 0x100b]),
+            ("bfc r2, #0, #32", [0xf36f, 0x021f]),
+            ("ubfx r0, r1, #3, #5", [0xf3c1, 0x00c4]),
+            ("sbfx r3, r4, #31, #1", [0xf344, 0x73c0]),
+            ("rbit r0, r1", [0xfa91, 0xf0a1]),
+            ("clz r2, r3", [0xfab3, 0xf283]),
+            ("rev.w r8, r1", [0xfa91, 0xf881]),
+            ("rev16.w r2, r3", [0xfa93, 0xf293]),
+            ("sxtb.w r8, r9", [0xfa4f, 0xf889]),
+            ("sxth.w r0, r1, ror #8", [0xfa0f, 0xf091]),
+            ("uxtb.w r0, r1", [0xfa5f, 0xf081]),
+            ("uxth.w r0, r1, ror #24", [0xfa1f, 0xf0b1]),
+            ("umull r0, r1, r2, r3", [0xfba2, 0x0103]),
+            ("smull r4, r5, r6, r7", [0xfb86, 0x4507]),
+            ("ldrex r0, [r1]", [0xe851, 0x0f00]),
+            ("ldrex r0, [r1, #1020]", [0xe851, 0x0fff]),
+            ("strex r2, r0, [r1]", [0xe841, 0x0200]),
+            ("strex r2, r0, [r1, #4]", [0xe841, 0x0201]),
+            ("clrex", [0xf3bf, 0x8f2f]),
+            ("pld [r0]", [0xf890, 0xf000]),
+            ("pld [r0, #4095]", [0xf890, 0xffff]),
+            ("pld [r0, #-255]", [0xf810, 0xfcff]),
+            ("pld [r0, r1]", [0xf810, 0xf001]),
+            ("pld [r0, r1, lsl #3]", [0xf810, 0xf031]),
+            ("adr.w r0, #12", [0xf20f, 0x000c]),
+            ("adr.w r8, #4095", [0xf60f, 0x78ff]),
+            ("adr.w r0, #-8", [0xf2af, 0x0008]),
+        ];
+        for (inst_str, expected) in cases {
+            assert_eq!(assemble(inst_str), expected, "{inst_str}");
+        }
+
+        let parse = |value: &str| {
+            let (opcode, rest) = value.split_once(' ').unwrap();
+            let opcode = ThumbOpcode::try_from(opcode).unwrap();
+            Thumb2Instruction::parse(opcode.mnemonic, opcode.set_flags, opcode.cond, rest)
+        };
+        assert!(parse("adds.w r0, r1, #0x101").is_err());
+        assert!(parse("ldr.w r0, [r1, #-256]").is_err());
+        assert!(parse("bne.w 0x100000").is_err());
+        assert!(parse("addeq.w r0, r1, r2").is_err());
+        assert!(parse("isb ish").is_err());
+        assert!(parse("msr cpsr, r0").is_err());
+        assert!(parse("msr cpsr_cc, r0").is_err());
+        assert!(parse("mrs r0, bogus").is_err());
+        assert!(parse("ldrd r0, r1, [r2, #2]").is_err());
+        assert!(parse("ldrd r0, r1, [r2, #1024]").is_err());
+        assert!(parse("ldrd r0, r1, [r2, r3]").is_err());
+        assert!(parse("ldr.w r0, [r1, #4], #4").is_err());
+        assert!(parse("bfi r0, r1, #4, #29").is_err());
+        assert!(parse("ubfx r0, r1, #0, #0").is_err());
+        assert!(parse("sxtb.w r0, r1, ror #4").is_err());
+        assert!(parse("ldrex r0, [r1, #2]").is_err());
+        assert!(parse("pld [r0, #4]!").is_err());
+        assert!(parse("adr.w r0, #4096").is_err());
+        assert_eq!(
+            parse("b.w 2").unwrap(),
+            Thumb2Instruction::Branch(Cond::AL, 2)
+        );
+    }
+
+    #[test]
+    fn test_unpredictable() {
+        let check = |value: &str| {
+            let (opcode, rest) = value.split_once(' ').unwrap();
+            let opcode = ThumbOpcode::try_from(opcode).unwrap();
+            Thumb2Instruction::parse(opcode.mnemonic, opcode.set_flags, opcode.cond, rest)
+                .unwrap()
+                .unpredictable()
+                .map(|unpredictable| unpredictable.severity)
+        };
+        for fine in [
+            "add.w sp, sp, #16",
+            "subw sp, sp, #300",
+            "mov.w r0, sp",
+            "mov.w sp, r7",
+            "cmp.w sp, #8",
+            "add.w r0, sp, #4",
+            "ldr.w pc, [sp], #4",
+            "push.w {r4-r11, lr}",
+            "pop.w {r4-r11, pc}",
+            "strd r0, r0, [r2]",
+        ] {
+            assert_eq!(check(fine), None, "{fine}");
+        }
+        for bad in [
+            "and.w sp, r0, r1",
+            "add.w r0, r1, sp",
+            "movs.w sp, r0",
+            "mov.w sp, sp",
+            "lsl.w r0, sp, r1",
+            "mul.w r0, pc, r1",
+            "ldrb.w sp, [r0]",
+            "pop.w {r0, sp}",
+            "ldm.w r0, {lr, pc}",
+            "tbb [r0, sp]",
+            "ldrd r0, r0, [r2]",
+            "ldrd r0, r1, [r0], #8",
+            "strd r0, pc, [r2]",
+            "mrs sp, apsr",
+            "msr apsr_nzcvq, pc",
+            "clz r0, sp",
+            "umull r0, r0, r1, r2",
+            "strex r0, r0, [r1]",
+            "ldrex pc, [r0]",
+        ] {
+            assert_eq!(check(bad), Some(Severity::Error), "{bad}");
+        }
+        assert_eq!(check("ldr.w r0, [r0], #4"), Some(Severity::Warning));
+    }
+}
@@ -0,0 +1,899 @@
+//! VFP floating-point instructions. The same 32-bit encodings are used in ARM and Thumb state;
+//! Thumb puts `al` in the condition field and takes any condition from an IT block instead.
+
+use crate::{
+    arch::Feature,
+    cond::Cond,
+    error::{AssemblerError, ParseError},
+    instructions::{
+        parse_bounded_immediate, parse_immediate, parse_reg_id, split_operands, Rd, Rn,
+    },
+    mnemonics::parse_mnemonic,
+    thumb::split_address,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum VfpMnemonic {
+    VABS,
+    VADD,
+    VCMP,
+    VCMPE,
+    VCVT,
+    VCVTR,
+    VDIV,
+    VFMA,
+    VFMS,
+    VFNMA,
+    VFNMS,
+    VLDM,
+    VLDMDB,
+    VLDMIA,
+    VLDR,
+    VMLA,
+    VMLS,
+    VMOV,
+    VMRS,
+    VMSR,
+    VMUL,
+    VNEG,
+    VNMLA,
+    VNMLS,
+    VNMUL,
+    VPOP,
+    VPUSH,
+    VSQRT,
+    VSTM,
+    VSTMDB,
+    VSTMIA,
+    VSTR,
+    VSUB,
+}
+
+impl std::fmt::Display for VfpMnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::VABS => "vabs",
+            Self::VADD => "vadd",
+            Self::VCMP => "vcmp",
+            Self::VCMPE => "vcmpe",
+            Self::VCVT => "vcvt",
+            Self::VCVTR => "vcvtr",
+            Self::VDIV => "vdiv",
+            Self::VFMA => "vfma",
+            Self::VFMS => "vfms",
+            Self::VFNMA => "vfnma",
+            Self::VFNMS => "vfnms",
+            Self::VLDM => "vldm",
+            Self::VLDMDB => "vldmdb",
+            Self::VLDMIA => "vldmia",
+            Self::VLDR => "vldr",
+            Self::VMLA => "vmla",
+            Self::VMLS => "vmls",
+            Self::VMOV => "vmov",
+            Self::VMRS => "vmrs",
+            Self::VMSR => "vmsr",
+            Self::VMUL => "vmul",
+            Self::VNEG => "vneg",
+            Self::VNMLA => "vnmla",
+            Self::VNMLS => "vnmls",
+            Self::VNMUL => "vnmul",
+            Self::VPOP => "vpop",
+            Self::VPUSH => "vpush",
+            Self::VSQRT => "vsqrt",
+            Self::VSTM => "vstm",
+            Self::VSTMDB => "vstmdb",
+            Self::VSTMIA => "vstmia",
+            Self::VSTR => "vstr",
+            Self::VSUB => "vsub",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl TryFrom<&str> for VfpMnemonic {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        parse_mnemonic(value)
+    }
+}
+
+impl VfpMnemonic {
+    /// The `o1`, `o2` and `op` bits of a three register arithmetic instruction.
+    fn arithmetic_opcode(self) -> Option<(u32, u32, u32)> {
+        match self {
+            Self::VMLA => Some((0, 0b00, 0)),
+            Self::VMLS => Some((0, 0b00, 1)),
+            Self::VNMLS => Some((0, 0b01, 0)),
+            Self::VNMLA => Some((0, 0b01, 1)),
+            Self::VMUL => Some((0, 0b10, 0)),
+            Self::VNMUL => Some((0, 0b10, 1)),
+            Self::VADD => Some((0, 0b11, 0)),
+            Self::VSUB => Some((0, 0b11, 1)),
+            Self::VDIV => Some((1, 0b00, 0)),
+            Self::VFNMS => Some((1, 0b01, 0)),
+            Self::VFNMA => Some((1, 0b01, 1)),
+            Self::VFMA => Some((1, 0b10, 0)),
+            Self::VFMS => Some((1, 0b10, 1)),
+            _ => None,
+        }
+    }
+
+    fn is_fused(self) -> bool {
+        matches!(self, Self::VFMA | Self::VFMS | Self::VFNMA | Self::VFNMS)
+    }
+}
+
+/// A single-precision `s0`-`s31` or double-precision `d0`-`d31` register.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VfpRegister {
+    Single(u8),
+    Double(u8),
+}
+
+impl TryFrom<&str> for VfpRegister {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let bad_register = || ParseError::BadRegister(value.to_owned());
+        let value = value.trim();
+        let number = value
+            .get(1..)
+            .and_then(|number| number.parse::<u8>().ok())
+            .filter(|number| *number < 32)
+            .ok_or_else(bad_register)?;
+
+        match value.get(..1) {
+            Some("s" | "S") => Ok(Self::Single(number)),
+            Some("d" | "D") => Ok(Self::Double(number)),
+            _ => Err(bad_register().into()),
+        }
+    }
+}
+
+impl std::fmt::Display for VfpRegister {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Single(number) => write!(f, "s{number}"),
+            Self::Double(number) => write!(f, "d{number}"),
+        }
+    }
+}
+
+impl VfpRegister {
+    pub fn is_double(self) -> bool {
+        matches!(self, Self::Double(_))
+    }
+
+    pub(crate) fn number(self) -> u8 {
+        match self {
+            Self::Single(number) | Self::Double(number) => number,
+        }
+    }
+
+    /// The 4-bit register field and the extra bit stored elsewhere in the instruction, which is
+    /// the low bit of a single register but the high bit of a double.
+    fn fields(self) -> (u32, u32) {
+        match self {
+            Self::Single(number) => (u32::from(number >> 1), u32::from(number & 1)),
+            Self::Double(number) => (u32::from(number & 0xF), u32::from(number >> 4)),
+        }
+    }
+
+    /// Encoded in the Vd position, bits 15-12 and 22.
+    pub(crate) fn vd(self) -> u32 {
+        let (field, extra) = self.fields();
+        (field << 12) | (extra << 22)
+    }
+
+    /// Encoded in the Vn position, bits 19-16 and 7.
+    pub(crate) fn vn(self) -> u32 {
+        let (field, extra) = self.fields();
+        (field << 16) | (extra << 7)
+    }
+
+    /// Encoded in the Vm position, bits 3-0 and 5.
+    pub(crate) fn vm(self) -> u32 {
+        let (field, extra) = self.fields();
+        field | (extra << 5)
+    }
+
+    /// The `sz` bit, set for double precision.
+    fn sz(self) -> u32 {
+        u32::from(self.is_double()) << 8
+    }
+
+    fn same_kind(self, other: Self) -> bool {
+        self.is_double() == other.is_double()
+    }
+}
+
+/// The system registers `vmrs`/`vmsr` can access.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SystemRegister {
+    Fpsid,
+    Fpscr,
+    Fpexc,
+}
+
+impl TryFrom<&str> for SystemRegister {
+    type Error = AssemblerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "fpsid" => Ok(Self::Fpsid),
+            "fpscr" => Ok(Self::Fpscr),
+            "fpexc" => Ok(Self::Fpexc),
+            _ => Err(ParseError::BadRegister(value.to_owned()).into()),
+        }
+    }
+}
+
+impl From<SystemRegister> for u32 {
+    fn from(value: SystemRegister) -> Self {
+        match value {
+            SystemRegister::Fpsid => 0b0000,
+            SystemRegister::Fpscr => 0b0001,
+            SystemRegister::Fpexc => 0b1000,
+        }
+    }
+}
+
+/// The kinds of `vcvt` between floating-point and integer formats.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Conversion {
+    /// Between single and double precision.
+    Precision,
+    /// From a signed (true) or unsigned 32-bit integer.
+    FromInteger(bool),
+    /// To a signed or unsigned 32-bit integer, rounding towards zero (`vcvt`) or using the
+    /// rounding mode in FPSCR (`vcvtr`).
+    ToInteger(bool, bool),
+}
+
+/// A conversion between floating point and fixed point in the same register.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FixedPoint {
+    pub to_fixed: bool,
+    pub signed: bool,
+    /// 16 or 32 bits.
+    pub size: u8,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum VfpInstruction {
+    /// `op Vd, Vn, Vm`
+    Arithmetic(VfpMnemonic, VfpRegister, VfpRegister, VfpRegister),
+    /// `vabs/vneg/vsqrt/vmov Vd, Vm`
+    Unary(VfpMnemonic, VfpRegister, VfpRegister),
+    /// `vcmp{e} Vd, Vm`, or compare with `#0` when there is no Vm
+    Compare(VfpMnemonic, VfpRegister, Option<VfpRegister>),
+    /// `vmov Vd, #imm` with the constant already in its 8-bit encoding
+    MoveImmediate(VfpRegister, u8),
+    /// `vmov Rt, Sn` or `vmov Sn, Rt`, with a flag for the move to the core register
+    MoveSingle(bool, Rd, VfpRegister),
+    /// `vmov Rt, Rt2, Dm` or `vmov Dm, Rt, Rt2`, with a flag for the move to core registers
+    MoveDouble(bool, Rd, Rn, VfpRegister),
+    /// `vmrs Rt, reg`, where `APSR_nzcv` is encoded as pc
+    MoveFromSystem(Rd, SystemRegister),
+    /// `vmsr reg, Rt`
+    MoveToSystem(SystemRegister, Rd),
+    /// `vcvt{r}.<to>.<from> Vd, Vm`
+    Convert(Conversion, VfpRegister, VfpRegister),
+    /// `vcvt.<to>.<from> Vd, Vd, #fbits`
+    ConvertFixed(FixedPoint, VfpRegister, u8),
+    /// `vldr/vstr Vd, [Rn{, #offset}]`
+    LoadStore(VfpMnemonic, VfpRegister, Rn, i16),
+    /// `vldm/vstm{ia,db} Rn{!}, {list}` with a flag for writeback, the first register and the
+    /// number of registers in the list
+    Multiple(VfpMnemonic, Rn, bool, VfpRegister, u8),
+}
+
+/// Encode `value` as the 8-bit `abcdefgh` constant of `vmov`, which covers ±(16..31)/16 × 2^n
+/// for n in -3..=4.
+pub fn encode_float_immediate(value: f64) -> Option<u8> {
+    let bits = value.to_bits();
+    let sign = (bits >> 63) as u8;
+    let exponent = ((bits >> 52) & 0x7FF) as i32 - 1023;
+    let mantissa = bits & 0x000F_FFFF_FFFF_FFFF;
+
+    if !(-3..=4).contains(&exponent) || mantissa & 0x0000_FFFF_FFFF_FFFF != 0 {
+        return None;
+    }
+
+    // The exponent is stored as NOT(b):b..b:cd, so b is set for exponents up to 0
+    let b = u8::from(exponent <= 0);
+    let cd = ((exponent + 1023) & 0b11) as u8;
+    Some((sign << 7) | (b << 6) | (cd << 4) | (mantissa >> 48) as u8)
+}
+
+pub(crate) fn parse_float_immediate(value: &str) -> Result<f64, AssemblerError> {
+    let bad_immediate = || ParseError::BadImmediate(value.to_owned());
+    let number = value.trim().strip_prefix('#').ok_or_else(bad_immediate)?;
+
+    match number.parse::<f64>() {
+        Ok(number) => Ok(number),
+        Err(_) => Ok(parse_immediate(number).map_err(|_| bad_immediate())? as f64),
+    }
+}
+
+/// Check the data type suffixes against the precision of `reg`. `f32`/`f64` are always accepted,
+/// and the plain sizes `32`/`64` where `sized` is set, as for loads and stores.
+fn check_data_type(types: &[&str], reg: VfpRegister, sized: bool) -> Result<(), AssemblerError> {
+    let ok = match types {
+        [] => true,
+        [data_type] => match data_type.to_ascii_lowercase().as_str() {
+            "f32" => !reg.is_double(),
+            "f64" => reg.is_double(),
+            "32" => sized && !reg.is_double(),
+            "64" => sized && reg.is_double(),
+            _ => false,
+        },
+        _ => false,
+    };
+
+    if ok {
+        Ok(())
+    } else {
+        Err(ParseError::BadDataType(types.join(".")).into())
+    }
+}
+
+/// Parse a list of consecutive registers of the same precision, such as `{d8-d15}` or
+/// `{s0, s1}`, returning the first register and the count.
+pub(crate) fn parse_vfp_reg_list(value: &str) -> Result<(VfpRegister, u8), AssemblerError> {
+    let bad_list = || ParseError::BadRegister(value.to_owned());
+    let inner = value
+        .trim()
+        .strip_prefix('{')
+        .and_then(|list| list.strip_suffix('}'))
+        .ok_or_else(bad_list)?;
+
+    let mut regs: Vec<VfpRegister> = vec![];
+    for item in inner.split(',') {
+        let (first, last) = match item.split_once('-') {
+            Some((first, last)) => (VfpRegister::try_from(first)?, VfpRegister::try_from(last)?),
+            None => {
+                let reg = VfpRegister::try_from(item)?;
+                (reg, reg)
+            }
+        };
+        if !first.same_kind(last) || first.number() > last.number() {
+            return Err(bad_list().into());
+        }
+        for number in first.number()..=last.number() {
+            regs.push(match first {
+                VfpRegister::Single(_) => VfpRegister::Single(number),
+                VfpRegister::Double(_) => VfpRegister::Double(number),
+            });
+        }
+    }
+
+    let first = *regs.first().ok_or_else(bad_list)?;
+    let consecutive = regs.iter().enumerate().all(|(i, reg)| {
+        reg.same_kind(first) && usize::from(reg.number()) == usize::from(first.number()) + i
+    });
+    let max = if first.is_double() { 16 } else { 32 };
+    if !consecutive || regs.len() > max {
+        return Err(bad_list().into());
+    }
+
+    Ok((first, regs.len() as u8))
+}
+
+impl VfpInstruction {
+    /// Parse a whole VFP instruction such as `vaddeq.f32 s0, s1, s2`, returning its condition
+    /// separately so that Thumb code can take it from an IT block.
+    pub fn parse(value: &str) -> Result<(Cond, Self), AssemblerError> {
+        let value = value.trim();
+        let (opcode, rest) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+        let (name, types) = opcode.split_once('.').unwrap_or((opcode, ""));
+        let types: Vec<&str> = types.split('.').filter(|t| !t.is_empty()).collect();
+        let mnemonic = VfpMnemonic::try_from(name)?;
+        let cond_maybe = &name[mnemonic.to_string().len()..];
+        let cond = if cond_maybe.is_empty() {
+            Cond::AL
+        } else {
+            Cond::try_from(cond_maybe)?
+        };
+
+        let operands = split_operands(rest);
+        let bad_operands = || ParseError::BadFlexOperand(rest.trim().to_owned());
+        let vfp_reg = |i: usize| -> Result<VfpRegister, AssemblerError> {
+            VfpRegister::try_from(*operands.get(i).ok_or(ParseError::RanOutOfOperands)?)
+        };
+        let core_reg = |i: usize| -> Result<u8, AssemblerError> {
+            parse_reg_id(operands.get(i).ok_or(ParseError::RanOutOfOperands)?)
+        };
+
+        let instruction = match mnemonic {
+            _ if mnemonic.arithmetic_opcode().is_some() => {
+                // The two operand form accumulates into Vd
+                let (vd, vn, vm) = match operands.len() {
+                    2 => (vfp_reg(0)?, vfp_reg(0)?, vfp_reg(1)?),
+                    3 => (vfp_reg(0)?, vfp_reg(1)?, vfp_reg(2)?),
+                    _ => return Err(bad_operands().into()),
+                };
+                if !vd.same_kind(vn) || !vd.same_kind(vm) {
+                    return Err(bad_operands().into());
+                }
+                check_data_type(&types, vd, false)?;
+                Self::Arithmetic(mnemonic, vd, vn, vm)
+            }
+            VfpMnemonic::VABS | VfpMnemonic::VNEG | VfpMnemonic::VSQRT => {
+                let (vd, vm) = (vfp_reg(0)?, vfp_reg(1)?);
+                if operands.len() != 2 || !vd.same_kind(vm) {
+                    return Err(bad_operands().into());
+                }
+                check_data_type(&types, vd, false)?;
+                Self::Unary(mnemonic, vd, vm)
+            }
+            VfpMnemonic::VCMP | VfpMnemonic::VCMPE => {
+                let vd = vfp_reg(0)?;
+                check_data_type(&types, vd, false)?;
+                match operands.as_slice() {
+                    [_, zero] if zero.starts_with('#') => {
+                        if parse_float_immediate(zero)? != 0.0 {
+                            return Err(ParseError::BadImmediate(zero.to_string()).into());
+                        }
+                        Self::Compare(mnemonic, vd, None)
+                    }
+                    [_, _] if vd.same_kind(vfp_reg(1)?) => {
+                        Self::Compare(mnemonic, vd, Some(vfp_reg(1)?))
+                    }
+                    _ => return Err(bad_operands().into()),
+                }
+            }
+            VfpMnemonic::VMOV => Self::parse_vmov(&operands, &types)?,
+            VfpMnemonic::VMRS => {
+                let rt = match operands.first() {
+                    Some(apsr) if apsr.eq_ignore_ascii_case("apsr_nzcv") => 15,
+                    _ => core_reg(0)?,
+                };
+                if operands.len() != 2 {
+                    return Err(bad_operands().into());
+                }
+                Self::MoveFromSystem(Rd(rt), SystemRegister::try_from(operands[1])?)
+            }
+            VfpMnemonic::VMSR => {
+                let reg = SystemRegister::try_from(*operands.first().ok_or(bad_operands())?)?;
+                if operands.len() != 2 {
+                    return Err(bad_operands().into());
+                }
+                Self::MoveToSystem(reg, Rd(core_reg(1)?))
+            }
+            VfpMnemonic::VCVT | VfpMnemonic::VCVTR => {
+                Self::parse_vcvt(mnemonic, &operands, &types)?
+            }
+            VfpMnemonic::VLDR | VfpMnemonic::VSTR => {
+                let vd = vfp_reg(0)?;
+                check_data_type(&types, vd, true)?;
+                let (base, offset) = split_address(operands.get(1).ok_or(bad_operands())?)?;
+                let offset = match offset {
+                    Some(offset) => parse_immediate(offset)?,
+                    None => 0,
+                };
+                if offset % 4 != 0 || !(-1020..=1020).contains(&offset) {
+                    return Err(ParseError::BadImmediate(offset.to_string()).into());
+                }
+                Self::LoadStore(mnemonic, vd, Rn(parse_reg_id(base)?), offset as i16)
+            }
+            VfpMnemonic::VPUSH | VfpMnemonic::VPOP => {
+                let [list] = operands.as_slice() else {
+                    return Err(bad_operands().into());
+                };
+                let (first, count) = parse_vfp_reg_list(list)?;
+                check_data_type(&types, first, true)?;
+                Self::Multiple(mnemonic, Rn(13), true, first, count)
+            }
+            _ => {
+                let [base, list] = operands.as_slice() else {
+                    return Err(bad_operands().into());
+                };
+                let (base, writeback) = match base.strip_suffix('!') {
+                    Some(base) => (base, true),
+                    None => (*base, false),
+                };
+                let decrement = matches!(mnemonic, VfpMnemonic::VLDMDB | VfpMnemonic::VSTMDB);
+                if decrement && !writeback {
+                    return Err(bad_operands().into());
+                }
+                let (first, count) = parse_vfp_reg_list(list)?;
+                check_data_type(&types, first, true)?;
+                Self::Multiple(
+                    mnemonic,
+                    Rn(parse_reg_id(base.trim())?),
+                    writeback,
+                    first,
+                    count,
+                )
+            }
+        };
+
+        Ok((cond, instruction))
+    }
+
+    fn parse_vmov(operands: &[&str], types: &[&str]) -> Result<Self, AssemblerError> {
+        let bad_operands = || ParseError::BadFlexOperand(operands.join(", "));
+        let vfp = |value: &str| VfpRegister::try_from(value).ok();
+
+        let instruction = match *operands {
+            [vd, imm] if imm.starts_with('#') => {
+                let vd = VfpRegister::try_from(vd)?;
+                check_data_type(types, vd, false)?;
+                let imm = encode_float_immediate(parse_float_immediate(imm)?)
+                    .ok_or_else(|| ParseError::BadImmediate(imm.to_owned()))?;
+                Self::MoveImmediate(vd, imm)
+            }
+            [vd, vm] => match (vfp(vd), vfp(vm)) {
+                (Some(vd), Some(vm)) if vd.same_kind(vm) => {
+                    check_data_type(types, vd, false)?;
+                    Self::Unary(VfpMnemonic::VMOV, vd, vm)
+                }
+                (None, Some(sn @ VfpRegister::Single(_))) => {
+                    Self::MoveSingle(true, Rd(parse_reg_id(vd)?), sn)
+                }
+                (Some(sn @ VfpRegister::Single(_)), None) => {
+                    Self::MoveSingle(false, Rd(parse_reg_id(vm)?), sn)
+                }
+                _ => return Err(bad_operands().into()),
+            },
+            [rt, rt2, dm] if vfp(dm).is_some_and(VfpRegister::is_double) => Self::MoveDouble(
+                true,
+                Rd(parse_reg_id(rt)?),
+                Rn(parse_reg_id(rt2)?),
+                VfpRegister::try_from(dm)?,
+            ),
+            [dm, rt, rt2] if vfp(dm).is_some_and(VfpRegister::is_double) => Self::MoveDouble(
+                false,
+                Rd(parse_reg_id(rt)?),
+                Rn(parse_reg_id(rt2)?),
+                VfpRegister::try_from(dm)?,
+            ),
+            _ => return Err(bad_operands().into()),
+        };
+
+        // Core register moves take no data type
+        if matches!(instruction, Self::MoveSingle(..) | Self::MoveDouble(..)) && !types.is_empty() {
+            return Err(ParseError::BadDataType(types.join(".")).into());
+        }
+
+        Ok(instruction)
+    }
+
+    fn parse_vcvt(
+        mnemonic: VfpMnemonic,
+        operands: &[&str],
+        types: &[&str],
+    ) -> Result<Self, AssemblerError> {
+        let bad_types = || ParseError::BadDataType(types.join("."));
+        let bad_operands = || ParseError::BadFlexOperand(operands.join(", "));
+        let [to, from] = types else {
+            return Err(bad_types().into());
+        };
+        let (to, from) = (to.to_ascii_lowercase(), from.to_ascii_lowercase());
+        let is_float = |t: &str| t == "f32" || t == "f64";
+        let (vd, vm) = match operands {
+            [vd, vm] | [vd, vm, _] => (VfpRegister::try_from(*vd)?, VfpRegister::try_from(*vm)?),
+            _ => return Err(bad_operands().into()),
+        };
+        let matches_type = |reg: VfpRegister, t: &str| match t {
+            "f64" => reg.is_double(),
+            _ => !reg.is_double(),
+        };
+
+        if let [_, _, fbits] = operands {
+            let (float, fixed) = if is_float(&to) {
+                (&to, &from)
+            } else {
+                (&from, &to)
+            };
+            let (signed, size) = match fixed.as_str() {
+                "s16" => (true, 16),
+                "u16" => (false, 16),
+                "s32" => (true, 32),
+                "u32" => (false, 32),
+                _ => return Err(bad_types().into()),
+            };
+            let fbits = parse_bounded_immediate(fbits, size)?;
+            if mnemonic != VfpMnemonic::VCVT
+                || !is_float(float)
+                || vd != vm
+                || !matches_type(vd, float)
+                || (size == 32 && fbits == 0)
+            {
+                return Err(bad_operands().into());
+            }
+            let fixed_point = FixedPoint {
+                to_fixed: !is_float(&to),
+                signed,
+                size: size as u8,
+            };
+            return Ok(Self::ConvertFixed(fixed_point, vd, fbits as u8));
+        }
+
+        let conversion = match (to.as_str(), from.as_str()) {
+            ("f64", "f32") | ("f32", "f64") if mnemonic == VfpMnemonic::VCVT => {
+                Conversion::Precision
+            }
+            ("f32" | "f64", "s32" | "u32") if mnemonic == VfpMnemonic::VCVT => {
+                Conversion::FromInteger(from == "s32")
+            }
+            ("s32" | "u32", "f32" | "f64") => {
+                Conversion::ToInteger(to == "s32", mnemonic == VfpMnemonic::VCVT)
+            }
+            _ => return Err(bad_types().into()),
+        };
+        if !matches_type(vd, &to) || !matches_type(vm, &from) {
+            return Err(bad_operands().into());
+        }
+
+        Ok(Self::Convert(conversion, vd, vm))
+    }
+
+    /// The registers whose precision and number decide the features needed.
+    fn registers(&self) -> Vec<VfpRegister> {
+        match *self {
+            Self::Arithmetic(_, vd, vn, vm) => vec![vd, vn, vm],
+            Self::Unary(_, vd, vm) | Self::Convert(_, vd, vm) => vec![vd, vm],
+            Self::Compare(_, vd, vm) => [Some(vd), vm].into_iter().flatten().collect(),
+            Self::MoveImmediate(vd, _)
+            | Self::MoveSingle(_, _, vd)
+            | Self::MoveDouble(_, _, _, vd)
+            | Self::ConvertFixed(_, vd, _)
+            | Self::LoadStore(_, vd, ..) => vec![vd],
+            Self::Multiple(_, _, _, first, count) => {
+                let last = first.number() + count - 1;
+                vec![match first {
+                    VfpRegister::Single(_) => VfpRegister::Single(last),
+                    VfpRegister::Double(_) => VfpRegister::Double(last),
+                }]
+            }
+            Self::MoveFromSystem(..) | Self::MoveToSystem(..) => vec![],
+        }
+    }
+
+    /// Everything the FPU must provide for this instruction: the VFP version, then double
+    /// precision arithmetic and the upper 16 double registers where they are used.
+    pub fn required_features(&self) -> Vec<Feature> {
+        let version = match self {
+            Self::Arithmetic(mnemonic, ..) if mnemonic.is_fused() => Feature::Vfpv4,
+            Self::MoveImmediate(..) | Self::ConvertFixed(..) => Feature::Vfpv3,
+            _ => Feature::Vfpv2,
+        };
+        let mut features = vec![version];
+
+        let registers = self.registers();
+        // Moving doubles around doesn't involve any double-precision arithmetic
+        let arithmetic = !matches!(
+            self,
+            Self::MoveDouble(..) | Self::LoadStore(..) | Self::Multiple(..)
+        );
+        if arithmetic && registers.iter().any(|reg| reg.is_double()) {
+            features.push(Feature::Fp64);
+        }
+        if registers
+            .iter()
+            .any(|reg| reg.is_double() && reg.number() >= 16)
+        {
+            features.push(Feature::VfpD32);
+        }
+
+        features
+    }
+
+    pub fn to_machine_code(&self, cond: Cond) -> u32 {
+        let encoding = match *self {
+            Self::Arithmetic(mnemonic, vd, vn, vm) => {
+                let (o1, o2, op) = mnemonic.arithmetic_opcode().unwrap_or_default();
+                0x0E00_0A00
+                    | (o1 << 23)
+                    | (o2 << 20)
+                    | (op << 6)
+                    | vd.sz()
+                    | vd.vd()
+                    | vn.vn()
+                    | vm.vm()
+            }
+            Self::Unary(mnemonic, vd, vm) => {
+                let (opc2, high) = match mnemonic {
+                    VfpMnemonic::VABS => (0, 1),
+                    VfpMnemonic::VNEG => (1, 0),
+                    VfpMnemonic::VSQRT => (1, 1),
+                    _ => (0, 0),
+                };
+                0x0EB0_0A40 | (opc2 << 16) | (high << 7) | vd.sz() | vd.vd() | vm.vm()
+            }
+            Self::Compare(mnemonic, vd, vm) => {
+                let e = u32::from(mnemonic == VfpMnemonic::VCMPE) << 7;
+                match vm {
+                    Some(vm) => 0x0EB4_0A40 | e | vd.sz() | vd.vd() | vm.vm(),
+                    None => 0x0EB5_0A40 | e | vd.sz() | vd.vd(),
+                }
+            }
+            Self::MoveImmediate(vd, imm) => {
+                let imm = u32::from(imm);
+                0x0EB0_0A00 | ((imm >> 4) << 16) | (imm & 0xF) | vd.sz() | vd.vd()
+            }
+            Self::MoveSingle(to_core, rt, sn) => {
+                0x0E00_0A10 | (u32::from(to_core) << 20) | (u32::from(rt.0) << 12) | sn.vn()
+            }
+            Self::MoveDouble(to_core, rt, rt2, dm) => {
+                0x0C40_0B10
+                    | (u32::from(to_core) << 20)
+                    | (u32::from(rt2.0) << 16)
+                    | (u32::from(rt.0) << 12)
+                    | dm.vm()
+            }
+            Self::MoveFromSystem(rt, reg) => {
+                0x0EF0_0A10 | (u32::from(reg) << 16) | (u32::from(rt.0) << 12)
+            }
+            Self::MoveToSystem(reg, rt) => {
+                0x0EE0_0A10 | (u32::from(reg) << 16) | (u32::from(rt.0) << 12)
+            }
+            Self::Convert(conversion, vd, vm) => match conversion {
+                Conversion::Precision => 0x0EB7_0AC0 | vm.sz() | vd.vd() | vm.vm(),
+                Conversion::FromInteger(signed) => {
+                    0x0EB8_0A40 | (u32::from(signed) << 7) | vd.sz() | vd.vd() | vm.vm()
+                }
+                Conversion::ToInteger(signed, round_to_zero) => {
+                    0x0EBC_0A40
+                        | (u32::from(signed) << 16)
+                        | (u32::from(round_to_zero) << 7)
+                        | vm.sz()
+                        | vd.vd()
+                        | vm.vm()
+                }
+            },
+            Self::ConvertFixed(fixed_point, vd, fbits) => {
+                let imm = u32::from(fixed_point.size - fbits);
+                0x0EBA_0A40
+                    | (u32::from(fixed_point.to_fixed) << 18)
+                    | (u32::from(!fixed_point.signed) << 16)
+                    | (u32::from(fixed_point.size == 32) << 7)
+                    | ((imm & 1) << 5)
+                    | (imm >> 1)
+                    | vd.sz()
+                    | vd.vd()
+            }
+            Self::LoadStore(mnemonic, vd, rn, offset) => {
+                let load = u32::from(mnemonic == VfpMnemonic::VLDR);
+                let up = u32::from(offset >= 0);
+                0x0D00_0A00
+                    | (up << 23)
+                    | (load << 20)
+                    | (u32::from(rn.0) << 16)
+                    | u32::from(offset.unsigned_abs() / 4)
+                    | vd.sz()
+                    | vd.vd()
+            }
+            Self::Multiple(mnemonic, rn, writeback, first, count) => {
+                let load = matches!(
+                    mnemonic,
+                    VfpMnemonic::VLDM | VfpMnemonic::VLDMIA | VfpMnemonic::VPOP
+                );
+                let decrement = matches!(
+                    mnemonic,
+                    VfpMnemonic::VLDMDB | VfpMnemonic::VSTMDB | VfpMnemonic::VPUSH
+                );
+                // P and U: increment after or decrement before
+                let addressing = if decrement { 0x0100_0000 } else { 0x0080_0000 };
+                let words = if first.is_double() { 2 * count } else { count };
+                0x0C00_0A00
+                    | addressing
+                    | (u32::from(writeback) << 21)
+                    | (u32::from(load) << 20)
+                    | (u32::from(rn.0) << 16)
+                    | u32::from(words)
+                    | first.sz()
+                    | first.vd()
+            }
+        };
+
+        ((cond as u32) << 28) | encoding
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{encode_float_immediate, VfpInstruction, VfpRegister};
+    use crate::arch::Feature;
+
+    #[test]
+    fn test_float_immediate() {
+        assert_eq!(encode_float_immediate(1.0), Some(0x70));
+        assert_eq!(encode_float_immediate(-0.5), Some(0xE0));
+        assert_eq!(encode_float_immediate(0.125), Some(0x40));
+        assert_eq!(encode_float_immediate(31.0), Some(0x3F));
+        assert_eq!(encode_float_immediate(0.0), None);
+        assert_eq!(encode_float_immediate(0.1), None);
+        assert_eq!(encode_float_immediate(32.0), None);
+    }
+
+    #[test]
+    fn test_vfp_encodings() {
+        // Checked against llvm-mc -triple=armv7a -mattr=+vfp4
+        let cases = [
+            ("vadd.f32 s0, s1, s2", 0xEE30_0A81),
+            ("vsub.f64 d0, d1, d2", 0xEE31_0B42),
+            ("vmuleq.f32 s31, s30, s29", 0x0E6F_FA2E),
+            ("vdiv.f64 d16, d17, d18", 0xEEC1_0BA2),
+            ("vmla.f32 s0, s1, s2", 0xEE00_0A81),
+            ("vnmul.f64 d3, d4, d5", 0xEE24_3B45),
+            ("vfma.f32 s0, s1, s2", 0xEEA0_0A81),
+            ("vneg.f32 s1, s2", 0xEEF1_0A41),
+            ("vabs.f64 d1, d2", 0xEEB0_1BC2),
+            ("vsqrt.f32 s0, s1", 0xEEB1_0AE0),
+            ("vcmp.f32 s0, s1", 0xEEB4_0A60),
+            ("vcmpe.f64 d0, #0", 0xEEB5_0BC0),
+            ("vmrs APSR_nzcv, fpscr", 0xEEF1_FA10),
+            ("vmsr fpscr, r3", 0xEEE1_3A10),
+            ("vmov r0, s1", 0xEE10_0A90),
+            ("vmov s2, r1", 0xEE01_1A10),
+            ("vmov r0, r1, d2", 0xEC51_0B12),
+            ("vmov d17, r2, r3", 0xEC43_2B31),
+            ("vmov.f32 s0, s1", 0xEEB0_0A60),
+            ("vmov.f32 s0, #1.0", 0xEEB7_0A00),
+            ("vmov.f64 d0, #-0.5", 0xEEBE_0B00),
+            ("vcvt.f64.f32 d0, s1", 0xEEB7_0AE0),
+            ("vcvt.f32.f64 s0, d1", 0xEEB7_0BC1),
+            ("vcvt.f32.s32 s0, s1", 0xEEB8_0AE0),
+            ("vcvt.f64.u32 d0, s1", 0xEEB8_0B60),
+            ("vcvt.s32.f32 s0, s1", 0xEEBD_0AE0),
+            ("vcvtr.u32.f64 s0, d1", 0xEEBC_0B41),
+            ("vcvt.s32.f32 s0, s0, #16", 0xEEBE_0AC8),
+            ("vcvt.f64.u16 d0, d0, #8", 0xEEBB_0B44),
+            ("vldr s0, [r0, #4]", 0xED90_0A01),
+            ("vstr d1, [sp, #-8]", 0xED0D_1B02),
+            ("vpush {d8-d15}", 0xED2D_8B10),
+            ("vpop {s16-s19}", 0xECBD_8A04),
+            ("vldmia r0!, {d0, d1}", 0xECB0_0B04),
+            ("vstmdb r1!, {s2-s3}", 0xED21_1A02),
+        ];
+
+        for (source, expected) in cases {
+            let (cond, instruction) = VfpInstruction::parse(source).unwrap();
+            assert_eq!(
+                instruction.to_machine_code(cond),
+                expected,
+                "{source}: {instruction:?}"
+            );
+        }
+
+        for bad in [
+            "vadd.f32 s0, s1, d2",
+            "vadd.f64 s0, s1, s2",
+            "vmov.f32 s0, #0.1",
+            "vldr s0, [r0, #2]",
+            "vstmdb r0, {d0}",
+            "vpush {d0, d2}",
+            "vcvtr.f32.s32 s0, s1",
+            "vmov.f32 r0, s1",
+        ] {
+            assert!(VfpInstruction::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_required_features() {
+        let features = |source| VfpInstruction::parse(source).unwrap().1.required_features();
+
+        assert_eq!(features("vadd.f32 s0, s1, s2"), [Feature::Vfpv2]);
+        assert_eq!(
+            features("vadd.f64 d0, d1, d20"),
+            [Feature::Vfpv2, Feature::Fp64, Feature::VfpD32]
+        );
+        assert_eq!(features("vmov.f32 s0, #2.0"), [Feature::Vfpv3]);
+        assert_eq!(features("vfma.f32 s0, s1, s2"), [Feature::Vfpv4]);
+        assert_eq!(features("vldr d0, [r0]"), [Feature::Vfpv2]);
+        assert_eq!(
+            features("vpush {d14-d17}"),
+            [Feature::Vfpv2, Feature::VfpD32]
+        );
+        assert_eq!(
+            VfpRegister::try_from("D31").unwrap(),
+            VfpRegister::Double(31)
+        );
+        assert!(VfpRegister::try_from("s32").is_err());
+    }
+}